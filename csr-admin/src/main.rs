@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+#[cfg(feature = "sqlite")]
+use rusqlite::types::Value;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+
+use csr_protocol::client::CleanClient;
+use csr_protocol::types::Result;
+use csr_protocol::types::{AccountBan, AdminToken, UserID};
+use csr_storage::StorageKey;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a new random storage key
+    GenerateKey,
+    /// Re-encrypt stored files with a new key
+    RotateKey {
+        /// Key the files are encrypted with now, leave out for plain files
+        #[arg(long)]
+        old_key: Option<String>,
+        /// Key to encrypt the files with, leave out to store them plain
+        #[arg(long)]
+        new_key: Option<String>,
+        /// The server's SQLite database, to rotate along with the files
+        #[arg(long)]
+        sqlite: Option<PathBuf>,
+        /// Stored files to rotate
+        #[arg(required_unless_present = "sqlite")]
+        files: Vec<PathBuf>,
+    },
+    /// Print everything the server stores about a user
+    ExportUser {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[arg(short, long)]
+        uid: u64,
+    },
+    /// Delete everything the server stores about a user
+    DeleteUser {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[arg(short, long)]
+        uid: u64,
+    },
+    /// Print how busy the server is
+    Stats {
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+    /// Ban an account from the whole server
+    Ban {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        account: AccountArgs,
+    },
+    /// Lift a ban on an account
+    Unban {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        account: AccountArgs,
+    },
+    /// Print every banned account
+    Bans {
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+}
+
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+struct AccountArgs {
+    #[arg(short, long)]
+    uid: Option<u64>,
+    /// Name to ban, matched ignoring case
+    #[arg(short, long)]
+    name: Option<String>,
+}
+
+impl AccountArgs {
+    fn ban(self) -> AccountBan {
+        match (self.uid, self.name) {
+            (Some(uid), _) => AccountBan::User(UserID(uid)),
+            (None, name) => AccountBan::Name(name.unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct ServerArgs {
+    #[arg(short, long)]
+    address: String,
+    /// Admin token configured on the server
+    #[arg(long, env = "CSR_ADMIN_TOKEN")]
+    admin_token: String,
+}
+
+async fn connect(server: &ServerArgs) -> Result<CleanClient> {
+    let mut client = CleanClient::new(&server.address).await?;
+    client.set_admin_token(AdminToken(server.admin_token.clone()));
+    Ok(client)
+}
+
+fn describe(ban: &AccountBan) -> String {
+    match ban {
+        AccountBan::User(uid) => format!("user {}", uid.0),
+        AccountBan::Name(name) => format!("name {}", name),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::GenerateKey => {
+            println!("{}", StorageKey::generate().to_hex());
+        }
+        Command::RotateKey { old_key, new_key, sqlite, files } => {
+            let old_key = old_key.map(|k| StorageKey::from_hex(&k)).transpose()?;
+            let new_key = new_key.map(|k| StorageKey::from_hex(&k)).transpose()?;
+            // decrypt everything before writing anything, so a wrong old key
+            // leaves every file as it was. The database is rotated in a single
+            // transaction, so it is left as it was too
+            let mut plain = Vec::new();
+            for f in &files {
+                plain.push(csr_storage::read(f, old_key.as_ref())?);
+            }
+            if let Some(db) = &sqlite {
+                rotate_db(db, old_key.as_ref(), new_key.as_ref())?;
+                println!("Rotated {}", db.display());
+            }
+            for (f, data) in files.iter().zip(plain) {
+                csr_storage::write(f, new_key.as_ref(), &data)?;
+                println!("Rotated {}", f.display());
+            }
+        }
+        Command::ExportUser { server, uid } => {
+            let ud = connect(&server).await?.export_user_data(UserID(uid)).await?;
+            println!("User: {}", ud.user_id().0);
+            if let Some(name) = ud.name() {
+                println!("Name: {}", name);
+            }
+            match ud.chips() {
+                Some(c) => println!("Chips: {}", c),
+                None => println!("Chips: none stored"),
+            }
+            match ud.profile() {
+                Some(p) => println!("Games: {} played, {} won", p.games_played(), p.wins()),
+                None => println!("Games: none recorded"),
+            }
+            for g in ud.games() {
+                println!("Played {:?} in session {} as {}, scoring {}{}", g.session_type(),
+                         g.session_id().0, g.name(), g.score().value(),
+                         if g.winner() { " and winning" } else { "" });
+            }
+            for sid in ud.lobbies() {
+                println!("Waiting in session {}", sid.0);
+            }
+            for sid in ud.banned_from() {
+                println!("Banned from session {}", sid.0);
+            }
+        }
+        Command::DeleteUser { server, uid } => {
+            connect(&server).await?.delete_user_data(UserID(uid)).await?;
+            println!("Deleted data for user {}", uid);
+        }
+        Command::Stats { server } => {
+            let stats = connect(&server).await?.server_stats().await?;
+            println!("Sessions: {}", stats.sessions());
+            println!("Games running: {}", stats.games_running());
+            println!("Users online: {}", stats.users_online());
+        }
+        Command::Ban { server, account } => {
+            let ban = account.ban();
+            connect(&server).await?.ban_account(ban.clone()).await?;
+            println!("Banned {}", describe(&ban));
+        }
+        Command::Unban { server, account } => {
+            let ban = account.ban();
+            connect(&server).await?.unban_account(ban.clone()).await?;
+            println!("Lifted the ban on {}", describe(&ban));
+        }
+        Command::Bans { server } => {
+            let bans = connect(&server).await?.account_bans().await?;
+            if bans.is_empty() {
+                println!("No accounts are banned");
+            }
+            for ban in &bans {
+                println!("{}", describe(ban));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the columns the server encrypts in its database, and whether each is kept as
+// text when there is no key
+#[cfg(feature = "sqlite")]
+const SEALED_COLUMNS: [(&str, &str, bool); 5] = [
+    ("sessions", "password", false),
+    ("players", "name", true),
+    ("result_players", "name", true),
+    ("result_players", "score", true),
+    ("result_players", "winner", true),
+];
+
+// decrypt every sealed value in the database with the old key, and seal it
+// again with the new one
+#[cfg(feature = "sqlite")]
+fn rotate_db(path: &Path, old_key: Option<&StorageKey>, new_key: Option<&StorageKey>)
+        -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+    for (table, column, text) in SEALED_COLUMNS {
+        let mut resealed = Vec::new();
+        let mut stmt = tx.prepare(&format!("SELECT rowid, {} FROM {}", column, table))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let plain = match row.get::<_, Value>(1)? {
+                Value::Blob(b) => csr_storage::unseal(b, old_key)?,
+                Value::Text(s) => s.into_bytes(),
+                Value::Integer(i) => i.to_string().into_bytes(),
+                Value::Real(r) => r.to_string().into_bytes(),
+                Value::Null => { continue; }
+            };
+            let value = match new_key {
+                Some(k) => Value::Blob(csr_storage::encrypt(k, &plain)?),
+                None if text => Value::Text(String::from_utf8(plain)?),
+                None => Value::Blob(plain),
+            };
+            resealed.push((row.get::<_, i64>(0)?, value));
+        }
+        drop(rows);
+        drop(stmt);
+        for (rowid, value) in resealed {
+            tx.execute(&format!("UPDATE {} SET {} = ?1 WHERE rowid = ?2", table, column),
+                       params![value, rowid])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn rotate_db(_path: &Path, _old_key: Option<&StorageKey>, _new_key: Option<&StorageKey>)
+        -> Result<()> {
+    Err("--sqlite needs csr-admin built with the sqlite feature".into())
+}
@@ -1,18 +1,60 @@
 use std::io::Write;
+use std::sync::Arc;
 
 use async_trait::async_trait;
+use tokio::sync::Notify;
 
 use csr_protocol::event::ServerEvent;
 use csr_protocol::types::Result;
 use csr_protocol::types::{
-    Coin, SessionID, UserID,
+    ClientAction, Coin, CoinCount, DiceCount, DiceSides, ErrorCode, Invitation, Score,
+    ScoreEntry, SessionData, SessionID, TeamID, UserID,
 };
 
-pub struct Game;
+use crate::template::Templates;
+
+pub struct Game {
+    templates: Arc<Templates>,
+    // the server says when the game is over
+    over: Notify,
+    // the session of a rematch the player agreed to
+    rematch: std::sync::Mutex<Option<SessionID>>,
+    // who the host was last handed to, and in which session
+    host: std::sync::Mutex<Option<(SessionID, UserID)>>,
+    // the session a friend last invited the player to, with the join token
+    // if the friend hosts it
+    invitation: std::sync::Mutex<Option<(SessionID, Option<String>)>>,
+}
 
 impl Game {
-    pub fn new() -> Self {
+    pub fn new(templates: Arc<Templates>) -> Self {
         Self {
+            templates: templates,
+            over: Notify::new(),
+            rematch: std::sync::Mutex::new(None),
+            host: std::sync::Mutex::new(None),
+            invitation: std::sync::Mutex::new(None),
+        }
+    }
+
+    // wait until the server says the game is over
+    pub async fn wait_for_game_over(&self) {
+        self.over.notified().await;
+    }
+
+    pub fn take_rematch(&self) -> Option<SessionID> {
+        self.rematch.lock().unwrap().take()
+    }
+
+    pub fn take_invitation(&self) -> Option<(SessionID, Option<String>)> {
+        self.invitation.lock().unwrap().take()
+    }
+
+    // the host of the session, if it was handed over since it was hosted
+    pub fn host_of(&self, sid: SessionID) -> Option<UserID> {
+        match *self.host.lock().unwrap() {
+            Some((s, uid)) if s == sid => Some(uid),
+            _ => None,
         }
     }
 }
@@ -21,27 +63,32 @@ impl Game {
 impl ServerEvent for Game {
     async fn join_info(&self, sid: SessionID, uid: UserID, user_name: &str)
             -> Result<()> {
-        println!("Session [{}]: User [{}]{} has joined this session",
-                 sid.0, uid.0, user_name);
+        println!("{}", self.templates.render("join", &[
+            ("sid", sid.0.to_string()),
+            ("uid", uid.0.to_string()),
+            ("name", user_name.to_owned()),
+        ]));
         Ok(())
     }
     async fn ping(&self, ping: &str) -> Result<String> {
         info!("Received ping message: {}", ping);
         Ok("pong".to_owned())
     }
-    async fn roll_dice(&self, sides: u8, count: u8) -> Result<Vec<u8>> {
+    async fn roll_dice(&self, sides: DiceSides, count: DiceCount)
+            -> Result<Vec<u8>> {
         let mut ret = Vec::new();
-        for x in 0..count {
+        for x in 0..count.value() {
             let input = read_input(
-                &format!("Guess the value of die {} with {} sides:", x, sides))?;
+                &format!("Guess the value of die {} with {} sides:", x,
+                         sides.value()))?;
             let value: u8 = input.parse()?;
             ret.push(value);
         }
         Ok(ret)
     }
-    async fn flip_coin(&self, count: u8) -> Result<Vec<Coin>> {
+    async fn flip_coin(&self, count: CoinCount) -> Result<Vec<Coin>> {
         let mut ret = Vec::new();
-        for x in 0..count {
+        for x in 0..count.value() {
             let input = read_input(
                 &format!("Guess coin flip {}, h or t", x))?;
             if input == "h" {
@@ -52,8 +99,12 @@ impl ServerEvent for Game {
         }
         Ok(ret)
     }
-    async fn winner(&self, uid: UserID, name: &str) -> Result<()> {
-        info!("Winner: [{}] {}", uid.0, name);
+    async fn winner(&self, uid: UserID, name: &str, payout: u64) -> Result<()> {
+        println!("{}", self.templates.render("winner", &[
+            ("uid", uid.0.to_string()),
+            ("name", name.to_owned()),
+            ("payout", payout.to_string()),
+        ]));
         Ok(())
     }
     async fn try_again(&self) -> Result<bool> {
@@ -63,8 +114,249 @@ impl ServerEvent for Game {
         }
         Ok(false)
     }
-    async fn error(&self, err: &str) -> Result<()> {
-        error!("Server error found: {}", err);
+    async fn error(&self, code: ErrorCode, action: ClientAction, message: &str)
+            -> Result<()> {
+        info!("Server error {:?}: {}", code, message);
+        println!("{}", self.templates.render("error", &[
+            ("message", message.to_owned()),
+        ]));
+        let hint = match action {
+            ClientAction::None => { return Ok(()); }
+            ClientAction::RetryJoin =>
+                "List the sessions with l and join again with j",
+            ClientAction::Reconnect =>
+                "Check your connection, then restart and join again to reconnect",
+            ClientAction::UpgradeClient =>
+                "This client may be out of date, upgrade it and try again",
+        };
+        println!("{}", self.templates.render("hint", &[
+            ("hint", hint.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn prompt(&self, text: &str) -> Result<String> {
+        read_input(text)
+    }
+    async fn round_result(&self, round: u8, uid: UserID, name: &str, wins: u8)
+            -> Result<()> {
+        println!("{}", self.templates.render("round", &[
+            ("round", round.to_string()),
+            ("uid", uid.0.to_string()),
+            ("name", name.to_owned()),
+            ("wins", wins.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn team_winner(&self, team: TeamID, members: &[String], payout: u64)
+            -> Result<()> {
+        println!("{}", self.templates.render("team_winner", &[
+            ("team", team.0.to_string()),
+            ("members", members.join(", ")),
+            ("payout", payout.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn team_round_result(&self, round: u8, team: TeamID, wins: u8)
+            -> Result<()> {
+        println!("{}", self.templates.render("team_round", &[
+            ("round", round.to_string()),
+            ("team", team.0.to_string()),
+            ("wins", wins.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn scoreboard(&self, round: u32, entries: &[ScoreEntry]) -> Result<()> {
+        println!("{}", self.templates.render("scoreboard_header", &[
+            ("round", round.to_string()),
+        ]));
+        for e in entries {
+            println!("{}", self.templates.render("scoreboard", &[
+                ("uid", e.user_id().0.to_string()),
+                ("name", e.user_name().to_owned()),
+                ("score", e.score().value().to_string()),
+            ]));
+        }
+        Ok(())
+    }
+    async fn champion(&self, uid: UserID, name: &str, score: Score) -> Result<()> {
+        println!("{}", self.templates.render("champion", &[
+            ("uid", uid.0.to_string()),
+            ("name", name.to_owned()),
+            ("score", score.value().to_string()),
+        ]));
+        Ok(())
+    }
+    async fn user_left(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        println!("{}", self.templates.render("leave", &[
+            ("sid", sid.0.to_string()),
+            ("uid", uid.0.to_string()),
+            ("name", user_name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn chat(&self, _sid: SessionID, uid: UserID, user_name: &str, text: &str)
+            -> Result<()> {
+        println!("{}", self.templates.render("chat", &[
+            ("uid", uid.0.to_string()),
+            ("name", user_name.to_owned()),
+            ("text", text.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn user_kicked(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        println!("{}", self.templates.render("kick", &[
+            ("sid", sid.0.to_string()),
+            ("uid", uid.0.to_string()),
+            ("name", user_name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn direct_message(&self, from: UserID, from_name: &str, _to: UserID,
+                            text: &str) -> Result<()> {
+        println!("{}", self.templates.render("dm", &[
+            ("uid", from.0.to_string()),
+            ("name", from_name.to_owned()),
+            ("text", text.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn session_cancelled(&self, sid: SessionID) -> Result<()> {
+        println!("{}", self.templates.render("cancel", &[
+            ("sid", sid.0.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn session_updated(&self, sd: &SessionData) -> Result<()> {
+        println!("{}", self.templates.render("update", &[
+            ("sid", sd.session_id().0.to_string()),
+            ("name", sd.name().to_owned()),
+            ("type", format!("{:?}", sd.session_type())),
+            ("players", sd.max_players().to_string()),
+            ("wager", sd.wager().to_string()),
+        ]));
+        Ok(())
+    }
+    async fn join_request(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        println!("{}", self.templates.render("request", &[
+            ("sid", sid.0.to_string()),
+            ("uid", uid.0.to_string()),
+            ("name", user_name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn waiting_for_player(&self, uid: UserID, name: &str) -> Result<()> {
+        println!("{}", self.templates.render("waiting", &[
+            ("uid", uid.0.to_string()),
+            ("name", name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn rematch(&self, sd: &SessionData) -> Result<bool> {
+        println!("{}", self.templates.render("rematch", &[
+            ("sid", sd.session_id().0.to_string()),
+        ]));
+        let again = read_input("Play again? [y/n]")?;
+        if again == "y" {
+            *self.rematch.lock().unwrap() = Some(sd.session_id());
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    async fn ready_check(&self) -> Result<bool> {
+        let ready = read_input("The game is about to start, ready? [y/n]")?;
+        Ok(ready != "n")
+    }
+    async fn player_ready(&self, uid: UserID, name: &str, ready: bool) -> Result<()> {
+        let template = if ready { "ready" } else { "not_ready" };
+        println!("{}", self.templates.render(template, &[
+            ("uid", uid.0.to_string()),
+            ("name", name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn game_over(&self, _sid: SessionID) -> Result<()> {
+        self.over.notify_one();
+        Ok(())
+    }
+    async fn countdown(&self, secs: u32) -> Result<()> {
+        println!("{}", self.templates.render("countdown", &[
+            ("secs", secs.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn game_paused(&self, sid: SessionID) -> Result<()> {
+        println!("{}", self.templates.render("paused", &[
+            ("sid", sid.0.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn game_resumed(&self, sid: SessionID) -> Result<()> {
+        println!("{}", self.templates.render("resumed", &[
+            ("sid", sid.0.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn game_started(&self, sd: &SessionData) -> Result<()> {
+        println!("{}", self.templates.render("started", &[
+            ("sid", sd.session_id().0.to_string()),
+            ("type", format!("{:?}", sd.session_type())),
+            ("players", sd.users().len().to_string()),
+        ]));
+        Ok(())
+    }
+    async fn game_ended(&self, _sid: SessionID, scores: &[ScoreEntry]) -> Result<()> {
+        println!("{}", self.templates.render("ended", &[]));
+        for e in scores {
+            println!("{}", self.templates.render("scoreboard", &[
+                ("uid", e.user_id().0.to_string()),
+                ("name", e.user_name().to_owned()),
+                ("score", e.score().value().to_string()),
+            ]));
+        }
+        Ok(())
+    }
+    async fn start_reminder(&self, secs: u64) -> Result<()> {
+        println!("{}", self.templates.render("reminder", &[
+            ("mins", (secs / 60).to_string()),
+            ("secs", secs.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn host_changed(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        *self.host.lock().unwrap() = Some((sid, uid));
+        println!("{}", self.templates.render("host", &[
+            ("sid", sid.0.to_string()),
+            ("uid", uid.0.to_string()),
+            ("name", user_name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn session_expired(&self, sid: SessionID) -> Result<()> {
+        println!("{}", self.templates.render("expired", &[
+            ("sid", sid.0.to_string()),
+        ]));
+        Ok(())
+    }
+    async fn presence_changed(&self, uid: UserID, name: &str, online: bool) -> Result<()> {
+        let template = if online { "online" } else { "offline" };
+        println!("{}", self.templates.render(template, &[
+            ("uid", uid.0.to_string()),
+            ("name", name.to_owned()),
+        ]));
+        Ok(())
+    }
+    async fn invitation(&self, inv: &Invitation) -> Result<()> {
+        *self.invitation.lock().unwrap() =
+            Some((inv.session_id(), inv.join_token().map(|t| t.to_owned())));
+        println!("{}", self.templates.render("invite", &[
+            ("sid", inv.session_id().0.to_string()),
+            ("uid", inv.from_id().0.to_string()),
+            ("name", inv.from_name().to_owned()),
+        ]));
         Ok(())
     }
 }
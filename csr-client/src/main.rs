@@ -2,20 +2,27 @@
 
 use std::path::Path;
 use std::ffi::OsStr;
+use std::path::PathBuf;
 use std::io::{Read, Write};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use clap::Parser;
+use tokio::task::JoinHandle;
 
-use csr_protocol::client::CleanClient;
+use csr_protocol::client::{CleanClient, RetryPolicy};
+use csr_protocol::error::Error;
 use csr_protocol::types::Result;
 use csr_protocol::types::{
-    SessionID, SessionType, UserID,
+    AuthToken, Compression, HostInfo, JoinInfo, ListFilter, Profile, SessionChanges, SessionID,
+    SessionStatus, SessionType, TeamID, UserID, Visibility,
 };
 
 mod game;
+mod template;
 
 use game::{Game, read_input};
+use template::Templates;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -23,11 +30,53 @@ struct Cli {
     #[arg(short, long)]
     address: String,
     #[arg(short, long)]
-    uid: u64,
-    #[arg(short, long)]
     name: String,
+    /// Host ephemeral sessions, left out of leaderboards and stats
+    #[arg(long)]
+    ephemeral: bool,
+    /// Team to join in team mode sessions
+    #[arg(long)]
+    team: Option<u32>,
+    /// File of templates used to format announcements
+    #[arg(short, long)]
+    templates: Option<PathBuf>,
+    /// File the user registered with each server is kept in, use another to
+    /// play as someone else
+    #[arg(long, default_value = USER_FILE)]
+    user_file: PathBuf,
+    /// Play as a guest, who can't host and is forgotten when the client exits
+    #[arg(long)]
+    guest: bool,
+    /// Sign in with a token from an identity provider the server trusts,
+    /// instead of registering
+    #[arg(long, env = "CSR_BEARER_TOKEN", conflicts_with = "guest")]
+    bearer_token: Option<String>,
+    /// Compress messages to and from the server with gzip or zstd
+    #[arg(long)]
+    compression: Option<Compression>,
+    /// Seconds to wait for the server to answer a request, 0 to wait forever
+    #[arg(long, default_value_t = 30)]
+    deadline_secs: u64,
+    /// Times to make a call that only looks something up while the server
+    /// can't be reached, 1 to give up straight away
+    #[arg(long, default_value_t = 3)]
+    attempts: u32,
+    /// CA that signed the server's certificate, to connect over mutual TLS
+    #[cfg(feature = "tls")]
+    #[arg(long, requires_all = ["cert", "key"])]
+    ca: Option<PathBuf>,
+    /// Certificate to identify ourselves to the server with
+    #[cfg(feature = "tls")]
+    #[arg(long, requires_all = ["ca", "key"])]
+    cert: Option<PathBuf>,
+    /// Private key of the certificate
+    #[cfg(feature = "tls")]
+    #[arg(long, requires_all = ["ca", "cert"])]
+    key: Option<PathBuf>,
 }
 
+const USER_FILE: &str = ".csr-users";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // initialize logger
@@ -48,13 +97,52 @@ async fn main() -> Result<()> {
         .init();
 
     let cli = Cli::parse();
-    let uid = UserID(cli.uid);
     let username = cli.name.clone();
+    let templates = match &cli.templates {
+        Some(path) => Arc::new(Templates::load(path)?),
+        None => Arc::new(Templates::new()),
+    };
 
     // connect to the server
-    let mut client = CleanClient::new(&cli.address).await?;
+    let mut client = connect(&cli).await?;
+    if let Some(compression) = cli.compression {
+        client = client.with_compression(compression);
+    }
+    if cli.deadline_secs > 0 {
+        client.set_deadline(Some(Duration::from_secs(cli.deadline_secs)));
+    }
+    client.set_retry_policy(RetryPolicy::new(cli.attempts));
+    let cached = if cli.guest || cli.bearer_token.is_some() {
+        None
+    } else {
+        cached_user(&cli.user_file, &cli.address)?
+    };
+    let uid = match (&cli.bearer_token, cached) {
+        (Some(token), _) => {
+            let uid = client.sign_in_with_bearer(token).await?;
+            println!("Signed in as user {}", uid.0);
+            uid
+        }
+        (None, Some((uid, token))) => {
+            client.set_auth_token(token);
+            uid
+        }
+        (None, None) if cli.guest => {
+            let (uid, _) = client.register_guest(&username).await?;
+            println!("Playing as guest {}", uid.0);
+            uid
+        }
+        (None, None) => {
+            let (uid, token) = client.register_user(&username).await?;
+            cache_user(&cli.user_file, &cli.address, uid, &token)?;
+            println!("Registered as user {}", uid.0);
+            uid
+        }
+    };
+    let listener = Arc::new(Game::new(templates.clone()));
     let mut handle = None;
     let mut join_id = None;
+    let mut host_id = None;
 
     println!("Connected to server at {}", cli.address);
     println!("Type ? for help");
@@ -64,41 +152,205 @@ async fn main() -> Result<()> {
 
         if input == "h" {
             // host a new session
-            let st = read_input("Session type [c or d]:")?;
-            let pc = read_input("Player count [1-255]:")?;
+            let st = read_input("Session type [c, d or custom game name]:")?;
+            let pc = read_input("Maximum players [1-255]:")?;
+            let bo = read_input("Best of [1-255, default 1]:")?;
+            let tm = read_input("Team mode [y/n, default n]:")?;
+            let wg = read_input("Wager in chips [default 0]:")?;
+            let ms = read_input("Start when full [y/n, default y]:")?;
+            let mp = read_input("Minimum players to start early [default 1]:")?;
+            let name = read_input("Session name [optional]:")?;
+            let vs = read_input("Visibility [public, unlisted or private, default public]:")?;
+            let pw = read_input("Password [optional]:")?;
+            let ap = read_input("Approve players before they join [y/n, default n]:")?;
+            let sa = read_input("Start in minutes [optional]:")?;
             let session_type;
-            if st == "c" {
-                session_type = SessionType::Coin;
-            } else if st == "d" {
-                session_type = SessionType::Dice;
-            } else {
-                println!("Invalid session type {}", st);
-                println!("Enter c for Coin game");
-                println!("or d for Dice game");
-                continue;
+            match parse_session_type(&st) {
+                Some(t) => session_type = t,
+                None => {
+                    print_session_types();
+                    continue;
+                }
             }
-            let player_count: u8;
+            let max_players: u8;
             if let Ok(pcu8) = pc.parse() {
                 if pcu8 > 0 {
-                    player_count = pcu8;
+                    max_players = pcu8;
                 } else {
-                    println!("Player count cannot be zero");
+                    println!("Maximum players cannot be zero");
                     continue;
                 }
             } else {
-                println!("Invalid player count {}", pc);
+                println!("Invalid maximum players {}", pc);
+                println!("Valid values are between 1 and 255");
+                continue;
+            }
+            let series_length: u8;
+            if bo.is_empty() {
+                series_length = 1;
+            } else if let Ok(bou8) = bo.parse() {
+                series_length = bou8;
+            } else {
+                println!("Invalid series length {}", bo);
                 println!("Valid values are between 1 and 255");
                 continue;
             }
-            let sd = client.host_session(session_type, player_count).await?;
+            let wager: u64;
+            if wg.is_empty() {
+                wager = 0;
+            } else if let Ok(wgu64) = wg.parse() {
+                wager = wgu64;
+            } else {
+                println!("Invalid wager {}", wg);
+                continue;
+            }
+            let min_players: u8;
+            if mp.is_empty() {
+                min_players = 1;
+            } else if let Ok(mpu8) = mp.parse() {
+                min_players = mpu8;
+            } else {
+                println!("Invalid minimum players {}", mp);
+                continue;
+            }
+            if min_players > max_players {
+                println!("Minimum players cannot be more than {}", max_players);
+                continue;
+            }
+            let visibility;
+            if vs.is_empty() {
+                visibility = Visibility::Public;
+            } else if let Some(v) = parse_visibility(&vs) {
+                visibility = v;
+            } else {
+                println!("Invalid visibility {}", vs);
+                continue;
+            }
+            if visibility == Visibility::Private && pw.is_empty() {
+                println!("Private sessions need a password");
+                continue;
+            }
+            let mut start_at = None;
+            if !sa.is_empty() {
+                match sa.parse::<u64>() {
+                    Ok(mins) if mins > 0 => {
+                        start_at = Some(SystemTime::now() + Duration::from_secs(mins * 60));
+                    }
+                    _ => {
+                        println!("Invalid start time {}", sa);
+                        continue;
+                    }
+                }
+            }
+            let mut house_target = None;
+            if max_players == 1 {
+                // a single player plays against the house
+                let ht = read_input("Points to beat the house each round:")?;
+                match ht.parse() {
+                    Ok(t) => house_target = Some(t),
+                    Err(_) => {
+                        println!("Invalid house target {}", ht);
+                        continue;
+                    }
+                }
+            }
+            let mut hi = HostInfo::new(session_type, max_players)
+                .with_ephemeral(cli.ephemeral)
+                .with_series_length(series_length)
+                .with_team_mode(tm == "y")
+                .with_wager(wager)
+                .with_host(uid)
+                .with_min_players(min_players)
+                .with_auto_start(ms != "n")
+                .with_name(&name)
+                .with_visibility(visibility)
+                .with_password(Some(pw))
+                .with_approve_joins(ap == "y");
+            if let Some(t) = house_target {
+                hi = hi.with_house_target(t);
+            }
+            if let Some(at) = start_at {
+                hi = hi.with_start_at(at);
+            }
+            let sd = match client.host_session(hi).await {
+                Ok(sd) => sd,
+                Err(e) => {
+                    println!("Unable to host: {}", e);
+                    continue;
+                }
+            };
+            host_id = Some(sd.session_id());
             println!("Hosting session: {}", sd.session_id().0);
+            println!("Invite code: {}", sd.invite_code());
             println!("Use j command to join this session");
+        } else if input == "p" {
+            // host with settings the server keeps ready
+            let presets = client.list_presets().await?;
+            if presets.is_empty() {
+                println!("The server has no presets");
+                continue;
+            }
+            for p in &presets {
+                let hi = p.settings();
+                println!("{}: {:?}, {}-{} players, best of {}", p.name(), hi.session_type(),
+                         hi.min_players(), hi.max_players(), hi.series_length());
+            }
+            let name = read_input("Preset:")?;
+            match client.host_from_preset(&name, uid).await {
+                Ok(sd) => {
+                    host_id = Some(sd.session_id());
+                    println!("Hosting session: {}", sd.session_id().0);
+                    println!("Invite code: {}", sd.invite_code());
+                    println!("Use j command to join this session");
+                }
+                Err(e) => { println!("Unable to host preset {}: {}", name, e); }
+            }
         } else if input == "l" {
-            let sessions = client.list_sessions().await?;
-            for sd in sessions {
+            let filter = read_input("Filter [j joinable, m mine, a session type, or empty]:")?;
+            let sessions = match client.list_sessions(parse_filter(&filter, uid)).await {
+                Ok(sessions) => sessions,
+                Err(e) => {
+                    println!("Unable to list sessions: {}", e);
+                    continue;
+                }
+            };
+            if sessions.custom_types().len() > 0 {
+                println!("Custom games: {}", sessions.custom_types().join(", "));
+            }
+            for sd in sessions.sessions() {
                 println!("---");
-                println!("Session {} Type {:?}", sd.session_id().0, sd.session_type());
-                println!("User count: {}", sd.users().len());
+                if !sd.name().is_empty() {
+                    println!("{}", sd.name());
+                }
+                if sd.locked() {
+                    println!("[locked] join with the session password");
+                }
+                if sd.approve_joins() {
+                    println!("[approval] the host lets each player in");
+                }
+                if sd.ephemeral() {
+                    println!("Session {} Type {:?} (ephemeral)", sd.session_id().0,
+                             sd.session_type());
+                } else {
+                    println!("Session {} Type {:?}", sd.session_id().0, sd.session_type());
+                }
+                println!("User count: {}/{}", sd.users().len(), sd.max_players());
+                if sd.min_players() > 1 {
+                    println!("Needs {} players to start", sd.min_players());
+                }
+                if sd.team_mode() {
+                    println!("Team mode, join with --team");
+                }
+                if sd.wager() > 0 {
+                    println!("Wager: {} chips", sd.wager());
+                }
+                if let (1, Some(t)) = (sd.max_players(), sd.house_target()) {
+                    println!("Solo against the house, {} points a round", t);
+                }
+                if let Some(at) = sd.start_at() {
+                    let left = at.duration_since(SystemTime::now()).unwrap_or_default();
+                    println!("Starts in {} minutes", left.as_secs().div_ceil(60));
+                }
                 for u in sd.users() {
                     print!("{},", u);
                 }
@@ -106,22 +358,108 @@ async fn main() -> Result<()> {
                     println!("");
                 }
             }
-        } else if input == "j" {
-            let si = read_input("Session ID:")?;
-            let sid: u64;
+            // who is around to play
+            match client.online_users().await {
+                Ok(users) if !users.is_empty() => {
+                    println!("---");
+                    let names: Vec<String> = users.iter()
+                        .map(|u| format!("[{}] {}", u.user_id().0, u.name())).collect();
+                    println!("Online: {}", names.join(", "));
+                }
+                Ok(_) => {}
+                Err(e) => { println!("Unable to list online users: {}", e); }
+            }
+        } else if input == "j" || input == "y" {
+            let si = if input == "y" {
+                match listener.take_invitation() {
+                    Some((sid, Some(token))) => format!("{} {}", sid.0, token),
+                    Some((sid, None)) => sid.0.to_string(),
+                    None => {
+                        println!("No invitation to accept");
+                        continue;
+                    }
+                }
+            } else {
+                read_input("Session ID or invite code, then a join token if given one:")?
+            };
+            // a join token stands in for the password
+            let (si, join_token) = match si.split_once(' ') {
+                Some((si, token)) => (si.to_owned(), Some(token.trim().to_owned())),
+                None => (si, None),
+            };
+            let session_id;
             if let Ok(siu64) = si.parse() {
-                sid = siu64;
+                session_id = SessionID(siu64);
+                // check the session can be joined before listening to it
+                let details = match client.get_session(session_id).await {
+                    Ok(d) => d,
+                    Err(e) => {
+                        println!("Unable to find session {}: {}", siu64, e);
+                        continue;
+                    }
+                };
+                let sd = details.session();
+                if details.has_player(uid) {
+                    // already seated, such as after reconnecting
+                    println!("Already in session {}, listening again", siu64);
+                } else if details.status() != SessionStatus::Waiting {
+                    println!("Session {} has already started", siu64);
+                    continue;
+                } else if details.players().len() >= sd.max_players() as usize {
+                    println!("Session {} is full", siu64);
+                    continue;
+                } else {
+                    let password = if sd.locked() && join_token.is_none() {
+                        read_input("Password:")?
+                    } else {
+                        String::new()
+                    };
+                    // join the session, under another name if ours is taken or can't be
+                    // used
+                    let mut name = username.clone();
+                    let joined = loop {
+                        let mut ji = JoinInfo::new(session_id, uid, &name)
+                            .with_team(cli.team.map(TeamID))
+                            .with_password(&password);
+                        if let Some(t) = &join_token {
+                            ji = ji.with_join_token(t);
+                        }
+                        match client.join_session(ji).await {
+                            Err(e) if name_refused(&*e) => { name = rename(&name, &*e)?; }
+                            r => break r,
+                        }
+                    };
+                    if let Err(e) = joined {
+                        println!("Unable to join: {}", e);
+                        continue;
+                    }
+                }
+            } else if !si.is_empty() {
+                // invite codes are letters, so never look like an ID
+                let mut name = username.clone();
+                let joined = loop {
+                    match client.join_by_code(&si, uid, &name, cli.team.map(TeamID)).await {
+                        Err(e) if name_refused(&*e) => { name = rename(&name, &*e)?; }
+                        r => break r,
+                    }
+                };
+                match joined {
+                    Ok(sd) => session_id = sd.session_id(),
+                    Err(e) => {
+                        println!("Unable to join: {}", e);
+                        continue;
+                    }
+                }
             } else {
-                println!("Invalid session ID: {}, please enter a valid u64", si);
+                println!("Enter a session ID or an invite code");
                 continue;
             }
-            let session_id = SessionID(sid);
-            // join the session
-            client.join_session(session_id, uid, &username).await?;
 
-            // start listening to the server events
-            let listener = Arc::new(Game::new());
-            handle = Some(client.server_events_listen(session_id, uid, listener).await?);
+            // start listening to the server events, catching up on any missed
+            // when listening again
+            let resume_from = client.last_sequence(session_id, uid);
+            handle = Some(client.server_events_listen(session_id, uid, listener.clone(),
+                                                      resume_from).await?);
 
             join_id = Some(session_id);
         } else if input == "s" {
@@ -133,11 +471,319 @@ async fn main() -> Result<()> {
                     continue;
                 }
             }
-            // start the game
-            client.start_session(session_id).await?;
+            // start the game, or wait for it to start
+            if let Err(e) = client.start_session(session_id, uid).await {
+                println!("Unable to start: {}", e);
+                continue;
+            }
 
-            // break out to finalize the game
-            break;
+            // wait for the game to end, or for the events to stop if it never
+            // gets that far
+            let mut h = match handle.take() {
+                Some(h) => h,
+                None => { break; }
+            };
+            let over = tokio::select! {
+                r = wait_for_events(&mut h) => { r?; false }
+                _ = listener.wait_for_game_over() => true,
+            };
+            // the host may have been handed to someone else since
+            let hosting = match listener.host_of(session_id) {
+                Some(h) => h == uid,
+                None => host_id == Some(session_id),
+            };
+            if over && hosting {
+                // the host can offer everyone another game
+                if read_input("Offer everyone a rematch? [y/n]")? == "y" {
+                    match client.rematch(session_id, uid).await {
+                        Ok(sd) => {
+                            println!("Rematch in session: {}", sd.session_id().0);
+                            println!("Use s command to start the rematch");
+                            handle = Some(client.server_events_listen(sd.session_id(), uid,
+                                                                      listener.clone(),
+                                                                      0).await?);
+                            join_id = Some(sd.session_id());
+                            host_id = Some(sd.session_id());
+                            continue;
+                        }
+                        Err(e) => { println!("Unable to offer a rematch: {}", e); }
+                    }
+                }
+            } else if over {
+                // the session stays open for a while in case of a rematch
+                println!("Waiting in case the host offers a rematch");
+                wait_for_events(&mut h).await?;
+            }
+
+            // follow the other players into the rematch
+            if let Some(sid) = listener.take_rematch() {
+                println!("Joined the rematch in session: {}", sid.0);
+                println!("Use s command when ready to play");
+                handle = Some(client.server_events_listen(sid, uid, listener.clone(), 0).await?);
+                join_id = Some(sid);
+                continue;
+            }
+            println!("Game over");
+            return Ok(());
+        } else if input == "k" {
+            match join_id {
+                Some(session_id) => {
+                    let target = read_input("User ID to remove:")?;
+                    match target.parse() {
+                        Ok(t) => {
+                            if let Err(e) = client.kick_user(session_id, uid,
+                                                             UserID(t)).await {
+                                println!("Unable to remove user: {}", e);
+                            }
+                        }
+                        Err(_) => { println!("Invalid user ID: {}", target); }
+                    }
+                }
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "n" {
+            match join_id {
+                Some(session_id) => {
+                    let target = read_input("User ID to ban or unban:")?;
+                    match target.parse() {
+                        Ok(t) => {
+                            let r = if read_input("Ban or unban? [b/u]")? == "u" {
+                                client.unban_user(session_id, uid, UserID(t)).await
+                            } else {
+                                client.ban_user(session_id, uid, UserID(t)).await
+                            };
+                            if let Err(e) = r {
+                                println!("Unable to change the ban: {}", e);
+                            }
+                        }
+                        Err(_) => { println!("Invalid user ID: {}", target); }
+                    }
+                }
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "a" {
+            match join_id {
+                Some(session_id) => {
+                    let target = read_input("User ID asking to join:")?;
+                    match target.parse() {
+                        Ok(t) => {
+                            let accept = read_input("Let them in? [y/n]")? == "y";
+                            if let Err(e) = client.answer_join(session_id, uid, UserID(t),
+                                                               accept).await {
+                                println!("Unable to answer: {}", e);
+                            }
+                        }
+                        Err(_) => { println!("Invalid user ID: {}", target); }
+                    }
+                }
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "t" {
+            match join_id {
+                Some(session_id) => {
+                    let target = read_input("User ID of the new host:")?;
+                    match target.parse() {
+                        Ok(t) => {
+                            if let Err(e) = client.transfer_host(session_id, uid,
+                                                                 UserID(t)).await {
+                                println!("Unable to hand over the host: {}", e);
+                            }
+                        }
+                        Err(_) => { println!("Invalid user ID: {}", target); }
+                    }
+                }
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "i" {
+            match join_id {
+                Some(session_id) => match client.create_join_token(session_id, uid, None).await {
+                    Ok(jt) => {
+                        let left = jt.expires_at().duration_since(SystemTime::now())
+                            .unwrap_or_default();
+                        println!("Share with one player, who can join with j and: {} {}",
+                                 session_id.0, jt.token());
+                        println!("Expires in {} minutes", left.as_secs().div_ceil(60));
+                    }
+                    Err(e) => { println!("Unable to create a join token: {}", e); }
+                },
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "o" {
+            match join_id {
+                Some(session_id) => {
+                    println!("Leave a setting empty to keep it");
+                    if let Some(changes) = read_changes()? {
+                        if let Err(e) = client.update_session(session_id, uid,
+                                                              changes).await {
+                            println!("Unable to change settings: {}", e);
+                        }
+                    }
+                }
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "d" {
+            match join_id {
+                Some(session_id) => {
+                    if let Err(e) = client.cancel_session(session_id, uid).await {
+                        println!("Unable to cancel session: {}", e);
+                        continue;
+                    }
+                    println!("Cancelled session {}", session_id.0);
+                    join_id = None;
+                    handle = None;
+                }
+                None => { println!("Join a session you are hosting first"); }
+            }
+        } else if input == "c" {
+            match join_id {
+                Some(session_id) => {
+                    let text = read_input("Message:")?;
+                    client.send_chat(session_id, uid, &text).await?;
+                }
+                None => { println!("Join a session to chat"); }
+            }
+        } else if input == "r" {
+            match join_id {
+                Some(session_id) => {
+                    for cm in client.chat_history(session_id).await? {
+                        println!("{}", templates.render("chat", &[
+                            ("uid", cm.user_id().0.to_string()),
+                            ("name", cm.user_name().to_owned()),
+                            ("text", cm.text().to_owned()),
+                        ]));
+                    }
+                }
+                None => { println!("Join a session to read its chat"); }
+            }
+        } else if input == "m" {
+            let target = read_input("User ID to message:")?;
+            match target.parse() {
+                Ok(t) => {
+                    let text = read_input("Message:")?;
+                    if let Err(e) = client.send_dm(uid, UserID(t), &text).await {
+                        println!("Unable to send message: {}", e);
+                    }
+                }
+                Err(_) => { println!("Invalid user ID: {}", target); }
+            }
+        } else if input == "z" || input == "u" {
+            let target = read_input("User ID:")?;
+            match target.parse() {
+                Ok(t) => {
+                    let r = if input == "z" {
+                        client.block_user(uid, UserID(t)).await
+                    } else {
+                        client.unblock_user(uid, UserID(t)).await
+                    };
+                    if let Err(e) = r {
+                        println!("Unable to update blocked users: {}", e);
+                    }
+                }
+                Err(_) => { println!("Invalid user ID: {}", target); }
+            }
+        } else if input == "g" {
+            let target = read_input("Friend [+ID to add, -ID to remove, empty to list]:")?;
+            if target.is_empty() {
+                for f in client.friends(uid).await? {
+                    let waiting = if f.mutual() { "" } else { ", hasn't added you yet" };
+                    println!("[{}] {}{}", f.user_id().0, f.name(), waiting);
+                }
+                continue;
+            }
+            let (add, id) = match target.strip_prefix('-') {
+                Some(id) => (false, id),
+                None => (true, target.trim_start_matches('+')),
+            };
+            match id.parse() {
+                Ok(f) => {
+                    let r = if add {
+                        client.add_friend(uid, UserID(f)).await
+                    } else {
+                        client.remove_friend(uid, UserID(f)).await
+                    };
+                    if let Err(e) = r {
+                        println!("Unable to update friends: {}", e);
+                    }
+                }
+                Err(_) => { println!("Invalid user ID: {}", id); }
+            }
+        } else if input == "w" {
+            match join_id {
+                Some(session_id) => {
+                    let target = read_input("Friend's user ID:")?;
+                    match target.parse() {
+                        Ok(f) => {
+                            match client.send_invite(session_id, uid, UserID(f)).await {
+                                Ok(_) => { println!("Invited user {}", f); }
+                                Err(e) => { println!("Unable to invite: {}", e); }
+                            }
+                        }
+                        Err(_) => { println!("Invalid user ID: {}", target); }
+                    }
+                }
+                None => { println!("Join a session to invite a friend"); }
+            }
+        } else if input == "v" {
+            match join_id {
+                Some(session_id) => {
+                    client.leave_session(session_id, uid).await?;
+                    println!("Left session {}", session_id.0);
+                    join_id = None;
+                    handle = None;
+                }
+                None => { println!("Not in a session"); }
+            }
+        } else if input == "b" {
+            let chips = client.balance(uid).await?;
+            println!("Balance: {} chips", chips);
+        } else if input == "f" {
+            let id = read_input("User ID [default you]:")?;
+            let target = if id.is_empty() {
+                uid
+            } else {
+                match id.parse() {
+                    Ok(id) => UserID(id),
+                    Err(_) => {
+                        println!("Invalid user ID {}", id);
+                        continue;
+                    }
+                }
+            };
+            match client.get_profile(target).await {
+                Ok(p) => print_profile(&p),
+                Err(e) => println!("Unable to get profile: {}", e),
+            }
+        } else if input == "e" {
+            let ud = client.export_user_data(uid).await?;
+            println!("User: {}", ud.user_id().0);
+            if let Some(name) = ud.name() {
+                println!("Name: {}", name);
+            }
+            match ud.chips() {
+                Some(c) => println!("Chips: {}", c),
+                None => println!("Chips: none stored"),
+            }
+            match ud.profile() {
+                Some(p) => print_profile(p),
+                None => println!("Games: none recorded"),
+            }
+            for g in ud.games() {
+                println!("Played {:?} in session {} as {}, scoring {}{}", g.session_type(),
+                         g.session_id().0, g.name(), g.score().value(),
+                         if g.winner() { " and winning" } else { "" });
+            }
+            for sid in ud.lobbies() {
+                println!("Waiting in session {}", sid.0);
+            }
+            for sid in ud.banned_from() {
+                println!("Banned from session {}", sid.0);
+            }
+        } else if input == "x" {
+            let confirm = read_input("Delete all your data on the server? [y/n]")?;
+            if confirm == "y" {
+                client.delete_user_data(uid).await?;
+                println!("Your data has been deleted");
+            }
         } else if input == "q" {
             break;
         } else if input == "?" {
@@ -149,11 +795,9 @@ async fn main() -> Result<()> {
     }
 
     match handle {
-        Some(h) => {
+        Some(mut h) => {
             // wait for the game to end
-            if let (Err(e),) = futures::try_join!(h)? {
-                error!("Game exited with error {:?}", e);
-            }
+            wait_for_events(&mut h).await?;
             println!("Game over");
         }
         None => {
@@ -164,12 +808,223 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+// wait for the server to stop sending events, which it does a while after the
+// game is over
+async fn wait_for_events(h: &mut JoinHandle<Result<()>>) -> Result<()> {
+    if let Err(e) = h.await? {
+        error!("Game exited with error {:?}", e);
+    }
+    Ok(())
+}
+
+// the session already has a player going by the name we joined with
+fn name_refused(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(e.downcast_ref::<Error>(), Some(Error::NameTaken | Error::InvalidName(_)))
+}
+
+fn rename(name: &str, e: &(dyn std::error::Error + Send + Sync + 'static)) -> Result<String> {
+    match e.downcast_ref::<Error>() {
+        Some(Error::InvalidName(ne)) =>
+            read_input(&format!("{:?} can't be used, {}, pick another name:", name, ne)),
+        _ => read_input(&format!("Someone in the session is already called {}, pick another name:",
+                                 name)),
+    }
+}
+
+// over mutual TLS when given a certificate, the address has to be https then
+#[cfg(feature = "tls")]
+async fn connect(cli: &Cli) -> Result<CleanClient> {
+    match (&cli.ca, &cli.cert, &cli.key) {
+        (Some(ca), Some(cert), Some(key)) =>
+            CleanClient::with_tls(&cli.address, &std::fs::read(ca)?, &std::fs::read(cert)?,
+                                  &std::fs::read(key)?).await,
+        _ => CleanClient::new(&cli.address).await,
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect(cli: &Cli) -> Result<CleanClient> {
+    CleanClient::new(&cli.address).await
+}
+
+// the user registered with the server at the address, if there is one. Each
+// line of the file holds a server address, the ID it gave us and the token to
+// authenticate with
+fn cached_user(path: &Path, address: &str) -> Result<Option<(UserID, AuthToken)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    for line in std::fs::read_to_string(path)?.lines() {
+        let mut fields = line.split(' ');
+        if let (Some(a), Some(uid), Some(token)) = (fields.next(), fields.next(), fields.next()) {
+            if a == address {
+                return Ok(Some((UserID(uid.parse()?), AuthToken(token.to_owned()))));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn cache_user(path: &Path, address: &str, uid: UserID, token: &AuthToken) -> Result<()> {
+    let mut f = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(f, "{} {} {}", address, uid.0, token.0)?;
+    Ok(())
+}
+
+fn parse_session_type(st: &str) -> Option<SessionType> {
+    if st == "c" {
+        return Some(SessionType::Coin);
+    } else if st == "d" {
+        return Some(SessionType::Dice);
+    } else if !st.is_empty() {
+        return Some(SessionType::Custom(st.to_owned()));
+    }
+    None
+}
+
+fn parse_visibility(vs: &str) -> Option<Visibility> {
+    match vs {
+        "public" => Some(Visibility::Public),
+        "unlisted" => Some(Visibility::Unlisted),
+        "private" => Some(Visibility::Private),
+        _ => None,
+    }
+}
+
+// any of j for joinable sessions, m for the ones we host and a session type,
+// separated by spaces
+fn parse_filter(filter: &str, uid: UserID) -> ListFilter {
+    let mut ret = ListFilter::new();
+    for word in filter.split_whitespace() {
+        if word == "j" {
+            ret = ret.with_joinable_only(true);
+        } else if word == "m" {
+            ret = ret.with_host(uid);
+        } else if let Some(t) = parse_session_type(word) {
+            ret = ret.with_session_type(t);
+        }
+    }
+    ret
+}
+
+fn print_session_types() {
+    println!("Enter c for Coin game");
+    println!("or d for Dice game");
+    println!("or the name of a custom game hosted by the server");
+}
+
+// ask the host what to change, None if anything entered was invalid
+fn read_changes() -> Result<Option<SessionChanges>> {
+    let mut changes = SessionChanges::new();
+    let st = read_input("Session type [c, d or custom game name]:")?;
+    if !st.is_empty() {
+        match parse_session_type(&st) {
+            Some(t) => changes = changes.with_session_type(t),
+            None => { print_session_types(); return Ok(None); }
+        }
+    }
+    let pc = read_input("Maximum players [1-255]:")?;
+    if !pc.is_empty() {
+        match pc.parse() {
+            Ok(p) => changes = changes.with_max_players(p),
+            Err(_) => { println!("Invalid maximum players {}", pc); return Ok(None); }
+        }
+    }
+    let bo = read_input("Best of [1-255]:")?;
+    if !bo.is_empty() {
+        match bo.parse() {
+            Ok(b) => changes = changes.with_series_length(b),
+            Err(_) => { println!("Invalid series length {}", bo); return Ok(None); }
+        }
+    }
+    let tm = read_input("Team mode [y/n]:")?;
+    if !tm.is_empty() {
+        changes = changes.with_team_mode(tm == "y");
+    }
+    let wg = read_input("Wager in chips:")?;
+    if !wg.is_empty() {
+        match wg.parse() {
+            Ok(w) => changes = changes.with_wager(w),
+            Err(_) => { println!("Invalid wager {}", wg); return Ok(None); }
+        }
+    }
+    let ht = read_input("Points to beat the house each round, for one player:")?;
+    if !ht.is_empty() {
+        match ht.parse() {
+            Ok(t) => changes = changes.with_house_target(t),
+            Err(_) => { println!("Invalid house target {}", ht); return Ok(None); }
+        }
+    }
+    let ms = read_input("Start when full [y/n]:")?;
+    if !ms.is_empty() {
+        changes = changes.with_auto_start(ms != "n");
+    }
+    let mp = read_input("Minimum players to start early:")?;
+    if !mp.is_empty() {
+        match mp.parse() {
+            Ok(m) => changes = changes.with_min_players(m),
+            Err(_) => { println!("Invalid minimum players {}", mp); return Ok(None); }
+        }
+    }
+    let name = read_input("Session name:")?;
+    if !name.is_empty() {
+        changes = changes.with_name(&name);
+    }
+    let vs = read_input("Visibility [public, unlisted or private]:")?;
+    if !vs.is_empty() {
+        match parse_visibility(&vs) {
+            Some(v) => changes = changes.with_visibility(v),
+            None => { println!("Invalid visibility {}", vs); return Ok(None); }
+        }
+    }
+    let pw = read_input("Password [- to remove]:")?;
+    if pw == "-" {
+        changes = changes.with_password("");
+    } else if !pw.is_empty() {
+        changes = changes.with_password(&pw);
+    }
+    let ap = read_input("Approve players before they join [y/n]:")?;
+    if !ap.is_empty() {
+        changes = changes.with_approve_joins(ap == "y");
+    }
+    Ok(Some(changes))
+}
+
+fn print_profile(p: &Profile) {
+    println!("User {} {}: {} games played, {} won, {} lost", p.user_id().0, p.name(),
+             p.games_played(), p.wins(), p.losses());
+    if let Some(st) = p.favorite_type() {
+        println!("Favorite game: {:?}", st);
+    }
+}
+
 fn print_help() {
     println!("Available commands:");
     println!("h\thost a session");
-    println!("l\tlist sessions");
+    println!("p\thost a session from a server preset");
+    println!("l\tlist sessions and who is online");
     println!("j\tjoin a session");
     println!("s\tstart current session");
+    println!("v\tleave current session");
+    println!("k\tremove a player from the session you are hosting");
+    println!("n\tban or unban a player from the session you are hosting");
+    println!("a\tanswer a player asking to join the session you are hosting");
+    println!("t\thand the session you are hosting to another player");
+    println!("i\tinvite one player to the session you are hosting with a join token");
+    println!("o\tchange settings of the session you are hosting");
+    println!("d\tcancel the session you are hosting");
+    println!("c\tchat in current session");
+    println!("r\tread chat history of current session");
+    println!("m\tmessage a user");
+    println!("z\tblock messages from a user");
+    println!("u\tunblock messages from a user");
+    println!("g\tadd, remove or list friends");
+    println!("w\tinvite a friend to current session");
+    println!("y\tjoin the session a friend last invited you to");
+    println!("b\tshow chip balance");
+    println!("f\tshow your or another user's game record");
+    println!("e\texport your data");
+    println!("x\tdelete your data");
     println!("q\tquit");
     println!("?\tprint this menu");
 }
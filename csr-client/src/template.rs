@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anstyle::{AnsiColor, Reset, Style};
+
+use csr_protocol::types::Result;
+
+// Announcement templates
+//
+// Templates are plain text with `{placeholder}` values filled in by the
+// announcement, and style names such as `{red}`, `{bold}` and `{reset}` to
+// color the output. A template file overrides the defaults, one template per
+// line in the form `name = template`, with `#` starting a comment.
+pub struct Templates {
+    templates: HashMap<String, String>,
+}
+
+impl Templates {
+    pub fn new() -> Self {
+        let mut templates = HashMap::new();
+        for (name, template) in [
+            ("cancel", "Session [{sid}] was cancelled by the host"),
+            ("champion", "{bold}Champion: [{uid}] {name} with {score} points{reset}"),
+            ("chat", "{cyan}[{uid}] {name}:{reset} {text}"),
+            ("countdown", "Starting in {secs}..."),
+            ("dm", "{magenta}From [{uid}] {name}:{reset} {text}"),
+            ("ended", "Final scores:"),
+            ("error", "{red}Server error: {message}{reset}"),
+            ("expired", "Session [{sid}] was closed by the server"),
+            ("hint", "{yellow}{hint}{reset}"),
+            ("host", "Session [{sid}]: User [{uid}]{name} is now the host"),
+            ("invite", "{bold}[{uid}] {name} invites you to session [{sid}], accept with y{reset}"),
+            ("join", "Session [{sid}]: User [{uid}]{name} has joined this session"),
+            ("kick", "Session [{sid}]: User [{uid}]{name} was removed by the host"),
+            ("leave", "Session [{sid}]: User [{uid}]{name} has left this session"),
+            ("not_ready", "{yellow}[{uid}] {name} is not ready{reset}"),
+            ("offline", "[{uid}] {name} went offline"),
+            ("online", "{green}[{uid}] {name} is online{reset}"),
+            ("paused", "{yellow}Session [{sid}] is paused by the host{reset}"),
+            ("ready", "[{uid}] {name} is ready"),
+            ("reminder", "{bold}The game starts in {mins} minutes{reset}"),
+            ("rematch", "{bold}The host offers a rematch in session [{sid}]{reset}"),
+            ("request", "{yellow}Session [{sid}]: User [{uid}]{name} asks to join, answer with a{reset}"),
+            ("resumed", "Session [{sid}] has resumed"),
+            ("round", "Round {round} won by [{uid}] {name}, with {wins} wins so far"),
+            ("scoreboard", "  [{uid}] {name}: {score}"),
+            ("scoreboard_header", "Scores after round {round}:"),
+            ("started", "{bold}Game started in session [{sid}]: {type}, {players} players{reset}"),
+            ("team_round", "Round {round} won by team {team}, with {wins} wins so far"),
+            ("team_winner", "{bold}Winning team {team}: {members}, {payout} chips each{reset}"),
+            ("update", "Session [{sid}] {name} changed: {type}, {players} players, {wager} chip wager"),
+            ("waiting", "Waiting for [{uid}] {name} to take their turn"),
+            ("winner", "{bold}Winner: [{uid}] {name}, {payout} chips{reset}"),
+        ] {
+            templates.insert(name.to_owned(), template.to_owned());
+        }
+        Self {
+            templates: templates,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut ret = Self::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((name, template)) => {
+                    ret.templates.insert(name.trim().to_owned(),
+                                         template.trim().to_owned());
+                }
+                None => { warn!("Ignoring invalid template line: {}", line); }
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn render(&self, name: &str, values: &[(&str, String)]) -> String {
+        let template = match self.templates.get(name) {
+            Some(t) => t,
+            None => { return String::new(); }
+        };
+        let mut ret = String::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            ret.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(e) => start + e,
+                None => {
+                    // an unclosed brace is left as it was written
+                    ret.push_str(&rest[start..]);
+                    return ret;
+                }
+            };
+            let key = &rest[start + 1..end];
+            if let Some((_, v)) = values.iter().find(|(k, _)| *k == key) {
+                ret.push_str(v);
+            } else if let Some(s) = style(key) {
+                ret.push_str(&s);
+            } else {
+                // leave unknown placeholders as they were written
+                ret.push_str(&rest[start..=end]);
+            }
+            rest = &rest[end + 1..];
+        }
+        ret.push_str(rest);
+        ret
+    }
+}
+
+fn style(name: &str) -> Option<String> {
+    let s = match name {
+        "black" => AnsiColor::Black.on_default(),
+        "red" => AnsiColor::Red.on_default(),
+        "green" => AnsiColor::Green.on_default(),
+        "yellow" => AnsiColor::Yellow.on_default(),
+        "blue" => AnsiColor::Blue.on_default(),
+        "magenta" => AnsiColor::Magenta.on_default(),
+        "cyan" => AnsiColor::Cyan.on_default(),
+        "white" => AnsiColor::White.on_default(),
+        "bold" => Style::new().bold(),
+        "italic" => Style::new().italic(),
+        "underline" => Style::new().underline(),
+        "reset" => { return Some(format!("{}", Reset.render())); }
+        _ => { return None; }
+    };
+    Some(format!("{}", s.render()))
+}
@@ -1,13 +1,26 @@
 use std::env;
 use std::error::Error;
 
+// every version of the protocol still served, each generated into its own
+// module. The unversioned package isn't generated, server::Legacy answers it
+// as v1
+const VERSIONS: [&str; 1] = ["v1"];
+
 fn main() -> Result<(), Box<dyn Error>> {
     // setup the protobuf compiler from source
     env::set_var("PROTOC", protobuf_src::protoc());
 
-    println!("cargo:rerun-if-changed=protos/csr.proto");
-
-    // build our grpc service
-    tonic_build::compile_protos("protos/csr.proto")?;
+    // build our grpc service, only the halves the crate's features ask for
+    let client = env::var_os("CARGO_FEATURE_CLIENT").is_some();
+    let server = env::var_os("CARGO_FEATURE_SERVER").is_some();
+    for version in VERSIONS {
+        let dir = format!("protos/clean/{}", version);
+        let proto = format!("{}/csr.proto", dir);
+        println!("cargo:rerun-if-changed={}", proto);
+        tonic_build::configure()
+            .build_client(client)
+            .build_server(server)
+            .compile_protos(&[&proto], &[&dir])?;
+    }
     Ok(())
 }
@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+use csr_protocol::compat;
+use csr_protocol::types::Result;
+
+const USAGE: &str = "usage: csr-compat record REVISION [DIR]";
+
+// record the current revision's fixtures, which the compat test checks the
+// types against from then on
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let revision = match (args.first().map(|a| a.as_str()), args.get(1)) {
+        (Some("record"), Some(r)) => r,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let dir = args.get(2).map(PathBuf::from).unwrap_or_else(compat::fixtures_dir);
+    for path in compat::record(&dir, revision)? {
+        println!("Recorded {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use csr_protocol::conformance;
+use csr_protocol::types::Result;
+
+const DEFAULT_ADDRESS: &str = "http://127.0.0.1:5555";
+
+// check the server at the address given, or a local one, follows the protocol
+#[tokio::main]
+async fn main() -> Result<()> {
+    let address = std::env::args().nth(1).unwrap_or_else(|| DEFAULT_ADDRESS.to_owned());
+    let report = conformance::run(&address).await?;
+    for check in report.checks() {
+        match check.failure() {
+            None => println!("PASS {}", check.name()),
+            Some(f) => println!("FAIL {}: {}", check.name(), f),
+        }
+    }
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
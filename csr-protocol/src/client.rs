@@ -1,73 +1,991 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures_util::TryFutureExt;
-use tonic::Request;
-use tonic::transport::{Channel, Uri};
+use prost::Message;
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint, Uri};
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+use tonic::{Code, GrpcMethod, Request, Response, Status, TimeoutExpired};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+#[cfg(any(unix, feature = "quic"))]
+use tower::service_fn;
 
 use crate::clean;
 use crate::error::Error;
 use crate::event::ServerEvent;
+#[cfg(feature = "quic")]
+use crate::quic::QuicStream;
 use crate::types::Result;
 use crate::types::{
-    CoinGuess, DiceGuess, EventRegister, FlipCoin, JoinInfo, HostInfo, Ping, Pong,
-    RollDice, Sessions, SessionData, SessionID, SessionType, StartInfo, UserID, Winner,
+    AccountBan, AccountType, AdminToken, AuthToken, BalanceInfo, BlockInfo, CancelInfo, Champion,
+    ChatMessage, Compression, ClientAction, CloseReason, CoinGuess, DiceGuess, DirectMessage,
+    ErrorCode, EventRegister, FlipCoin, Friend, FriendInfo, GameEnded, Invitation, JoinAnswer,
+    JoinInfo, JoinToken, HostInfo, KickInfo, LeaveInfo, ListFilter, LobbyChange, OnlineUser, Ping,
+    PlayerReady, Pong, Preset, PresenceChange, Profile, ReconnectToken, RematchInfo, RollDice,
+    RoundResult, Scoreboard, ServerError, ServerStats, SessionChanges, SessionDetails, Sessions,
+    SessionData, SessionID, StartInfo, TeamID, TeamRoundResult, TeamWinner, TransferInfo,
+    UserDataExport, UserID, VoteKickInfo, WaitingForPlayer, Winner, ADMIN_TOKEN_KEY, AUTH_TOKEN_KEY,
+    BEARER_KEY, RECONNECT_TOKEN_KEY,
 };
 
+// what the client proves who it is with
+#[derive(Clone)]
+enum Credential {
+    // issued by the server when registering
+    Token(AuthToken),
+    // issued by an identity provider the server trusts
+    Bearer(String),
+}
+
+// attaches the credential to every request, once there is one
+#[derive(Clone, Default)]
+struct Credentials(Arc<std::sync::RwLock<Option<Credential>>>);
+
+impl Interceptor for Credentials {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let (key, value) = match &*self.0.read().unwrap() {
+            Some(Credential::Token(t)) => (AUTH_TOKEN_KEY, t.0.clone()),
+            Some(Credential::Bearer(t)) => (BEARER_KEY, format!("Bearer {}", t)),
+            None => { return Ok(request); }
+        };
+        let value = MetadataValue::try_from(value)
+            .map_err(|_| Status::internal("Invalid auth token"))?;
+        request.metadata_mut().insert(key, value);
+        Ok(request)
+    }
+}
+
+// calls that wait on other players, for the host to let the caller in, for
+// them to answer a rematch or for the game to finish, and streams open for as
+// long as the caller listens, so can take as long as they need
+const UNBOUNDED: &[&str] = &[
+    "JoinSession", "JoinByCode", "PlaySession", "StartSession", "Rematch", "WatchSessions",
+];
+
+// bounds how long every other call can take, once there is a deadline
+#[derive(Clone, Default)]
+struct Deadline(Arc<std::sync::RwLock<Option<Duration>>>);
+
+impl Interceptor for Deadline {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let unbounded = request.extensions().get::<GrpcMethod>()
+            .is_some_and(|m| UNBOUNDED.contains(&m.method()));
+        if let (Some(deadline), false) = (*self.0.read().unwrap(), unbounded) {
+            request.set_timeout(deadline);
+        }
+        Ok(request)
+    }
+}
+
+type Bounded = InterceptedService<Channel, Deadline>;
+
+// how many times a call that is safe to repeat is made while the server can't
+// be reached, waiting twice as long after each attempt, up to the most it
+// waits. Calls that change anything are only ever made once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    // waits 100ms after the first attempt, and never more than 5s
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+
+    // every call is made once
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration, max_backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self.max_backoff = max_backoff.max(backoff);
+        self
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+// the server couldn't be reached, rather than refusing the call, which it
+// always explains with details
+fn unreachable(s: &Status) -> bool {
+    s.code() == Code::Unavailable && s.details().is_empty()
+}
+
+// a refused call is turned back into the error the server reported, from the
+// code in the status details. A server refusing to authenticate the request,
+// turning a name away, turning away a banned user or asking the caller to slow
+// down, is told apart from other refusals, so the caller can register again,
+// pick another name, give up or wait. So is the call running out of time
+fn from_status(s: Status) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+    match s.code() {
+        Code::DeadlineExceeded => { return Box::new(Error::DeadlineExceeded); }
+        // the client gave up waiting before the server did
+        Code::Cancelled if s.message() == TimeoutExpired(()).to_string() => {
+            return Box::new(Error::DeadlineExceeded);
+        }
+        _ => {}
+    }
+    let details = match clean::ErrorDetails::decode(s.details()) {
+        Ok(d) if !s.details().is_empty() => d,
+        _ => { return Box::new(s); }
+    };
+    let code = ErrorCode::from(details.code);
+    Box::new(match (code, details.name) {
+        (ErrorCode::Unauthenticated, _) => Error::Unauthenticated,
+        (ErrorCode::NameTaken, _) => Error::NameTaken,
+        (ErrorCode::InvalidName, Some(r)) => Error::InvalidName(r.into()),
+        (ErrorCode::Banned, _) => Error::Banned,
+        (ErrorCode::RateLimited, _) => Error::RateLimited,
+        (ErrorCode::GuestCannotHost, _) => Error::GuestCannotHost,
+        _ => Error::Refused(code, details.action.into(), s.message().to_owned()),
+    })
+}
+
+// how a client connects, for applications that need more than an address.
+// Nothing is set until asked for, the same as CleanClient::new
+#[derive(Clone)]
+pub struct CleanClientBuilder {
+    address: String,
+    connect_timeout: Option<Duration>,
+    keepalive: Option<Duration>,
+    user_agent: Option<String>,
+    lazy: bool,
+    retry: RetryPolicy,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTlsConfig>,
+}
+
+impl CleanClientBuilder {
+    // a unix:// address connects to a server listening on a local socket
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_owned(),
+            connect_timeout: None,
+            keepalive: None,
+            user_agent: None,
+            lazy: false,
+            retry: RetryPolicy::none(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    // give up connecting after the timeout, rather than whenever the OS does
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    // ping the server this often, even while no call is running, so a
+    // connection that has silently dropped is noticed and replaced
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    // sent with every request ahead of tonic's own user agent
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_owned());
+        self
+    }
+
+    // don't connect until the first call, so a client can be made before the
+    // server is up. Connecting fails on that call instead
+    pub fn with_lazy_connect(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // connect to an https address over TLS, trusting the server if its PEM
+    // encoded certificate is signed by the CA
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, ca: &[u8]) -> Self {
+        let tls = self.tls.take().unwrap_or_default();
+        self.tls = Some(tls.ca_certificate(Certificate::from_pem(ca)));
+        self
+    }
+
+    // prove who we are with our own PEM encoded certificate and its private
+    // key, for servers that ask for one
+    #[cfg(feature = "tls")]
+    pub fn with_identity(mut self, cert: &[u8], key: &[u8]) -> Self {
+        let tls = self.tls.take().unwrap_or_default();
+        self.tls = Some(tls.identity(Identity::from_pem(cert, key)));
+        self
+    }
+
+    pub async fn connect(self) -> Result<CleanClient> {
+        #[cfg(unix)]
+        if let Some(path) = self.address.strip_prefix("unix://") {
+            let path = path.to_owned();
+            // every connection goes to the socket, whatever the address says
+            let endpoint = self.configure(Endpoint::from_static("http://localhost"))?;
+            let connector = service_fn(move |_: Uri| {
+                let path = path.clone();
+                async move {
+                    let stream = tokio::net::UnixStream::connect(path).await?;
+                    Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                }
+            });
+            let channel = if self.lazy {
+                endpoint.connect_with_connector_lazy(connector)
+            } else {
+                endpoint.connect_with_connector(connector).await?
+            };
+            let mut client = CleanClient::from_channel(channel);
+            client.set_retry_policy(self.retry);
+            return Ok(client);
+        }
+        let endpoint = self.configure(Channel::builder(self.address.parse::<Uri>()?))?;
+        let channel = if self.lazy { endpoint.connect_lazy() } else { endpoint.connect().await? };
+        let mut client = CleanClient::from_channel(channel);
+        client.set_retry_policy(self.retry);
+        Ok(client)
+    }
+
+    fn configure(&self, mut endpoint: Endpoint) -> Result<Endpoint> {
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(interval) = self.keepalive {
+            endpoint = endpoint.http2_keep_alive_interval(interval).keep_alive_while_idle(true);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            endpoint = endpoint.user_agent(user_agent.clone())?;
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(tls.clone())?;
+        }
+        Ok(endpoint)
+    }
+}
+
 pub struct CleanClient {
-    client: clean::clean_client::CleanClient<Channel>,
+    client: clean::clean_client::CleanClient<InterceptedService<Bounded, Credentials>>,
+    admin_client: clean::clean_admin_client::CleanAdminClient<Bounded>,
+    credentials: Credentials,
+    deadline: Deadline,
+    retry: RetryPolicy,
+    tokens: HashMap<EventRegister, ReconnectToken>,
+    // the sequence of the last request handled on each event stream
+    sequences: HashMap<EventRegister, Arc<AtomicU64>>,
+    admin: Option<AdminToken>,
 }
 
 impl CleanClient {
+    // a unix:// address connects to a server listening on a local socket.
+    // CleanClientBuilder connects with more options
     pub async fn new(address: &str) -> Result<Self> {
-        let uri = address.parse::<Uri>()?;
-        let client = clean::clean_client::CleanClient::connect(uri).await?;
-        Ok(Self {
-            client: client,
-        })
+        CleanClientBuilder::new(address).connect().await
+    }
+
+    // connect to an https address over mutual TLS, trusting the server if its
+    // certificate is signed by the CA, and proving who we are with our own
+    // certificate and its private key. All of them are PEM encoded
+    #[cfg(feature = "tls")]
+    pub async fn with_tls(address: &str, ca: &[u8], cert: &[u8], key: &[u8]) -> Result<Self> {
+        CleanClientBuilder::new(address).with_tls(ca).with_identity(cert, key).connect().await
+    }
+
+    // connect to a server's QUIC listener at host:port, trusting it if its
+    // certificate is signed by the PEM encoded CA. Every reconnect opens a new
+    // QUIC connection, and each client has one of its own, so an application
+    // can keep its event stream apart from its other calls with a second
+    // client
+    #[cfg(feature = "quic")]
+    pub async fn with_quic(address: &str, ca: &[u8]) -> Result<Self> {
+        let unknown = || Error::UnknownAddress(address.to_owned());
+        let (host, _) = address.rsplit_once(':').ok_or_else(unknown)?;
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_owned();
+        let remote = tokio::net::lookup_host(address).await?.next().ok_or_else(unknown)?;
+        let mut roots = quinn::rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut &ca[..]) {
+            roots.add(cert?)?;
+        }
+        let config = quinn::ClientConfig::with_root_certificates(Arc::new(roots))?;
+        let local = if remote.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let mut endpoint = quinn::Endpoint::client(local.parse()?)?;
+        endpoint.set_default_client_config(config);
+        // every connection goes to the QUIC endpoint, whatever the address says
+        let channel = Endpoint::from_static("http://localhost")
+            .connect_with_connector(service_fn(move |_: Uri| {
+                let connecting = endpoint.connect(remote, &host);
+                async move {
+                    let connection = connecting
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+                        .await?;
+                    let (send, recv) = connection.open_bi().await?;
+                    let stream = QuicStream::new(send, recv);
+                    Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                }
+            })).await?;
+        Ok(Self::from_channel(channel))
+    }
+
+    // use a channel the application has already set up, such as one with a
+    // custom connector, through a proxy, or balanced over several endpoints
+    pub fn from_channel(channel: Channel) -> Self {
+        let credentials = Credentials::default();
+        let deadline = Deadline::default();
+        let bounded = InterceptedService::new(channel, deadline.clone());
+        Self {
+            admin_client: clean::clean_admin_client::CleanAdminClient::new(bounded.clone()),
+            client: clean::clean_client::CleanClient::with_interceptor(bounded,
+                                                                       credentials.clone()),
+            credentials: credentials,
+            deadline: deadline,
+            retry: RetryPolicy::none(),
+            tokens: HashMap::new(),
+            sequences: HashMap::new(),
+            admin: None,
+        }
+    }
+
+    // compress requests with the encoding, which the server has to accept, and
+    // ask for responses to be compressed the same way
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        let encoding = compression.into();
+        self.client = self.client.send_compressed(encoding).accept_compressed(encoding);
+        self.admin_client = self.admin_client.send_compressed(encoding)
+            .accept_compressed(encoding);
+        self
+    }
+
+    // give up on every request after this that takes longer than the deadline,
+    // with DeadlineExceeded, or wait as long as it takes without one. Joining,
+    // which can wait on the host, and the event stream are never cut short
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        *self.deadline.0.write().unwrap() = deadline;
+    }
+
+    // make the calls that only look things up again under the policy, when
+    // the server can't be reached. They are made once without one
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    // makes a call that is safe to repeat, with a fresh request each time,
+    // until it is answered, refused, or the policy runs out of attempts
+    async fn retried<C, M, T, F, Fut>(&self, client: &C, request: impl Fn() -> Result<Request<M>>,
+                                      call: F) -> Result<T>
+            where C: Clone, F: Fn(C, Request<M>) -> Fut,
+                  Fut: Future<Output = std::result::Result<Response<T>, Status>> {
+        let mut attempt = 1;
+        let mut backoff = self.retry.backoff;
+        loop {
+            match call(client.clone(), request()?).await {
+                Err(s) if unreachable(&s) && attempt < self.retry.max_attempts => {
+                    debug!("Server unreachable, trying again in {:?}: {}", backoff, s.message());
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.retry.max_backoff);
+                    attempt = attempt + 1;
+                }
+                response => { return Ok(response.map_err(from_status)?.into_inner()); }
+            }
+        }
+    }
+
+    // authenticate every request after this with a token the server issued
+    // earlier
+    pub fn set_auth_token(&mut self, token: AuthToken) {
+        *self.credentials.0.write().unwrap() = Some(Credential::Token(token));
+    }
+
+    // authenticate every request after this with a token from an identity
+    // provider the server trusts, returning the user the server knows its
+    // subject as. Signing in for the first time registers the user
+    pub async fn sign_in_with_bearer(&mut self, token: &str) -> Result<UserID> {
+        *self.credentials.0.write().unwrap() = Some(Credential::Bearer(token.to_owned()));
+        let response = self.client.who_am_i(Request::new(clean::Empty{})).await
+            .map_err(from_status)?;
+        Ok(UserID(response.into_inner().user_id))
     }
 
     // client drive API
-    pub async fn host_session(&mut self, typ: SessionType, player_count: u8)
-            -> Result<SessionData> {
-        let hi = HostInfo::new(typ, player_count);
+    pub async fn host_session(&mut self, hi: HostInfo) -> Result<SessionData> {
         let request = Request::new(hi.into());
-        let response = self.client.host_session(request).await?;
+        let response = self.client.host_session(request).await.map_err(from_status)?;
+        Ok(response.into_inner().try_into()?)
+    }
+
+    pub async fn list_presets(&mut self) -> Result<Vec<Preset>> {
+        let response = self.retried(&self.client, || Ok(Request::new(clean::Empty{})),
+                                    |mut c, r| async move { c.list_presets(r).await }).await?;
+        response.presets.into_iter().map(|p| Ok(p.try_into()?)).collect()
+    }
+
+    // host a session with the settings of one of the server's presets
+    pub async fn host_from_preset(&mut self, name: &str, host: UserID) -> Result<SessionData> {
+        let request = Request::new(clean::PresetHostInfo {
+            name: name.to_owned(),
+            host_id: host.0,
+        });
+        let response = self.client.host_from_preset(request).await.map_err(from_status)?;
         Ok(response.into_inner().try_into()?)
     }
 
-    pub async fn list_sessions(&mut self) -> Result<Vec<SessionData>> {
-        let request = Request::new(clean::Empty{});
-        let response = self.client.list_sessions(request).await?;
-        let s: Sessions = response.into_inner().try_into()?;
-        Ok(s.sessions().to_vec())
+    pub async fn list_sessions(&mut self, filter: ListFilter) -> Result<Sessions> {
+        let filter: clean::ListFilter = filter.into();
+        let response = self.retried(&self.client, || Ok(Request::new(filter.clone())),
+                                    |mut c, r| async move { c.list_sessions(r).await }).await?;
+        Ok(response.try_into()?)
     }
 
-    pub async fn join_session(&mut self, sid: SessionID, uid: UserID,
-                              user_name: &str) -> Result<()> {
-        let ji = JoinInfo::new(sid, uid, user_name);
+    // every public session as it is now, then each change to them as it
+    // happens. Nothing more arrives once the server stops the watch, after
+    // which the client watches again to catch up
+    pub async fn watch_sessions(&mut self) -> Result<mpsc::Receiver<LobbyChange>> {
+        let request = Request::new(clean::Empty {});
+        let mut stream = self.client.watch_sessions(request).await.map_err(from_status)?
+            .into_inner();
+        let (tx, rx) = mpsc::channel(100);
+        tokio::spawn(async move {
+            loop {
+                let lc = match stream.message().await {
+                    Ok(Some(lc)) => lc,
+                    Ok(None) => { return; }
+                    Err(status) => {
+                        warn!("Stopped watching sessions: {}", from_status(status));
+                        return;
+                    }
+                };
+                let change = match LobbyChange::try_from(lc) {
+                    Ok(change) => change,
+                    Err(e) => {
+                        warn!("Skipping lobby change: {}", e);
+                        continue;
+                    }
+                };
+                if tx.send(change).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    // waits for the host to answer when the session approves joins
+    pub async fn join_session(&mut self, ji: JoinInfo) -> Result<()> {
         let request = Request::new(ji.into());
-        let _ = self.client.join_session(request).await?;
+        let _ = self.client.join_session(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // join with the invite code from the host, returning the session joined
+    pub async fn join_by_code(&mut self, code: &str, uid: UserID, user_name: &str,
+                              team: Option<TeamID>) -> Result<SessionData> {
+        let request = Request::new(clean::CodeJoinInfo {
+            code: code.to_owned(),
+            user_id: uid.0,
+            user_name: user_name.to_owned(),
+            team: team.map(|t| t.0).unwrap_or(0),
+        });
+        let response = self.client.join_by_code(request).await.map_err(from_status)?;
+        Ok(response.into_inner().try_into()?)
+    }
+
+    // a single use token to share with one player, letting them into the
+    // session we're hosting without the password. Leave the time out for the
+    // longest the server allows
+    pub async fn create_join_token(&mut self, sid: SessionID, host: UserID,
+                                   ttl: Option<Duration>) -> Result<JoinToken> {
+        let mut request = Request::new(clean::JoinTokenRequest {
+            session_id: sid.0,
+            host_id: host.0,
+            ttl_secs: ttl.map_or(0, |t| t.as_secs().try_into().unwrap_or(u32::MAX)),
+        });
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let response = self.client.create_join_token(request).await.map_err(from_status)?;
+        Ok(response.into_inner().into())
+    }
+
+    // the host can start early, anyone else only once the session is full
+    pub async fn start_session(&mut self, sid: SessionID, uid: UserID) -> Result<()> {
+        let si = StartInfo::new(sid, uid);
+        let mut request = Request::new(si.into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.start_session(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // leave a session before it starts
+    pub async fn leave_session(&mut self, sid: SessionID, uid: UserID) -> Result<()> {
+        let mut request = Request::new(LeaveInfo::new(sid, uid).into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.leave_session(request).await.map_err(from_status)?;
+        self.tokens.remove(&EventRegister::new(sid, uid));
+        self.sequences.remove(&EventRegister::new(sid, uid));
+        Ok(())
+    }
+
+    // remove a player from a session we're hosting, before it starts
+    pub async fn kick_user(&mut self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let mut request = Request::new(KickInfo::new(sid, host, target).into());
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let _ = self.client.kick_user(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // stop a player joining a session we're hosting, removing them if they
+    // already have
+    pub async fn ban_user(&mut self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let mut request = Request::new(KickInfo::new(sid, host, target).into());
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let _ = self.client.ban_user(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn unban_user(&mut self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let mut request = Request::new(KickInfo::new(sid, host, target).into());
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let _ = self.client.unban_user(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // close a session we're hosting, before it starts
+    pub async fn cancel_session(&mut self, sid: SessionID, uid: UserID) -> Result<()> {
+        let mut request = Request::new(CancelInfo::new(sid, uid).into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.cancel_session(request).await.map_err(from_status)?;
+        self.tokens.retain(|er, _| er.session_id() != sid);
+        self.sequences.retain(|er, _| er.session_id() != sid);
+        Ok(())
+    }
+
+    // change the settings of a session we're hosting, before it starts
+    pub async fn update_session(&mut self, sid: SessionID, host: UserID,
+                                changes: SessionChanges) -> Result<()> {
+        let mut request = Request::new(clean::UpdateInfo {
+            session_id: sid.0,
+            host_id: host.0,
+            changes: Some(changes.into()),
+        });
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let _ = self.client.update_session(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // let a player into a session we're hosting, or turn them away
+    pub async fn answer_join(&mut self, sid: SessionID, host: UserID, uid: UserID,
+                             accept: bool) -> Result<()> {
+        let mut request = Request::new(JoinAnswer::new(sid, host, uid, accept).into());
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let _ = self.client.answer_join(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // offer the players of a finished game we hosted another game in a new
+    // session, returning the new session
+    pub async fn rematch(&mut self, sid: SessionID, host: UserID) -> Result<SessionData> {
+        let mut request = Request::new(RematchInfo::new(sid, host).into());
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let sd = self.client.rematch(request).await.map_err(from_status)?.into_inner().try_into()?;
+        self.tokens.retain(|er, _| er.session_id() != sid);
+        self.sequences.retain(|er, _| er.session_id() != sid);
+        Ok(sd)
+    }
+
+    // hand a session we're hosting over to another of its players
+    pub async fn transfer_host(&mut self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let mut request = Request::new(TransferInfo::new(sid, host, target).into());
+        self.add_reconnect_token(&mut request, sid, host)?;
+        let _ = self.client.transfer_host(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // hold a game we're hosting once the current round is over
+    pub async fn pause_session(&mut self, sid: SessionID, uid: UserID) -> Result<()> {
+        let mut request = Request::new(StartInfo::new(sid, uid).into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.pause_session(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn resume_session(&mut self, sid: SessionID, uid: UserID) -> Result<()> {
+        let mut request = Request::new(StartInfo::new(sid, uid).into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.resume_session(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // vote to remove another player from the game we're playing
+    pub async fn vote_kick(&mut self, sid: SessionID, uid: UserID, target: UserID)
+            -> Result<()> {
+        let mut request = Request::new(VoteKickInfo::new(sid, uid, target).into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.vote_kick(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn send_chat(&mut self, sid: SessionID, uid: UserID, text: &str)
+            -> Result<()> {
+        let cm = ChatMessage::new(sid, uid, "", text);
+        let mut request = Request::new(cm.into());
+        self.add_reconnect_token(&mut request, sid, uid)?;
+        let _ = self.client.send_chat(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // who is in a session and whether it has started, before joining it
+    pub async fn get_session(&mut self, sid: SessionID) -> Result<SessionDetails> {
+        let request = || Ok(Request::new(clean::SessionRequest {
+            session_id: sid.0,
+        }));
+        let response = self.retried(&self.client, request,
+                                    |mut c, r| async move { c.get_session(r).await }).await?;
+        Ok(response.try_into()?)
+    }
+
+    pub async fn chat_history(&mut self, sid: SessionID) -> Result<Vec<ChatMessage>> {
+        let request = || Ok(Request::new(clean::ChatHistoryRequest {
+            session_id: sid.0,
+        }));
+        let response = self.retried(&self.client, request,
+                                    |mut c, r| async move { c.get_chat_history(r).await }).await?;
+        Ok(response.messages.into_iter().map(|m| m.into()).collect())
+    }
+
+    pub async fn send_dm(&mut self, from: UserID, to: UserID, text: &str)
+            -> Result<()> {
+        let mut request = Request::new(DirectMessage::new(from, "", to, text).into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.client.send_direct_message(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn block_user(&mut self, uid: UserID, target: UserID) -> Result<()> {
+        let mut request = Request::new(BlockInfo::new(uid, target).into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.client.block_user(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn unblock_user(&mut self, uid: UserID, target: UserID) -> Result<()> {
+        let mut request = Request::new(BlockInfo::new(uid, target).into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.client.unblock_user(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn add_friend(&mut self, uid: UserID, friend: UserID) -> Result<()> {
+        let mut request = Request::new(FriendInfo::new(uid, friend).into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.client.add_friend(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn remove_friend(&mut self, uid: UserID, friend: UserID) -> Result<()> {
+        let mut request = Request::new(FriendInfo::new(uid, friend).into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.client.remove_friend(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // everyone the user has added, and whether they have added the user back
+    pub async fn friends(&mut self, uid: UserID) -> Result<Vec<Friend>> {
+        let request = || {
+            let mut request = Request::new(clean::FriendsRequest {
+                user_id: uid.0,
+            });
+            self.add_admin_token(&mut request)?;
+            Ok(request)
+        };
+        let response = self.retried(&self.client, request,
+                                    |mut c, r| async move { c.list_friends(r).await }).await?;
+        Ok(response.friends.into_iter().map(|f| f.into()).collect())
+    }
+
+    // ask a friend to join a session the user is in
+    pub async fn send_invite(&mut self, sid: SessionID, from: UserID, friend: UserID)
+            -> Result<()> {
+        let mut request = Request::new(Invitation::new(from, "", friend, sid).into());
+        self.add_reconnect_token(&mut request, sid, from)?;
+        let _ = self.client.send_invite(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    // registered users with an event stream open, in any session
+    pub async fn online_users(&mut self) -> Result<Vec<OnlineUser>> {
+        let request = || Ok(Request::new(clean::Empty{}));
+        let response = self.retried(&self.client, request,
+                                    |mut c, r| async move { c.list_online_users(r).await }).await?;
+        Ok(response.users.into_iter().map(|u| u.into()).collect())
+    }
+
+    // requests made as a user listening for events in a session need the
+    // token issued to them
+    fn add_reconnect_token<T>(&self, request: &mut Request<T>, sid: SessionID,
+                              uid: UserID) -> Result<()> {
+        if let Some(t) = self.tokens.get(&EventRegister::new(sid, uid)) {
+            request.metadata_mut().insert(RECONNECT_TOKEN_KEY,
+                                          MetadataValue::try_from(t.0.as_str())?);
+        }
+        Ok(())
+    }
+
+    // the token issued when listening to server events, needed to listen
+    // again for the same session and user
+    pub fn reconnect_token(&self, sid: SessionID, uid: UserID)
+            -> Option<&ReconnectToken> {
+        self.tokens.get(&EventRegister::new(sid, uid))
+    }
+
+    // the last request handled while listening to server events, to resume
+    // from when listening again for the same session and user
+    pub fn last_sequence(&self, sid: SessionID, uid: UserID) -> u64 {
+        self.sequences.get(&EventRegister::new(sid, uid))
+            .map_or(0, |s| s.load(Ordering::SeqCst))
+    }
+
+    // use a token issued to an earlier client, such as before a restart
+    pub fn set_reconnect_token(&mut self, sid: SessionID, uid: UserID,
+                               token: ReconnectToken) {
+        self.tokens.insert(EventRegister::new(sid, uid), token);
+    }
+
+    // act as an admin when managing user data, and to reach the admin service
+    pub fn set_admin_token(&mut self, token: AdminToken) {
+        self.admin = Some(token);
+    }
+
+    pub async fn export_user_data(&mut self, uid: UserID) -> Result<UserDataExport> {
+        let response = self.retried(&self.client, || self.user_data_request(uid),
+                                    |mut c, r| async move { c.export_user_data(r).await }).await?;
+        Ok(response.into())
+    }
+
+    pub async fn delete_user_data(&mut self, uid: UserID) -> Result<()> {
+        let request = self.user_data_request(uid)?;
+        let _ = self.client.delete_user_data(request).await.map_err(from_status)?;
         Ok(())
     }
 
-    pub async fn start_session(&mut self, sid: SessionID) -> Result<()> {
-        let si = StartInfo::new(sid);
-        let request = Request::new(si.into());
-        let _ = self.client.start_session(request).await?;
+    // how busy the server is, which needs the admin token
+    pub async fn server_stats(&mut self) -> Result<ServerStats> {
+        let request = || {
+            let mut request = Request::new(clean::Empty{});
+            self.add_admin_token(&mut request)?;
+            Ok(request)
+        };
+        let response = self.retried(&self.admin_client, request,
+                                    |mut c, r| async move { c.get_server_stats(r).await }).await?;
+        Ok(response.into())
+    }
+
+    // ban an account from the whole server, which needs the admin token
+    pub async fn ban_account(&mut self, ban: AccountBan) -> Result<()> {
+        let mut request = Request::new(ban.into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.admin_client.ban_account(request).await.map_err(from_status)?;
         Ok(())
     }
 
-    // listen for server events
+    pub async fn unban_account(&mut self, ban: AccountBan) -> Result<()> {
+        let mut request = Request::new(ban.into());
+        self.add_admin_token(&mut request)?;
+        let _ = self.admin_client.unban_account(request).await.map_err(from_status)?;
+        Ok(())
+    }
+
+    pub async fn account_bans(&mut self) -> Result<Vec<AccountBan>> {
+        let request = || {
+            let mut request = Request::new(clean::Empty{});
+            self.add_admin_token(&mut request)?;
+            Ok(request)
+        };
+        let response = self.retried(&self.admin_client, request,
+                                    |mut c, r| async move { c.list_account_bans(r).await }).await?;
+        let bans: std::result::Result<Vec<AccountBan>, Error> =
+            response.bans.into_iter().map(|b| b.try_into()).collect();
+        Ok(bans?)
+    }
+
+    fn user_data_request(&self, uid: UserID) -> Result<Request<clean::UserDataRequest>> {
+        let mut request = Request::new(clean::UserDataRequest {
+            user_id: uid.0,
+        });
+        self.add_admin_token(&mut request)?;
+        Ok(request)
+    }
+
+    // requests made as a user outside of a session can be made as an admin
+    // instead, to manage any user
+    fn add_admin_token<T>(&self, request: &mut Request<T>) -> Result<()> {
+        if let Some(admin) = &self.admin {
+            request.metadata_mut().insert(ADMIN_TOKEN_KEY,
+                                          MetadataValue::try_from(admin.0.as_str())?);
+        }
+        Ok(())
+    }
+
+    // ask the server for a user ID, to use from then on. Every request after
+    // this is authenticated as the new user, and the token is returned so it
+    // can be kept for next time
+    pub async fn register_user(&mut self, name: &str) -> Result<(UserID, AuthToken)> {
+        self.register(name, AccountType::Registered).await
+    }
+
+    // join as a guest, who can't host and is forgotten once the server stops.
+    // Leave the name empty to be given one
+    pub async fn register_guest(&mut self, name: &str) -> Result<(UserID, AuthToken)> {
+        self.register(name, AccountType::Guest).await
+    }
+
+    async fn register(&mut self, name: &str, account_type: AccountType)
+            -> Result<(UserID, AuthToken)> {
+        let account_type: clean::AccountType = account_type.into();
+        let request = Request::new(clean::RegisterRequest {
+            name: name.to_owned(),
+            account_type: account_type.into(),
+        });
+        let r = self.client.register_user(request).await.map_err(from_status)?.into_inner();
+        let token = AuthToken(r.token);
+        self.set_auth_token(token.clone());
+        Ok((UserID(r.user_id), token))
+    }
+
+    pub async fn balance(&mut self, uid: UserID) -> Result<u64> {
+        let request = || Ok(Request::new(clean::BalanceRequest {
+            user_id: uid.0,
+        }));
+        let response = self.retried(&self.client, request,
+                                    |mut c, r| async move { c.balance(r).await }).await?;
+        let bi: BalanceInfo = response.into();
+        Ok(bi.chips())
+    }
+
+    // any user's profile can be looked at, not just our own
+    pub async fn get_profile(&mut self, uid: UserID) -> Result<Profile> {
+        let request = || Ok(Request::new(clean::ProfileRequest {
+            user_id: uid.0,
+        }));
+        let response = self.retried(&self.client, request,
+                                    |mut c, r| async move { c.get_profile(r).await }).await?;
+        Ok(response.into())
+    }
+
+    // listen for server events, answering them on the same stream. A client
+    // listening again after its stream dropped can resume from the last
+    // sequence it handled, to be sent what it missed first, or pass zero to
+    // start afresh
     pub async fn server_events_listen(&mut self, sid: SessionID, uid: UserID,
-            listener: Arc<dyn ServerEvent>) -> Result<JoinHandle<Result<()>>> {
-        let (tx, mut rx) = mpsc::channel::<(clean::server_request::Msg, EventRegister)>(100);
-        let mut client_clone = self.client.clone();
+            listener: Arc<dyn ServerEvent>, resume_from: u64)
+            -> Result<JoinHandle<Result<()>>> {
+        let er = EventRegister::new(sid, uid);
+        let token = self.tokens.get(&er).cloned()
+            .unwrap_or_else(|| ReconnectToken(String::new()));
+        let mut register: clean::EventRegister = (er.clone(), token).into();
+        register.resume_from = resume_from;
+        // say who is listening before anything else, and keep the sender to
+        // answer with
+        let (otx, orx) = mpsc::channel::<clean::ClientMessage>(100);
+        otx.send(clean::ClientMessage {
+            msg: Some(clean::client_message::Msg::Register(register)),
+        }).await?;
+        let request = Request::new(ReceiverStream::new(orx));
+        let response = self.client.play_session(request).await.map_err(from_status)?;
+
+        // keep the issued token to present with requests and reconnects
+        let token = response.metadata().get(RECONNECT_TOKEN_KEY)
+            .and_then(|t| t.to_str().ok())
+            .map(|t| ReconnectToken(t.to_owned()))
+            .ok_or_else(|| Error::MissingReconnectToken)?;
+        self.tokens.insert(er.clone(), token);
+        let seen = Arc::new(AtomicU64::new(resume_from));
+        self.sequences.insert(er, seen.clone());
+        let mut stream = response.into_inner();
+
+        // each request is handled along with its ID, to answer it with
+        let (tx, mut rx) = mpsc::channel::<(u64, clean::server_request::Msg)>(100);
+        let beats = otx.clone();
+        let reader = tokio::spawn(async move {
+            while let Ok(Some(event)) = stream.message().await {
+                // unpack batches so each request is handled on its own
+                let requests = match event.msg {
+                    Some(clean::server_request::Msg::Batch(b)) => b.requests,
+                    _ => vec![event],
+                };
+                for r in requests {
+                    // anything arriving out of order, or a second time, was
+                    // already handled. Older servers don't number requests
+                    if r.sequence != 0 {
+                        let last = seen.load(Ordering::SeqCst);
+                        if r.sequence <= last {
+                            warn!("Dropping request {}, already up to {}", r.sequence, last);
+                            continue;
+                        }
+                        seen.store(r.sequence, Ordering::SeqCst);
+                    }
+                    let (id, sr) = match r.msg {
+                        Some(sr) => (r.request_id, sr),
+                        None => { continue; }
+                    };
+                    // answered here rather than by the handler, which may be
+                    // busy waiting on the player
+                    if let clean::server_request::Msg::Heartbeat(n) = sr {
+                        let cm = clean::ClientMessage {
+                            msg: Some(clean::client_message::Msg::Heartbeat(n)),
+                        };
+                        if beats.send(cm).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    if let Err(e) = tx.send((id, sr)).await {
+                        error!("Failed to send server event: {:?}", e);
+                        return;
+                    }
+                }
+            }
+        });
 
         let handle: JoinHandle<Result<()>> = tokio::spawn(async move {
+            // stop reading the stream once nothing is handling it, including
+            // when the handle is aborted
+            let _reader = AbortOnDrop(reader);
             let mut error = None;
-            while let Some((event, er)) = rx.recv().await {
+            while let Some((id, event)) = rx.recv().await {
+                // the server says why it is ending the stream, which is a
+                // clean finish once the game is over
+                if let clean::server_request::Msg::StreamClosing(sc) = &event {
+                    let reason: CloseReason = sc.reason.into();
+                    info!("Server closing event stream for user {:?}: {:?}", uid, reason);
+                    if matches!(reason, CloseReason::Finished) {
+                        return Ok(());
+                    }
+                    return Err(Box::new(Error::StreamClosed(reason)));
+                }
                 let server_el = Arc::clone(&listener);
                 let cr = match server_listener_handler(server_el, event).await {
                     Ok(i) => i,
@@ -80,15 +998,13 @@ impl CleanClient {
 
                 if let Some(c) = cr {
                     info!("Responding with {:?} for user {:?}", c, uid);
-                    let cm = clean::ClientResponse {
-                        msg: Some(c),
-                    };
-                    let cer = clean::ClientEventResponse {
-                        er: Some(er.clone().into()),
-                        client_response: Some(cm),
+                    let cm = clean::ClientMessage {
+                        msg: Some(clean::client_message::Msg::Response(clean::ClientResponse {
+                            msg: Some(c),
+                            request_id: id,
+                        })),
                     };
-                    let r = Request::new(cer);
-                    if let Err(e) = client_clone.respond_to_server_event(r).await {
+                    if let Err(e) = otx.send(cm).await {
                         error!("Failed to respond to server event: {:?}", e);
                         error = Some(format!("{:?}", e));
                         break;
@@ -102,22 +1018,16 @@ impl CleanClient {
         }.map_err(|e| Box::new(e) as
                   Box<dyn std::error::Error + Send + Sync + 'static>));
 
-        let er = EventRegister::new(sid, uid);
-        let request = Request::new(er.clone().into());
-        let mut stream = self.client.server_events(request).await?.into_inner();
+        Ok(handle)
+    }
+}
 
-        tokio::spawn(async move {
-            while let Ok(Some(event)) = stream.message().await {
-                if let Some(sr) = event.msg {
-                    if let Err(e) = tx.send((sr, er.clone())).await {
-                        error!("Failed to send server event: {:?}", e);
-                        break;
-                    }
-                }
-            }
-        });
+// aborts a task once dropped, so it can't outlive its owner
+struct AbortOnDrop(JoinHandle<()>);
 
-        Ok(handle)
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
     }
 }
 
@@ -138,20 +1048,20 @@ async fn server_listener_handler(server_el: Arc<dyn ServerEvent>,
             return Ok(Some(clean::client_response::Msg::Pong(pong.into())));
         }
         clean::server_request::Msg::Dice(rd) => {
-            let rd: RollDice = rd.into();
+            let rd: RollDice = rd.try_into()?;
             let r = server_el.roll_dice(rd.sides(), rd.count()).await?;
             let dg = DiceGuess::new(&r);
             return Ok(Some(clean::client_response::Msg::DiceGuess(dg.into())));
         }
         clean::server_request::Msg::Coin(fc) => {
-            let fc: FlipCoin = fc.into();
+            let fc: FlipCoin = fc.try_into()?;
             let r = server_el.flip_coin(fc.count()).await?;
             let cg = CoinGuess::new(&r);
             return Ok(Some(clean::client_response::Msg::CoinGuess(cg.into())));
         }
         clean::server_request::Msg::Winner(w) => {
             let w: Winner = w.into();
-            server_el.winner(w.user_id(), w.user_name()).await?;
+            server_el.winner(w.user_id(), w.user_name(), w.payout()).await?;
             return Ok(None);
         }
         clean::server_request::Msg::TryAgain(_) => {
@@ -159,8 +1069,159 @@ async fn server_listener_handler(server_el: Arc<dyn ServerEvent>,
             return Ok(Some(clean::client_response::Msg::Again(r)));
         }
         clean::server_request::Msg::Error(e) => {
-            server_el.error(&e).await?;
+            // older servers only send the message
+            server_el.error(ErrorCode::Unspecified, ClientAction::None, &e).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::ServerError(se) => {
+            let se: ServerError = se.into();
+            server_el.error(se.code(), se.action(), se.message()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Prompt(p) => {
+            let r = server_el.prompt(&p).await?;
+            return Ok(Some(clean::client_response::Msg::Answer(r)));
+        }
+        clean::server_request::Msg::RoundResult(rr) => {
+            let rr: RoundResult = rr.into();
+            server_el.round_result(rr.round(), rr.user_id(), rr.user_name(),
+                                   rr.wins()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::TeamWinner(tw) => {
+            let tw: TeamWinner = tw.into();
+            server_el.team_winner(tw.team(), tw.members(), tw.payout()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::TeamRoundResult(tr) => {
+            let tr: TeamRoundResult = tr.into();
+            server_el.team_round_result(tr.round(), tr.team(), tr.wins()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Scoreboard(sb) => {
+            let sb: Scoreboard = sb.into();
+            server_el.scoreboard(sb.round(), sb.entries()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Champion(c) => {
+            let c: Champion = c.into();
+            server_el.champion(c.user_id(), c.user_name(), c.score()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::SessionExpired(sid) => {
+            server_el.session_expired(SessionID(sid)).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::UserLeft(ji) => {
+            let ji: JoinInfo = ji.into();
+            server_el.user_left(ji.session_id(), ji.user_id(), ji.user_name()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Chat(cm) => {
+            let cm: ChatMessage = cm.into();
+            server_el.chat(cm.session_id(), cm.user_id(), cm.user_name(),
+                           cm.text()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::UserKicked(ji) => {
+            let ji: JoinInfo = ji.into();
+            server_el.user_kicked(ji.session_id(), ji.user_id(), ji.user_name()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::DirectMessage(dm) => {
+            let dm: DirectMessage = dm.into();
+            server_el.direct_message(dm.from_id(), dm.from_name(), dm.to_id(),
+                                     dm.text()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::SessionCancelled(sid) => {
+            server_el.session_cancelled(SessionID(sid)).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::SessionUpdated(sd) => {
+            let sd: SessionData = sd.try_into()?;
+            server_el.session_updated(&sd).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::JoinRequest(ji) => {
+            let ji: JoinInfo = ji.into();
+            server_el.join_request(ji.session_id(), ji.user_id(), ji.user_name()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::WaitingForPlayer(w) => {
+            let w: WaitingForPlayer = w.into();
+            server_el.waiting_for_player(w.user_id(), w.user_name()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Rematch(sd) => {
+            let sd: SessionData = sd.try_into()?;
+            let r = server_el.rematch(&sd).await?;
+            return Ok(Some(clean::client_response::Msg::Again(r)));
+        }
+        clean::server_request::Msg::ReadyCheck(_) => {
+            let r = server_el.ready_check().await?;
+            return Ok(Some(clean::client_response::Msg::Ready(r)));
+        }
+        clean::server_request::Msg::PlayerReady(pr) => {
+            let pr: PlayerReady = pr.into();
+            server_el.player_ready(pr.user_id(), pr.user_name(), pr.ready()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::GameOver(sid) => {
+            server_el.game_over(SessionID(sid)).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::HostChanged(ji) => {
+            let ji: JoinInfo = ji.into();
+            server_el.host_changed(ji.session_id(), ji.user_id(), ji.user_name()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Countdown(secs) => {
+            server_el.countdown(secs).await?;
             return Ok(None);
         }
+        clean::server_request::Msg::GamePaused(sid) => {
+            server_el.game_paused(SessionID(sid)).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::GameResumed(sid) => {
+            server_el.game_resumed(SessionID(sid)).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::GameStarted(sd) => {
+            let sd: SessionData = sd.try_into()?;
+            server_el.game_started(&sd).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::GameEnded(ge) => {
+            let ge: GameEnded = ge.into();
+            server_el.game_ended(ge.session_id(), ge.scores()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::StartReminder(secs) => {
+            server_el.start_reminder(secs).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::Invitation(inv) => {
+            server_el.invitation(&inv.into()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::PresenceChanged(pc) => {
+            let pc: PresenceChange = pc.into();
+            server_el.presence_changed(pc.user_id(), pc.name(), pc.online()).await?;
+            return Ok(None);
+        }
+        clean::server_request::Msg::StreamClosing(_) => {
+            // the listener ends with this, so it never reaches the handler
+            return Ok(None);
+        }
+        clean::server_request::Msg::Heartbeat(_) => {
+            // answered as soon as it is read, so it never reaches the handler
+            return Ok(None);
+        }
+        clean::server_request::Msg::Batch(_) => {
+            // batches are unpacked as they arrive, so can't be nested
+            return Err(Box::new(Error::InvalidServerRequest));
+        }
     }
 }
@@ -0,0 +1,240 @@
+// Compatibility checks against messages from earlier protocol revisions
+//
+// Every revision of the protocol a client or server may still be running has
+// a directory of golden fixtures, one encoded message per file written as hex,
+// recorded from that revision's types. Checking decodes each one and converts
+// it with today's conversions, failing any that no longer decode, are
+// rejected, or lose what they held on the way through. Record the current
+// revision with the csr-compat binary before changing the protocol; the
+// crate's compat test checks every revision on each cargo test.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use prost::Message;
+
+use crate::clean;
+use crate::error::Error;
+use crate::types::Result;
+use crate::types::{
+    AccountBan, ChatMessage, ClientResponse, CloseReason, Coin, CoinCount, CoinGuess, DiceCount,
+    DiceGuess, DiceSides, FlipCoin, HostInfo, JoinInfo, ListFilter, LobbyChange, Ping, Pong,
+    Preset, RollDice, ServerRequest, SessionChanges, SessionData, SessionDetails, SessionID,
+    SessionPlayer, SessionStatus, SessionType, Sessions, UserID, Visibility, Winner,
+};
+
+const EXTENSION: &str = "hex";
+
+pub struct Check {
+    name: String,
+    failure: Option<String>,
+}
+
+impl Check {
+    // the revision and fixture checked, as in v1/host_info
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    // why the fixture was rejected, None if it passed
+    pub fn failure(&self) -> Option<&str> { self.failure.as_deref() }
+}
+
+#[derive(Default)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn check(&mut self, name: String, r: std::result::Result<(), String>) {
+        self.checks.push(Check {
+            name: name,
+            failure: r.err(),
+        });
+    }
+
+    pub fn checks<'a>(&'a self) -> &'a [Check] { &self.checks }
+    pub fn passed(&self) -> bool { self.checks.iter().all(|c| c.failure.is_none()) }
+}
+
+// a message kept as a fixture, how it is built today and how it is read back
+struct Fixture {
+    name: &'static str,
+    record: fn() -> Result<Vec<u8>>,
+    accept: fn(&[u8]) -> std::result::Result<(), String>,
+}
+
+macro_rules! fixture {
+    ($name:expr, $proto:ty, $domain:ty, $value:expr) => {
+        Fixture {
+            name: $name,
+            record: || {
+                let value: $domain = $value;
+                Ok(<$proto>::from(value).encode_to_vec())
+            },
+            accept: accept::<$proto, $domain>,
+        }
+    };
+}
+
+// where the fixtures kept with the crate are
+pub fn fixtures_dir() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"))
+}
+
+// writes every fixture as the current types encode it, under the revision's
+// directory. Fixtures already recorded are replaced
+pub fn record(dir: &Path, revision: &str) -> Result<Vec<PathBuf>> {
+    let dir = dir.join(revision);
+    fs::create_dir_all(&dir)?;
+    let mut written = Vec::new();
+    for f in fixtures() {
+        let path = dir.join(f.name).with_extension(EXTENSION);
+        fs::write(&path, to_hex(&(f.record)()?))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+// checks the fixtures of every revision in the directory. Fixtures for
+// messages the crate has since dropped fail, removing a message breaks
+// whoever still sends it
+pub fn check(dir: &Path) -> Result<Report> {
+    let fixtures = fixtures();
+    let mut revisions = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    revisions.retain(|p| p.is_dir());
+    revisions.sort();
+    let mut report = Report::default();
+    for revision in revisions {
+        let mut files = fs::read_dir(&revision)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.retain(|p| p.extension().is_some_and(|e| e == EXTENSION));
+        files.sort();
+        for file in files {
+            let name = file.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+            let label = match revision.file_name().and_then(|s| s.to_str()) {
+                Some(r) => format!("{}/{}", r, name),
+                None => name.to_owned(),
+            };
+            let r = match fixtures.iter().find(|f| f.name == name) {
+                None => Err("no message of that name".to_owned()),
+                Some(f) => fs::read_to_string(&file)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| from_hex(&text))
+                    .and_then(|bytes| (f.accept)(&bytes)),
+            };
+            report.check(label, r);
+        }
+    }
+    Ok(report)
+}
+
+// the message decodes, converts, and converts back to what was decoded, so
+// nothing the older revision sent is lost
+fn accept<P, T>(bytes: &[u8]) -> std::result::Result<(), String>
+        where P: Message + Default + Clone + PartialEq + From<T>,
+              T: TryFrom<P, Error = Error> {
+    let proto = P::decode(bytes).map_err(|e| format!("doesn't decode, {}", e))?;
+    let value = T::try_from(proto.clone()).map_err(|e| format!("rejected, {}", e))?;
+    if P::from(value) != proto {
+        return Err("changed converting to the current types".to_owned());
+    }
+    Ok(())
+}
+
+// a message of each kind converted with TryFrom, filled in so that every
+// field the conversion reads is on the wire
+fn fixtures() -> Vec<Fixture> {
+    vec![
+        fixture!("host_info", clean::HostInfo, HostInfo, host_info()),
+        fixture!("preset", clean::Preset, Preset, Preset::new("quick", host_info())),
+        fixture!("session_data", clean::SessionData, SessionData, session_data()),
+        fixture!("session_details", clean::SessionDetails, SessionDetails,
+            SessionDetails::new(session_data(), vec![
+                SessionPlayer::new(UserID(1), "alice"),
+                SessionPlayer::new(UserID(2), "bob"),
+            ], SessionStatus::Waiting)),
+        fixture!("lobby_change_created", clean::LobbyChange, LobbyChange,
+            LobbyChange::Created(session_data())),
+        fixture!("lobby_change_removed", clean::LobbyChange, LobbyChange,
+            LobbyChange::Removed(SessionID(7))),
+        fixture!("session_changes", clean::SessionChanges, SessionChanges,
+            SessionChanges::new()
+                .with_session_type(SessionType::Coin)
+                .with_max_players(6)
+                .with_name("rematch")
+                .with_visibility(Visibility::Private)),
+        fixture!("list_filter", clean::ListFilter, ListFilter,
+            ListFilter::new()
+                .with_session_type(SessionType::Dice)
+                .with_joinable_only(true)
+                .with_host(UserID(1))),
+        fixture!("sessions", clean::Sessions, Sessions,
+            Sessions::new(&[session_data()], &["poker".to_owned()])),
+        fixture!("account_ban_user", clean::AccountBan, AccountBan,
+            AccountBan::User(UserID(3))),
+        fixture!("account_ban_name", clean::AccountBan, AccountBan,
+            AccountBan::Name("mallory".to_owned())),
+        fixture!("roll_dice", clean::RollDice, RollDice,
+            RollDice::new(DiceSides::new(6)?, DiceCount::new(2)?)),
+        fixture!("flip_coin", clean::FlipCoin, FlipCoin,
+            FlipCoin::new(CoinCount::new(3)?)),
+        fixture!("coin_guess", clean::CoinGuess, CoinGuess,
+            CoinGuess::new(&[Coin::Heads, Coin::Tails])),
+        fixture!("server_request_join_info", clean::ServerRequest, ServerRequest,
+            ServerRequest::JoinInfo(JoinInfo::new(SessionID(7), UserID(2), "bob"))),
+        fixture!("server_request_ping", clean::ServerRequest, ServerRequest,
+            ServerRequest::Ping(Ping::new("ping"))),
+        fixture!("server_request_roll_dice", clean::ServerRequest, ServerRequest,
+            ServerRequest::RollDice(RollDice::new(DiceSides::new(6)?, DiceCount::new(2)?))),
+        fixture!("server_request_winner", clean::ServerRequest, ServerRequest,
+            ServerRequest::Winner(Winner::new(UserID(1), "alice", 40))),
+        fixture!("server_request_chat", clean::ServerRequest, ServerRequest,
+            ServerRequest::Chat(
+                ChatMessage::new(SessionID(7), UserID(1), "alice", "good luck"))),
+        fixture!("server_request_session_updated", clean::ServerRequest, ServerRequest,
+            ServerRequest::SessionUpdated(session_data())),
+        fixture!("server_request_stream_closing", clean::ServerRequest, ServerRequest,
+            ServerRequest::StreamClosing(CloseReason::Shutdown)),
+        fixture!("client_response_pong", clean::ClientResponse, ClientResponse,
+            ClientResponse::Pong(Pong::new("pong"))),
+        fixture!("client_response_dice_guess", clean::ClientResponse, ClientResponse,
+            ClientResponse::DiceGuess(DiceGuess::new(&[3, 5]))),
+        fixture!("client_response_coin_guess", clean::ClientResponse, ClientResponse,
+            ClientResponse::CoinGuess(CoinGuess::new(&[Coin::Heads]))),
+        fixture!("client_response_again", clean::ClientResponse, ClientResponse,
+            ClientResponse::Again(true)),
+    ]
+}
+
+fn host_info() -> HostInfo {
+    HostInfo::new(SessionType::Dice, 4)
+        .with_name("friday dice")
+        .with_wager(20)
+        .with_min_players(2)
+        .with_series_length(3)
+}
+
+fn session_data() -> SessionData {
+    let users = ["alice".to_owned(), "bob".to_owned()];
+    SessionData::new(SessionID(7), SessionType::Dice, &users)
+        .with_name("friday dice")
+        .with_max_players(4)
+        .with_wager(20)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut text: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    text.push('\n');
+    text
+}
+
+fn from_hex(text: &str) -> std::result::Result<Vec<u8>, String> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_owned());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
@@ -0,0 +1,687 @@
+// Conformance checks for servers implementing the Clean protocol
+//
+// A scripted set of clients plays through hosting, listing, joining, handing
+// over the host, kicking, cancelling and a full coin game with a rematch
+// against a running server, checking the server answers each call in time,
+// reports the errors it should, and sends game events in the expected order.
+// Run it against an address with the csr-conformance binary, or call `run`
+// after serving a `Clean` implementation with `make_server`.
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::client::CleanClient;
+use crate::error::{Error, NameError};
+use crate::event::ServerEvent;
+use crate::types::Result;
+use crate::types::{
+    AccountType, ClientAction, CloseReason, Coin, CoinCount, DiceCount, DiceSides, ErrorCode,
+    HostInfo, Invitation, JoinInfo, ListFilter, LobbyChange, Score, ScoreEntry, SessionData,
+    SessionID, SessionStatus, SessionType, TeamID, UserID, Visibility,
+};
+
+// how long any single call can take
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+// how long a whole game can take
+const GAME_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Check {
+    name: &'static str,
+    failure: Option<String>,
+}
+
+impl Check {
+    pub fn name(&self) -> &'static str { self.name }
+    // why the check failed, None if it passed
+    pub fn failure(&self) -> Option<&str> { self.failure.as_deref() }
+}
+
+#[derive(Default)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn check(&mut self, name: &'static str, r: std::result::Result<(), String>) {
+        self.checks.push(Check {
+            name: name,
+            failure: r.err(),
+        });
+    }
+
+    pub fn checks<'a>(&'a self) -> &'a [Check] { &self.checks }
+    pub fn passed(&self) -> bool { self.checks.iter().all(|c| c.failure.is_none()) }
+}
+
+// records the events a player is sent, answering anything asked of it with
+// the same guesses every time
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<&'static str>>,
+    over: tokio::sync::Notify,
+}
+
+impl Recorder {
+    fn record(&self, event: &'static str) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn events(&self) -> Vec<&'static str> {
+        self.events.lock().unwrap().clone()
+    }
+
+    // the events of a game, once the server says it is over. The game can
+    // end before the last events are handled. Other users coming and going
+    // isn't part of the game
+    async fn game_events(&self) -> Vec<&'static str> {
+        let _ = tokio::time::timeout(STEP_TIMEOUT, self.over.notified()).await;
+        self.events().into_iter().filter(|e| *e != "presence_changed").collect()
+    }
+}
+
+#[tonic::async_trait]
+impl ServerEvent for Recorder {
+    async fn join_info(&self, _: SessionID, _: UserID, _: &str) -> Result<()> {
+        self.record("join_info");
+        Ok(())
+    }
+    async fn ping(&self, _: &str) -> Result<String> {
+        self.record("ping");
+        Ok("pong".to_owned())
+    }
+    async fn roll_dice(&self, _: DiceSides, count: DiceCount) -> Result<Vec<u8>> {
+        self.record("roll_dice");
+        Ok(vec![1; count.value() as usize])
+    }
+    async fn flip_coin(&self, count: CoinCount) -> Result<Vec<Coin>> {
+        self.record("flip_coin");
+        Ok(vec![Coin::Heads; count.value() as usize])
+    }
+    async fn winner(&self, _: UserID, _: &str, _: u64) -> Result<()> {
+        self.record("winner");
+        Ok(())
+    }
+    async fn try_again(&self) -> Result<bool> {
+        self.record("try_again");
+        Ok(false)
+    }
+    async fn error(&self, _: ErrorCode, _: ClientAction, _: &str) -> Result<()> {
+        self.record("error");
+        Ok(())
+    }
+    async fn prompt(&self, _: &str) -> Result<String> {
+        self.record("prompt");
+        Ok(String::new())
+    }
+    async fn round_result(&self, _: u8, _: UserID, _: &str, _: u8) -> Result<()> {
+        self.record("round_result");
+        Ok(())
+    }
+    async fn team_winner(&self, _: TeamID, _: &[String], _: u64) -> Result<()> {
+        self.record("team_winner");
+        Ok(())
+    }
+    async fn team_round_result(&self, _: u8, _: TeamID, _: u8) -> Result<()> {
+        self.record("team_round_result");
+        Ok(())
+    }
+    async fn scoreboard(&self, _: u32, _: &[ScoreEntry]) -> Result<()> {
+        self.record("scoreboard");
+        Ok(())
+    }
+    async fn champion(&self, _: UserID, _: &str, _: Score) -> Result<()> {
+        self.record("champion");
+        Ok(())
+    }
+    async fn session_expired(&self, _: SessionID) -> Result<()> {
+        self.record("session_expired");
+        Ok(())
+    }
+    async fn user_left(&self, _: SessionID, _: UserID, _: &str) -> Result<()> {
+        self.record("user_left");
+        Ok(())
+    }
+    async fn chat(&self, _: SessionID, _: UserID, _: &str, _: &str) -> Result<()> {
+        self.record("chat");
+        Ok(())
+    }
+    async fn user_kicked(&self, _: SessionID, _: UserID, _: &str) -> Result<()> {
+        self.record("user_kicked");
+        Ok(())
+    }
+    async fn direct_message(&self, _: UserID, _: &str, _: UserID, _: &str) -> Result<()> {
+        self.record("direct_message");
+        Ok(())
+    }
+    async fn session_cancelled(&self, _: SessionID) -> Result<()> {
+        self.record("session_cancelled");
+        Ok(())
+    }
+    async fn session_updated(&self, _: &SessionData) -> Result<()> {
+        self.record("session_updated");
+        Ok(())
+    }
+    async fn join_request(&self, _: SessionID, _: UserID, _: &str) -> Result<()> {
+        self.record("join_request");
+        Ok(())
+    }
+    async fn waiting_for_player(&self, _: UserID, _: &str) -> Result<()> {
+        self.record("waiting_for_player");
+        Ok(())
+    }
+    async fn rematch(&self, _: &SessionData) -> Result<bool> {
+        self.record("rematch");
+        Ok(false)
+    }
+    async fn ready_check(&self) -> Result<bool> {
+        self.record("ready_check");
+        Ok(true)
+    }
+    async fn player_ready(&self, _: UserID, _: &str, _: bool) -> Result<()> {
+        self.record("player_ready");
+        Ok(())
+    }
+    async fn game_over(&self, _: SessionID) -> Result<()> {
+        self.record("game_over");
+        self.over.notify_one();
+        Ok(())
+    }
+    async fn host_changed(&self, _: SessionID, _: UserID, _: &str) -> Result<()> {
+        self.record("host_changed");
+        Ok(())
+    }
+    async fn countdown(&self, _: u32) -> Result<()> {
+        self.record("countdown");
+        Ok(())
+    }
+    async fn game_paused(&self, _: SessionID) -> Result<()> {
+        self.record("game_paused");
+        Ok(())
+    }
+    async fn game_resumed(&self, _: SessionID) -> Result<()> {
+        self.record("game_resumed");
+        Ok(())
+    }
+    async fn game_started(&self, _: &SessionData) -> Result<()> {
+        self.record("game_started");
+        Ok(())
+    }
+    async fn game_ended(&self, _: SessionID, _: &[ScoreEntry]) -> Result<()> {
+        self.record("game_ended");
+        Ok(())
+    }
+    async fn start_reminder(&self, _: u64) -> Result<()> {
+        self.record("start_reminder");
+        Ok(())
+    }
+    async fn invitation(&self, _: &Invitation) -> Result<()> {
+        self.record("invitation");
+        Ok(())
+    }
+    async fn presence_changed(&self, _: UserID, _: &str, _: bool) -> Result<()> {
+        self.record("presence_changed");
+        Ok(())
+    }
+}
+
+// run a call, failing it if the server takes too long
+async fn step<T>(timeout: Duration, f: impl Future<Output = Result<T>>)
+        -> std::result::Result<T, String> {
+    match tokio::time::timeout(timeout, f).await {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(e)) => Err(format!("{}", e)),
+        Err(_) => Err(format!("no answer after {:?}", timeout)),
+    }
+}
+
+// a call the server should refuse
+async fn refused<T>(f: impl Future<Output = Result<T>>) -> std::result::Result<(), String> {
+    match step(STEP_TIMEOUT, f).await {
+        Ok(_) => Err("accepted, should have been refused".to_owned()),
+        Err(e) if e.starts_with("no answer") => Err(e),
+        Err(_) => Ok(()),
+    }
+}
+
+// wait for an event stream to end, with the reason the server gave. A stream
+// closed because the game is over ends the listener cleanly
+async fn closed(handle: tokio::task::JoinHandle<Result<()>>, reason: CloseReason)
+        -> std::result::Result<(), String> {
+    let expected = format!("{}", Error::StreamClosed(reason));
+    match tokio::time::timeout(STEP_TIMEOUT, handle).await {
+        Ok(Ok(Ok(_))) if matches!(reason, CloseReason::Finished) => Ok(()),
+        Ok(Ok(Err(e))) if format!("{}", e) == expected => Ok(()),
+        Ok(Ok(Err(e))) => Err(format!("stream ended with {}, expected {:?}", e, reason)),
+        Ok(Ok(Ok(_))) => Err(format!("stream ended without saying why, expected {:?}", reason)),
+        Ok(Err(e)) => Err(format!("listener failed: {}", e)),
+        Err(_) => Err(format!("stream still open after {:?}", STEP_TIMEOUT)),
+    }
+}
+
+// the order every player should see a game in
+fn game_order(events: &[&'static str]) -> std::result::Result<(), String> {
+    if events.contains(&"error") {
+        return Err(format!("sent an error during the game: {:?}", events));
+    }
+    let position = |event| events.iter().position(|e| *e == event);
+    let first_guess = position("flip_coin")
+        .ok_or_else(|| format!("never asked for a guess: {:?}", events))?;
+    let winner = position("winner")
+        .ok_or_else(|| format!("never announced a winner: {:?}", events))?;
+    let again = position("try_again")
+        .ok_or_else(|| format!("never asked to play again: {:?}", events))?;
+    if events.first() != Some(&"ready_check") {
+        return Err(format!("first event should be the ready check: {:?}", events));
+    }
+    if events.iter().skip(1).find(|e| **e != "player_ready") != Some(&"ping") {
+        return Err(format!("expected a ping once everyone is ready: {:?}", events));
+    }
+    let started = position("game_started")
+        .ok_or_else(|| format!("never said the game started: {:?}", events))?;
+    let ended = position("game_ended")
+        .ok_or_else(|| format!("never sent the final scores: {:?}", events))?;
+    let over = position("game_over")
+        .ok_or_else(|| format!("never said the game was over: {:?}", events))?;
+    if !(started < first_guess && first_guess < winner && winner < ended && ended < again
+         && again < over) {
+        return Err(format!("expected the start, guesses, the winner, final scores, play again, \
+                            then game over: {:?}", events));
+    }
+    Ok(())
+}
+
+// run every check against the server at the address
+pub async fn run(address: &str) -> Result<Report> {
+    let mut report = Report::default();
+    let mut a = CleanClient::new(address).await?;
+    let mut b = CleanClient::new(address).await?;
+    let mut c = CleanClient::new(address).await?;
+
+    // every client registers, and is authenticated as its user from then on
+    let (ua, _) = step(STEP_TIMEOUT, a.register_user("a")).await?;
+    let (ub, _) = step(STEP_TIMEOUT, b.register_user("b")).await?;
+    let (uc, _) = step(STEP_TIMEOUT, c.register_user("c")).await?;
+    report.check("register_user", if ua != ub && ub != uc && ua != uc {
+        Ok(())
+    } else {
+        Err("handed out the same ID twice".to_owned())
+    });
+    report.check("register_empty_name",
+                 refused(CleanClient::new(address).await?.register_user(" ")).await);
+    // a line break could pass for another user in the server's user file
+    let forged = format!("x\n{} forged - a", ua.0);
+    report.check("register_control_name",
+                 refused(CleanClient::new(address).await?.register_user(&forged)).await);
+    let mut anonymous = CleanClient::new(address).await?;
+    report.check("unauthenticated", match step(STEP_TIMEOUT,
+                                               anonymous.list_sessions(ListFilter::new())).await {
+        Ok(_) => Err("listed sessions without a token".to_owned()),
+        Err(e) if e == format!("{}", Error::Unauthenticated) => Ok(()),
+        Err(e) => Err(format!("refused with {}, expected {}", e, Error::Unauthenticated)),
+    });
+    report.check("act_as_other_user", refused(a.balance(ub)).await);
+    report.check("admin_without_token", refused(a.server_stats()).await);
+    report.check("profile_unknown", refused(a.get_profile(UserID(u64::MAX))).await);
+    let mut guest = CleanClient::new(address).await?;
+    match step(STEP_TIMEOUT, guest.register_guest("")).await {
+        Ok((ug, _)) => {
+            let hi = HostInfo::new(SessionType::Coin, 2).with_host(ug);
+            report.check("guest_host", refused(guest.host_session(hi)).await);
+            let hi = HostInfo::new(SessionType::Coin, 2).with_host(ua).with_auto_start(false);
+            report.check("guest_join", match step(STEP_TIMEOUT, a.host_session(hi)).await {
+                Ok(sd) => {
+                    let sid = sd.session_id();
+                    let joined = async {
+                        guest.join_session(JoinInfo::new(sid, ug, "guest")).await?;
+                        guest.get_session(sid).await
+                    };
+                    match step(STEP_TIMEOUT, joined).await {
+                        Ok(d) if d.players().iter().any(|p| p.user_id() == ug
+                                && p.account_type() == AccountType::Guest) => Ok(()),
+                        Ok(_) => Err("guest not marked as one in the session".to_owned()),
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            });
+        }
+        Err(e) => report.check("register_guest", Err(e)),
+    }
+
+    // hosting and listing, watched from another client
+    let watching = step(STEP_TIMEOUT, c.watch_sessions()).await;
+    let hi = HostInfo::new(SessionType::Coin, 2).with_host(ua).with_auto_start(false);
+    let sd = match step(STEP_TIMEOUT, a.host_session(hi)).await {
+        Ok(sd) => sd,
+        Err(e) => {
+            report.check("host_session", Err(e));
+            return Ok(report);
+        }
+    };
+    let sid = sd.session_id();
+    report.check("host_session", if sd.session_type() == &SessionType::Coin
+                                     && sd.max_players() == 2 {
+        Ok(())
+    } else {
+        Err(format!("hosted session doesn't match: {:?}, {} players", sd.session_type(),
+                    sd.max_players()))
+    });
+    report.check("list_sessions", match step(STEP_TIMEOUT,
+                                             a.list_sessions(ListFilter::new())).await {
+        Ok(s) if s.sessions().iter().any(|sd| sd.session_id() == sid) => Ok(()),
+        Ok(_) => Err(format!("session {:?} not listed", sid)),
+        Err(e) => Err(e),
+    });
+    report.check("watch_sessions", match watching {
+        Ok(mut changes) => loop {
+            match tokio::time::timeout(STEP_TIMEOUT, changes.recv()).await {
+                Ok(Some(LobbyChange::Created(sd))) if sd.session_id() == sid => {
+                    break if sd.invite_code().is_empty() {
+                        Ok(())
+                    } else {
+                        Err("invite code sent to everyone watching".to_owned())
+                    };
+                }
+                Ok(Some(_)) => continue,
+                Ok(None) => break Err("watch ended before the session was hosted".to_owned()),
+                Err(_) => break Err(format!("session {:?} never seen while watching", sid)),
+            }
+        },
+        Err(e) => Err(e),
+    });
+    // servers don't have to offer any presets
+    report.check("host_from_preset", match step(STEP_TIMEOUT, a.list_presets()).await {
+        Ok(presets) => match presets.first() {
+            Some(p) => match step(STEP_TIMEOUT, a.host_from_preset(p.name(), ua)).await {
+                Ok(sd) if sd.max_players() != p.settings().max_players() =>
+                    Err(format!("preset {} hosted for {} players", p.name(), sd.max_players())),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            },
+            None => Ok(()),
+        },
+        Err(e) => Err(e),
+    });
+    report.check("host_unknown_preset",
+                 refused(a.host_from_preset("no such preset", ua)).await);
+    let range = HostInfo::new(SessionType::Coin, 2).with_host(ua).with_min_players(3);
+    report.check("host_player_range", refused(a.host_session(range)).await);
+    let private = HostInfo::new(SessionType::Coin, 2).with_host(ua)
+        .with_visibility(Visibility::Private);
+    report.check("private_needs_password", refused(a.host_session(private)).await);
+    let unlisted = HostInfo::new(SessionType::Coin, 2).with_host(ua)
+        .with_visibility(Visibility::Unlisted);
+    report.check("list_unlisted", match step(STEP_TIMEOUT, a.host_session(unlisted)).await {
+        Ok(hidden) => match step(STEP_TIMEOUT, a.list_sessions(ListFilter::new())).await {
+            Ok(s) if s.sessions().iter().any(|sd| sd.session_id() == hidden.session_id()) =>
+                Err(format!("unlisted session {:?} listed", hidden.session_id())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    });
+    let dice = ListFilter::new().with_session_type(SessionType::Dice);
+    report.check("list_filter", match step(STEP_TIMEOUT, a.list_sessions(dice)).await {
+        Ok(s) if s.sessions().iter().any(|sd| sd.session_id() == sid) =>
+            Err(format!("coin session {:?} listed as a dice session", sid)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    });
+
+    // joining
+    let joined = match step(STEP_TIMEOUT, a.join_session(JoinInfo::new(sid, ua, "a"))).await {
+        Ok(_) => step(STEP_TIMEOUT, b.join_session(JoinInfo::new(sid, ub, "b"))).await,
+        Err(e) => Err(e),
+    };
+    let joined_ok = joined.is_ok();
+    report.check("join_session", joined);
+    if joined_ok {
+        report.check("get_session", match step(STEP_TIMEOUT, c.get_session(sid)).await {
+            Ok(d) if d.status() != SessionStatus::Waiting =>
+                Err(format!("lobby reported as {:?}", d.status())),
+            Ok(d) if !(d.has_player(ua) && d.has_player(ub) && d.players().len() == 2) =>
+                Err("players don't match who joined".to_owned()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        });
+    }
+    report.check("join_twice", refused(b.join_session(JoinInfo::new(sid, ub, "b"))).await);
+    if joined_ok {
+        report.check("join_full", refused(c.join_session(JoinInfo::new(sid, uc, "c"))).await);
+    }
+    report.check("join_unknown",
+                 refused(c.join_session(JoinInfo::new(SessionID(u64::MAX), uc, "c"))).await);
+    report.check("start_outsider", refused(c.start_session(sid, uc)).await);
+    let hi = HostInfo::new(SessionType::Coin, 3).with_host(ua).with_auto_start(false);
+    report.check("join_name_taken", match step(STEP_TIMEOUT, a.host_session(hi)).await {
+        Ok(sd) => {
+            let named = async {
+                a.join_session(JoinInfo::new(sd.session_id(), ua, "a")).await?;
+                b.join_session(JoinInfo::new(sd.session_id(), ub, "a")).await
+            };
+            match step(STEP_TIMEOUT, named).await {
+                Ok(_) => Err("two players joined with the same name".to_owned()),
+                Err(e) if e == format!("{}", Error::NameTaken) => Ok(()),
+                Err(e) => Err(format!("refused with {}, expected {}", e, Error::NameTaken)),
+            }
+        }
+        Err(e) => Err(e),
+    });
+    let hi = HostInfo::new(SessionType::Coin, 2).with_host(ua).with_auto_start(false);
+    report.check("join_blank_name", match step(STEP_TIMEOUT, a.host_session(hi)).await {
+        Ok(sd) => {
+            let blank = Error::InvalidName(NameError::Empty);
+            let ji = JoinInfo::new(sd.session_id(), uc, "  ");
+            match step(STEP_TIMEOUT, c.join_session(ji)).await {
+                Ok(_) => Err("joined with a blank name".to_owned()),
+                Err(e) if e == format!("{}", blank) => Ok(()),
+                Err(e) => Err(format!("refused with {}, expected {}", e, blank)),
+            }
+        }
+        Err(e) => Err(e),
+    });
+    let private = HostInfo::new(SessionType::Coin, 3).with_host(ua).with_auto_start(false)
+        .with_visibility(Visibility::Private).with_password(Some("secret".to_owned()));
+    report.check("join_token", match step(STEP_TIMEOUT, a.host_session(private)).await {
+        Ok(sd) => {
+            let sid = sd.session_id();
+            let used = async {
+                let jt = a.create_join_token(sid, ua, None).await?;
+                let ji = JoinInfo::new(sid, uc, "c").with_join_token(jt.token());
+                c.join_session(ji).await.map(|_| jt)
+            };
+            match step(STEP_TIMEOUT, used).await {
+                Ok(jt) => {
+                    let again = JoinInfo::new(sid, ub, "b").with_join_token(jt.token());
+                    refused(b.join_session(again)).await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    });
+
+    // friends
+    report.check("invite_stranger", refused(a.send_invite(sid, ua, uc)).await);
+    let befriended = async {
+        a.add_friend(ua, uc).await?;
+        let one_way = a.friends(ua).await?;
+        c.add_friend(uc, ua).await?;
+        Ok((one_way, a.friends(ua).await?))
+    };
+    report.check("friends", match step(STEP_TIMEOUT, befriended).await {
+        Ok((one_way, _)) if one_way.iter().any(|f| f.user_id() == uc && f.mutual()) =>
+            Err("friend marked as mutual before adding back".to_owned()),
+        Ok((_, both)) if both.iter().any(|f| f.user_id() == uc && f.mutual()) => Ok(()),
+        Ok(_) => Err("friend missing or not mutual after both added".to_owned()),
+        Err(e) => Err(e),
+    });
+
+    // playing a game through
+    if joined_ok {
+        let ra = Arc::new(Recorder::default());
+        let rb = Arc::new(Recorder::default());
+        let listening = async {
+            a.server_events_listen(sid, ua, ra.clone(), 0).await?;
+            b.server_events_listen(sid, ub, rb.clone(), 0).await
+        };
+        match step(STEP_TIMEOUT, listening).await {
+            Ok(hb) => {
+                report.check("online_users", match step(STEP_TIMEOUT, c.online_users()).await {
+                    Ok(users) if users.iter().any(|u| u.user_id() == ua)
+                        && users.iter().any(|u| u.user_id() == ub) => Ok(()),
+                    Ok(users) => Err(format!("listening players missing from {:?}", users)),
+                    Err(e) => Err(e),
+                });
+                let played = step(GAME_TIMEOUT, a.start_session(sid, ua)).await;
+                let played_ok = played.is_ok();
+                report.check("game", played);
+                if played_ok {
+                    report.check("game_order_host", game_order(&ra.game_events().await));
+                    report.check("game_order_player", game_order(&rb.game_events().await));
+                    report.check("get_session_finished",
+                                 match step(STEP_TIMEOUT, c.get_session(sid)).await {
+                        Ok(d) if d.status() == SessionStatus::Finished => Ok(()),
+                        Ok(d) => Err(format!("finished game reported as {:?}", d.status())),
+                        Err(e) => Err(e),
+                    });
+                    report.check("profile", match step(STEP_TIMEOUT, c.get_profile(ua)).await {
+                        Ok(p) if p.games_played() == 0 =>
+                            Err("finished game left out of the profile".to_owned()),
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(e),
+                    });
+                    report.check("presence_changed", if ra.events().contains(&"presence_changed") {
+                        Ok(())
+                    } else {
+                        Err("host never told the player came online".to_owned())
+                    });
+
+                    // the player turns the rematch down, so only the host is seated
+                    report.check("rematch", match step(STEP_TIMEOUT, a.rematch(sid, ua)).await {
+                        Ok(sd) if !rb.events().contains(&"rematch") =>
+                            Err(format!("player never offered rematch {:?}", sd.session_id())),
+                        Ok(sd) if sd.users() != ["a"] =>
+                            Err(format!("rematch seated {:?}, expected only a", sd.users())),
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(e),
+                    });
+                    report.check("rematch_reason", closed(hb, CloseReason::Finished).await);
+                }
+            }
+            Err(e) => report.check("play_session", Err(e)),
+        }
+    }
+
+    // handing over the host, kicking and cancelling end streams with a reason
+    let hi = HostInfo::new(SessionType::Dice, 3).with_host(ua).with_auto_start(false);
+    match step(STEP_TIMEOUT, a.host_session(hi)).await {
+        Ok(sd) => {
+            let sid = sd.session_id();
+            let setup = async {
+                a.join_session(JoinInfo::new(sid, ua, "a")).await?;
+                c.join_session(JoinInfo::new(sid, uc, "c")).await?;
+                let ha = a.server_events_listen(sid, ua, Arc::new(Recorder::default()), 0).await?;
+                let hc = c.server_events_listen(sid, uc, Arc::new(Recorder::default()), 0).await?;
+                Ok((ha, hc))
+            };
+            match step(STEP_TIMEOUT, setup).await {
+                Ok((ha, hc)) => {
+                    // votes to kick only count once a game is being played
+                    report.check("vote_kick_lobby", refused(a.vote_kick(sid, ua, uc)).await);
+                    report.check("pause_lobby", refused(a.pause_session(sid, ua)).await);
+                    // only the current host can remove players
+                    match step(STEP_TIMEOUT, a.transfer_host(sid, ua, uc)).await {
+                        Ok(_) => {
+                            report.check("kick_former_host",
+                                         refused(a.kick_user(sid, ua, uc)).await);
+                            report.check("transfer_host_back",
+                                         step(STEP_TIMEOUT, c.transfer_host(sid, uc, ua)).await);
+                        }
+                        Err(e) => report.check("transfer_host", Err(e)),
+                    }
+                    match step(STEP_TIMEOUT, a.kick_user(sid, ua, uc)).await {
+                        Ok(_) => report.check("kick_reason", closed(hc, CloseReason::Kicked).await),
+                        Err(e) => report.check("kick_user", Err(e)),
+                    }
+                    // banned players can't come back until they are unbanned
+                    match step(STEP_TIMEOUT, a.ban_user(sid, ua, uc)).await {
+                        Ok(_) => {
+                            let rejoin = c.join_session(JoinInfo::new(sid, uc, "c"));
+                            report.check("join_banned", refused(rejoin).await);
+                            let unbanned = async {
+                                a.unban_user(sid, ua, uc).await?;
+                                c.join_session(JoinInfo::new(sid, uc, "c")).await
+                            };
+                            report.check("unban_user", step(STEP_TIMEOUT, unbanned).await);
+                        }
+                        Err(e) => report.check("ban_user", Err(e)),
+                    }
+                    match step(STEP_TIMEOUT, a.cancel_session(sid, ua)).await {
+                        Ok(_) => report.check("cancel_reason",
+                                              closed(ha, CloseReason::Cancelled).await),
+                        Err(e) => report.check("cancel_session", Err(e)),
+                    }
+                }
+                Err(e) => report.check("stream_setup", Err(e)),
+            }
+        }
+        Err(e) => report.check("host_session_again", Err(e)),
+    }
+
+    // a scheduled session nobody else joins is cancelled at its start
+    let past = HostInfo::new(SessionType::Coin, 2).with_host(ua)
+        .with_start_at(SystemTime::now() - Duration::from_secs(60));
+    report.check("schedule_past", refused(a.host_session(past)).await);
+    let hi = HostInfo::new(SessionType::Coin, 2).with_host(ua).with_min_players(2)
+        .with_start_at(SystemTime::now() + Duration::from_secs(2));
+    match step(STEP_TIMEOUT, a.host_session(hi)).await {
+        Ok(sd) => {
+            let sid = sd.session_id();
+            let setup = async {
+                a.join_session(JoinInfo::new(sid, ua, "a")).await?;
+                a.server_events_listen(sid, ua, Arc::new(Recorder::default()), 0).await
+            };
+            match step(STEP_TIMEOUT, setup).await {
+                Ok(ha) => report.check("schedule_undersubscribed",
+                                       closed(ha, CloseReason::Cancelled).await),
+                Err(e) => report.check("schedule_setup", Err(e)),
+            }
+        }
+        Err(e) => report.check("schedule_session", Err(e)),
+    }
+
+    // one with enough players starts by itself, without waiting on anyone
+    let hi = HostInfo::new(SessionType::Coin, 2).with_host(ua).with_min_players(2)
+        .with_start_at(SystemTime::now() + Duration::from_secs(2));
+    match step(STEP_TIMEOUT, a.host_session(hi)).await {
+        Ok(sd) => {
+            let sid = sd.session_id();
+            let setup = async {
+                a.join_session(JoinInfo::new(sid, ua, "a")).await?;
+                b.join_session(JoinInfo::new(sid, ub, "b")).await?;
+                a.server_events_listen(sid, ua, Arc::new(Recorder::default()), 0).await?;
+                b.server_events_listen(sid, ub, Arc::new(Recorder::default()), 0).await?;
+                a.start_session(sid, ua).await
+            };
+            match step(Duration::from_secs(1), setup).await {
+                Ok(_) => {
+                    let finished = async {
+                        loop {
+                            let d = c.get_session(sid).await?;
+                            if d.status() == SessionStatus::Finished {
+                                return Ok(());
+                            }
+                            tokio::time::sleep(Duration::from_millis(250)).await;
+                        }
+                    };
+                    report.check("schedule_started", step(GAME_TIMEOUT, finished).await);
+                }
+                Err(e) => report.check("schedule_early_start", Err(e)),
+            }
+        }
+        Err(e) => report.check("schedule_full_session", Err(e)),
+    }
+
+    Ok(report)
+}
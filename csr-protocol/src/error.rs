@@ -1,15 +1,98 @@
+use crate::types::{ClientAction, CloseReason, ErrorCode};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Client disconnected")]
     ClientDisconnected,
     #[error("Client error {0:?}")]
     ClientError(String),
+    #[error("The server took too long to answer")]
+    DeadlineExceeded,
+    #[error("Banned from the server")]
+    Banned,
+    #[error("Guests can't host sessions, register to host")]
+    GuestCannotHost,
+    #[error("Invalid name, {0}")]
+    InvalidName(NameError),
+    #[error("Invalid account ban")]
+    InvalidAccountBan,
     #[error("Invalid session type")]
     InvalidSessionType,
     #[error("Invalid coin value")]
     InvalidCoinValue,
+    #[error("Invalid dice sides {0}")]
+    InvalidDiceSides(u32),
+    #[error("Invalid dice count {0}")]
+    InvalidDiceCount(u32),
+    #[error("Invalid coin count {0}")]
+    InvalidCoinCount(u32),
+    #[error("Invalid series length {0}")]
+    InvalidSeriesLength(u32),
+    #[error("Invalid preset")]
+    InvalidPreset,
+    #[error("Invalid session details")]
+    InvalidSessionDetails,
+    #[error("Invalid lobby change")]
+    InvalidLobbyChange,
     #[error("Invalid server request")]
     InvalidServerRequest,
     #[error("Invalid client response")]
     InvalidClientResponse,
+    #[error("The name is already taken in the session, pick another")]
+    NameTaken,
+    #[error("Too many requests, slow down")]
+    RateLimited,
+    // a call the server refused, with what went wrong and what to do about it
+    #[error("{2}")]
+    Refused(ErrorCode, ClientAction, String),
+    #[error("Server did not issue a reconnect token")]
+    MissingReconnectToken,
+    #[error("Answered request {0} after the server stopped waiting on it")]
+    StaleResponse(u64),
+    #[error("Server closed the event stream: {0:?}")]
+    StreamClosed(CloseReason),
+    #[error("Not authenticated, register with the server first")]
+    Unauthenticated,
+    #[error("Unknown compression {0}, expected gzip or zstd")]
+    UnknownCompression(String),
+    #[error("Unknown address {0}, expected host:port")]
+    UnknownAddress(String),
+}
+
+impl Error {
+    // the code sent to clients for the error, and what they can do about it
+    pub fn guidance(&self) -> (ErrorCode, ClientAction) {
+        match self {
+            Error::ClientDisconnected => (ErrorCode::ClientUnreachable, ClientAction::Reconnect),
+            Error::InvalidClientResponse | Error::InvalidServerRequest =>
+                (ErrorCode::InvalidResponse, ClientAction::UpgradeClient),
+            Error::Banned => (ErrorCode::Banned, ClientAction::None),
+            Error::GuestCannotHost => (ErrorCode::GuestCannotHost, ClientAction::None),
+            Error::InvalidName(_) => (ErrorCode::InvalidName, ClientAction::RetryJoin),
+            Error::NameTaken => (ErrorCode::NameTaken, ClientAction::RetryJoin),
+            Error::RateLimited => (ErrorCode::RateLimited, ClientAction::None),
+            Error::Unauthenticated => (ErrorCode::Unauthenticated, ClientAction::None),
+            Error::InvalidAccountBan | Error::InvalidSessionType | Error::InvalidCoinValue
+                | Error::InvalidDiceSides(_) | Error::InvalidDiceCount(_)
+                | Error::InvalidCoinCount(_) | Error::InvalidSeriesLength(_) | Error::InvalidPreset
+                | Error::InvalidSessionDetails | Error::InvalidLobbyChange =>
+                (ErrorCode::InvalidRequest, ClientAction::None),
+            Error::StaleResponse(_) => (ErrorCode::StaleResponse, ClientAction::None),
+            Error::Refused(code, action, _) => (*code, *action),
+            _ => (ErrorCode::Unspecified, ClientAction::None),
+        }
+    }
+}
+
+// why a name was turned away
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum NameError {
+    #[error("it is blank")]
+    Empty,
+    #[error("it is longer than {0} characters")]
+    TooLong(usize),
+    #[error("it has control characters in it")]
+    ControlCharacters,
+    #[error("it isn't allowed on this server")]
+    Filtered,
 }
@@ -1,13 +1,26 @@
+#[cfg(feature = "server")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "server")]
 use std::sync::Arc;
 
-use tokio::sync::mpsc::{Receiver, Sender};
+#[cfg(feature = "server")]
+use tokio::sync::mpsc::{Receiver, Sender, WeakSender};
+#[cfg(feature = "server")]
 use tokio::sync::Mutex;
 
+#[cfg(feature = "server")]
 use crate::error::Error;
 use crate::types::Result;
 use crate::types::{
-    ClientResponse, Coin, FlipCoin, JoinInfo, Ping, RollDice, ServerRequest,
-    SessionID, UserID, Winner,
+    ClientAction, Coin, CoinCount, DiceCount, DiceSides, ErrorCode, Invitation, Score, ScoreEntry,
+    SessionData, SessionID, TeamID, UserID,
+};
+// what the server sends and hears back, only needed to serve
+#[cfg(feature = "server")]
+use crate::types::{
+    Champion, ChatMessage, ClientResponse, CloseReason, DirectMessage, FlipCoin, GameEnded,
+    JoinInfo, PresenceChange, Ping, PlayerReady, RollDice, RoundResult, Scoreboard, ServerError,
+    ServerRequest, TeamRoundResult, TeamWinner, WaitingForPlayer, Winner,
 };
 
 #[tonic::async_trait]
@@ -15,86 +28,325 @@ pub trait ServerEvent: Send + Sync + 'static {
     async fn join_info(&self, sid: SessionID, uid: UserID, user_name: &str)
         -> Result<()>;
     async fn ping(&self, ping: &str) -> Result<String>;
-    async fn roll_dice(&self, sides: u8, count: u8) -> Result<Vec<u8>>;
-    async fn flip_coin(&self, count: u8) -> Result<Vec<Coin>>;
-    async fn winner(&self, uid: UserID, name: &str) -> Result<()>;
+    async fn roll_dice(&self, sides: DiceSides, count: DiceCount)
+        -> Result<Vec<u8>>;
+    async fn flip_coin(&self, count: CoinCount) -> Result<Vec<Coin>>;
+    async fn winner(&self, uid: UserID, name: &str, payout: u64) -> Result<()>;
     async fn try_again(&self) -> Result<bool>;
-    async fn error(&self, err: &str) -> Result<()>;
+    async fn error(&self, code: ErrorCode, action: ClientAction, message: &str)
+        -> Result<()>;
+    async fn prompt(&self, text: &str) -> Result<String>;
+    async fn round_result(&self, round: u8, uid: UserID, name: &str, wins: u8)
+        -> Result<()>;
+    async fn team_winner(&self, team: TeamID, members: &[String], payout: u64)
+        -> Result<()>;
+    async fn team_round_result(&self, round: u8, team: TeamID, wins: u8)
+        -> Result<()>;
+    async fn scoreboard(&self, round: u32, entries: &[ScoreEntry]) -> Result<()>;
+    async fn champion(&self, uid: UserID, name: &str, score: Score) -> Result<()>;
+    async fn session_expired(&self, sid: SessionID) -> Result<()>;
+    async fn user_left(&self, sid: SessionID, uid: UserID, user_name: &str)
+        -> Result<()>;
+    async fn chat(&self, sid: SessionID, uid: UserID, user_name: &str, text: &str)
+        -> Result<()>;
+    async fn user_kicked(&self, sid: SessionID, uid: UserID, user_name: &str)
+        -> Result<()>;
+    async fn direct_message(&self, from: UserID, from_name: &str, to: UserID,
+                            text: &str) -> Result<()>;
+    async fn session_cancelled(&self, sid: SessionID) -> Result<()>;
+    async fn session_updated(&self, sd: &SessionData) -> Result<()>;
+    async fn join_request(&self, sid: SessionID, uid: UserID, user_name: &str)
+        -> Result<()>;
+    async fn waiting_for_player(&self, uid: UserID, name: &str) -> Result<()>;
+    async fn rematch(&self, sd: &SessionData) -> Result<bool>;
+    async fn ready_check(&self) -> Result<bool>;
+    async fn player_ready(&self, uid: UserID, name: &str, ready: bool) -> Result<()>;
+    async fn game_over(&self, sid: SessionID) -> Result<()>;
+    async fn host_changed(&self, sid: SessionID, uid: UserID, user_name: &str)
+        -> Result<()>;
+    async fn countdown(&self, secs: u32) -> Result<()>;
+    async fn game_paused(&self, sid: SessionID) -> Result<()>;
+    async fn game_resumed(&self, sid: SessionID) -> Result<()>;
+    async fn game_started(&self, sd: &SessionData) -> Result<()>;
+    async fn game_ended(&self, sid: SessionID, scores: &[ScoreEntry]) -> Result<()>;
+    async fn start_reminder(&self, secs: u64) -> Result<()>;
+    async fn invitation(&self, inv: &Invitation) -> Result<()>;
+    async fn presence_changed(&self, uid: UserID, name: &str, online: bool) -> Result<()>;
 }
 
+// requests the client doesn't answer are sent without an ID
+#[cfg(feature = "server")]
+const UNANSWERED: u64 = 0;
+
+// each request is sent with its ID, and each answer comes back with the ID of
+// the request it answers
+#[cfg(feature = "server")]
 pub struct ServerEventSender {
-    tx: Sender<ServerRequest>,
-    rx: Arc<Mutex<Receiver<ClientResponse>>>,
+    tx: Sender<(u64, ServerRequest)>,
+    rx: Arc<Mutex<Receiver<(u64, ClientResponse)>>>,
+    next_id: AtomicU64,
 }
 
+#[cfg(feature = "server")]
 impl ServerEventSender {
-    pub fn new(tx: Sender<ServerRequest>, rx: Receiver<ClientResponse>) -> Self {
+    pub fn new(tx: Sender<(u64, ServerRequest)>, rx: Receiver<(u64, ClientResponse)>) -> Self {
         Self {
             tx: tx,
             rx: Arc::new(Mutex::new(rx)),
+            next_id: AtomicU64::new(1),
         }
     }
 
-    // wait for client messages
-    async fn poll(&self) -> Result<ClientResponse> {
-        let r = self.rx.lock().await.recv().await.ok_or_else(
-            || Error::ClientDisconnected)?;
-        match r {
-            ClientResponse::ClientError(e) => {
-                return Err(Box::new(Error::ClientError(e)));
+    // false once the client's event stream has closed
+    pub fn is_connected(&self) -> bool {
+        !self.tx.is_closed()
+    }
+
+    // the last message before the stream ends, once this sender is dropped
+    pub async fn stream_closing(&self, reason: CloseReason) -> Result<()> {
+        self.notify(ServerRequest::StreamClosing(reason)).await
+    }
+
+    pub fn notifier(&self) -> ServerEventNotifier {
+        ServerEventNotifier {
+            tx: self.tx.downgrade(),
+        }
+    }
+
+    async fn notify(&self, sr: ServerRequest) -> Result<()> {
+        Ok(self.tx.send((UNANSWERED, sr)).await?)
+    }
+
+    // send a request and wait for the client's answer to it, one request at a
+    // time. Late answers to requests that were given up on are turned down,
+    // rather than taken as the answer to this one
+    async fn ask(&self, sr: ServerRequest) -> Result<ClientResponse> {
+        let mut rx = self.rx.lock().await;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.tx.send((id, sr)).await?;
+        loop {
+            let (answered, r) = rx.recv().await.ok_or_else(|| Error::ClientDisconnected)?;
+            // older clients don't send the ID back, and answer in order
+            if answered != id && answered != UNANSWERED {
+                warn!("Dropping a late answer to request {} while waiting on {}", answered, id);
+                let stale = Error::StaleResponse(answered);
+                let (code, action) = stale.guidance();
+                self.error(code, action, &format!("{}", stale)).await?;
+                continue;
+            }
+            match r {
+                ClientResponse::ClientError(e) => {
+                    return Err(Box::new(Error::ClientError(e)));
+                }
+                _ => { return Ok(r); }
             }
-            _ => Ok(r),
         }
     }
 }
 
+// sends one way messages to a client, outside of any session. Holding a
+// notifier doesn't keep the client's event stream open
+#[cfg(feature = "server")]
+#[derive(Clone)]
+pub struct ServerEventNotifier {
+    tx: WeakSender<(u64, ServerRequest)>,
+}
+
+#[cfg(feature = "server")]
+impl ServerEventNotifier {
+    // the sender outlives the stream while the server still holds it, so
+    // check the stream itself too
+    pub fn is_connected(&self) -> bool {
+        self.tx.upgrade().is_some_and(|tx| !tx.is_closed())
+    }
+
+    pub async fn direct_message(&self, from: UserID, from_name: &str, to: UserID,
+                                text: &str) -> Result<()> {
+        let tx = self.tx.upgrade().ok_or_else(|| Error::ClientDisconnected)?;
+        let dm = DirectMessage::new(from, from_name, to, text);
+        Ok(tx.send((UNANSWERED, ServerRequest::DirectMessage(dm))).await?)
+    }
+
+    pub async fn invitation(&self, inv: Invitation) -> Result<()> {
+        let tx = self.tx.upgrade().ok_or_else(|| Error::ClientDisconnected)?;
+        Ok(tx.send((UNANSWERED, ServerRequest::Invitation(inv))).await?)
+    }
+
+    pub async fn presence_changed(&self, uid: UserID, name: &str, online: bool)
+            -> Result<()> {
+        let tx = self.tx.upgrade().ok_or_else(|| Error::ClientDisconnected)?;
+        let pc = PresenceChange::new(uid, name, online);
+        Ok(tx.send((UNANSWERED, ServerRequest::PresenceChanged(pc))).await?)
+    }
+}
+
+#[cfg(feature = "server")]
 #[tonic::async_trait]
 impl ServerEvent for ServerEventSender {
     async fn join_info(&self, sid: SessionID, uid: UserID, user_name: &str)
             -> Result<()> {
         let ji = JoinInfo::new(sid, uid, user_name);
-        Ok(self.tx.send(ServerRequest::JoinInfo(ji)).await?)
+        self.notify(ServerRequest::JoinInfo(ji)).await
     }
     async fn ping(&self, ping: &str) -> Result<String> {
         let p = Ping::new(ping);
-        self.tx.send(ServerRequest::Ping(p)).await?;
-        if let ClientResponse::Pong(p) = self.poll().await? {
+        if let ClientResponse::Pong(p) = self.ask(ServerRequest::Ping(p)).await? {
             return Ok(p.text().to_owned());
         } else {
             return Err(Error::InvalidClientResponse)?;
         }
     }
-    async fn roll_dice(&self, sides: u8, count: u8) -> Result<Vec<u8>> {
+    async fn roll_dice(&self, sides: DiceSides, count: DiceCount)
+            -> Result<Vec<u8>> {
         let r = RollDice::new(sides, count);
-        self.tx.send(ServerRequest::RollDice(r)).await?;
-        if let ClientResponse::DiceGuess(d) = self.poll().await? {
+        if let ClientResponse::DiceGuess(d) = self.ask(ServerRequest::RollDice(r)).await? {
             return Ok(d.number().to_vec());
         } else {
             return Err(Error::InvalidClientResponse)?;
         }
     }
-    async fn flip_coin(&self, count: u8) -> Result<Vec<Coin>> {
+    async fn flip_coin(&self, count: CoinCount) -> Result<Vec<Coin>> {
         let f = FlipCoin::new(count);
-        self.tx.send(ServerRequest::FlipCoin(f)).await?;
-        if let ClientResponse::CoinGuess(c) = self.poll().await? {
+        if let ClientResponse::CoinGuess(c) = self.ask(ServerRequest::FlipCoin(f)).await? {
             return Ok(c.coins().to_vec());
         } else {
             return Err(Error::InvalidClientResponse)?;
         }
     }
-    async fn winner(&self, uid: UserID, name: &str) -> Result<()> {
-        let w = Winner::new(uid, name);
-        Ok(self.tx.send(ServerRequest::Winner(w)).await?)
+    async fn winner(&self, uid: UserID, name: &str, payout: u64) -> Result<()> {
+        let w = Winner::new(uid, name, payout);
+        self.notify(ServerRequest::Winner(w)).await
     }
     async fn try_again(&self) -> Result<bool> {
-        self.tx.send(ServerRequest::TryAgain(true)).await?;
-        if let ClientResponse::Again(a) = self.poll().await? {
+        if let ClientResponse::Again(a) = self.ask(ServerRequest::TryAgain(true)).await? {
+            return Ok(a);
+        } else {
+            return Err(Error::InvalidClientResponse)?;
+        }
+    }
+    async fn error(&self, code: ErrorCode, action: ClientAction, message: &str)
+            -> Result<()> {
+        let se = ServerError::new(code, action, message);
+        self.notify(ServerRequest::ServerError(se)).await
+    }
+    async fn prompt(&self, text: &str) -> Result<String> {
+        if let ClientResponse::Answer(a) = self.ask(ServerRequest::Prompt(text.to_owned())).await? {
+            return Ok(a);
+        } else {
+            return Err(Error::InvalidClientResponse)?;
+        }
+    }
+    async fn round_result(&self, round: u8, uid: UserID, name: &str, wins: u8)
+            -> Result<()> {
+        let rr = RoundResult::new(round, uid, name, wins);
+        self.notify(ServerRequest::RoundResult(rr)).await
+    }
+    async fn team_winner(&self, team: TeamID, members: &[String], payout: u64)
+            -> Result<()> {
+        let tw = TeamWinner::new(team, members, payout);
+        self.notify(ServerRequest::TeamWinner(tw)).await
+    }
+    async fn team_round_result(&self, round: u8, team: TeamID, wins: u8)
+            -> Result<()> {
+        let tr = TeamRoundResult::new(round, team, wins);
+        self.notify(ServerRequest::TeamRoundResult(tr)).await
+    }
+    async fn scoreboard(&self, round: u32, entries: &[ScoreEntry]) -> Result<()> {
+        let sb = Scoreboard::new(round, entries);
+        self.notify(ServerRequest::Scoreboard(sb)).await
+    }
+    async fn champion(&self, uid: UserID, name: &str, score: Score) -> Result<()> {
+        let c = Champion::new(uid, name, score);
+        self.notify(ServerRequest::Champion(c)).await
+    }
+    async fn session_expired(&self, sid: SessionID) -> Result<()> {
+        self.notify(ServerRequest::SessionExpired(sid)).await
+    }
+    async fn user_left(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        let ji = JoinInfo::new(sid, uid, user_name);
+        self.notify(ServerRequest::UserLeft(ji)).await
+    }
+    async fn chat(&self, sid: SessionID, uid: UserID, user_name: &str, text: &str)
+            -> Result<()> {
+        let cm = ChatMessage::new(sid, uid, user_name, text);
+        self.notify(ServerRequest::Chat(cm)).await
+    }
+    async fn user_kicked(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        let ji = JoinInfo::new(sid, uid, user_name);
+        self.notify(ServerRequest::UserKicked(ji)).await
+    }
+    async fn direct_message(&self, from: UserID, from_name: &str, to: UserID,
+                            text: &str) -> Result<()> {
+        self.notifier().direct_message(from, from_name, to, text).await
+    }
+    async fn session_cancelled(&self, sid: SessionID) -> Result<()> {
+        self.notify(ServerRequest::SessionCancelled(sid)).await
+    }
+    async fn session_updated(&self, sd: &SessionData) -> Result<()> {
+        self.notify(ServerRequest::SessionUpdated(sd.clone())).await
+    }
+    async fn join_request(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        let ji = JoinInfo::new(sid, uid, user_name);
+        self.notify(ServerRequest::JoinRequest(ji)).await
+    }
+    async fn waiting_for_player(&self, uid: UserID, name: &str) -> Result<()> {
+        let w = WaitingForPlayer::new(uid, name);
+        self.notify(ServerRequest::WaitingForPlayer(w)).await
+    }
+    async fn rematch(&self, sd: &SessionData) -> Result<bool> {
+        if let ClientResponse::Again(a) = self.ask(ServerRequest::Rematch(sd.clone())).await? {
             return Ok(a);
         } else {
             return Err(Error::InvalidClientResponse)?;
         }
     }
-    async fn error(&self, err: &str) -> Result<()> {
-        Ok(self.tx.send(ServerRequest::ServerError(err.to_owned())).await?)
+    async fn ready_check(&self) -> Result<bool> {
+        if let ClientResponse::Ready(r) = self.ask(ServerRequest::ReadyCheck(true)).await? {
+            return Ok(r);
+        } else {
+            return Err(Error::InvalidClientResponse)?;
+        }
+    }
+    async fn player_ready(&self, uid: UserID, name: &str, ready: bool) -> Result<()> {
+        let pr = PlayerReady::new(uid, name, ready);
+        self.notify(ServerRequest::PlayerReady(pr)).await
+    }
+    async fn game_over(&self, sid: SessionID) -> Result<()> {
+        self.notify(ServerRequest::GameOver(sid)).await
+    }
+    async fn host_changed(&self, sid: SessionID, uid: UserID, user_name: &str)
+            -> Result<()> {
+        let ji = JoinInfo::new(sid, uid, user_name);
+        self.notify(ServerRequest::HostChanged(ji)).await
+    }
+    async fn countdown(&self, secs: u32) -> Result<()> {
+        self.notify(ServerRequest::Countdown(secs)).await
+    }
+    async fn game_paused(&self, sid: SessionID) -> Result<()> {
+        self.notify(ServerRequest::GamePaused(sid)).await
+    }
+    async fn game_resumed(&self, sid: SessionID) -> Result<()> {
+        self.notify(ServerRequest::GameResumed(sid)).await
+    }
+    async fn game_started(&self, sd: &SessionData) -> Result<()> {
+        self.notify(ServerRequest::GameStarted(sd.clone())).await
+    }
+    async fn game_ended(&self, sid: SessionID, scores: &[ScoreEntry]) -> Result<()> {
+        let ge = GameEnded::new(sid, scores);
+        self.notify(ServerRequest::GameEnded(ge)).await
+    }
+    async fn start_reminder(&self, secs: u64) -> Result<()> {
+        self.notify(ServerRequest::StartReminder(secs)).await
+    }
+    async fn invitation(&self, inv: &Invitation) -> Result<()> {
+        let inv = Invitation::new(inv.from_id(), inv.from_name(), inv.to_id(), inv.session_id())
+            .with_join_token(inv.join_token());
+        self.notifier().invitation(inv).await
+    }
+    async fn presence_changed(&self, uid: UserID, name: &str, online: bool) -> Result<()> {
+        self.notifier().presence_changed(uid, name, online).await
     }
 }
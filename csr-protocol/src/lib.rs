@@ -1,11 +1,29 @@
-#[macro_use] extern crate log;
+#[cfg_attr(any(feature = "client", feature = "server"), macro_use)]
+extern crate log;
 
+#[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "conformance")]
+pub mod conformance;
 pub mod error;
 pub mod event;
+#[cfg(all(feature = "client", feature = "server"))]
+pub mod local;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "server")]
 pub mod server;
 pub mod types;
 
+// each version of the protocol has its own package, and the crate is written
+// against the latest. Built with only the types, the messages are just
+// converted to and from
+#[cfg_attr(not(any(feature = "client", feature = "server")), allow(dead_code))]
 mod clean {
-    tonic::include_proto!("clean");
+    pub mod v1 {
+        tonic::include_proto!("clean.v1");
+    }
+    pub use self::v1::*;
 }
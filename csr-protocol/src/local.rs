@@ -0,0 +1,64 @@
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::transport::{Endpoint, Server, Uri};
+use tower::service_fn;
+
+use crate::client::CleanClient;
+use crate::server::{Clean, CleanServerBuilder};
+use crate::types::Result;
+use crate::types::AdminToken;
+
+// how much each direction of a pipe holds before its writer waits
+const PIPE_SIZE: usize = 64 * 1024;
+
+// a server running in the same process as its clients, which reach it over
+// in-memory pipes instead of the network. For tests, and for single binaries
+// holding both the server and a client. It serves until it and every client
+// connected to it are dropped
+#[derive(Clone)]
+pub struct LocalServer {
+    pipes: mpsc::UnboundedSender<DuplexStream>,
+}
+
+impl LocalServer {
+    pub fn serve(server: Arc<dyn Clean>, admin: Option<AdminToken>) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let clean = CleanServerBuilder::new(server).with_admin_token(admin);
+        let incoming = UnboundedReceiverStream::new(rx).map(Ok::<_, std::io::Error>);
+        tokio::spawn(async move {
+            let served = clean.router(Server::builder())
+                .serve_with_incoming(incoming)
+                .await;
+            if let Err(e) = served {
+                error!("Local server stopped: {:?}", e);
+            }
+        });
+        Self {
+            pipes: tx,
+        }
+    }
+
+    // a client with its own connection to the server, opening a new pipe
+    // whenever it reconnects
+    pub async fn connect(&self) -> Result<CleanClient> {
+        let pipes = self.pipes.clone();
+        // the address is never dialled
+        let channel = Endpoint::from_static("http://localhost")
+            .connect_with_connector(service_fn(move |_: Uri| {
+                let (client, server) = tokio::io::duplex(PIPE_SIZE);
+                let sent = pipes.send(server).map_err(|_| {
+                    std::io::Error::new(ErrorKind::NotConnected, "Local server stopped")
+                });
+                async move {
+                    sent?;
+                    Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(client))
+                }
+            })).await?;
+        Ok(CleanClient::from_channel(channel))
+    }
+}
@@ -0,0 +1,102 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use quinn::{RecvStream, SendStream};
+#[cfg(feature = "server")]
+use quinn::{Endpoint, ServerConfig};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+#[cfg(feature = "server")]
+use tokio::sync::mpsc;
+#[cfg(feature = "server")]
+use tokio_stream::wrappers::ReceiverStream;
+#[cfg(feature = "server")]
+use tonic::transport::server::Connected;
+
+#[cfg(feature = "server")]
+use crate::types::Result;
+
+// connections a listener has accepted streams on but the server hasn't taken
+#[cfg(feature = "server")]
+const BACKLOG: usize = 64;
+
+// both halves of a QUIC stream, which gRPC runs over as it would a TCP
+// connection. Each client connection has its own stream, so a packet lost on
+// one doesn't hold up any other
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicStream {
+    pub fn new(send: SendStream, recv: RecvStream) -> Self {
+        Self {
+            send: send,
+            recv: recv,
+        }
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>)
+            -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+            -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+    }
+}
+
+#[cfg(feature = "server")]
+impl Connected for QuicStream {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+// listens for QUIC on the address, serving TLS with the PEM encoded
+// certificate and its private key. Every stream a client opens is handed to
+// the server as a connection of its own, for serve_with_incoming
+#[cfg(feature = "server")]
+pub fn listen(addr: std::net::SocketAddr, cert: &[u8], key: &[u8])
+        -> Result<ReceiverStream<io::Result<QuicStream>>> {
+    let certs = rustls_pemfile::certs(&mut &cert[..]).collect::<io::Result<Vec<_>>>()?;
+    let key = rustls_pemfile::private_key(&mut &key[..])?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No private key"))?;
+    let config = ServerConfig::with_single_cert(certs, key)?;
+    let endpoint = Endpoint::server(config, addr)?;
+    let (tx, rx) = mpsc::channel(BACKLOG);
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let connection = match incoming.await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        debug!("QUIC handshake failed: {:?}", e);
+                        return;
+                    }
+                };
+                // the client is gone once it stops opening streams
+                while let Ok((send, recv)) = connection.accept_bi().await {
+                    if tx.send(Ok(QuicStream::new(send, recv))).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    Ok(ReceiverStream::new(rx))
+}
@@ -1,35 +1,544 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use tonic::{Request, Response, Status};
+use tonic::codegen::{http, BoxFuture, Service};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::server::NamedService;
+use tonic::service::Interceptor;
+use prost::Message;
+use tonic::transport::server::{Router, TcpConnectInfo};
+#[cfg(feature = "tls")]
+use tonic::transport::server::TlsConnectInfo;
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status, Streaming};
 use tokio::sync::Mutex;
-use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::wrappers::ReceiverStream;
+use tower::layer::util::{Identity, Stack};
+use tower::Layer;
 
 use crate::clean;
+use crate::error::Error;
 use crate::event::ServerEventSender;
 use crate::types::Result;
 use crate::types::{
-    ClientResponse, EventRegister, HostInfo, JoinInfo, SessionData, SessionID,
-    SessionType, StartInfo, UserID,
+    AccountBan, AccountType, AdminToken, AuthToken, BalanceInfo, BlockInfo, CancelInfo, ChatMessage,
+    ClientAction, ClientResponse, Compression, DirectMessage, ErrorCode, EventRegister, Friend,
+    FriendInfo, HostInfo, Invitation, JoinAnswer, JoinInfo, JoinToken, KickInfo, LeaveInfo,
+    ListFilter, LobbyChange, OnlineUser, PeerIdentity, Preset, Profile, RateKey, ReconnectToken,
+    RematchInfo, Requester, ServerRequest, ServerStats, SessionChanges, SessionData, SessionDetails,
+    SessionID, Sessions, StartInfo, TeamID, TransferInfo, UserDataExport, UserID, VoteKickInfo,
+    ADMIN_TOKEN_KEY, AUTH_TOKEN_KEY, BEARER_KEY, RECONNECT_TOKEN_KEY,
 };
 
-pub fn make_server(server: impl Clean)
-        -> clean::clean_server::CleanServer<CleanServer> {
+// the most requests coalesced into one stream message
+const MAX_BATCH: usize = 64;
+
+pub type UserService =
+    InterceptedService<clean::clean_server::CleanServer<CleanServer>, Authenticator>;
+pub type AdminService =
+    InterceptedService<clean::clean_admin_server::CleanAdminServer<AdminServer>, AdminGate>;
+
+// the service users call, and the admin service beside it, both backed by the
+// same server. Each accepts requests compressed with any of the encodings, and
+// compresses its responses with them for clients that ask. Without an admin
+// token, users can only manage their own data
+pub fn make_server(server: Arc<dyn Clean>, admin: Option<AdminToken>,
+                   compression: &[Compression])
+        -> (UserService, AdminService) {
+    make_hooked(server, admin, compression, Hooks::default())
+}
+
+fn make_hooked(server: Arc<dyn Clean>, admin: Option<AdminToken>, compression: &[Compression],
+               hooks: Hooks)
+        -> (UserService, AdminService) {
     let s = CleanServer::new(server);
-    clean::clean_server::CleanServer::new(s)
+    let auth = Authenticator {
+        server: s.server.clone(),
+        admin: admin.clone(),
+        hooks: hooks.clone(),
+    };
+    let a = AdminServer {
+        server: s.server.clone(),
+    };
+    let mut user = clean::clean_server::CleanServer::new(s);
+    let mut operator = clean::clean_admin_server::CleanAdminServer::new(a);
+    for c in compression {
+        let encoding = (*c).into();
+        user = user.accept_compressed(encoding).send_compressed(encoding);
+        operator = operator.accept_compressed(encoding).send_compressed(encoding);
+    }
+    (InterceptedService::new(user, auth),
+     InterceptedService::new(operator, AdminGate { admin: admin, hooks: hooks }))
+}
+
+// sets up the services a server answers on, so binaries only have to add
+// their own interceptors, layers and transport settings
+#[derive(Clone)]
+pub struct CleanServerBuilder<L = Identity> {
+    server: Arc<dyn Clean>,
+    admin: Option<AdminToken>,
+    compression: Vec<Compression>,
+    hooks: Vec<Hook>,
+    layer: L,
+}
+
+impl CleanServerBuilder {
+    pub fn new(server: Arc<dyn Clean>) -> Self {
+        Self {
+            server: server,
+            admin: None,
+            compression: Vec::new(),
+            hooks: Vec::new(),
+            layer: Identity::new(),
+        }
+    }
+}
+
+impl<L> CleanServerBuilder<L> {
+    // without one the admin service refuses everything
+    pub fn with_admin_token(mut self, admin: Option<AdminToken>) -> Self {
+        self.admin = admin;
+        self
+    }
+
+    // the encodings requests can be compressed with, which responses are
+    // compressed with for clients that ask
+    pub fn with_compression(mut self, compression: &[Compression]) -> Self {
+        self.compression = compression.to_vec();
+        self
+    }
+
+    // run on every request to either service once the server has checked its
+    // tokens, so it can see who is calling. Interceptors run in the order they
+    // were added, and any of them can refuse the request
+    pub fn with_interceptor<F>(mut self, interceptor: F) -> Self
+            where F: Fn(Request<()>) -> std::result::Result<Request<()>, Status>
+                + Send + Sync + 'static {
+        self.hooks.push(Arc::new(interceptor));
+        self
+    }
+
+    // wrap every service in a tower layer, such as for metrics or tracing. The
+    // first layer added is the outermost, as on a tonic Server
+    pub fn with_layer<N>(self, layer: N) -> CleanServerBuilder<Stack<N, L>> {
+        CleanServerBuilder {
+            server: self.server,
+            admin: self.admin,
+            compression: self.compression,
+            hooks: self.hooks,
+            layer: Stack::new(layer, self.layer),
+        }
+    }
+
+    // the user and admin services with the interceptors, but not the layers
+    pub fn services(&self) -> (UserService, AdminService) {
+        make_hooked(self.server.clone(), self.admin.clone(), &self.compression,
+                    Hooks(Arc::new(self.hooks.clone())))
+    }
+
+    // add the services to a server set up by the binary, inside its own
+    // layers. Clients from before the protocol was versioned are answered too.
+    // More services, such as health checks, can be added to what is returned
+    pub fn router<T>(self, server: Server<T>) -> Router<Stack<L, T>>
+            where L: Clone, T: Clone {
+        let (user, operator) = self.services();
+        server.layer(self.layer)
+            .add_service(user.clone())
+            .add_service(operator.clone())
+            .add_service(Legacy::new(user))
+            .add_service(Legacy::new(operator))
+    }
+}
+
+// answers clients built before the protocol was versioned, which call the
+// unversioned package, as the first version it became. Nothing is generated
+// for the unversioned package, so this only works while clean.v1 stays
+// identical to it on the wire
+#[derive(Clone)]
+pub struct Legacy<S> {
+    inner: S,
+}
+
+impl<S> Legacy<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner: inner,
+        }
+    }
+}
+
+impl NamedService for Legacy<UserService> {
+    const NAME: &'static str = "clean.Clean";
+}
+
+impl NamedService for Legacy<AdminService> {
+    const NAME: &'static str = "clean.CleanAdmin";
+}
+
+impl<S, B> Service<http::Request<B>> for Legacy<S>
+        where S: Service<http::Request<B>> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // /clean.Clean/HostSession is served as /clean.v1.Clean/HostSession
+        let path = request.uri().path().replacen("/clean.", "/clean.v1.", 1);
+        let mut parts = request.uri().clone().into_parts();
+        parts.path_and_query = path.parse().ok();
+        if let Ok(uri) = http::Uri::from_parts(parts) {
+            *request.uri_mut() = uri;
+        }
+        self.inner.call(request)
+    }
+}
+
+// logs every call's method, the address it came from, the status it was
+// answered with and how long that took. Streams are logged once they open.
+// Clones share whether they are logging, which can be switched at any time
+#[derive(Clone, Default)]
+pub struct RequestLog {
+    enabled: Arc<AtomicBool>,
+}
+
+impl RequestLog {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> Layer<S> for RequestLog {
+    type Service = Logged<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Logged {
+            inner: inner,
+            log: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Logged<S> {
+    inner: S,
+    log: RequestLog,
+}
+
+impl<S, B, R> Service<http::Request<B>> for Logged<S>
+        where S: Service<http::Request<B>, Response = http::Response<R>>,
+              S::Future: Send + 'static, S::Error: 'static {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if !self.log.is_enabled() {
+            return Box::pin(self.inner.call(request));
+        }
+        let method = request.uri().path().to_owned();
+        let peer = peer_addr(&request).map_or_else(|| "local".to_owned(), |a| a.to_string());
+        let started = Instant::now();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await;
+            // failures are sent in the headers, success at the end of the body
+            let code = match &response {
+                Ok(r) => r.headers().get("grpc-status")
+                    .map_or(Code::Ok, |s| Code::from_bytes(s.as_bytes())),
+                Err(_) => Code::Internal,
+            };
+            info!("{} from {} {:?} in {:?}", method, peer, code, started.elapsed());
+            response
+        })
+    }
+}
+
+// the address a call came from, over TCP with or without TLS
+fn peer_addr<B>(request: &http::Request<B>) -> Option<SocketAddr> {
+    let addr = request.extensions().get::<TcpConnectInfo>().and_then(|i| i.remote_addr());
+    #[cfg(feature = "tls")]
+    let addr = addr.or_else(|| {
+        request.extensions().get::<TlsConnectInfo<TcpConnectInfo>>()
+            .and_then(|i| i.get_ref().remote_addr())
+    });
+    addr
+}
+
+// an interceptor added by the binary
+type Hook = Arc<dyn Fn(Request<()>) -> std::result::Result<Request<()>, Status> + Send + Sync>;
+
+// the binary's interceptors, run after the server's own in the order they were
+// added
+#[derive(Clone, Default)]
+struct Hooks(Arc<Vec<Hook>>);
+
+impl Interceptor for Hooks {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        for hook in self.0.iter() {
+            request = hook(request)?;
+        }
+        Ok(request)
+    }
+}
+
+// lets through only requests carrying the admin token, refusing everything
+// when the server doesn't have one
+#[derive(Clone)]
+pub struct AdminGate {
+    admin: Option<AdminToken>,
+    hooks: Hooks,
+}
+
+impl Interceptor for AdminGate {
+    fn call(&mut self, request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let presented = request.metadata().get(ADMIN_TOKEN_KEY).and_then(|t| t.to_str().ok());
+        match (&self.admin, presented) {
+            (Some(admin), Some(t)) if admin.0 == t => self.hooks.call(request),
+            _ => Err(unauthenticated()),
+        }
+    }
+}
+
+// checks the token sent with each request, and tells the handler who sent it.
+// An admin token stands for any user. Requests without a token are passed on
+// unauthenticated, and refused by everything but registration. Over mutual TLS
+// the client's certificate is passed on too, for the server to authorize with
+#[derive(Clone)]
+pub struct Authenticator {
+    server: Arc<dyn Clean>,
+    admin: Option<AdminToken>,
+    hooks: Hooks,
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        #[cfg(feature = "tls")]
+        if let Some(certs) = request.peer_certs() {
+            if let Some(cert) = certs.first() {
+                request.extensions_mut().insert(PeerIdentity(cert.to_vec()));
+            }
+        }
+        let peer = request.extensions().get::<PeerIdentity>().cloned();
+        let presented = |key| request.metadata().get(key)
+            .and_then(|t| t.to_str().ok()).map(|t| t.to_owned());
+        if let (Some(admin), Some(t)) = (&self.admin, presented(ADMIN_TOKEN_KEY)) {
+            if admin.0 == t {
+                request.extensions_mut().insert(Requester::Admin);
+                return self.hooks.call(request);
+            }
+        }
+        if let Some(t) = presented(AUTH_TOKEN_KEY) {
+            let uid = self.server.authenticate(&AuthToken(t), peer.as_ref())
+                .ok_or_else(unauthenticated)?;
+            request.extensions_mut().insert(Requester::User(uid));
+        } else if let Some(t) = presented(BEARER_KEY) {
+            let uid = t.strip_prefix("Bearer ")
+                .and_then(|jwt| self.server.authenticate_bearer(jwt))
+                .ok_or_else(unauthenticated)?;
+            request.extensions_mut().insert(Requester::User(uid));
+        }
+        self.hooks.call(request)
+    }
+}
+
+fn unauthenticated() -> Status {
+    rejected(Error::Unauthenticated)
+}
+
+// the gRPC code each error is sent with, for callers that don't read the
+// details
+fn status_code(code: ErrorCode) -> Code {
+    match code {
+        ErrorCode::Unspecified => Code::Internal,
+        ErrorCode::ClientUnreachable | ErrorCode::UserOffline => Code::Unavailable,
+        ErrorCode::SessionNotFound | ErrorCode::UnknownUser => Code::NotFound,
+        ErrorCode::UserAlreadyInSession | ErrorCode::NameTaken => Code::AlreadyExists,
+        ErrorCode::InvalidResponse | ErrorCode::InvalidRequest | ErrorCode::InvalidJoinToken
+            | ErrorCode::InvalidName | ErrorCode::StaleResponse => Code::InvalidArgument,
+        ErrorCode::SessionStarted | ErrorCode::InsufficientChips | ErrorCode::UserNotInSession
+            | ErrorCode::InvalidState | ErrorCode::Banned => Code::FailedPrecondition,
+        ErrorCode::SessionFull | ErrorCode::SessionLimitReached | ErrorCode::RateLimited =>
+            Code::ResourceExhausted,
+        ErrorCode::NotHost | ErrorCode::WrongPassword | ErrorCode::JoinRejected
+            | ErrorCode::UserBanned | ErrorCode::NotFriends | ErrorCode::UserBlocked
+            | ErrorCode::GuestCannotHost => Code::PermissionDenied,
+        ErrorCode::Unauthenticated => Code::Unauthenticated,
+    }
+}
+
+// a refused call, sent with the gRPC code for what went wrong, and the error
+// code and what the client can do about it in the status details. Invalid
+// names carry why too, so the client can pick another one
+fn refusal(code: ErrorCode, action: ClientAction, e: &(dyn std::error::Error + 'static))
+        -> Status {
+    let name = match e.downcast_ref::<Error>() {
+        Some(Error::InvalidName(ne)) => Some(clean::NameRejection::from(*ne)),
+        _ => None,
+    };
+    let details = clean::ErrorDetails {
+        code: clean::ErrorCode::from(code).into(),
+        action: clean::ClientAction::from(action).into(),
+        name: name,
+    };
+    Status::with_details(status_code(code), format!("{}", e), details.encode_to_vec().into())
+}
+
+// the server decides what its own errors mean to the client
+fn refused(server: &dyn Clean, e: impl Into<Box<dyn std::error::Error + Send + Sync>>)
+        -> Status {
+    let e = e.into();
+    let (code, action) = server.guidance(e.as_ref());
+    refusal(code, action, e.as_ref())
+}
+
+fn rejected(e: Error) -> Status {
+    let (code, action) = e.guidance();
+    refusal(code, action, &e)
+}
+
+// a request for the client, with the ID its answer will come back with, and
+// its place on the stream
+fn outbound((id, sr): (u64, ServerRequest), sequence: u64) -> clean::ServerRequest {
+    let mut request = clean::ServerRequest::from(sr);
+    request.request_id = id;
+    request.sequence = sequence;
+    request
+}
+
+// the requests last sent to a user in a session, kept across their streams so
+// a client that reconnects can be sent what it missed. Requests waiting on an
+// answer aren't kept, as the game asks them again once the player is back
+#[derive(Default)]
+struct Replay {
+    // the sequence of the last request sent
+    sequence: u64,
+    sent: VecDeque<clean::ServerRequest>,
+}
+
+impl Replay {
+    // number the next request, keeping the last few that aren't answered
+    fn record(&mut self, request: (u64, ServerRequest), keep: usize) -> clean::ServerRequest {
+        self.sequence = self.sequence + 1;
+        let request = outbound(request, self.sequence);
+        if request.request_id == 0 && keep > 0 {
+            if self.sent.len() >= keep {
+                self.sent.pop_front();
+            }
+            self.sent.push_back(request.clone());
+        }
+        request
+    }
+
+    // everything kept that was sent after the given sequence
+    fn since(&self, sequence: u64) -> Vec<clean::ServerRequest> {
+        self.sent.iter().filter(|r| r.sequence > sequence).cloned().collect()
+    }
+}
+
+// a user listening for events in a session, with the token they reconnect with
+struct Registration {
+    token: ReconnectToken,
+    replay: Replay,
+}
+
+// who the request was authenticated as
+fn authenticated<T>(request: &Request<T>) -> std::result::Result<Requester, Status> {
+    request.extensions().get::<Requester>().copied().ok_or_else(unauthenticated)
+}
+
+// requests made as a user have to come from that user, or an admin
+fn acting_as<T>(request: &Request<T>, uid: UserID) -> std::result::Result<Requester, Status> {
+    match authenticated(request)? {
+        Requester::User(u) if u != uid =>
+            Err(Status::permission_denied("Not allowed to act as another user")),
+        by => Ok(by),
+    }
 }
 
 pub struct CleanServer {
-    server: Box<dyn Clean>,
-    channels: Arc<Mutex<HashMap<EventRegister, Sender<ClientResponse>>>>,
+    server: Arc<dyn Clean>,
+    registrations: Arc<Mutex<HashMap<EventRegister, Registration>>>,
 }
 
 impl CleanServer {
-    pub fn new(server: impl Clean) -> Self {
+    pub fn new(server: Arc<dyn Clean>) -> Self {
         Self {
-            server: Box::new(server),
-            channels: Arc::new(Mutex::new(HashMap::new())),
+            server: server,
+            registrations: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // spends one of the caller's requests, counted against the user they are
+    // authenticated as, or their address when they aren't. Admins aren't
+    // limited
+    fn throttle<T>(&self, request: &Request<T>) -> std::result::Result<(), Status> {
+        let key = match request.extensions().get::<Requester>() {
+            Some(Requester::Admin) => { return Ok(()); }
+            Some(Requester::User(uid)) => RateKey::User(*uid),
+            None => match request.remote_addr() {
+                Some(addr) => RateKey::Address(addr.ip()),
+                None => { return Ok(()); }
+            },
+        };
+        if !self.server.allow_request(key) {
+            return Err(rejected(Error::RateLimited));
+        }
+        Ok(())
+    }
+
+    // requests made as a user need to be authenticated as them, and once they
+    // listen for events in the session, need their reconnect token too
+    async fn check_owner<T>(&self, request: &Request<T>, er: &EventRegister)
+            -> std::result::Result<(), Status> {
+        acting_as(request, er.user_id())?;
+        if self.registrations.lock().await.contains_key(er) {
+            let presented = request.metadata().get(RECONNECT_TOKEN_KEY)
+                .and_then(|t| t.to_str().ok()).unwrap_or("");
+            self.authorize(er, presented, false).await?;
+        }
+        Ok(())
+    }
+
+    // issue a token on the first registration, and check it after that
+    async fn authorize(&self, er: &EventRegister, token: &str, issue: bool)
+            -> std::result::Result<ReconnectToken, Status> {
+        let mut registrations = self.registrations.lock().await;
+        match registrations.get(er) {
+            Some(r) if r.token.0 == token => Ok(r.token.clone()),
+            Some(_) => Err(Status::permission_denied("Invalid reconnect token")),
+            None if issue => {
+                let t = ReconnectToken::generate();
+                registrations.insert(er.clone(), Registration {
+                    token: t.clone(),
+                    replay: Replay::default(),
+                });
+                Ok(t)
+            }
+            None => Err(Status::permission_denied("Not registered for events")),
         }
     }
 }
@@ -37,14 +546,127 @@ impl CleanServer {
 #[tonic::async_trait]
 pub trait Clean: Send + Sync + 'static {
     // client initiated API
-    async fn host_session(&self, typ: SessionType, player_count: u8) -> Result<SessionData>;
-    async fn list_sessions(&self) -> Result<Vec<SessionData>>;
-    async fn join_session(&self, sid: SessionID, uid: UserID, user_name: &str)
+    async fn host_session(&self, hi: HostInfo) -> Result<SessionData>;
+    async fn presets(&self) -> Result<Vec<Preset>>;
+    async fn host_from_preset(&self, name: &str, host: UserID) -> Result<SessionData>;
+    async fn list_sessions(&self, filter: &ListFilter) -> Result<Vec<SessionData>>;
+    async fn session_details(&self, sid: SessionID) -> Result<SessionDetails>;
+    async fn custom_types(&self) -> Result<Vec<String>>;
+    async fn join_session(&self, ji: JoinInfo) -> Result<()>;
+    async fn join_by_code(&self, code: &str, uid: UserID, user_name: &str,
+                          team: Option<TeamID>) -> Result<SessionData>;
+    // a single use token letting one player into the host's session until it
+    // expires, lasting the server's longest when no time is given
+    async fn create_join_token(&self, sid: SessionID, host: UserID, ttl: Option<Duration>)
+        -> Result<JoinToken>;
+    async fn start_session(&self, sid: SessionID, uid: UserID) -> Result<()>;
+    async fn leave_session(&self, sid: SessionID, uid: UserID) -> Result<()>;
+    async fn kick_user(&self, sid: SessionID, host: UserID, target: UserID)
+        -> Result<()>;
+    async fn ban_user(&self, sid: SessionID, host: UserID, target: UserID)
+        -> Result<()>;
+    async fn unban_user(&self, sid: SessionID, host: UserID, target: UserID)
         -> Result<()>;
-    async fn start_session(&self, sid: SessionID) -> Result<()>;
+    async fn cancel_session(&self, sid: SessionID, uid: UserID) -> Result<()>;
+    async fn update_session(&self, sid: SessionID, host: UserID, changes: SessionChanges)
+        -> Result<()>;
+    async fn answer_join(&self, sid: SessionID, host: UserID, uid: UserID, accept: bool)
+        -> Result<()>;
+    async fn rematch(&self, sid: SessionID, host: UserID) -> Result<SessionData>;
+    async fn transfer_host(&self, sid: SessionID, host: UserID, target: UserID)
+        -> Result<()>;
+    // true once enough players have voted and the target is removed
+    async fn vote_kick(&self, sid: SessionID, uid: UserID, target: UserID) -> Result<bool>;
+    async fn pause_session(&self, sid: SessionID, host: UserID) -> Result<()>;
+    async fn resume_session(&self, sid: SessionID, host: UserID) -> Result<()>;
+    async fn send_chat(&self, sid: SessionID, uid: UserID, text: &str) -> Result<()>;
+    async fn chat_history(&self, sid: SessionID) -> Result<Vec<ChatMessage>>;
+    async fn send_dm(&self, from: UserID, to: UserID, text: &str) -> Result<()>;
+    async fn block_user(&self, uid: UserID, target: UserID) -> Result<()>;
+    async fn unblock_user(&self, uid: UserID, target: UserID) -> Result<()>;
+    async fn add_friend(&self, uid: UserID, friend: UserID) -> Result<()>;
+    async fn remove_friend(&self, uid: UserID, friend: UserID) -> Result<()>;
+    async fn friends(&self, uid: UserID) -> Result<Vec<Friend>>;
+    // only friends who have added each other can be invited
+    async fn send_invite(&self, sid: SessionID, from: UserID, to: UserID) -> Result<()>;
+    async fn online_users(&self) -> Result<Vec<OnlineUser>>;
+    // hand out a user ID nobody else has, with the token to authenticate as
+    // them. The peer is the certificate the client connected with, if any
+    async fn register_user(&self, name: &str, account_type: AccountType,
+                           peer: Option<&PeerIdentity>) -> Result<(UserID, AuthToken)>;
+    fn account_type(&self, uid: UserID) -> AccountType;
+    // the user the token was issued to, if it can be used from this peer
+    fn authenticate(&self, token: &AuthToken, peer: Option<&PeerIdentity>) -> Option<UserID>;
+    // the user signed in with a token from an identity provider, if the server
+    // trusts one and the token is valid
+    fn authenticate_bearer(&self, token: &str) -> Option<UserID>;
+    // whether the caller can make another request yet, counting this one
+    fn allow_request(&self, key: RateKey) -> bool;
+    // the code a refused request is sent to the client with, and what it can
+    // do about it
+    fn guidance(&self, e: &(dyn std::error::Error + 'static)) -> (ErrorCode, ClientAction);
+    // how often each event stream is sent a heartbeat, and how long a client
+    // can go without answering one before it counts as dropped
+    fn heartbeat(&self) -> (Duration, Duration);
+    // how many of the requests last sent to each user are kept, to send again
+    // when they reconnect
+    fn replay_buffer(&self) -> usize;
+    // each public session created, changed or removed from now on
+    fn watch_sessions(&self) -> broadcast::Receiver<LobbyChange>;
+    async fn balance(&self, uid: UserID) -> Result<u64>;
+    async fn profile(&self, uid: UserID) -> Result<Profile>;
+    async fn export_user_data(&self, uid: UserID) -> Result<UserDataExport>;
+    async fn delete_user_data(&self, uid: UserID, by: Requester) -> Result<()>;
+    // operational endpoints, only reached with the admin token
+    async fn server_stats(&self) -> Result<ServerStats>;
+    async fn ban_account(&self, ban: AccountBan) -> Result<()>;
+    async fn unban_account(&self, ban: AccountBan) -> Result<()>;
+    async fn account_bans(&self) -> Result<Vec<AccountBan>>;
     // server callbacks
     async fn register_server_event_sender(&self, sid: SessionID, uid: UserID,
                           s: ServerEventSender) -> Result<()>;
+    // the user's event stream dropped without the server closing it
+    async fn client_disconnected(&self, sid: SessionID, uid: UserID) -> Result<()>;
+    // one of the user's event streams ended, however it was closed
+    async fn stream_closed(&self, sid: SessionID, uid: UserID) -> Result<()>;
+}
+
+pub struct AdminServer {
+    server: Arc<dyn Clean>,
+}
+
+#[tonic::async_trait]
+impl clean::clean_admin_server::CleanAdmin for AdminServer {
+    async fn get_server_stats(&self, _request: Request<clean::Empty>)
+            -> std::result::Result<Response<clean::ServerStats>, Status> {
+        let stats = self.server.server_stats().await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(stats.into()))
+    }
+    async fn ban_account(&self, request: Request<clean::AccountBan>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ban: AccountBan = request.into_inner().try_into()
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        self.server.ban_account(ban).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty {}))
+    }
+    async fn unban_account(&self, request: Request<clean::AccountBan>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ban: AccountBan = request.into_inner().try_into()
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        self.server.unban_account(ban).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty {}))
+    }
+    async fn list_account_bans(&self, _request: Request<clean::Empty>)
+            -> std::result::Result<Response<clean::AccountBans>, Status> {
+        let bans = self.server.account_bans().await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::AccountBans {
+            bans: bans.into_iter().map(|b| b.into()).collect(),
+        }))
+    }
 }
 
 #[tonic::async_trait]
@@ -52,93 +674,578 @@ impl clean::clean_server::Clean for CleanServer {
     // client initiated API
     async fn host_session(&self, request: Request<clean::HostInfo>)
             -> std::result::Result<Response<clean::SessionData>, Status> {
+        self.throttle(&request)?;
+        let by = match request.get_ref().host_id {
+            Some(host) => acting_as(&request, UserID(host))?,
+            None => authenticated(&request)?,
+        };
+        // guests can't get around hosting by leaving themselves out
+        if let Requester::User(uid) = by {
+            if self.server.account_type(uid) == AccountType::Guest {
+                return Err(rejected(Error::GuestCannotHost));
+            }
+        }
         let hi: HostInfo = request.into_inner().try_into()
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
-        let c = self.server.host_session(hi.session_type(), hi.player_count()).await
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        let c = self.server.host_session(hi).await.map_err(|e| refused(self.server.as_ref(), e))?;
         let reply = c.into();
         Ok(Response::new(reply))
     }
-    async fn list_sessions(&self, _: Request<clean::Empty>)
+    async fn list_presets(&self, request: Request<clean::Empty>)
+            -> std::result::Result<Response<clean::Presets>, Status> {
+        authenticated(&request)?;
+        let presets = self.server.presets().await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Presets {
+            presets: presets.into_iter().map(|p| p.into()).collect(),
+        }))
+    }
+    async fn host_from_preset(&self, request: Request<clean::PresetHostInfo>)
+            -> std::result::Result<Response<clean::SessionData>, Status> {
+        self.throttle(&request)?;
+        acting_as(&request, UserID(request.get_ref().host_id))?;
+        let phi = request.into_inner();
+        let sd = self.server.host_from_preset(&phi.name, UserID(phi.host_id)).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(sd.into()))
+    }
+    async fn list_sessions(&self, request: Request<clean::ListFilter>)
             -> std::result::Result<Response<clean::Sessions>, Status> {
-        let c = self.server.list_sessions().await
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
-        let v: Vec<clean::SessionData> = c.iter().map(|sd| sd.clone().into()).collect();
-        Ok(Response::new(
-            clean::Sessions {
-                data: v,
-            }
-        ))
+        self.throttle(&request)?;
+        authenticated(&request)?;
+        let filter: ListFilter = request.into_inner().try_into()
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        let c = self.server.list_sessions(&filter).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        let t = self.server.custom_types().await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(Sessions::new(&c, &t).into()))
+    }
+    async fn get_session(&self, request: Request<clean::SessionRequest>)
+            -> std::result::Result<Response<clean::SessionDetails>, Status> {
+        authenticated(&request)?;
+        let sid = SessionID(request.into_inner().session_id);
+        let details = self.server.session_details(sid).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(details.into()))
     }
     async fn join_session(&self, request: Request<clean::JoinInfo>)
             -> std::result::Result<Response<clean::Empty>, Status> {
-        let ji: JoinInfo = request.into_inner().into();
-        self.server.join_session(ji.session_id(), ji.user_id(), ji.user_name()).await
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
+        let ji: JoinInfo = request.get_ref().clone().into();
+        acting_as(&request, ji.user_id())?;
+        self.server.join_session(ji).await.map_err(|e| refused(self.server.as_ref(), e))?;
         Ok(Response::new(clean::Empty{}))
     }
+    async fn join_by_code(&self, request: Request<clean::CodeJoinInfo>)
+            -> std::result::Result<Response<clean::SessionData>, Status> {
+        acting_as(&request, UserID(request.get_ref().user_id))?;
+        let cji = request.into_inner();
+        let team = if cji.team == 0 { None } else { Some(TeamID(cji.team)) };
+        let sd = self.server.join_by_code(&cji.code, UserID(cji.user_id), &cji.user_name,
+                                          team).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(sd.into()))
+    }
+    async fn create_join_token(&self, request: Request<clean::JoinTokenRequest>)
+            -> std::result::Result<Response<clean::JoinToken>, Status> {
+        let jr = request.get_ref().clone();
+        let host = EventRegister::new(SessionID(jr.session_id), UserID(jr.host_id));
+        self.check_owner(&request, &host).await?;
+        let ttl = if jr.ttl_secs == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(jr.ttl_secs.into()))
+        };
+        let jt = self.server.create_join_token(host.session_id(), host.user_id(), ttl).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(jt.into()))
+    }
     async fn start_session(&self, request: Request<clean::StartInfo>)
             -> std::result::Result<Response<clean::Empty>, Status> {
-        let si: StartInfo = request.into_inner().into();
-        self.server.start_session(si.session_id()).await
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
+        let si: StartInfo = request.get_ref().clone().into();
+        let er = EventRegister::new(si.session_id(), si.user_id());
+        self.check_owner(&request, &er).await?;
+        self.server.start_session(si.session_id(), si.user_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn leave_session(&self, request: Request<clean::LeaveInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let li: LeaveInfo = request.get_ref().clone().into();
+        let er = EventRegister::new(li.session_id(), li.user_id());
+        self.check_owner(&request, &er).await?;
+        self.server.leave_session(li.session_id(), li.user_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        self.registrations.lock().await.remove(&er);
         Ok(Response::new(clean::Empty{}))
     }
+    async fn kick_user(&self, request: Request<clean::KickInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ki: KickInfo = request.get_ref().clone().into();
+        let host = EventRegister::new(ki.session_id(), ki.host_id());
+        self.check_owner(&request, &host).await?;
+        self.server.kick_user(ki.session_id(), ki.host_id(), ki.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        let target = EventRegister::new(ki.session_id(), ki.target_id());
+        self.registrations.lock().await.remove(&target);
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn ban_user(&self, request: Request<clean::KickInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ki: KickInfo = request.get_ref().clone().into();
+        let host = EventRegister::new(ki.session_id(), ki.host_id());
+        self.check_owner(&request, &host).await?;
+        self.server.ban_user(ki.session_id(), ki.host_id(), ki.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        // a banned player still in the lobby is removed from it
+        let target = EventRegister::new(ki.session_id(), ki.target_id());
+        self.registrations.lock().await.remove(&target);
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn unban_user(&self, request: Request<clean::KickInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ki: KickInfo = request.get_ref().clone().into();
+        let host = EventRegister::new(ki.session_id(), ki.host_id());
+        self.check_owner(&request, &host).await?;
+        self.server.unban_user(ki.session_id(), ki.host_id(), ki.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn cancel_session(&self, request: Request<clean::CancelInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ci: CancelInfo = request.get_ref().clone().into();
+        let host = EventRegister::new(ci.session_id(), ci.user_id());
+        self.check_owner(&request, &host).await?;
+        self.server.cancel_session(ci.session_id(), ci.user_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        // nobody can listen to the session any more
+        let sid = ci.session_id();
+        self.registrations.lock().await.retain(|er, _| er.session_id() != sid);
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn update_session(&self, request: Request<clean::UpdateInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ui = request.get_ref().clone();
+        let sid = SessionID(ui.session_id);
+        let host = UserID(ui.host_id);
+        self.check_owner(&request, &EventRegister::new(sid, host)).await?;
+        let changes: SessionChanges = ui.changes.unwrap_or_default().try_into()
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        self.server.update_session(sid, host, changes).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn answer_join(&self, request: Request<clean::JoinAnswer>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ja: JoinAnswer = request.get_ref().clone().into();
+        let host = EventRegister::new(ja.session_id(), ja.host_id());
+        self.check_owner(&request, &host).await?;
+        self.server.answer_join(ja.session_id(), ja.host_id(), ja.user_id(), ja.accept()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn rematch(&self, request: Request<clean::RematchInfo>)
+            -> std::result::Result<Response<clean::SessionData>, Status> {
+        let ri: RematchInfo = request.get_ref().clone().into();
+        let host = EventRegister::new(ri.session_id(), ri.host_id());
+        self.check_owner(&request, &host).await?;
+        let sd = self.server.rematch(ri.session_id(), ri.host_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        // the finished session is gone, its players listen to the new one
+        let sid = ri.session_id();
+        self.registrations.lock().await.retain(|er, _| er.session_id() != sid);
+        Ok(Response::new(sd.into()))
+    }
+    async fn transfer_host(&self, request: Request<clean::TransferInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let ti: TransferInfo = request.get_ref().clone().into();
+        let host = EventRegister::new(ti.session_id(), ti.host_id());
+        self.check_owner(&request, &host).await?;
+        self.server.transfer_host(ti.session_id(), ti.host_id(), ti.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn vote_kick(&self, request: Request<clean::VoteKickInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let vi: VoteKickInfo = request.get_ref().clone().into();
+        let er = EventRegister::new(vi.session_id(), vi.user_id());
+        self.check_owner(&request, &er).await?;
+        let kicked = self.server.vote_kick(vi.session_id(), vi.user_id(), vi.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        if kicked {
+            let target = EventRegister::new(vi.session_id(), vi.target_id());
+            self.registrations.lock().await.remove(&target);
+        }
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn pause_session(&self, request: Request<clean::StartInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let si: StartInfo = request.get_ref().clone().into();
+        let er = EventRegister::new(si.session_id(), si.user_id());
+        self.check_owner(&request, &er).await?;
+        self.server.pause_session(si.session_id(), si.user_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn resume_session(&self, request: Request<clean::StartInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let si: StartInfo = request.get_ref().clone().into();
+        let er = EventRegister::new(si.session_id(), si.user_id());
+        self.check_owner(&request, &er).await?;
+        self.server.resume_session(si.session_id(), si.user_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn send_chat(&self, request: Request<clean::ChatMessage>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let cm: ChatMessage = request.get_ref().clone().into();
+        let er = EventRegister::new(cm.session_id(), cm.user_id());
+        self.check_owner(&request, &er).await?;
+        self.server.send_chat(cm.session_id(), cm.user_id(), cm.text()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn get_chat_history(&self, request: Request<clean::ChatHistoryRequest>)
+            -> std::result::Result<Response<clean::ChatHistory>, Status> {
+        authenticated(&request)?;
+        let sid = SessionID(request.into_inner().session_id);
+        let messages = self.server.chat_history(sid).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::ChatHistory {
+            messages: messages.into_iter().map(|m| m.into()).collect(),
+        }))
+    }
+    async fn send_direct_message(&self, request: Request<clean::DirectMessage>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let dm: DirectMessage = request.get_ref().clone().into();
+        acting_as(&request, dm.from_id())?;
+        self.server.send_dm(dm.from_id(), dm.to_id(), dm.text()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn block_user(&self, request: Request<clean::BlockInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let bi: BlockInfo = request.get_ref().clone().into();
+        acting_as(&request, bi.user_id())?;
+        self.server.block_user(bi.user_id(), bi.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn unblock_user(&self, request: Request<clean::BlockInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let bi: BlockInfo = request.get_ref().clone().into();
+        acting_as(&request, bi.user_id())?;
+        self.server.unblock_user(bi.user_id(), bi.target_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn add_friend(&self, request: Request<clean::FriendInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let fi: FriendInfo = request.get_ref().clone().into();
+        acting_as(&request, fi.user_id())?;
+        self.server.add_friend(fi.user_id(), fi.friend_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn remove_friend(&self, request: Request<clean::FriendInfo>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let fi: FriendInfo = request.get_ref().clone().into();
+        acting_as(&request, fi.user_id())?;
+        self.server.remove_friend(fi.user_id(), fi.friend_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn list_friends(&self, request: Request<clean::FriendsRequest>)
+            -> std::result::Result<Response<clean::Friends>, Status> {
+        let uid = UserID(request.get_ref().user_id);
+        acting_as(&request, uid)?;
+        let friends = self.server.friends(uid).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Friends {
+            friends: friends.into_iter().map(|f| f.into()).collect(),
+        }))
+    }
+    async fn send_invite(&self, request: Request<clean::Invitation>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let inv: Invitation = request.get_ref().clone().into();
+        let er = EventRegister::new(inv.session_id(), inv.from_id());
+        self.check_owner(&request, &er).await?;
+        self.server.send_invite(inv.session_id(), inv.from_id(), inv.to_id()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    async fn list_online_users(&self, request: Request<clean::Empty>)
+            -> std::result::Result<Response<clean::OnlineUsers>, Status> {
+        authenticated(&request)?;
+        let users = self.server.online_users().await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::OnlineUsers {
+            users: users.into_iter().map(|u| u.into()).collect(),
+        }))
+    }
+    async fn register_user(&self, request: Request<clean::RegisterRequest>)
+            -> std::result::Result<Response<clean::Registration>, Status> {
+        let peer = request.extensions().get::<PeerIdentity>().cloned();
+        let rr = request.into_inner();
+        let (uid, token) = self.server.register_user(&rr.name, rr.account_type.into(),
+                                                     peer.as_ref()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Registration {
+            user_id: uid.0,
+            token: token.0,
+        }))
+    }
+    async fn who_am_i(&self, request: Request<clean::Empty>)
+            -> std::result::Result<Response<clean::Identity>, Status> {
+        match authenticated(&request)? {
+            Requester::User(uid) => Ok(Response::new(clean::Identity {
+                user_id: uid.0,
+            })),
+            Requester::Admin => Err(Status::permission_denied("Admins aren't a user")),
+        }
+    }
+    async fn balance(&self, request: Request<clean::BalanceRequest>)
+            -> std::result::Result<Response<clean::BalanceInfo>, Status> {
+        let uid = UserID(request.get_ref().user_id);
+        acting_as(&request, uid)?;
+        let chips = self.server.balance(uid).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(BalanceInfo::new(uid, chips).into()))
+    }
+    async fn get_profile(&self, request: Request<clean::ProfileRequest>)
+            -> std::result::Result<Response<clean::Profile>, Status> {
+        authenticated(&request)?;
+        let profile = self.server.profile(UserID(request.get_ref().user_id)).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(profile.into()))
+    }
+    async fn export_user_data(&self, request: Request<clean::UserDataRequest>)
+            -> std::result::Result<Response<clean::UserDataExport>, Status> {
+        let uid = UserID(request.get_ref().user_id);
+        acting_as(&request, uid)?;
+        let ud = self.server.export_user_data(uid).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(ud.into()))
+    }
+    async fn delete_user_data(&self, request: Request<clean::UserDataRequest>)
+            -> std::result::Result<Response<clean::Empty>, Status> {
+        let uid = UserID(request.get_ref().user_id);
+        let by = acting_as(&request, uid)?;
+        self.server.delete_user_data(uid, by).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        Ok(Response::new(clean::Empty{}))
+    }
+    type WatchSessionsStream = ReceiverStream<std::result::Result<clean::LobbyChange, Status>>;
+    async fn watch_sessions(&self, request: Request<clean::Empty>)
+            -> std::result::Result<Response<Self::WatchSessionsStream>, Status> {
+        self.throttle(&request)?;
+        authenticated(&request)?;
+        // listen before listing, so nothing changing in between is missed
+        let mut changes = self.server.watch_sessions();
+        let current = self.server.list_sessions(&ListFilter::new()).await
+            .map_err(|e| refused(self.server.as_ref(), e))?;
+        let (tx, rx) = mpsc::channel(100);
+        tokio::spawn(async move {
+            for sd in current {
+                if tx.send(Ok(LobbyChange::Created(sd).into())).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                let change = tokio::select! {
+                    change = changes.recv() => change,
+                    _ = tx.closed() => { return; }
+                };
+                let lc = match change {
+                    Ok(lc) => lc,
+                    // the watcher can't tell what it missed, so has to start
+                    // over with the sessions as they are now
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        let status = Status::data_loss(format!("Missed {} lobby changes", n));
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => { return; }
+                };
+                if tx.send(Ok(lc.into())).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
     // server callbacks
-    type ServerEventsStream = ReceiverStream<std::result::Result<clean::ServerRequest, Status>>;
-    async fn server_events(&self, request: Request<clean::EventRegister>)
-            -> std::result::Result<Response<Self::ServerEventsStream>, Status> {
+    type PlaySessionStream = ReceiverStream<std::result::Result<clean::ServerRequest, Status>>;
+    async fn play_session(&self, mut request: Request<Streaming<clean::ClientMessage>>)
+            -> std::result::Result<Response<Self::PlaySessionStream>, Status> {
         // outer channel to return message to the client
         let (tx, rx) = mpsc::channel(100);
 
         // inner channel to pass values from the server implementation
         let (ctx, mut crx) = mpsc::channel(100);
 
-        let er: EventRegister = request.into_inner().into();
+        // the client says who it is listening as before anything else
+        let per = match request.get_mut().message().await? {
+            Some(clean::ClientMessage { msg: Some(clean::client_message::Msg::Register(er)) }) =>
+                er,
+            _ => { return Err(Status::invalid_argument("Expected the stream to start with \
+                                                        an EventRegister")); }
+        };
+        acting_as(&request, UserID(per.user_id))?;
+        let presented = per.reconnect_token.clone();
+        let resume_from = per.resume_from;
+        let er: EventRegister = per.into();
+        let token = self.authorize(&er, &presented, true).await?;
 
         // a responder channel to respond to a server event
         let (rtx, rrx) = mpsc::channel(100);
 
-        // store the transmitter to send messages back to the client
-        self.channels.lock().await.insert(er.clone(), rtx);
-
         // give the server an event sender so it can send message to the client
-        self.server.register_server_event_sender(er.session_id(), er.user_id(),
-            ServerEventSender::new(ctx, rrx)).await
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
+        let registered = self.server.register_server_event_sender(er.session_id(),
+            er.user_id(), ServerEventSender::new(ctx, rrx)).await;
+        if let Err(e) = registered {
+            return Err(refused(self.server.as_ref(), e));
+        }
+
+        // pass the client's answers on to the event sender waiting for them,
+        // noting when the client was last heard from
+        let mut inbound = request.into_inner();
+        let user = er.user_id();
+        let heard = Arc::new(std::sync::Mutex::new(Instant::now()));
+        let last_heard = heard.clone();
+        let reader = tokio::spawn(async move {
+            while let Ok(Some(cm)) = inbound.message().await {
+                *last_heard.lock().unwrap() = Instant::now();
+                let cr = match cm.msg {
+                    Some(clean::client_message::Msg::Response(cr)) => {
+                        let id = cr.request_id;
+                        ClientResponse::try_from(cr).map(|r| (id, r))
+                    }
+                    Some(clean::client_message::Msg::Heartbeat(_)) => { continue; }
+                    _ => Err(Error::InvalidClientResponse),
+                };
+                match cr {
+                    Ok(cr) => {
+                        // nothing is waiting on the user any more
+                        if rtx.send(cr).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => { warn!("Invalid response from user {:?}: {:?}", user, e); }
+                }
+            }
+        });
 
         // listen for messages from the server
         // and send them to the client
+        let server = self.server.clone();
+        let (every, timeout) = server.heartbeat();
+        let keep = server.replay_buffer();
+        let registrations = self.registrations.clone();
         tokio::spawn(async move {
+            let mut dropped = false;
+            let mut beats = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+            let mut beat: u64 = 0;
+            // a client that reconnects first catches up on what it missed
+            if resume_from > 0 {
+                let missed = match registrations.lock().await.get(&er) {
+                    Some(r) => r.replay.since(resume_from),
+                    None => Vec::new(),
+                };
+                if missed.first().is_some_and(|r| r.sequence > resume_from + 1) {
+                    warn!("User {:?} missed more than can be replayed after {}", er.user_id(),
+                          resume_from);
+                }
+                info!("Replaying {} requests to user {:?}", missed.len(), er.user_id());
+                for r in missed {
+                    // a failed send shows up as the stream closing
+                    let _ = tx.send(Ok(r)).await;
+                }
+            }
             loop {
-                if let Some(se) = crx.recv().await {
-                    let s: clean::ServerRequest = se.into();
+                let next = tokio::select! {
+                    se = crx.recv() => se,
+                    // the client went away without the server ending the stream
+                    _ = tx.closed() => {
+                        dropped = true;
+                        None
+                    }
+                    _ = beats.tick() => {
+                        // the connection may look open, but nobody is
+                        // answering on the other end
+                        if heard.lock().unwrap().elapsed() > timeout {
+                            warn!("No heartbeat from user {:?} in {:?}", er.user_id(), timeout);
+                            dropped = true;
+                            None
+                        } else {
+                            beat = beat + 1;
+                            let hb = clean::ServerRequest {
+                                msg: Some(clean::server_request::Msg::Heartbeat(beat)),
+                                request_id: 0,
+                                sequence: 0,
+                            };
+                            // a failed send shows up as the stream closing
+                            let _ = tx.send(Ok(hb)).await;
+                            continue;
+                        }
+                    }
+                };
+                if let Some(se) = next {
+                    // coalesce anything else already waiting, such as a burst
+                    // of notifications, into a single message
+                    let mut pending = vec![se];
+                    while pending.len() < MAX_BATCH {
+                        match crx.try_recv() {
+                            Ok(more) => pending.push(more),
+                            Err(_) => break,
+                        }
+                    }
+                    // numbered while the user is registered, which they no
+                    // longer are once they have left or been removed
+                    let mut requests: Vec<clean::ServerRequest> = {
+                        let mut registrations = registrations.lock().await;
+                        match registrations.get_mut(&er) {
+                            Some(r) => pending.into_iter()
+                                .map(|p| r.replay.record(p, keep)).collect(),
+                            None => pending.into_iter().map(|p| outbound(p, 0)).collect(),
+                        }
+                    };
+                    let s = if requests.len() == 1 {
+                        requests.remove(0)
+                    } else {
+                        clean::ServerRequest {
+                            msg: Some(clean::server_request::Msg::Batch(
+                                clean::BatchedServerRequests { requests: requests })),
+                            request_id: 0,
+                            sequence: 0,
+                        }
+                    };
                     if let Err(e) = tx.send(Ok(s)).await {
                         error!("Could not send server event to client: {:?}", e);
                     }
                 } else {
-                    info!("Server shutting down");
+                    info!("Event stream for {:?} closed", er);
                     break;
                 }
             }
+            // nothing more can reach the user, so stop reading their
+            // responses. Anything still waiting on an answer from them fails
+            drop(crx);
+            reader.abort();
+            if dropped {
+                warn!("User {:?} dropped from session {:?}", er.user_id(), er.session_id());
+                if let Err(e) = server.client_disconnected(er.session_id(), er.user_id()).await {
+                    warn!("Unable to handle {:?} dropping: {:?}", er.user_id(), e);
+                }
+            }
+            if let Err(e) = server.stream_closed(er.session_id(), er.user_id()).await {
+                warn!("Unable to handle {:?} closing a stream: {:?}", er.user_id(), e);
+            }
         });
-        Ok(Response::new(ReceiverStream::new(rx)))
-    }
-
-    async fn respond_to_server_event(&self, request: Request<clean::ClientEventResponse>)
-            -> std::result::Result<Response<clean::Empty>, Status> {
-        let inner = request.into_inner();
-        let er = inner.er
-            .ok_or_else(|| Status::internal("Invalid response"))?.into();
-        let i: clean::ClientResponse = inner.client_response
-            .ok_or_else(|| Status::internal("Invalid response"))?.into();
-        let cr: ClientResponse = i.try_into()
+        let mut response = Response::new(ReceiverStream::new(rx));
+        let value = MetadataValue::try_from(token.0.as_str())
             .map_err(|e| Status::internal(&format!("{}", e)))?;
-        // send this response to the waiting server event sender
-        self.channels.lock().await.get(&er)
-            .ok_or_else(|| Status::internal("Invalid response"))?
-            .send(cr).await
-            .map_err(|e| Status::internal(&format!("{}", e)))?;
-        Ok(Response::new(clean::Empty{}))
+        response.metadata_mut().insert(RECONNECT_TOKEN_KEY, value);
+        Ok(response)
     }
 }
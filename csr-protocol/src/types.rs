@@ -1,30 +1,79 @@
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
 
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SessionID(pub u64);
 
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UserID(pub u64);
 
-use crate::error::Error;
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TeamID(pub u32);
+
+// stands in for the house when it wins a single player game
+pub const HOUSE_ID: UserID = UserID(0);
+pub const HOUSE_NAME: &str = "The house";
+
+// times go over the wire as unix seconds
+fn to_unix(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn from_unix(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+// a series is at least one game, and one too long to count is refused rather
+// than cut down to something else
+fn series_length(length: u32) -> std::result::Result<u8, Error> {
+    let length = u8::try_from(length).map_err(|_| Error::InvalidSeriesLength(length))?;
+    Ok(length.max(1))
+}
 
-// import the protobuf types
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "server")]
+use rand::Rng;
+use tonic::codec::CompressionEncoding;
+
+use crate::error::{Error, NameError};
+
+// import the protobuf types, of the latest version of the protocol
 use crate::clean;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SessionType {
     Dice,
     Coin,
+    Custom(String),
+}
+
+impl SessionType {
+    // name of the custom game, empty for the built in games
+    pub fn custom_type<'a>(&'a self) -> &'a str {
+        match self {
+            SessionType::Custom(name) => name,
+            _ => "",
+        }
+    }
 }
 
-impl TryFrom<i32> for SessionType {
+impl TryFrom<(i32, String)> for SessionType {
     type Error = Error;
 
-    fn try_from(proto: i32) -> std::result::Result<Self, Self::Error> {
-        if proto == clean::SessionType::Dice as i32 {
+    fn try_from(proto: (i32, String)) -> std::result::Result<Self, Self::Error> {
+        let (typ, custom_type) = proto;
+        if typ == clean::SessionType::Dice as i32 {
             return Ok(SessionType::Dice);
-        } else if proto == clean::SessionType::Coin as i32 {
+        } else if typ == clean::SessionType::Coin as i32 {
             return Ok(SessionType::Coin);
+        } else if typ == clean::SessionType::Custom as i32 && !custom_type.is_empty() {
+            return Ok(SessionType::Custom(custom_type));
         } else {
             return Err(Error::InvalidSessionType);
         }
@@ -36,12 +85,14 @@ impl From<SessionType> for clean::SessionType {
         match st {
             SessionType::Dice => clean::SessionType::Dice,
             SessionType::Coin => clean::SessionType::Coin,
+            SessionType::Custom(_) => clean::SessionType::Custom,
         }
     }
 }
 
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Coin {
     Heads,
     Tails,
@@ -70,21 +121,441 @@ impl From<Coin> for clean::Coin {
     }
 }
 
+// known errors reported to the client, unknown values from newer servers are
+// treated as unspecified
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    Unspecified,
+    ClientUnreachable,
+    SessionNotFound,
+    SessionStarted,
+    InsufficientChips,
+    InvalidResponse,
+    SessionFull,
+    UserAlreadyInSession,
+    UserNotInSession,
+    NotHost,
+    WrongPassword,
+    InvalidJoinToken,
+    JoinRejected,
+    UserBanned,
+    UnknownUser,
+    UserOffline,
+    NotFriends,
+    UserBlocked,
+    SessionLimitReached,
+    InvalidRequest,
+    InvalidState,
+    NameTaken,
+    InvalidName,
+    Banned,
+    RateLimited,
+    Unauthenticated,
+    GuestCannotHost,
+    StaleResponse,
+}
+
+impl From<i32> for ErrorCode {
+    fn from(proto: i32) -> Self {
+        match clean::ErrorCode::try_from(proto) {
+            Ok(clean::ErrorCode::ClientUnreachable) => ErrorCode::ClientUnreachable,
+            Ok(clean::ErrorCode::SessionNotFound) => ErrorCode::SessionNotFound,
+            Ok(clean::ErrorCode::SessionStarted) => ErrorCode::SessionStarted,
+            Ok(clean::ErrorCode::InsufficientChips) => ErrorCode::InsufficientChips,
+            Ok(clean::ErrorCode::InvalidResponse) => ErrorCode::InvalidResponse,
+            Ok(clean::ErrorCode::SessionFull) => ErrorCode::SessionFull,
+            Ok(clean::ErrorCode::UserAlreadyInSession) => ErrorCode::UserAlreadyInSession,
+            Ok(clean::ErrorCode::UserNotInSession) => ErrorCode::UserNotInSession,
+            Ok(clean::ErrorCode::NotHost) => ErrorCode::NotHost,
+            Ok(clean::ErrorCode::WrongPassword) => ErrorCode::WrongPassword,
+            Ok(clean::ErrorCode::InvalidJoinToken) => ErrorCode::InvalidJoinToken,
+            Ok(clean::ErrorCode::JoinRejected) => ErrorCode::JoinRejected,
+            Ok(clean::ErrorCode::UserBanned) => ErrorCode::UserBanned,
+            Ok(clean::ErrorCode::UnknownUser) => ErrorCode::UnknownUser,
+            Ok(clean::ErrorCode::UserOffline) => ErrorCode::UserOffline,
+            Ok(clean::ErrorCode::NotFriends) => ErrorCode::NotFriends,
+            Ok(clean::ErrorCode::UserBlocked) => ErrorCode::UserBlocked,
+            Ok(clean::ErrorCode::SessionLimitReached) => ErrorCode::SessionLimitReached,
+            Ok(clean::ErrorCode::InvalidRequest) => ErrorCode::InvalidRequest,
+            Ok(clean::ErrorCode::InvalidState) => ErrorCode::InvalidState,
+            Ok(clean::ErrorCode::NameTaken) => ErrorCode::NameTaken,
+            Ok(clean::ErrorCode::InvalidName) => ErrorCode::InvalidName,
+            Ok(clean::ErrorCode::Banned) => ErrorCode::Banned,
+            Ok(clean::ErrorCode::RateLimited) => ErrorCode::RateLimited,
+            Ok(clean::ErrorCode::Unauthenticated) => ErrorCode::Unauthenticated,
+            Ok(clean::ErrorCode::GuestCannotHost) => ErrorCode::GuestCannotHost,
+            Ok(clean::ErrorCode::StaleResponse) => ErrorCode::StaleResponse,
+            _ => ErrorCode::Unspecified,
+        }
+    }
+}
+
+impl From<ErrorCode> for clean::ErrorCode {
+    fn from(ec: ErrorCode) -> Self {
+        match ec {
+            ErrorCode::Unspecified => clean::ErrorCode::ErrorUnspecified,
+            ErrorCode::ClientUnreachable => clean::ErrorCode::ClientUnreachable,
+            ErrorCode::SessionNotFound => clean::ErrorCode::SessionNotFound,
+            ErrorCode::SessionStarted => clean::ErrorCode::SessionStarted,
+            ErrorCode::InsufficientChips => clean::ErrorCode::InsufficientChips,
+            ErrorCode::InvalidResponse => clean::ErrorCode::InvalidResponse,
+            ErrorCode::SessionFull => clean::ErrorCode::SessionFull,
+            ErrorCode::UserAlreadyInSession => clean::ErrorCode::UserAlreadyInSession,
+            ErrorCode::UserNotInSession => clean::ErrorCode::UserNotInSession,
+            ErrorCode::NotHost => clean::ErrorCode::NotHost,
+            ErrorCode::WrongPassword => clean::ErrorCode::WrongPassword,
+            ErrorCode::InvalidJoinToken => clean::ErrorCode::InvalidJoinToken,
+            ErrorCode::JoinRejected => clean::ErrorCode::JoinRejected,
+            ErrorCode::UserBanned => clean::ErrorCode::UserBanned,
+            ErrorCode::UnknownUser => clean::ErrorCode::UnknownUser,
+            ErrorCode::UserOffline => clean::ErrorCode::UserOffline,
+            ErrorCode::NotFriends => clean::ErrorCode::NotFriends,
+            ErrorCode::UserBlocked => clean::ErrorCode::UserBlocked,
+            ErrorCode::SessionLimitReached => clean::ErrorCode::SessionLimitReached,
+            ErrorCode::InvalidRequest => clean::ErrorCode::InvalidRequest,
+            ErrorCode::InvalidState => clean::ErrorCode::InvalidState,
+            ErrorCode::NameTaken => clean::ErrorCode::NameTaken,
+            ErrorCode::InvalidName => clean::ErrorCode::InvalidName,
+            ErrorCode::Banned => clean::ErrorCode::Banned,
+            ErrorCode::RateLimited => clean::ErrorCode::RateLimited,
+            ErrorCode::Unauthenticated => clean::ErrorCode::Unauthenticated,
+            ErrorCode::GuestCannotHost => clean::ErrorCode::GuestCannotHost,
+            ErrorCode::StaleResponse => clean::ErrorCode::StaleResponse,
+        }
+    }
+}
+
+// what the client can do to recover from an error
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClientAction {
+    None,
+    RetryJoin,
+    Reconnect,
+    UpgradeClient,
+}
+
+impl From<i32> for ClientAction {
+    fn from(proto: i32) -> Self {
+        match clean::ClientAction::try_from(proto) {
+            Ok(clean::ClientAction::RetryJoin) => ClientAction::RetryJoin,
+            Ok(clean::ClientAction::Reconnect) => ClientAction::Reconnect,
+            Ok(clean::ClientAction::UpgradeClient) => ClientAction::UpgradeClient,
+            _ => ClientAction::None,
+        }
+    }
+}
+
+impl From<ClientAction> for clean::ClientAction {
+    fn from(ca: ClientAction) -> Self {
+        match ca {
+            ClientAction::None => clean::ClientAction::ActionNone,
+            ClientAction::RetryJoin => clean::ClientAction::RetryJoin,
+            ClientAction::Reconnect => clean::ClientAction::Reconnect,
+            ClientAction::UpgradeClient => clean::ClientAction::UpgradeClient,
+        }
+    }
+}
+
+// why the server ended a client's event stream, unknown values from newer
+// servers are treated as unspecified
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CloseReason {
+    Unspecified,
+    Kicked,
+    Left,
+    Cancelled,
+    Expired,
+    Shutdown,
+    Finished,
+    Failed,
+}
+
+impl From<i32> for CloseReason {
+    fn from(proto: i32) -> Self {
+        match clean::CloseReason::try_from(proto) {
+            Ok(clean::CloseReason::Kicked) => CloseReason::Kicked,
+            Ok(clean::CloseReason::Left) => CloseReason::Left,
+            Ok(clean::CloseReason::Cancelled) => CloseReason::Cancelled,
+            Ok(clean::CloseReason::Expired) => CloseReason::Expired,
+            Ok(clean::CloseReason::Shutdown) => CloseReason::Shutdown,
+            Ok(clean::CloseReason::Finished) => CloseReason::Finished,
+            Ok(clean::CloseReason::Failed) => CloseReason::Failed,
+            _ => CloseReason::Unspecified,
+        }
+    }
+}
+
+impl From<CloseReason> for clean::CloseReason {
+    fn from(cr: CloseReason) -> Self {
+        match cr {
+            CloseReason::Unspecified => clean::CloseReason::CloseUnspecified,
+            CloseReason::Kicked => clean::CloseReason::Kicked,
+            CloseReason::Left => clean::CloseReason::Left,
+            CloseReason::Cancelled => clean::CloseReason::Cancelled,
+            CloseReason::Expired => clean::CloseReason::Expired,
+            CloseReason::Shutdown => clean::CloseReason::Shutdown,
+            CloseReason::Finished => clean::CloseReason::Finished,
+            CloseReason::Failed => clean::CloseReason::Failed,
+        }
+    }
+}
+
+// number of sides on a die, one of the standard polygonal dice
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+pub struct DiceSides(u8);
+
+impl DiceSides {
+    pub const VALID: [u8; 5] = [4, 6, 8, 12, 20];
+
+    pub fn new(sides: u8) -> std::result::Result<Self, Error> {
+        if !Self::VALID.contains(&sides) {
+            return Err(Error::InvalidDiceSides(sides.into()));
+        }
+        Ok(Self(sides))
+    }
+
+    pub fn value(&self) -> u8 { self.0 }
+}
+
+impl TryFrom<u8> for DiceSides {
+    type Error = Error;
+
+    fn try_from(sides: u8) -> std::result::Result<Self, Self::Error> {
+        Self::new(sides)
+    }
+}
+
+impl From<DiceSides> for u8 {
+    fn from(v: DiceSides) -> Self {
+        v.0
+    }
+}
+
+// number of dice rolled in a game
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+pub struct DiceCount(u8);
+
+impl DiceCount {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 6;
+
+    pub fn new(count: u8) -> std::result::Result<Self, Error> {
+        if count < Self::MIN || count > Self::MAX {
+            return Err(Error::InvalidDiceCount(count.into()));
+        }
+        Ok(Self(count))
+    }
+
+    pub fn value(&self) -> u8 { self.0 }
+}
+
+impl TryFrom<u8> for DiceCount {
+    type Error = Error;
+
+    fn try_from(count: u8) -> std::result::Result<Self, Self::Error> {
+        Self::new(count)
+    }
+}
+
+impl From<DiceCount> for u8 {
+    fn from(v: DiceCount) -> Self {
+        v.0
+    }
+}
+
+// number of coins flipped in a game
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+pub struct CoinCount(u8);
+
+impl CoinCount {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 6;
+
+    pub fn new(count: u8) -> std::result::Result<Self, Error> {
+        if count < Self::MIN || count > Self::MAX {
+            return Err(Error::InvalidCoinCount(count.into()));
+        }
+        Ok(Self(count))
+    }
+
+    pub fn value(&self) -> u8 { self.0 }
+}
+
+impl TryFrom<u8> for CoinCount {
+    type Error = Error;
+
+    fn try_from(count: u8) -> std::result::Result<Self, Self::Error> {
+        Self::new(count)
+    }
+}
+
+impl From<CoinCount> for u8 {
+    fn from(v: CoinCount) -> Self {
+        v.0
+    }
+}
+
+// points a player earned in a game, every value is valid
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Score(u32);
+
+impl Score {
+    pub fn new(points: u32) -> Self {
+        Self(points)
+    }
+
+    pub fn add(&mut self, points: u32) {
+        self.0 = self.0.saturating_add(points);
+    }
+
+    pub fn value(&self) -> u32 { self.0 }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HostInfo {
     typ: SessionType,
-    player_count: u8,
+    max_players: u8,
+    ephemeral: bool,
+    series_length: u8,
+    team_mode: bool,
+    wager: u64,
+    host: Option<UserID>,
+    min_players: u8,
+    auto_start: bool,
+    name: String,
+    visibility: Visibility,
+    password: Option<String>,
+    house_target: Option<u32>,
+    approve_joins: bool,
+    start_at: Option<SystemTime>,
 }
 
 impl HostInfo {
-    pub fn new(typ: SessionType, player_count: u8) -> Self {
+    pub fn new(typ: SessionType, max_players: u8) -> Self {
         Self {
             typ: typ,
-            player_count: player_count,
+            max_players: max_players,
+            ephemeral: false,
+            series_length: 1,
+            team_mode: false,
+            wager: 0,
+            host: None,
+            min_players: 1,
+            auto_start: true,
+            name: String::new(),
+            visibility: Visibility::Public,
+            password: None,
+            house_target: None,
+            approve_joins: false,
+            start_at: None,
         }
     }
 
-    pub fn session_type(&self) -> SessionType { self.typ }
-    pub fn player_count(&self) -> u8 { self.player_count }
+    // ephemeral sessions are left out of leaderboards, stats and archives
+    pub fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    // play a best of series_length series before declaring the winner
+    pub fn with_series_length(mut self, series_length: u8) -> Self {
+        self.series_length = series_length.max(1);
+        self
+    }
+
+    // players join a team, and teams compete for the win
+    pub fn with_team_mode(mut self, team_mode: bool) -> Self {
+        self.team_mode = team_mode;
+        self
+    }
+
+    // chips each player puts in the pot when the game starts
+    pub fn with_wager(mut self, wager: u64) -> Self {
+        self.wager = wager;
+        self
+    }
+
+    // the host can start the game before the session is full
+    pub fn with_host(mut self, host: UserID) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    // the fewest players the host can start the game with
+    pub fn with_min_players(mut self, min_players: u8) -> Self {
+        self.min_players = min_players.max(1);
+        self
+    }
+
+    // start as soon as the session is full, otherwise wait for the host
+    pub fn with_auto_start(mut self, auto_start: bool) -> Self {
+        self.auto_start = auto_start;
+        self
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    // unlisted and private sessions are left out of the session list, and
+    // private ones need a password
+    pub fn with_visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
+    // players have to give the password to join
+    pub fn with_password(mut self, password: Option<String>) -> Self {
+        self.password = password.filter(|p| !p.is_empty());
+        self
+    }
+
+    // a single player beats the house by scoring at least this in a round
+    pub fn with_house_target(mut self, house_target: u32) -> Self {
+        self.house_target = Some(house_target);
+        self
+    }
+
+    // players wait in a queue until the host accepts them
+    pub fn with_approve_joins(mut self, approve_joins: bool) -> Self {
+        self.approve_joins = approve_joins;
+        self
+    }
+
+    // the game starts at this time, rather than when the host or a full
+    // lobby starts it
+    pub fn with_start_at(mut self, start_at: SystemTime) -> Self {
+        self.start_at = Some(start_at);
+        self
+    }
+
+    pub fn session_type<'a>(&'a self) -> &'a SessionType { &self.typ }
+    pub fn max_players(&self) -> u8 { self.max_players }
+    pub fn ephemeral(&self) -> bool { self.ephemeral }
+    pub fn series_length(&self) -> u8 { self.series_length }
+    pub fn team_mode(&self) -> bool { self.team_mode }
+    pub fn wager(&self) -> u64 { self.wager }
+    pub fn host(&self) -> Option<UserID> { self.host }
+    pub fn min_players(&self) -> u8 { self.min_players }
+    pub fn auto_start(&self) -> bool { self.auto_start }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn visibility(&self) -> Visibility { self.visibility }
+    pub fn password(&self) -> Option<&str> { self.password.as_deref() }
+    pub fn house_target(&self) -> Option<u32> { self.house_target }
+    pub fn approve_joins(&self) -> bool { self.approve_joins }
+    pub fn start_at(&self) -> Option<SystemTime> { self.start_at }
 }
 
 impl TryFrom<clean::HostInfo> for HostInfo {
@@ -92,437 +563,2663 @@ impl TryFrom<clean::HostInfo> for HostInfo {
 
     fn try_from(proto: clean::HostInfo) -> std::result::Result<Self, Self::Error> {
         Ok(Self {
-            typ: proto.r#type.try_into()?,
-            player_count: proto.player_count as u8,
+            typ: (proto.r#type, proto.custom_type).try_into()?,
+            max_players: proto.max_players as u8,
+            ephemeral: proto.ephemeral,
+            series_length: series_length(proto.series_length)?,
+            team_mode: proto.team_mode,
+            wager: proto.wager,
+            host: proto.host_id.map(UserID),
+            min_players: (proto.min_players as u8).max(1),
+            auto_start: !proto.manual_start,
+            name: proto.name,
+            visibility: proto.visibility.into(),
+            password: proto.password.filter(|p| !p.is_empty()),
+            house_target: proto.house_target,
+            approve_joins: proto.approve_joins,
+            start_at: proto.start_at.map(from_unix),
         })
     }
 }
 
 impl From<HostInfo> for clean::HostInfo {
     fn from(hi: HostInfo) -> Self {
+        let custom_type = hi.typ.custom_type().to_owned();
         let t: clean::SessionType = hi.typ.into();
+        let visibility: clean::Visibility = hi.visibility.into();
         Self {
             r#type: t.into(),
-            player_count: hi.player_count as u32,
+            max_players: hi.max_players as u32,
+            custom_type: custom_type,
+            ephemeral: hi.ephemeral,
+            series_length: hi.series_length as u32,
+            team_mode: hi.team_mode,
+            wager: hi.wager,
+            host_id: hi.host.map(|h| h.0),
+            min_players: hi.min_players as u32,
+            manual_start: !hi.auto_start,
+            name: hi.name,
+            visibility: visibility.into(),
+            password: hi.password,
+            house_target: hi.house_target,
+            approve_joins: hi.approve_joins,
+            start_at: hi.start_at.map(to_unix),
+        }
+    }
+}
+
+// session settings the server offers by name
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Preset {
+    name: String,
+    hi: HostInfo,
+}
+
+impl Preset {
+    pub fn new(name: &str, hi: HostInfo) -> Self {
+        Self {
+            name: name.to_owned(),
+            hi: hi,
+        }
+    }
+
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn settings<'a>(&'a self) -> &'a HostInfo { &self.hi }
+}
+
+impl TryFrom<clean::Preset> for Preset {
+    type Error = Error;
+
+    fn try_from(proto: clean::Preset) -> std::result::Result<Self, Self::Error> {
+        let hi = proto.settings.ok_or_else(|| Error::InvalidPreset)?;
+        Ok(Self {
+            name: proto.name,
+            hi: hi.try_into()?,
+        })
+    }
+}
+
+impl From<Preset> for clean::Preset {
+    fn from(p: Preset) -> Self {
+        Self {
+            name: p.name,
+            settings: Some(p.hi.into()),
         }
     }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SessionData {
     sid: SessionID,
     typ: SessionType,
     users: Vec<String>,
+    ephemeral: bool,
+    team_mode: bool,
+    wager: u64,
+    name: String,
+    max_players: u8,
+    min_players: u8,
+    visibility: Visibility,
+    locked: bool,
+    invite_code: String,
+    house_target: Option<u32>,
+    approve_joins: bool,
+    start_at: Option<SystemTime>,
 }
 
 impl SessionData {
     pub fn new(sid: SessionID, typ: SessionType, users: &[String]) -> Self {
         Self {
-            sid: sid,
-            typ: typ,
-            users: users.to_vec(),
+            sid: sid,
+            typ: typ,
+            users: users.to_vec(),
+            ephemeral: false,
+            team_mode: false,
+            wager: 0,
+            name: String::new(),
+            max_players: 0,
+            min_players: 1,
+            visibility: Visibility::Public,
+            locked: false,
+            invite_code: String::new(),
+            house_target: None,
+            approve_joins: false,
+            start_at: None,
+        }
+    }
+
+    pub fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    pub fn with_team_mode(mut self, team_mode: bool) -> Self {
+        self.team_mode = team_mode;
+        self
+    }
+
+    pub fn with_wager(mut self, wager: u64) -> Self {
+        self.wager = wager;
+        self
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    pub fn with_max_players(mut self, max_players: u8) -> Self {
+        self.max_players = max_players;
+        self
+    }
+
+    pub fn with_min_players(mut self, min_players: u8) -> Self {
+        self.min_players = min_players.max(1);
+        self
+    }
+
+    pub fn with_visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
+    pub fn with_locked(mut self, locked: bool) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    pub fn with_invite_code(mut self, invite_code: &str) -> Self {
+        self.invite_code = invite_code.to_owned();
+        self
+    }
+
+    pub fn with_house_target(mut self, house_target: Option<u32>) -> Self {
+        self.house_target = house_target;
+        self
+    }
+
+    pub fn with_approve_joins(mut self, approve_joins: bool) -> Self {
+        self.approve_joins = approve_joins;
+        self
+    }
+
+    pub fn with_start_at(mut self, start_at: Option<SystemTime>) -> Self {
+        self.start_at = start_at;
+        self
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn session_type<'a>(&'a self) -> &'a SessionType { &self.typ }
+    pub fn users<'a>(&'a self) -> &'a [String] { &self.users }
+    pub fn ephemeral(&self) -> bool { self.ephemeral }
+    pub fn team_mode(&self) -> bool { self.team_mode }
+    pub fn wager(&self) -> u64 { self.wager }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn max_players(&self) -> u8 { self.max_players }
+    pub fn min_players(&self) -> u8 { self.min_players }
+    pub fn visibility(&self) -> Visibility { self.visibility }
+    pub fn locked(&self) -> bool { self.locked }
+    // empty unless we're the host
+    pub fn invite_code<'a>(&'a self) -> &'a str { &self.invite_code }
+    pub fn house_target(&self) -> Option<u32> { self.house_target }
+    pub fn approve_joins(&self) -> bool { self.approve_joins }
+    // when a scheduled session starts its game
+    pub fn start_at(&self) -> Option<SystemTime> { self.start_at }
+}
+
+impl TryFrom<clean::SessionData> for SessionData {
+    type Error = Error;
+
+    fn try_from(proto: clean::SessionData) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            sid: SessionID(proto.session_id),
+            typ: (proto.r#type, proto.custom_type).try_into()?,
+            users: proto.users,
+            ephemeral: proto.ephemeral,
+            team_mode: proto.team_mode,
+            wager: proto.wager,
+            name: proto.name,
+            max_players: proto.max_players as u8,
+            min_players: (proto.min_players as u8).max(1),
+            visibility: proto.visibility.into(),
+            locked: proto.locked,
+            invite_code: proto.invite_code,
+            house_target: proto.house_target,
+            approve_joins: proto.approve_joins,
+            start_at: proto.start_at.map(from_unix),
+        })
+    }
+}
+
+impl From<SessionData> for clean::SessionData {
+    fn from(sd: SessionData) -> Self {
+        let custom_type = sd.typ.custom_type().to_owned();
+        let t: clean::SessionType = sd.typ.into();
+        let visibility: clean::Visibility = sd.visibility.into();
+        Self {
+            session_id: sd.sid.0,
+            r#type: t.into(),
+            users: sd.users,
+            custom_type: custom_type,
+            ephemeral: sd.ephemeral,
+            team_mode: sd.team_mode,
+            wager: sd.wager,
+            name: sd.name,
+            max_players: sd.max_players as u32,
+            min_players: sd.min_players as u32,
+            visibility: visibility.into(),
+            locked: sd.locked,
+            invite_code: sd.invite_code,
+            house_target: sd.house_target,
+            approve_joins: sd.approve_joins,
+            start_at: sd.start_at.map(to_unix),
+        }
+    }
+}
+
+// where a session is in its life
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionStatus {
+    Waiting,
+    InProgress,
+    Finished,
+}
+
+impl From<i32> for SessionStatus {
+    fn from(proto: i32) -> Self {
+        match clean::SessionStatus::try_from(proto) {
+            Ok(clean::SessionStatus::SessionInProgress) => SessionStatus::InProgress,
+            Ok(clean::SessionStatus::SessionFinished) => SessionStatus::Finished,
+            _ => SessionStatus::Waiting,
+        }
+    }
+}
+
+impl From<SessionStatus> for clean::SessionStatus {
+    fn from(ss: SessionStatus) -> Self {
+        match ss {
+            SessionStatus::Waiting => clean::SessionStatus::SessionWaiting,
+            SessionStatus::InProgress => clean::SessionStatus::SessionInProgress,
+            SessionStatus::Finished => clean::SessionStatus::SessionFinished,
+        }
+    }
+}
+
+// who can find and join a session
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Visibility {
+    #[default]
+    Public,
+    // left out of the session list
+    Unlisted,
+    // unlisted, and needs a password
+    Private,
+}
+
+impl From<i32> for Visibility {
+    fn from(proto: i32) -> Self {
+        match clean::Visibility::try_from(proto) {
+            Ok(clean::Visibility::Unlisted) => Visibility::Unlisted,
+            Ok(clean::Visibility::Private) => Visibility::Private,
+            _ => Visibility::Public,
+        }
+    }
+}
+
+impl From<Visibility> for clean::Visibility {
+    fn from(v: Visibility) -> Self {
+        match v {
+            Visibility::Public => clean::Visibility::Public,
+            Visibility::Unlisted => clean::Visibility::Unlisted,
+            Visibility::Private => clean::Visibility::Private,
+        }
+    }
+}
+
+// registered users keep their account across server restarts, while guests
+// are forgotten once the server stops and have fewer privileges
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountType {
+    #[default]
+    Registered,
+    Guest,
+}
+
+impl From<i32> for AccountType {
+    fn from(proto: i32) -> Self {
+        match clean::AccountType::try_from(proto) {
+            Ok(clean::AccountType::AccountGuest) => AccountType::Guest,
+            _ => AccountType::Registered,
+        }
+    }
+}
+
+impl From<AccountType> for clean::AccountType {
+    fn from(at: AccountType) -> Self {
+        match at {
+            AccountType::Registered => clean::AccountType::AccountRegistered,
+            AccountType::Guest => clean::AccountType::AccountGuest,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionPlayer {
+    uid: UserID,
+    name: String,
+    team: Option<TeamID>,
+    account_type: AccountType,
+}
+
+impl SessionPlayer {
+    pub fn new(uid: UserID, name: &str) -> Self {
+        Self {
+            uid: uid,
+            name: name.to_owned(),
+            team: None,
+            account_type: AccountType::Registered,
+        }
+    }
+
+    pub fn with_team(mut self, team: Option<TeamID>) -> Self {
+        self.team = team;
+        self
+    }
+
+    pub fn with_account_type(mut self, account_type: AccountType) -> Self {
+        self.account_type = account_type;
+        self
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn team(&self) -> Option<TeamID> { self.team }
+    pub fn account_type(&self) -> AccountType { self.account_type }
+}
+
+impl From<clean::SessionPlayer> for SessionPlayer {
+    fn from(proto: clean::SessionPlayer) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            name: proto.user_name,
+            team: if proto.team == 0 { None } else { Some(TeamID(proto.team)) },
+            account_type: proto.account_type.into(),
+        }
+    }
+}
+
+impl From<SessionPlayer> for clean::SessionPlayer {
+    fn from(sp: SessionPlayer) -> Self {
+        let account_type: clean::AccountType = sp.account_type.into();
+        Self {
+            user_id: sp.uid.0,
+            user_name: sp.name,
+            team: sp.team.map_or(0, |t| t.0),
+            account_type: account_type.into(),
+        }
+    }
+}
+
+// everything about one session, with who is playing by user ID
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionDetails {
+    sd: SessionData,
+    players: Vec<SessionPlayer>,
+    status: SessionStatus,
+}
+
+impl SessionDetails {
+    pub fn new(sd: SessionData, players: Vec<SessionPlayer>, status: SessionStatus) -> Self {
+        Self {
+            sd: sd,
+            players: players,
+            status: status,
+        }
+    }
+
+    pub fn session<'a>(&'a self) -> &'a SessionData { &self.sd }
+    pub fn players<'a>(&'a self) -> &'a [SessionPlayer] { &self.players }
+    pub fn status(&self) -> SessionStatus { self.status }
+
+    pub fn has_player(&self, uid: UserID) -> bool {
+        self.players.iter().any(|p| p.uid == uid)
+    }
+}
+
+impl TryFrom<clean::SessionDetails> for SessionDetails {
+    type Error = Error;
+
+    fn try_from(proto: clean::SessionDetails) -> std::result::Result<Self, Self::Error> {
+        let sd = proto.session.ok_or_else(|| Error::InvalidSessionDetails)?;
+        Ok(Self {
+            sd: sd.try_into()?,
+            players: proto.players.into_iter().map(|p| p.into()).collect(),
+            status: proto.status.into(),
+        })
+    }
+}
+
+impl From<SessionDetails> for clean::SessionDetails {
+    fn from(sd: SessionDetails) -> Self {
+        let status: clean::SessionStatus = sd.status.into();
+        Self {
+            session: Some(sd.sd.into()),
+            players: sd.players.into_iter().map(|p| p.into()).collect(),
+            status: status.into(),
+        }
+    }
+}
+
+// a session appearing in, changing in or leaving the list of public sessions
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LobbyChange {
+    Created(SessionData),
+    Updated(SessionData),
+    Removed(SessionID),
+}
+
+impl TryFrom<clean::LobbyChange> for LobbyChange {
+    type Error = Error;
+
+    fn try_from(proto: clean::LobbyChange) -> std::result::Result<Self, Self::Error> {
+        match proto.change.ok_or_else(|| Error::InvalidLobbyChange)? {
+            clean::lobby_change::Change::Created(sd) => Ok(LobbyChange::Created(sd.try_into()?)),
+            clean::lobby_change::Change::Updated(sd) => Ok(LobbyChange::Updated(sd.try_into()?)),
+            clean::lobby_change::Change::Removed(sid) =>
+                Ok(LobbyChange::Removed(SessionID(sid))),
+        }
+    }
+}
+
+impl From<LobbyChange> for clean::LobbyChange {
+    fn from(lc: LobbyChange) -> Self {
+        let change = match lc {
+            LobbyChange::Created(sd) => clean::lobby_change::Change::Created(sd.into()),
+            LobbyChange::Updated(sd) => clean::lobby_change::Change::Updated(sd.into()),
+            LobbyChange::Removed(sid) => clean::lobby_change::Change::Removed(sid.0),
+        };
+        Self {
+            change: Some(change),
+        }
+    }
+}
+
+// settings the host changes before the game starts, anything left out stays
+// the same
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionChanges {
+    typ: Option<SessionType>,
+    max_players: Option<u8>,
+    series_length: Option<u8>,
+    team_mode: Option<bool>,
+    wager: Option<u64>,
+    min_players: Option<u8>,
+    auto_start: Option<bool>,
+    name: Option<String>,
+    visibility: Option<Visibility>,
+    password: Option<String>,
+    house_target: Option<u32>,
+    approve_joins: Option<bool>,
+}
+
+impl SessionChanges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_session_type(mut self, typ: SessionType) -> Self {
+        self.typ = Some(typ);
+        self
+    }
+
+    pub fn with_max_players(mut self, max_players: u8) -> Self {
+        self.max_players = Some(max_players);
+        self
+    }
+
+    pub fn with_series_length(mut self, series_length: u8) -> Self {
+        self.series_length = Some(series_length.max(1));
+        self
+    }
+
+    pub fn with_team_mode(mut self, team_mode: bool) -> Self {
+        self.team_mode = Some(team_mode);
+        self
+    }
+
+    pub fn with_wager(mut self, wager: u64) -> Self {
+        self.wager = Some(wager);
+        self
+    }
+
+    pub fn with_min_players(mut self, min_players: u8) -> Self {
+        self.min_players = Some(min_players.max(1));
+        self
+    }
+
+    pub fn with_auto_start(mut self, auto_start: bool) -> Self {
+        self.auto_start = Some(auto_start);
+        self
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn with_visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = Some(visibility);
+        self
+    }
+
+    // an empty password unlocks the session
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_owned());
+        self
+    }
+
+    pub fn with_house_target(mut self, house_target: u32) -> Self {
+        self.house_target = Some(house_target);
+        self
+    }
+
+    pub fn with_approve_joins(mut self, approve_joins: bool) -> Self {
+        self.approve_joins = Some(approve_joins);
+        self
+    }
+
+    pub fn session_type(&self) -> Option<&SessionType> { self.typ.as_ref() }
+    pub fn max_players(&self) -> Option<u8> { self.max_players }
+    pub fn series_length(&self) -> Option<u8> { self.series_length }
+    pub fn team_mode(&self) -> Option<bool> { self.team_mode }
+    pub fn wager(&self) -> Option<u64> { self.wager }
+    pub fn min_players(&self) -> Option<u8> { self.min_players }
+    pub fn auto_start(&self) -> Option<bool> { self.auto_start }
+    pub fn name(&self) -> Option<&str> { self.name.as_deref() }
+    pub fn visibility(&self) -> Option<Visibility> { self.visibility }
+    pub fn password(&self) -> Option<&str> { self.password.as_deref() }
+    pub fn house_target(&self) -> Option<u32> { self.house_target }
+    pub fn approve_joins(&self) -> Option<bool> { self.approve_joins }
+}
+
+impl TryFrom<clean::SessionChanges> for SessionChanges {
+    type Error = Error;
+
+    fn try_from(proto: clean::SessionChanges) -> std::result::Result<Self, Self::Error> {
+        let typ = match proto.r#type {
+            Some(t) => Some((t, proto.custom_type).try_into()?),
+            None => None,
+        };
+        Ok(Self {
+            typ: typ,
+            max_players: proto.max_players.map(|p| p as u8),
+            series_length: proto.series_length.map(series_length).transpose()?,
+            team_mode: proto.team_mode,
+            wager: proto.wager,
+            min_players: proto.min_players.map(|m| (m as u8).max(1)),
+            auto_start: proto.manual_start.map(|m| !m),
+            name: proto.name,
+            visibility: proto.visibility.map(Visibility::from),
+            password: proto.password,
+            house_target: proto.house_target,
+            approve_joins: proto.approve_joins,
+        })
+    }
+}
+
+impl From<SessionChanges> for clean::SessionChanges {
+    fn from(sc: SessionChanges) -> Self {
+        let custom_type = sc.typ.as_ref().map_or("", |t| t.custom_type()).to_owned();
+        let t: Option<clean::SessionType> = sc.typ.map(|t| t.into());
+        Self {
+            r#type: t.map(|t| t.into()),
+            custom_type: custom_type,
+            max_players: sc.max_players.map(|p| p as u32),
+            series_length: sc.series_length.map(|s| s as u32),
+            team_mode: sc.team_mode,
+            wager: sc.wager,
+            min_players: sc.min_players.map(|m| m as u32),
+            manual_start: sc.auto_start.map(|a| !a),
+            name: sc.name,
+            visibility: sc.visibility.map(|v| clean::Visibility::from(v).into()),
+            password: sc.password,
+            house_target: sc.house_target,
+            approve_joins: sc.approve_joins,
+        }
+    }
+}
+
+// which sessions to list, anything not set matches every session
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListFilter {
+    typ: Option<SessionType>,
+    joinable_only: bool,
+    host: Option<UserID>,
+}
+
+impl ListFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_session_type(mut self, typ: SessionType) -> Self {
+        self.typ = Some(typ);
+        self
+    }
+
+    // only sessions that haven't started and still have room
+    pub fn with_joinable_only(mut self, joinable_only: bool) -> Self {
+        self.joinable_only = joinable_only;
+        self
+    }
+
+    pub fn with_host(mut self, host: UserID) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    pub fn session_type(&self) -> Option<&SessionType> { self.typ.as_ref() }
+    pub fn joinable_only(&self) -> bool { self.joinable_only }
+    pub fn host(&self) -> Option<UserID> { self.host }
+}
+
+impl TryFrom<clean::ListFilter> for ListFilter {
+    type Error = Error;
+
+    fn try_from(proto: clean::ListFilter) -> std::result::Result<Self, Self::Error> {
+        let typ = match proto.r#type {
+            Some(t) => Some((t, proto.custom_type).try_into()?),
+            None => None,
+        };
+        Ok(Self {
+            typ: typ,
+            joinable_only: proto.joinable_only,
+            host: proto.host_id.map(UserID),
+        })
+    }
+}
+
+impl From<ListFilter> for clean::ListFilter {
+    fn from(lf: ListFilter) -> Self {
+        let custom_type = lf.typ.as_ref().map_or("", |t| t.custom_type()).to_owned();
+        let t: Option<clean::SessionType> = lf.typ.map(|t| t.into());
+        Self {
+            r#type: t.map(|t| t.into()),
+            custom_type: custom_type,
+            joinable_only: lf.joinable_only,
+            host_id: lf.host.map(|h| h.0),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sessions {
+    data: Vec<SessionData>,
+    custom_types: Vec<String>,
+}
+
+impl Sessions {
+    pub fn new(data: &[SessionData], custom_types: &[String]) -> Self {
+        Self {
+            data: data.to_vec(),
+            custom_types: custom_types.to_vec(),
+        }
+    }
+
+    pub fn sessions<'a>(&'a self) -> &'a [SessionData] { &self.data }
+    pub fn custom_types<'a>(&'a self) -> &'a [String] { &self.custom_types }
+}
+
+impl TryFrom<clean::Sessions> for Sessions {
+    type Error = Error;
+
+    fn try_from(proto: clean::Sessions) -> std::result::Result<Self, Self::Error> {
+        let mut data = Vec::new();
+        for d in proto.data {
+            data.push(d.try_into()?);
+        }
+        Ok(Self {
+            data: data,
+            custom_types: proto.custom_types,
+        })
+    }
+}
+
+impl From<Sessions> for clean::Sessions {
+    fn from(s: Sessions) -> Self {
+        Self {
+            data: s.data.iter().map(|d| d.clone().into()).collect(),
+            custom_types: s.custom_types,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JoinInfo {
+    sid: SessionID,
+    uid: UserID,
+    user_name: String,
+    team: Option<TeamID>,
+    password: String,
+    join_token: Option<String>,
+}
+
+impl JoinInfo {
+    pub fn new(sid: SessionID, uid: UserID, user_name: &str) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+            user_name: user_name.to_owned(),
+            team: None,
+            password: String::new(),
+            join_token: None,
+        }
+    }
+
+    pub fn with_team(mut self, team: Option<TeamID>) -> Self {
+        self.team = team;
+        self
+    }
+
+    // needed to join locked sessions
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = password.to_owned();
+        self
+    }
+
+    // joins with a token the host handed out, instead of the password
+    pub fn with_join_token(mut self, token: &str) -> Self {
+        self.join_token = Some(token.to_owned());
+        self
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.user_name }
+    pub fn team(&self) -> Option<TeamID> { self.team }
+    pub fn password<'a>(&'a self) -> &'a str { &self.password }
+    pub fn join_token(&self) -> Option<&str> { self.join_token.as_deref() }
+}
+
+// team zero on the wire means no team
+impl From<clean::JoinInfo> for JoinInfo {
+    fn from(proto: clean::JoinInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+            user_name: proto.user_name,
+            team: if proto.team == 0 { None } else { Some(TeamID(proto.team)) },
+            password: proto.password,
+            join_token: proto.join_token,
+        }
+    }
+}
+
+impl From<JoinInfo> for clean::JoinInfo {
+    fn from(ji: JoinInfo) -> Self {
+        Self {
+            session_id: ji.sid.0,
+            user_id: ji.uid.0,
+            user_name: ji.user_name,
+            team: ji.team.map(|t| t.0).unwrap_or(0),
+            password: ji.password,
+            join_token: ji.join_token,
+        }
+    }
+}
+
+// lets one player into a session until it expires, handed out by the host to
+// share however they like
+#[derive(Clone, Debug)]
+pub struct JoinToken {
+    token: String,
+    sid: SessionID,
+    expires_at: SystemTime,
+}
+
+impl JoinToken {
+    #[cfg(feature = "server")]
+    pub fn generate(sid: SessionID, expires_at: SystemTime) -> Self {
+        Self {
+            token: format!("{:032x}", rand::thread_rng().gen::<u128>()),
+            sid: sid,
+            expires_at: expires_at,
+        }
+    }
+
+    pub fn token<'a>(&'a self) -> &'a str { &self.token }
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn expires_at(&self) -> SystemTime { self.expires_at }
+}
+
+impl From<clean::JoinToken> for JoinToken {
+    fn from(proto: clean::JoinToken) -> Self {
+        Self {
+            token: proto.token,
+            sid: SessionID(proto.session_id),
+            expires_at: from_unix(proto.expires_at),
+        }
+    }
+}
+
+impl From<JoinToken> for clean::JoinToken {
+    fn from(jt: JoinToken) -> Self {
+        Self {
+            token: jt.token,
+            session_id: jt.sid.0,
+            expires_at: to_unix(jt.expires_at),
+        }
+    }
+}
+
+// everything stored on the server about a user
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserDataExport {
+    uid: UserID,
+    chips: Option<u64>,
+    name: Option<String>,
+    profile: Option<Profile>,
+    games: Vec<PlayedGame>,
+    lobbies: Vec<SessionID>,
+    banned_from: Vec<SessionID>,
+}
+
+impl UserDataExport {
+    pub fn new(uid: UserID, chips: Option<u64>) -> Self {
+        Self {
+            uid: uid,
+            chips: chips,
+            name: None,
+            profile: None,
+            games: Vec::new(),
+            lobbies: Vec::new(),
+            banned_from: Vec::new(),
+        }
+    }
+
+    pub fn with_name(mut self, name: Option<&str>) -> Self {
+        self.name = name.map(|n| n.to_owned());
+        self
+    }
+
+    pub fn with_profile(mut self, profile: Option<Profile>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    // the games they finished, oldest first
+    pub fn with_games(mut self, games: Vec<PlayedGame>) -> Self {
+        self.games = games;
+        self
+    }
+
+    // the saved lobbies they are waiting in
+    pub fn with_lobbies(mut self, lobbies: Vec<SessionID>) -> Self {
+        self.lobbies = lobbies;
+        self
+    }
+
+    // the saved lobbies they are banned from
+    pub fn with_banned_from(mut self, banned_from: Vec<SessionID>) -> Self {
+        self.banned_from = banned_from;
+        self
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn chips(&self) -> Option<u64> { self.chips }
+    pub fn name(&self) -> Option<&str> { self.name.as_deref() }
+    pub fn profile(&self) -> Option<&Profile> { self.profile.as_ref() }
+    pub fn games<'a>(&'a self) -> &'a [PlayedGame] { &self.games }
+    pub fn lobbies<'a>(&'a self) -> &'a [SessionID] { &self.lobbies }
+    pub fn banned_from<'a>(&'a self) -> &'a [SessionID] { &self.banned_from }
+}
+
+impl From<clean::UserDataExport> for UserDataExport {
+    fn from(proto: clean::UserDataExport) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            chips: proto.chips,
+            name: proto.name,
+            profile: proto.profile.map(|p| p.into()),
+            // games of a type this side doesn't know are left out
+            games: proto.games.into_iter().filter_map(|g| g.try_into().ok()).collect(),
+            lobbies: proto.lobbies.into_iter().map(SessionID).collect(),
+            banned_from: proto.banned_from.into_iter().map(SessionID).collect(),
+        }
+    }
+}
+
+impl From<UserDataExport> for clean::UserDataExport {
+    fn from(ud: UserDataExport) -> Self {
+        Self {
+            user_id: ud.uid.0,
+            chips: ud.chips,
+            name: ud.name,
+            profile: ud.profile.map(|p| p.into()),
+            games: ud.games.into_iter().map(|g| g.into()).collect(),
+            lobbies: ud.lobbies.into_iter().map(|sid| sid.0).collect(),
+            banned_from: ud.banned_from.into_iter().map(|sid| sid.0).collect(),
+        }
+    }
+}
+
+// how a user did in one finished game, under the name they played it with
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayedGame {
+    sid: SessionID,
+    session_type: SessionType,
+    finished_at: SystemTime,
+    name: String,
+    score: Score,
+    winner: bool,
+}
+
+impl PlayedGame {
+    pub fn new(sid: SessionID, session_type: SessionType, finished_at: SystemTime, name: &str,
+               score: Score, winner: bool) -> Self {
+        Self {
+            sid: sid,
+            session_type: session_type,
+            finished_at: finished_at,
+            name: name.to_owned(),
+            score: score,
+            winner: winner,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn session_type(&self) -> &SessionType { &self.session_type }
+    pub fn finished_at(&self) -> SystemTime { self.finished_at }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn score(&self) -> Score { self.score }
+    pub fn winner(&self) -> bool { self.winner }
+}
+
+impl TryFrom<clean::PlayedGame> for PlayedGame {
+    type Error = Error;
+
+    fn try_from(proto: clean::PlayedGame) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            sid: SessionID(proto.session_id),
+            session_type: SessionType::try_from((proto.session_type, proto.custom_type))?,
+            finished_at: from_unix(proto.finished_at),
+            name: proto.name,
+            score: Score::new(proto.score),
+            winner: proto.winner,
+        })
+    }
+}
+
+impl From<PlayedGame> for clean::PlayedGame {
+    fn from(g: PlayedGame) -> Self {
+        let custom_type = g.session_type.custom_type().to_owned();
+        let t: clean::SessionType = g.session_type.into();
+        Self {
+            session_id: g.sid.0,
+            session_type: t.into(),
+            custom_type: custom_type,
+            finished_at: to_unix(g.finished_at),
+            name: g.name,
+            score: g.score.value(),
+            winner: g.winner,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BalanceInfo {
+    uid: UserID,
+    chips: u64,
+}
+
+impl BalanceInfo {
+    pub fn new(uid: UserID, chips: u64) -> Self {
+        Self {
+            uid: uid,
+            chips: chips,
+        }
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn chips(&self) -> u64 { self.chips }
+}
+
+impl From<clean::BalanceInfo> for BalanceInfo {
+    fn from(proto: clean::BalanceInfo) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            chips: proto.chips,
+        }
+    }
+}
+
+impl From<BalanceInfo> for clean::BalanceInfo {
+    fn from(bi: BalanceInfo) -> Self {
+        Self {
+            user_id: bi.uid.0,
+            chips: bi.chips,
+        }
+    }
+}
+
+// how a user has done over every game they have finished
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Profile {
+    uid: UserID,
+    name: String,
+    games_played: u32,
+    wins: u32,
+    favorite_type: Option<SessionType>,
+}
+
+impl Profile {
+    pub fn new(uid: UserID, name: &str, games_played: u32, wins: u32) -> Self {
+        Self {
+            uid: uid,
+            name: name.to_owned(),
+            games_played: games_played,
+            wins: wins,
+            favorite_type: None,
+        }
+    }
+
+    // the type of game they have played the most
+    pub fn with_favorite_type(mut self, st: Option<SessionType>) -> Self {
+        self.favorite_type = st;
+        self
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn games_played(&self) -> u32 { self.games_played }
+    pub fn wins(&self) -> u32 { self.wins }
+    pub fn losses(&self) -> u32 { self.games_played - self.wins }
+    pub fn favorite_type(&self) -> Option<&SessionType> { self.favorite_type.as_ref() }
+}
+
+impl From<clean::Profile> for Profile {
+    fn from(proto: clean::Profile) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            name: proto.name,
+            games_played: proto.games_played,
+            wins: proto.wins,
+            favorite_type: SessionType::try_from((proto.favorite_type,
+                                                  proto.favorite_custom_type)).ok(),
+        }
+    }
+}
+
+impl From<Profile> for clean::Profile {
+    fn from(p: Profile) -> Self {
+        let (t, favorite_custom_type) = match p.favorite_type {
+            Some(st) => {
+                let custom_type = st.custom_type().to_owned();
+                (st.into(), custom_type)
+            }
+            None => (clean::SessionType::TypeUnspecified, String::new()),
+        };
+        Self {
+            user_id: p.uid.0,
+            name: p.name,
+            games_played: p.games_played,
+            wins: p.wins,
+            favorite_type: t.into(),
+            favorite_custom_type: favorite_custom_type,
+        }
+    }
+}
+
+pub struct StartInfo {
+    sid: SessionID,
+    uid: UserID,
+}
+
+impl StartInfo {
+    pub fn new(sid: SessionID, uid: UserID) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+}
+
+impl From<clean::StartInfo> for StartInfo {
+    fn from(proto: clean::StartInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+        }
+    }
+}
+
+impl From<StartInfo> for clean::StartInfo {
+    fn from(si: StartInfo) -> Self {
+        Self {
+            session_id: si.sid.0,
+            user_id: si.uid.0,
+        }
+    }
+}
+
+pub struct LeaveInfo {
+    sid: SessionID,
+    uid: UserID,
+}
+
+impl LeaveInfo {
+    pub fn new(sid: SessionID, uid: UserID) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+}
+
+impl From<clean::LeaveInfo> for LeaveInfo {
+    fn from(proto: clean::LeaveInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+        }
+    }
+}
+
+impl From<LeaveInfo> for clean::LeaveInfo {
+    fn from(li: LeaveInfo) -> Self {
+        Self {
+            session_id: li.sid.0,
+            user_id: li.uid.0,
+        }
+    }
+}
+
+pub struct CancelInfo {
+    sid: SessionID,
+    uid: UserID,
+}
+
+impl CancelInfo {
+    pub fn new(sid: SessionID, uid: UserID) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+}
+
+impl From<clean::CancelInfo> for CancelInfo {
+    fn from(proto: clean::CancelInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+        }
+    }
+}
+
+impl From<CancelInfo> for clean::CancelInfo {
+    fn from(ci: CancelInfo) -> Self {
+        Self {
+            session_id: ci.sid.0,
+            user_id: ci.uid.0,
+        }
+    }
+}
+
+pub struct KickInfo {
+    sid: SessionID,
+    host: UserID,
+    target: UserID,
+}
+
+impl KickInfo {
+    pub fn new(sid: SessionID, host: UserID, target: UserID) -> Self {
+        Self {
+            sid: sid,
+            host: host,
+            target: target,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn host_id(&self) -> UserID { self.host }
+    pub fn target_id(&self) -> UserID { self.target }
+}
+
+impl From<clean::KickInfo> for KickInfo {
+    fn from(proto: clean::KickInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            host: UserID(proto.host_id),
+            target: UserID(proto.target_id),
+        }
+    }
+}
+
+impl From<KickInfo> for clean::KickInfo {
+    fn from(ki: KickInfo) -> Self {
+        Self {
+            session_id: ki.sid.0,
+            host_id: ki.host.0,
+            target_id: ki.target.0,
+        }
+    }
+}
+
+pub struct JoinAnswer {
+    sid: SessionID,
+    host: UserID,
+    uid: UserID,
+    accept: bool,
+}
+
+impl JoinAnswer {
+    pub fn new(sid: SessionID, host: UserID, uid: UserID, accept: bool) -> Self {
+        Self {
+            sid: sid,
+            host: host,
+            uid: uid,
+            accept: accept,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn host_id(&self) -> UserID { self.host }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn accept(&self) -> bool { self.accept }
+}
+
+impl From<clean::JoinAnswer> for JoinAnswer {
+    fn from(proto: clean::JoinAnswer) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            host: UserID(proto.host_id),
+            uid: UserID(proto.user_id),
+            accept: proto.accept,
+        }
+    }
+}
+
+impl From<JoinAnswer> for clean::JoinAnswer {
+    fn from(ja: JoinAnswer) -> Self {
+        Self {
+            session_id: ja.sid.0,
+            host_id: ja.host.0,
+            user_id: ja.uid.0,
+            accept: ja.accept,
+        }
+    }
+}
+
+pub struct RematchInfo {
+    sid: SessionID,
+    host: UserID,
+}
+
+impl RematchInfo {
+    pub fn new(sid: SessionID, host: UserID) -> Self {
+        Self {
+            sid: sid,
+            host: host,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn host_id(&self) -> UserID { self.host }
+}
+
+impl From<clean::RematchInfo> for RematchInfo {
+    fn from(proto: clean::RematchInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            host: UserID(proto.host_id),
+        }
+    }
+}
+
+impl From<RematchInfo> for clean::RematchInfo {
+    fn from(ri: RematchInfo) -> Self {
+        Self {
+            session_id: ri.sid.0,
+            host_id: ri.host.0,
+        }
+    }
+}
+
+pub struct TransferInfo {
+    sid: SessionID,
+    host: UserID,
+    target: UserID,
+}
+
+impl TransferInfo {
+    pub fn new(sid: SessionID, host: UserID, target: UserID) -> Self {
+        Self {
+            sid: sid,
+            host: host,
+            target: target,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn host_id(&self) -> UserID { self.host }
+    pub fn target_id(&self) -> UserID { self.target }
+}
+
+impl From<clean::TransferInfo> for TransferInfo {
+    fn from(proto: clean::TransferInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            host: UserID(proto.host_id),
+            target: UserID(proto.target_id),
+        }
+    }
+}
+
+impl From<TransferInfo> for clean::TransferInfo {
+    fn from(ti: TransferInfo) -> Self {
+        Self {
+            session_id: ti.sid.0,
+            host_id: ti.host.0,
+            target_id: ti.target.0,
+        }
+    }
+}
+
+pub struct VoteKickInfo {
+    sid: SessionID,
+    uid: UserID,
+    target: UserID,
+}
+
+impl VoteKickInfo {
+    pub fn new(sid: SessionID, uid: UserID, target: UserID) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+            target: target,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn target_id(&self) -> UserID { self.target }
+}
+
+impl From<clean::VoteKickInfo> for VoteKickInfo {
+    fn from(proto: clean::VoteKickInfo) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+            target: UserID(proto.target_id),
+        }
+    }
+}
+
+impl From<VoteKickInfo> for clean::VoteKickInfo {
+    fn from(vi: VoteKickInfo) -> Self {
+        Self {
+            session_id: vi.sid.0,
+            user_id: vi.uid.0,
+            target_id: vi.target.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChatMessage {
+    sid: SessionID,
+    uid: UserID,
+    name: String,
+    text: String,
+}
+
+impl ChatMessage {
+    pub fn new(sid: SessionID, uid: UserID, name: &str, text: &str) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+            name: name.to_owned(),
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn text<'a>(&'a self) -> &'a str { &self.text }
+}
+
+impl From<clean::ChatMessage> for ChatMessage {
+    fn from(proto: clean::ChatMessage) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+            name: proto.user_name,
+            text: proto.text,
+        }
+    }
+}
+
+impl From<ChatMessage> for clean::ChatMessage {
+    fn from(cm: ChatMessage) -> Self {
+        Self {
+            session_id: cm.sid.0,
+            user_id: cm.uid.0,
+            user_name: cm.name,
+            text: cm.text,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirectMessage {
+    from: UserID,
+    from_name: String,
+    to: UserID,
+    text: String,
+}
+
+impl DirectMessage {
+    pub fn new(from: UserID, from_name: &str, to: UserID, text: &str) -> Self {
+        Self {
+            from: from,
+            from_name: from_name.to_owned(),
+            to: to,
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn from_id(&self) -> UserID { self.from }
+    pub fn from_name<'a>(&'a self) -> &'a str { &self.from_name }
+    pub fn to_id(&self) -> UserID { self.to }
+    pub fn text<'a>(&'a self) -> &'a str { &self.text }
+}
+
+impl From<clean::DirectMessage> for DirectMessage {
+    fn from(proto: clean::DirectMessage) -> Self {
+        Self {
+            from: UserID(proto.from_id),
+            from_name: proto.from_name,
+            to: UserID(proto.to_id),
+            text: proto.text,
+        }
+    }
+}
+
+impl From<DirectMessage> for clean::DirectMessage {
+    fn from(dm: DirectMessage) -> Self {
+        Self {
+            from_id: dm.from.0,
+            from_name: dm.from_name,
+            to_id: dm.to.0,
+            text: dm.text,
+        }
+    }
+}
+
+pub struct BlockInfo {
+    user_id: UserID,
+    target_id: UserID,
+}
+
+impl BlockInfo {
+    pub fn new(uid: UserID, target: UserID) -> Self {
+        Self {
+            user_id: uid,
+            target_id: target,
+        }
+    }
+
+    pub fn user_id(&self) -> UserID { self.user_id }
+    pub fn target_id(&self) -> UserID { self.target_id }
+}
+
+impl From<clean::BlockInfo> for BlockInfo {
+    fn from(proto: clean::BlockInfo) -> Self {
+        Self {
+            user_id: UserID(proto.user_id),
+            target_id: UserID(proto.target_id),
+        }
+    }
+}
+
+impl From<BlockInfo> for clean::BlockInfo {
+    fn from(bi: BlockInfo) -> Self {
+        Self {
+            user_id: bi.user_id.0,
+            target_id: bi.target_id.0,
+        }
+    }
+}
+
+pub struct FriendInfo {
+    user_id: UserID,
+    friend_id: UserID,
+}
+
+impl FriendInfo {
+    pub fn new(uid: UserID, friend: UserID) -> Self {
+        Self {
+            user_id: uid,
+            friend_id: friend,
+        }
+    }
+
+    pub fn user_id(&self) -> UserID { self.user_id }
+    pub fn friend_id(&self) -> UserID { self.friend_id }
+}
+
+impl From<clean::FriendInfo> for FriendInfo {
+    fn from(proto: clean::FriendInfo) -> Self {
+        Self {
+            user_id: UserID(proto.user_id),
+            friend_id: UserID(proto.friend_id),
+        }
+    }
+}
+
+impl From<FriendInfo> for clean::FriendInfo {
+    fn from(fi: FriendInfo) -> Self {
+        Self {
+            user_id: fi.user_id.0,
+            friend_id: fi.friend_id.0,
+        }
+    }
+}
+
+// mutual once the friend has added the user back
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Friend {
+    uid: UserID,
+    name: String,
+    mutual: bool,
+}
+
+impl Friend {
+    pub fn new(uid: UserID, name: &str, mutual: bool) -> Self {
+        Self {
+            uid: uid,
+            name: name.to_owned(),
+            mutual: mutual,
+        }
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn mutual(&self) -> bool { self.mutual }
+}
+
+impl From<clean::Friend> for Friend {
+    fn from(proto: clean::Friend) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            name: proto.name,
+            mutual: proto.mutual,
+        }
+    }
+}
+
+impl From<Friend> for clean::Friend {
+    fn from(f: Friend) -> Self {
+        Self {
+            user_id: f.uid.0,
+            name: f.name,
+            mutual: f.mutual,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Invitation {
+    from: UserID,
+    from_name: String,
+    to: UserID,
+    sid: SessionID,
+    join_token: Option<String>,
+}
+
+impl Invitation {
+    pub fn new(from: UserID, from_name: &str, to: UserID, sid: SessionID) -> Self {
+        Self {
+            from: from,
+            from_name: from_name.to_owned(),
+            to: to,
+            sid: sid,
+            join_token: None,
+        }
+    }
+
+    // lets the friend in without the password
+    pub fn with_join_token(mut self, token: Option<&str>) -> Self {
+        self.join_token = token.map(|t| t.to_owned());
+        self
+    }
+
+    pub fn from_id(&self) -> UserID { self.from }
+    pub fn from_name<'a>(&'a self) -> &'a str { &self.from_name }
+    pub fn to_id(&self) -> UserID { self.to }
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn join_token(&self) -> Option<&str> { self.join_token.as_deref() }
+}
+
+impl From<clean::Invitation> for Invitation {
+    fn from(proto: clean::Invitation) -> Self {
+        Self {
+            from: UserID(proto.from_id),
+            from_name: proto.from_name,
+            to: UserID(proto.to_id),
+            sid: SessionID(proto.session_id),
+            join_token: proto.join_token,
+        }
+    }
+}
+
+impl From<Invitation> for clean::Invitation {
+    fn from(inv: Invitation) -> Self {
+        Self {
+            from_id: inv.from.0,
+            from_name: inv.from_name,
+            to_id: inv.to.0,
+            session_id: inv.sid.0,
+            join_token: inv.join_token,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OnlineUser {
+    uid: UserID,
+    name: String,
+}
+
+impl OnlineUser {
+    pub fn new(uid: UserID, name: &str) -> Self {
+        Self {
+            uid: uid,
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+}
+
+impl From<clean::OnlineUser> for OnlineUser {
+    fn from(proto: clean::OnlineUser) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            name: proto.name,
+        }
+    }
+}
+
+impl From<OnlineUser> for clean::OnlineUser {
+    fn from(ou: OnlineUser) -> Self {
+        Self {
+            user_id: ou.uid.0,
+            name: ou.name,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PresenceChange {
+    uid: UserID,
+    name: String,
+    online: bool,
+}
+
+impl PresenceChange {
+    pub fn new(uid: UserID, name: &str, online: bool) -> Self {
+        Self {
+            uid: uid,
+            name: name.to_owned(),
+            online: online,
+        }
+    }
+
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn online(&self) -> bool { self.online }
+}
+
+impl From<clean::PresenceChange> for PresenceChange {
+    fn from(proto: clean::PresenceChange) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            name: proto.name,
+            online: proto.online,
+        }
+    }
+}
+
+impl From<PresenceChange> for clean::PresenceChange {
+    fn from(pc: PresenceChange) -> Self {
+        Self {
+            user_id: pc.uid.0,
+            name: pc.name,
+            online: pc.online,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct EventRegister {
+    sid: SessionID,
+    uid: UserID,
+}
+
+impl EventRegister {
+    pub fn new(sid: SessionID, uid: UserID) -> Self {
+        Self {
+            sid: sid,
+            uid: uid,
+        }
+    }
+
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn user_id(&self) -> UserID { self.uid }
+}
+
+impl From<clean::EventRegister> for EventRegister {
+    fn from(proto: clean::EventRegister) -> Self {
+        Self {
+            sid: SessionID(proto.session_id),
+            uid: UserID(proto.user_id),
+        }
+    }
+}
+
+impl From<EventRegister> for clean::EventRegister {
+    fn from(er: EventRegister) -> Self {
+        Self {
+            session_id: er.sid.0,
+            user_id: er.uid.0,
+            reconnect_token: String::new(),
+            resume_from: 0,
+        }
+    }
+}
+
+// metadata key the reconnect token is returned under from PlaySession
+pub const RECONNECT_TOKEN_KEY: &str = "x-reconnect-token";
+
+// proves ownership of an event stream registration, so only the client that
+// first registered for a session and user can reconnect and respond to it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReconnectToken(pub String);
+
+impl ReconnectToken {
+    #[cfg(feature = "server")]
+    pub fn generate() -> Self {
+        Self(format!("{:032x}", rand::thread_rng().gen::<u128>()))
+    }
+}
+
+impl From<clean::NameRejection> for NameError {
+    fn from(proto: clean::NameRejection) -> Self {
+        match clean::NameProblem::try_from(proto.problem) {
+            Ok(clean::NameProblem::NameTooLong) => NameError::TooLong(proto.max_length as usize),
+            Ok(clean::NameProblem::NameControlCharacters) => NameError::ControlCharacters,
+            Ok(clean::NameProblem::NameFiltered) => NameError::Filtered,
+            _ => NameError::Empty,
+        }
+    }
+}
+
+impl From<NameError> for clean::NameRejection {
+    fn from(ne: NameError) -> Self {
+        let (problem, max_length) = match ne {
+            NameError::Empty => (clean::NameProblem::NameEmpty, 0),
+            NameError::TooLong(max) => (clean::NameProblem::NameTooLong, max as u32),
+            NameError::ControlCharacters => (clean::NameProblem::NameControlCharacters, 0),
+            NameError::Filtered => (clean::NameProblem::NameFiltered, 0),
+        };
+        Self {
+            problem: problem.into(),
+            max_length: max_length,
+        }
+    }
+}
+
+// an account banned from the whole server. Names are matched ignoring case
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountBan {
+    User(UserID),
+    Name(String),
+}
+
+impl TryFrom<clean::AccountBan> for AccountBan {
+    type Error = Error;
+
+    fn try_from(proto: clean::AccountBan) -> std::result::Result<Self, Self::Error> {
+        match proto.target {
+            Some(clean::account_ban::Target::UserId(uid)) => Ok(AccountBan::User(UserID(uid))),
+            Some(clean::account_ban::Target::Name(name)) => Ok(AccountBan::Name(name)),
+            None => Err(Error::InvalidAccountBan),
+        }
+    }
+}
+
+impl From<AccountBan> for clean::AccountBan {
+    fn from(ban: AccountBan) -> Self {
+        let target = match ban {
+            AccountBan::User(uid) => clean::account_ban::Target::UserId(uid.0),
+            AccountBan::Name(name) => clean::account_ban::Target::Name(name),
+        };
+        Self {
+            target: Some(target),
+        }
+    }
+}
+
+// how busy the server is, for operators
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerStats {
+    sessions: u32,
+    games_running: u32,
+    users_online: u32,
+}
+
+impl ServerStats {
+    pub fn new(sessions: u32, games_running: u32, users_online: u32) -> Self {
+        Self {
+            sessions: sessions,
+            games_running: games_running,
+            users_online: users_online,
+        }
+    }
+
+    pub fn sessions(&self) -> u32 { self.sessions }
+    pub fn games_running(&self) -> u32 { self.games_running }
+    pub fn users_online(&self) -> u32 { self.users_online }
+}
+
+impl From<clean::ServerStats> for ServerStats {
+    fn from(proto: clean::ServerStats) -> Self {
+        Self {
+            sessions: proto.sessions,
+            games_running: proto.games_running,
+            users_online: proto.users_online,
+        }
+    }
+}
+
+impl From<ServerStats> for clean::ServerStats {
+    fn from(ss: ServerStats) -> Self {
+        Self {
+            sessions: ss.sessions,
+            games_running: ss.games_running,
+            users_online: ss.users_online,
+        }
+    }
+}
+
+// the certificate a client presented when it connected over mutual TLS, in DER
+// form, identifying the connection whatever token is sent over it
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PeerIdentity(pub Vec<u8>);
+
+// metadata key every request carries the auth token under
+pub const AUTH_TOKEN_KEY: &str = "x-auth-token";
+
+// issued to a user when they register, and sent with every request after that
+// to prove who is making it
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct AuthToken(pub String);
+
+impl AuthToken {
+    #[cfg(feature = "server")]
+    pub fn generate() -> Self {
+        Self(format!("{:032x}", rand::thread_rng().gen::<u128>()))
+    }
+}
+
+// metadata key a bearer token from an identity provider the server trusts is
+// sent under, instead of an auth token the server issued
+pub const BEARER_KEY: &str = "authorization";
+
+pub const ADMIN_TOKEN_KEY: &str = "x-admin-token";
+
+// configured on the server to allow operators to manage any user's data
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminToken(pub String);
+
+// who a request counts against when limiting how often requests can be made,
+// the user it was authenticated as, or the address it came from without one
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RateKey {
+    User(UserID),
+    Address(IpAddr),
+}
+
+// how messages are compressed on the wire
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        match s {
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(Error::UnknownCompression(s.to_owned())),
+        }
+    }
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
+}
+
+// who made a request on a user's data, the user themselves or an admin
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Requester {
+    Admin,
+    User(UserID),
+}
+
+impl From<(EventRegister, ReconnectToken)> for clean::EventRegister {
+    fn from(er: (EventRegister, ReconnectToken)) -> Self {
+        let (er, token) = er;
+        Self {
+            session_id: er.sid.0,
+            user_id: er.uid.0,
+            reconnect_token: token.0,
+            resume_from: 0,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ping {
+    text: String,
+}
+
+impl Ping {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn text<'a>(&'a self) -> &'a str { &self.text }
+}
+
+impl From<clean::Ping> for Ping {
+    fn from(proto: clean::Ping) -> Self {
+        Self {
+            text: proto.text,
+        }
+    }
+}
+
+impl From<Ping> for clean::Ping {
+    fn from(p: Ping) -> Self {
+        Self {
+            text: p.text,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pong {
+    text: String,
+}
+
+impl Pong {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn text<'a>(&'a self) -> &'a str { &self.text }
+}
+
+impl From<clean::Pong> for Pong {
+    fn from(proto: clean::Pong) -> Self {
+        Self {
+            text: proto.text,
+        }
+    }
+}
+
+impl From<Pong> for clean::Pong {
+    fn from(p: Pong) -> Self {
+        Self {
+            text: p.text,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RollDice {
+    sides: DiceSides,
+    count: DiceCount,
+}
+
+impl RollDice {
+    pub fn new(sides: DiceSides, count: DiceCount) -> Self {
+        Self {
+            sides: sides,
+            count: count,
+        }
+    }
+
+    pub fn sides(&self) -> DiceSides { self.sides }
+    pub fn count(&self) -> DiceCount { self.count }
+}
+
+impl TryFrom<clean::RollDice> for RollDice {
+    type Error = Error;
+
+    fn try_from(proto: clean::RollDice) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            // checked whole, so a value past a u8 can't wrap round to a valid one
+            sides: DiceSides::new(u8::try_from(proto.sides)
+                                  .map_err(|_| Error::InvalidDiceSides(proto.sides))?)?,
+            count: DiceCount::new(u8::try_from(proto.count)
+                                  .map_err(|_| Error::InvalidDiceCount(proto.count))?)?,
+        })
+    }
+}
+
+impl From<RollDice> for clean::RollDice {
+    fn from(rd: RollDice) -> Self {
+        Self {
+            sides: rd.sides.value() as u32,
+            count: rd.count.value() as u32,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlipCoin {
+    count: CoinCount,
+}
+
+impl FlipCoin {
+    pub fn new(count: CoinCount) -> Self {
+        Self {
+            count: count,
+        }
+    }
+
+    pub fn count(&self) -> CoinCount { self.count }
+}
+
+impl TryFrom<clean::FlipCoin> for FlipCoin {
+    type Error = Error;
+
+    fn try_from(proto: clean::FlipCoin) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            count: CoinCount::new(u8::try_from(proto.count)
+                                  .map_err(|_| Error::InvalidCoinCount(proto.count))?)?,
+        })
+    }
+}
+
+impl From<FlipCoin> for clean::FlipCoin {
+    fn from(fc: FlipCoin) -> Self {
+        Self {
+            count: fc.count.value() as u32,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiceGuess {
+    number: Vec<u8>,
+}
+
+impl DiceGuess {
+    pub fn new(number: &[u8]) -> Self {
+        Self {
+            number: number.to_vec(),
         }
     }
 
-    pub fn session_id(&self) -> SessionID { self.sid }
-    pub fn session_type(&self) -> SessionType { self.typ }
-    pub fn users<'a>(&'a self) -> &'a [String] { &self.users }
+    pub fn number<'a>(&'a self) -> &'a [u8] { &self.number }
 }
 
-impl TryFrom<clean::SessionData> for SessionData {
-    type Error = Error;
-
-    fn try_from(proto: clean::SessionData) -> std::result::Result<Self, Self::Error> {
-        Ok(Self {
-            sid: SessionID(proto.session_id),
-            typ: proto.r#type.try_into()?,
-            users: proto.users,
-        })
+impl From<clean::DiceGuess> for DiceGuess {
+    fn from(proto: clean::DiceGuess) -> Self {
+        Self {
+            number: proto.number.iter().map(|n| *n as u8).collect(),
+        }
     }
 }
 
-impl From<SessionData> for clean::SessionData {
-    fn from(sd: SessionData) -> Self {
-        let t: clean::SessionType = sd.typ.into();
+impl From<DiceGuess> for clean::DiceGuess {
+    fn from(dg: DiceGuess) -> Self {
         Self {
-            session_id: sd.sid.0,
-            r#type: t.into(),
-            users: sd.users,
+            number: dg.number.iter().map(|n| *n as u32).collect(),
         }
     }
 }
 
-pub struct Sessions {
-    data: Vec<SessionData>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoinGuess {
+    coins: Vec<Coin>,
 }
 
-impl Sessions {
-    pub fn new(data: &[SessionData]) -> Self {
+impl CoinGuess {
+    pub fn new(coins: &[Coin]) -> Self {
         Self {
-            data: data.to_vec(),
+            coins: coins.to_vec(),
         }
     }
 
-    pub fn sessions<'a>(&'a self) -> &'a [SessionData] { &self.data }
+    pub fn coins<'a>(&'a self) -> &'a [Coin] { &self.coins }
 }
 
-impl TryFrom<clean::Sessions> for Sessions {
+impl TryFrom<clean::CoinGuess> for CoinGuess {
     type Error = Error;
 
-    fn try_from(proto: clean::Sessions) -> std::result::Result<Self, Self::Error> {
-        let mut data = Vec::new();
-        for d in proto.data {
-            data.push(d.try_into()?);
+    fn try_from(proto: clean::CoinGuess) -> std::result::Result<Self, Self::Error> {
+        let mut coins = Vec::new();
+        for coin in proto.coins {
+            coins.push(coin.try_into()?);
         }
         Ok(Self {
-            data: data,
+            coins: coins,
         })
     }
 }
 
-impl From<Sessions> for clean::Sessions {
-    fn from(s: Sessions) -> Self {
+impl From<CoinGuess> for clean::CoinGuess {
+    fn from(cg: CoinGuess) -> Self {
         Self {
-            data: s.data.iter().map(|d| d.clone().into()).collect(),
+            coins: cg.coins.iter()
+                .map(|c| c.clone().into())
+                .map(|c: clean::Coin| c.into())
+                .collect(),
         }
     }
 }
 
-pub struct JoinInfo {
-    sid: SessionID,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Winner {
     uid: UserID,
-    user_name: String,
+    name: String,
+    payout: u64,
 }
 
-impl JoinInfo {
-    pub fn new(sid: SessionID, uid: UserID, user_name: &str) -> Self {
+impl Winner {
+    pub fn new(uid: UserID, name: &str, payout: u64) -> Self {
         Self {
-            sid: sid,
             uid: uid,
-            user_name: user_name.to_owned(),
+            name: name.to_owned(),
+            payout: payout,
         }
     }
 
-    pub fn session_id(&self) -> SessionID { self.sid }
     pub fn user_id(&self) -> UserID { self.uid }
-    pub fn user_name<'a>(&'a self) -> &'a str { &self.user_name }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn payout(&self) -> u64 { self.payout }
 }
 
-impl From<clean::JoinInfo> for JoinInfo {
-    fn from(proto: clean::JoinInfo) -> Self {
+impl From<clean::Winner> for Winner {
+    fn from(proto: clean::Winner) -> Self {
         Self {
-            sid: SessionID(proto.session_id),
             uid: UserID(proto.user_id),
-            user_name: proto.user_name,
+            name: proto.user_name,
+            payout: proto.payout,
         }
     }
 }
 
-impl From<JoinInfo> for clean::JoinInfo {
-    fn from(ji: JoinInfo) -> Self {
+impl From<Winner> for clean::Winner {
+    fn from(w: Winner) -> Self {
         Self {
-            session_id: ji.sid.0,
-            user_id: ji.uid.0,
-            user_name: ji.user_name,
+            user_id: w.uid.0,
+            user_name: w.name,
+            payout: w.payout,
         }
     }
 }
 
-pub struct StartInfo {
-    sid: SessionID,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoundResult {
+    round: u8,
+    uid: UserID,
+    name: String,
+    wins: u8,
 }
 
-impl StartInfo {
-    pub fn new(sid: SessionID) -> Self {
+impl RoundResult {
+    pub fn new(round: u8, uid: UserID, name: &str, wins: u8) -> Self {
         Self {
-            sid: sid,
+            round: round,
+            uid: uid,
+            name: name.to_owned(),
+            wins: wins,
         }
     }
 
-    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn round(&self) -> u8 { self.round }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn wins(&self) -> u8 { self.wins }
 }
 
-impl From<clean::StartInfo> for StartInfo {
-    fn from(proto: clean::StartInfo) -> Self {
+impl From<clean::RoundResult> for RoundResult {
+    fn from(proto: clean::RoundResult) -> Self {
         Self {
-            sid: SessionID(proto.session_id),
+            round: proto.round as u8,
+            uid: UserID(proto.user_id),
+            name: proto.user_name,
+            wins: proto.wins as u8,
         }
     }
 }
 
-impl From<StartInfo> for clean::StartInfo {
-    fn from(si: StartInfo) -> Self {
+impl From<RoundResult> for clean::RoundResult {
+    fn from(rr: RoundResult) -> Self {
         Self {
-            session_id: si.sid.0,
+            round: rr.round as u32,
+            user_id: rr.uid.0,
+            user_name: rr.name,
+            wins: rr.wins as u32,
         }
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct EventRegister {
-    sid: SessionID,
-    uid: UserID,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TeamWinner {
+    team: TeamID,
+    members: Vec<String>,
+    payout: u64,
 }
 
-impl EventRegister {
-    pub fn new(sid: SessionID, uid: UserID) -> Self {
+impl TeamWinner {
+    pub fn new(team: TeamID, members: &[String], payout: u64) -> Self {
         Self {
-            sid: sid,
-            uid: uid,
+            team: team,
+            members: members.to_vec(),
+            payout: payout,
         }
     }
 
-    pub fn session_id(&self) -> SessionID { self.sid }
-    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn team(&self) -> TeamID { self.team }
+    pub fn members<'a>(&'a self) -> &'a [String] { &self.members }
+    pub fn payout(&self) -> u64 { self.payout }
 }
 
-impl From<clean::EventRegister> for EventRegister {
-    fn from(proto: clean::EventRegister) -> Self {
+impl From<clean::TeamWinner> for TeamWinner {
+    fn from(proto: clean::TeamWinner) -> Self {
         Self {
-            sid: SessionID(proto.session_id),
-            uid: UserID(proto.user_id),
+            team: TeamID(proto.team),
+            members: proto.members,
+            payout: proto.payout,
         }
     }
 }
 
-impl From<EventRegister> for clean::EventRegister {
-    fn from(er: EventRegister) -> Self {
+impl From<TeamWinner> for clean::TeamWinner {
+    fn from(tw: TeamWinner) -> Self {
         Self {
-            session_id: er.sid.0,
-            user_id: er.uid.0,
+            team: tw.team.0,
+            members: tw.members,
+            payout: tw.payout,
         }
     }
 }
 
-pub struct Ping {
-    text: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TeamRoundResult {
+    round: u8,
+    team: TeamID,
+    wins: u8,
 }
 
-impl Ping {
-    pub fn new(text: &str) -> Self {
+impl TeamRoundResult {
+    pub fn new(round: u8, team: TeamID, wins: u8) -> Self {
         Self {
-            text: text.to_owned(),
+            round: round,
+            team: team,
+            wins: wins,
         }
     }
 
-    pub fn text<'a>(&'a self) -> &'a str { &self.text }
+    pub fn round(&self) -> u8 { self.round }
+    pub fn team(&self) -> TeamID { self.team }
+    pub fn wins(&self) -> u8 { self.wins }
 }
 
-impl From<clean::Ping> for Ping {
-    fn from(proto: clean::Ping) -> Self {
+impl From<clean::TeamRoundResult> for TeamRoundResult {
+    fn from(proto: clean::TeamRoundResult) -> Self {
         Self {
-            text: proto.text,
+            round: proto.round as u8,
+            team: TeamID(proto.team),
+            wins: proto.wins as u8,
         }
     }
 }
 
-impl From<Ping> for clean::Ping {
-    fn from(p: Ping) -> Self {
+impl From<TeamRoundResult> for clean::TeamRoundResult {
+    fn from(tr: TeamRoundResult) -> Self {
         Self {
-            text: p.text,
+            round: tr.round as u32,
+            team: tr.team.0,
+            wins: tr.wins as u32,
         }
     }
 }
 
-pub struct Pong {
-    text: String,
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScoreEntry {
+    uid: UserID,
+    name: String,
+    score: Score,
 }
 
-impl Pong {
-    pub fn new(text: &str) -> Self {
+impl ScoreEntry {
+    pub fn new(uid: UserID, name: &str, score: Score) -> Self {
         Self {
-            text: text.to_owned(),
+            uid: uid,
+            name: name.to_owned(),
+            score: score,
         }
     }
 
-    pub fn text<'a>(&'a self) -> &'a str { &self.text }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn score(&self) -> Score { self.score }
 }
 
-impl From<clean::Pong> for Pong {
-    fn from(proto: clean::Pong) -> Self {
+impl From<clean::ScoreEntry> for ScoreEntry {
+    fn from(proto: clean::ScoreEntry) -> Self {
         Self {
-            text: proto.text,
+            uid: UserID(proto.user_id),
+            name: proto.user_name,
+            score: Score::new(proto.score),
         }
     }
 }
 
-impl From<Pong> for clean::Pong {
-    fn from(p: Pong) -> Self {
+impl From<ScoreEntry> for clean::ScoreEntry {
+    fn from(se: ScoreEntry) -> Self {
         Self {
-            text: p.text,
+            user_id: se.uid.0,
+            user_name: se.name,
+            score: se.score.value(),
         }
     }
 }
 
-pub struct RollDice {
-    sides: u8,
-    count: u8,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scoreboard {
+    round: u32,
+    entries: Vec<ScoreEntry>,
 }
 
-impl RollDice {
-    pub fn new(sides: u8, count: u8) -> Self {
+impl Scoreboard {
+    pub fn new(round: u32, entries: &[ScoreEntry]) -> Self {
         Self {
-            sides: sides,
-            count: count,
+            round: round,
+            entries: entries.to_vec(),
         }
     }
 
-    pub fn sides(&self) -> u8 { self.sides }
-    pub fn count(&self) -> u8 { self.count }
+    pub fn round(&self) -> u32 { self.round }
+    pub fn entries<'a>(&'a self) -> &'a [ScoreEntry] { &self.entries }
 }
 
-impl From<clean::RollDice> for RollDice {
-    fn from(proto: clean::RollDice) -> Self {
+impl From<clean::Scoreboard> for Scoreboard {
+    fn from(proto: clean::Scoreboard) -> Self {
         Self {
-            sides: proto.sides as u8,
-            count: proto.count as u8,
+            round: proto.round,
+            entries: proto.entries.into_iter().map(|e| e.into()).collect(),
         }
     }
 }
 
-impl From<RollDice> for clean::RollDice {
-    fn from(rd: RollDice) -> Self {
+impl From<Scoreboard> for clean::Scoreboard {
+    fn from(sb: Scoreboard) -> Self {
         Self {
-            sides: rd.sides as u32,
-            count: rd.count as u32,
+            round: sb.round,
+            entries: sb.entries.into_iter().map(|e| e.into()).collect(),
         }
     }
 }
 
-pub struct FlipCoin {
-    count: u8,
+// the scores each player finished a game with
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameEnded {
+    sid: SessionID,
+    scores: Vec<ScoreEntry>,
 }
 
-impl FlipCoin {
-    pub fn new(count: u8) -> Self {
+impl GameEnded {
+    pub fn new(sid: SessionID, scores: &[ScoreEntry]) -> Self {
         Self {
-            count: count,
+            sid: sid,
+            scores: scores.to_vec(),
         }
     }
 
-    pub fn count(&self) -> u8 { self.count }
+    pub fn session_id(&self) -> SessionID { self.sid }
+    pub fn scores<'a>(&'a self) -> &'a [ScoreEntry] { &self.scores }
 }
 
-impl From<clean::FlipCoin> for FlipCoin {
-    fn from(proto: clean::FlipCoin) -> Self {
+impl From<clean::GameEnded> for GameEnded {
+    fn from(proto: clean::GameEnded) -> Self {
         Self {
-            count: proto.count as u8,
+            sid: SessionID(proto.session_id),
+            scores: proto.scores.into_iter().map(|e| e.into()).collect(),
         }
     }
 }
 
-impl From<FlipCoin> for clean::FlipCoin {
-    fn from(fc: FlipCoin) -> Self {
+impl From<GameEnded> for clean::GameEnded {
+    fn from(ge: GameEnded) -> Self {
         Self {
-            count: fc.count as u32,
+            session_id: ge.sid.0,
+            scores: ge.scores.into_iter().map(|e| e.into()).collect(),
         }
     }
 }
 
-pub struct DiceGuess {
-    number: Vec<u8>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Champion {
+    uid: UserID,
+    name: String,
+    score: Score,
 }
 
-impl DiceGuess {
-    pub fn new(number: &[u8]) -> Self {
+impl Champion {
+    pub fn new(uid: UserID, name: &str, score: Score) -> Self {
         Self {
-            number: number.to_vec(),
+            uid: uid,
+            name: name.to_owned(),
+            score: score,
         }
     }
 
-    pub fn number<'a>(&'a self) -> &'a [u8] { &self.number }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn score(&self) -> Score { self.score }
 }
 
-impl From<clean::DiceGuess> for DiceGuess {
-    fn from(proto: clean::DiceGuess) -> Self {
+impl From<clean::Champion> for Champion {
+    fn from(proto: clean::Champion) -> Self {
         Self {
-            number: proto.number.iter().map(|n| *n as u8).collect(),
+            uid: UserID(proto.user_id),
+            name: proto.user_name,
+            score: Score::new(proto.score),
         }
     }
 }
 
-impl From<DiceGuess> for clean::DiceGuess {
-    fn from(dg: DiceGuess) -> Self {
+impl From<Champion> for clean::Champion {
+    fn from(c: Champion) -> Self {
         Self {
-            number: dg.number.iter().map(|n| *n as u32).collect(),
+            user_id: c.uid.0,
+            user_name: c.name,
+            score: c.score.value(),
         }
     }
 }
 
-pub struct CoinGuess {
-    coins: Vec<Coin>,
+// who everyone is waiting on in a turn based game
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WaitingForPlayer {
+    uid: UserID,
+    name: String,
 }
 
-impl CoinGuess {
-    pub fn new(coins: &[Coin]) -> Self {
+impl WaitingForPlayer {
+    pub fn new(uid: UserID, name: &str) -> Self {
         Self {
-            coins: coins.to_vec(),
+            uid: uid,
+            name: name.to_owned(),
         }
     }
 
-    pub fn coins<'a>(&'a self) -> &'a [Coin] { &self.coins }
+    pub fn user_id(&self) -> UserID { self.uid }
+    pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
 }
 
-impl TryFrom<clean::CoinGuess> for CoinGuess {
-    type Error = Error;
-
-    fn try_from(proto: clean::CoinGuess) -> std::result::Result<Self, Self::Error> {
-        let mut coins = Vec::new();
-        for coin in proto.coins {
-            coins.push(coin.try_into()?);
+impl From<clean::WaitingForPlayer> for WaitingForPlayer {
+    fn from(proto: clean::WaitingForPlayer) -> Self {
+        Self {
+            uid: UserID(proto.user_id),
+            name: proto.user_name,
         }
-        Ok(Self {
-            coins: coins,
-        })
     }
 }
 
-impl From<CoinGuess> for clean::CoinGuess {
-    fn from(cg: CoinGuess) -> Self {
+impl From<WaitingForPlayer> for clean::WaitingForPlayer {
+    fn from(w: WaitingForPlayer) -> Self {
         Self {
-            coins: cg.coins.iter()
-                .map(|c| c.clone().into())
-                .map(|c: clean::Coin| c.into())
-                .collect(),
+            user_id: w.uid.0,
+            user_name: w.name,
         }
     }
 }
 
-pub struct Winner {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayerReady {
     uid: UserID,
     name: String,
+    ready: bool,
 }
 
-impl Winner {
-    pub fn new(uid: UserID, name: &str) -> Self {
+impl PlayerReady {
+    pub fn new(uid: UserID, name: &str, ready: bool) -> Self {
         Self {
             uid: uid,
             name: name.to_owned(),
+            ready: ready,
         }
     }
 
     pub fn user_id(&self) -> UserID { self.uid }
     pub fn user_name<'a>(&'a self) -> &'a str { &self.name }
+    pub fn ready(&self) -> bool { self.ready }
 }
 
-impl From<clean::Winner> for Winner {
-    fn from(proto: clean::Winner) -> Self {
+impl From<clean::PlayerReady> for PlayerReady {
+    fn from(proto: clean::PlayerReady) -> Self {
         Self {
             uid: UserID(proto.user_id),
             name: proto.user_name,
+            ready: proto.ready,
         }
     }
 }
 
-impl From<Winner> for clean::Winner {
-    fn from(w: Winner) -> Self {
+impl From<PlayerReady> for clean::PlayerReady {
+    fn from(pr: PlayerReady) -> Self {
         Self {
-            user_id: w.uid.0,
-            user_name: w.name,
+            user_id: pr.uid.0,
+            user_name: pr.name,
+            ready: pr.ready,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerError {
+    code: ErrorCode,
+    action: ClientAction,
+    message: String,
+}
+
+impl ServerError {
+    pub fn new(code: ErrorCode, action: ClientAction, message: &str) -> Self {
+        Self {
+            code: code,
+            action: action,
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode { self.code }
+    pub fn action(&self) -> ClientAction { self.action }
+    pub fn message<'a>(&'a self) -> &'a str { &self.message }
+}
+
+impl From<clean::ServerError> for ServerError {
+    fn from(proto: clean::ServerError) -> Self {
+        Self {
+            code: proto.code.into(),
+            action: proto.action.into(),
+            message: proto.message,
+        }
+    }
+}
+
+impl From<ServerError> for clean::ServerError {
+    fn from(se: ServerError) -> Self {
+        let code: clean::ErrorCode = se.code.into();
+        let action: clean::ClientAction = se.action.into();
+        Self {
+            code: code.into(),
+            action: action.into(),
+            message: se.message,
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ServerRequest {
     JoinInfo(JoinInfo),
     Ping(Ping),
@@ -530,7 +3227,36 @@ pub enum ServerRequest {
     FlipCoin(FlipCoin),
     Winner(Winner),
     TryAgain(bool),
-    ServerError(String),
+    ServerError(ServerError),
+    Prompt(String),
+    RoundResult(RoundResult),
+    TeamWinner(TeamWinner),
+    TeamRoundResult(TeamRoundResult),
+    Scoreboard(Scoreboard),
+    Champion(Champion),
+    SessionExpired(SessionID),
+    UserLeft(JoinInfo),
+    Chat(ChatMessage),
+    UserKicked(JoinInfo),
+    DirectMessage(DirectMessage),
+    SessionCancelled(SessionID),
+    SessionUpdated(SessionData),
+    JoinRequest(JoinInfo),
+    StreamClosing(CloseReason),
+    WaitingForPlayer(WaitingForPlayer),
+    Rematch(SessionData),
+    ReadyCheck(bool),
+    PlayerReady(PlayerReady),
+    GameOver(SessionID),
+    HostChanged(JoinInfo),
+    Countdown(u32),
+    GamePaused(SessionID),
+    GameResumed(SessionID),
+    GameStarted(SessionData),
+    GameEnded(GameEnded),
+    StartReminder(u64),
+    Invitation(Invitation),
+    PresenceChanged(PresenceChange),
 }
 
 impl TryFrom<clean::ServerRequest> for ServerRequest {
@@ -544,15 +3270,80 @@ impl TryFrom<clean::ServerRequest> for ServerRequest {
             clean::server_request::Msg::Ping(p) =>
                 return Ok(ServerRequest::Ping(p.into())),
             clean::server_request::Msg::Dice(rd) =>
-                return Ok(ServerRequest::RollDice(rd.into())),
+                return Ok(ServerRequest::RollDice(rd.try_into()?)),
             clean::server_request::Msg::Coin(fc) =>
-                return Ok(ServerRequest::FlipCoin(fc.into())),
+                return Ok(ServerRequest::FlipCoin(fc.try_into()?)),
             clean::server_request::Msg::Winner(w) =>
                 return Ok(ServerRequest::Winner(w.into())),
             clean::server_request::Msg::TryAgain(t) =>
                 return Ok(ServerRequest::TryAgain(t)),
             clean::server_request::Msg::Error(e) =>
-                return Ok(ServerRequest::ServerError(e)),
+                return Ok(ServerRequest::ServerError(
+                    ServerError::new(ErrorCode::Unspecified, ClientAction::None, &e))),
+            clean::server_request::Msg::ServerError(e) =>
+                return Ok(ServerRequest::ServerError(e.into())),
+            clean::server_request::Msg::Prompt(p) =>
+                return Ok(ServerRequest::Prompt(p)),
+            clean::server_request::Msg::RoundResult(rr) =>
+                return Ok(ServerRequest::RoundResult(rr.into())),
+            clean::server_request::Msg::TeamWinner(tw) =>
+                return Ok(ServerRequest::TeamWinner(tw.into())),
+            clean::server_request::Msg::TeamRoundResult(tr) =>
+                return Ok(ServerRequest::TeamRoundResult(tr.into())),
+            clean::server_request::Msg::Scoreboard(sb) =>
+                return Ok(ServerRequest::Scoreboard(sb.into())),
+            clean::server_request::Msg::Champion(c) =>
+                return Ok(ServerRequest::Champion(c.into())),
+            clean::server_request::Msg::SessionExpired(sid) =>
+                return Ok(ServerRequest::SessionExpired(SessionID(sid))),
+            clean::server_request::Msg::UserLeft(ji) =>
+                return Ok(ServerRequest::UserLeft(ji.into())),
+            clean::server_request::Msg::Chat(cm) =>
+                return Ok(ServerRequest::Chat(cm.into())),
+            clean::server_request::Msg::UserKicked(ji) =>
+                return Ok(ServerRequest::UserKicked(ji.into())),
+            clean::server_request::Msg::DirectMessage(dm) =>
+                return Ok(ServerRequest::DirectMessage(dm.into())),
+            clean::server_request::Msg::SessionCancelled(sid) =>
+                return Ok(ServerRequest::SessionCancelled(SessionID(sid))),
+            clean::server_request::Msg::SessionUpdated(sd) =>
+                return Ok(ServerRequest::SessionUpdated(sd.try_into()?)),
+            clean::server_request::Msg::JoinRequest(ji) =>
+                return Ok(ServerRequest::JoinRequest(ji.into())),
+            clean::server_request::Msg::StreamClosing(sc) =>
+                return Ok(ServerRequest::StreamClosing(sc.reason.into())),
+            clean::server_request::Msg::WaitingForPlayer(w) =>
+                return Ok(ServerRequest::WaitingForPlayer(w.into())),
+            clean::server_request::Msg::Rematch(sd) =>
+                return Ok(ServerRequest::Rematch(sd.try_into()?)),
+            clean::server_request::Msg::ReadyCheck(r) =>
+                return Ok(ServerRequest::ReadyCheck(r)),
+            clean::server_request::Msg::PlayerReady(pr) =>
+                return Ok(ServerRequest::PlayerReady(pr.into())),
+            clean::server_request::Msg::GameOver(sid) =>
+                return Ok(ServerRequest::GameOver(SessionID(sid))),
+            clean::server_request::Msg::HostChanged(ji) =>
+                return Ok(ServerRequest::HostChanged(ji.into())),
+            clean::server_request::Msg::Countdown(secs) =>
+                return Ok(ServerRequest::Countdown(secs)),
+            clean::server_request::Msg::GamePaused(sid) =>
+                return Ok(ServerRequest::GamePaused(SessionID(sid))),
+            clean::server_request::Msg::GameResumed(sid) =>
+                return Ok(ServerRequest::GameResumed(SessionID(sid))),
+            clean::server_request::Msg::GameStarted(sd) =>
+                return Ok(ServerRequest::GameStarted(sd.try_into()?)),
+            clean::server_request::Msg::GameEnded(ge) =>
+                return Ok(ServerRequest::GameEnded(ge.into())),
+            clean::server_request::Msg::StartReminder(secs) =>
+                return Ok(ServerRequest::StartReminder(secs)),
+            clean::server_request::Msg::Invitation(inv) =>
+                return Ok(ServerRequest::Invitation(inv.into())),
+            clean::server_request::Msg::PresenceChanged(pc) =>
+                return Ok(ServerRequest::PresenceChanged(pc.into())),
+            // batches and heartbeats only exist on the wire, and are dealt
+            // with on receipt
+            clean::server_request::Msg::Batch(_) | clean::server_request::Msg::Heartbeat(_) =>
+                return Err(Error::InvalidServerRequest),
         }
     }
 }
@@ -573,20 +3364,86 @@ impl From<ServerRequest> for clean::ServerRequest {
             ServerRequest::TryAgain(t) =>
                 clean::server_request::Msg::TryAgain(t.into()),
             ServerRequest::ServerError(e) =>
-                clean::server_request::Msg::Error(e),
+                clean::server_request::Msg::ServerError(e.into()),
+            ServerRequest::Prompt(p) =>
+                clean::server_request::Msg::Prompt(p),
+            ServerRequest::RoundResult(rr) =>
+                clean::server_request::Msg::RoundResult(rr.into()),
+            ServerRequest::TeamWinner(tw) =>
+                clean::server_request::Msg::TeamWinner(tw.into()),
+            ServerRequest::TeamRoundResult(tr) =>
+                clean::server_request::Msg::TeamRoundResult(tr.into()),
+            ServerRequest::Scoreboard(sb) =>
+                clean::server_request::Msg::Scoreboard(sb.into()),
+            ServerRequest::Champion(c) =>
+                clean::server_request::Msg::Champion(c.into()),
+            ServerRequest::SessionExpired(sid) =>
+                clean::server_request::Msg::SessionExpired(sid.0),
+            ServerRequest::UserLeft(ji) =>
+                clean::server_request::Msg::UserLeft(ji.into()),
+            ServerRequest::Chat(cm) =>
+                clean::server_request::Msg::Chat(cm.into()),
+            ServerRequest::UserKicked(ji) =>
+                clean::server_request::Msg::UserKicked(ji.into()),
+            ServerRequest::DirectMessage(dm) =>
+                clean::server_request::Msg::DirectMessage(dm.into()),
+            ServerRequest::SessionCancelled(sid) =>
+                clean::server_request::Msg::SessionCancelled(sid.0),
+            ServerRequest::SessionUpdated(sd) =>
+                clean::server_request::Msg::SessionUpdated(sd.into()),
+            ServerRequest::JoinRequest(ji) =>
+                clean::server_request::Msg::JoinRequest(ji.into()),
+            ServerRequest::StreamClosing(reason) => {
+                let reason: clean::CloseReason = reason.into();
+                clean::server_request::Msg::StreamClosing(
+                    clean::StreamClosing { reason: reason.into() })
+            }
+            ServerRequest::WaitingForPlayer(w) =>
+                clean::server_request::Msg::WaitingForPlayer(w.into()),
+            ServerRequest::Rematch(sd) =>
+                clean::server_request::Msg::Rematch(sd.into()),
+            ServerRequest::ReadyCheck(r) =>
+                clean::server_request::Msg::ReadyCheck(r),
+            ServerRequest::PlayerReady(pr) =>
+                clean::server_request::Msg::PlayerReady(pr.into()),
+            ServerRequest::GameOver(sid) =>
+                clean::server_request::Msg::GameOver(sid.0),
+            ServerRequest::HostChanged(ji) =>
+                clean::server_request::Msg::HostChanged(ji.into()),
+            ServerRequest::Countdown(secs) =>
+                clean::server_request::Msg::Countdown(secs),
+            ServerRequest::GamePaused(sid) =>
+                clean::server_request::Msg::GamePaused(sid.0),
+            ServerRequest::GameResumed(sid) =>
+                clean::server_request::Msg::GameResumed(sid.0),
+            ServerRequest::GameStarted(sd) =>
+                clean::server_request::Msg::GameStarted(sd.into()),
+            ServerRequest::GameEnded(ge) =>
+                clean::server_request::Msg::GameEnded(ge.into()),
+            ServerRequest::StartReminder(secs) =>
+                clean::server_request::Msg::StartReminder(secs),
+            ServerRequest::Invitation(inv) =>
+                clean::server_request::Msg::Invitation(inv.into()),
+            ServerRequest::PresenceChanged(pc) =>
+                clean::server_request::Msg::PresenceChanged(pc.into()),
         };
         Self {
             msg: Some(msg),
+            request_id: 0,
+            sequence: 0,
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ClientResponse {
     Pong(Pong),
     DiceGuess(DiceGuess),
     CoinGuess(CoinGuess),
     Again(bool),
     ClientError(String),
+    Answer(String),
+    Ready(bool),
 }
 
 impl TryFrom<clean::ClientResponse> for ClientResponse {
@@ -605,6 +3462,10 @@ impl TryFrom<clean::ClientResponse> for ClientResponse {
                 return Ok(ClientResponse::Again(a)),
             clean::client_response::Msg::Error(e) =>
                 return Ok(ClientResponse::ClientError(e)),
+            clean::client_response::Msg::Answer(a) =>
+                return Ok(ClientResponse::Answer(a)),
+            clean::client_response::Msg::Ready(r) =>
+                return Ok(ClientResponse::Ready(r)),
         }
     }
 }
@@ -622,9 +3483,14 @@ impl From<ClientResponse> for clean::ClientResponse {
                 clean::client_response::Msg::Again(a),
             ClientResponse::ClientError(a) =>
                 clean::client_response::Msg::Error(a),
+            ClientResponse::Answer(a) =>
+                clean::client_response::Msg::Answer(a),
+            ClientResponse::Ready(r) =>
+                clean::client_response::Msg::Ready(r),
         };
         Self {
             msg: Some(msg),
+            request_id: 0,
         }
     }
 }
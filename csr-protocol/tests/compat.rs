@@ -0,0 +1,15 @@
+// every fixture recorded from an earlier protocol revision still decodes and
+// converts with the current types, so a change that breaks older clients or
+// servers fails the build
+use csr_protocol::compat;
+
+#[test]
+fn fixtures_accepted() {
+    let report = compat::check(&compat::fixtures_dir()).unwrap();
+    assert!(!report.checks().is_empty(), "no fixtures in {}",
+            compat::fixtures_dir().display());
+    let failures: Vec<String> = report.checks().iter()
+        .filter_map(|c| c.failure().map(|f| format!("{}: {}", c.name(), f)))
+        .collect();
+    assert!(failures.is_empty(), "fixtures rejected:\n{}", failures.join("\n"));
+}
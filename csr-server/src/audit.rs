@@ -0,0 +1,37 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::{Requester, UserID};
+use csr_storage::StorageKey;
+
+// a record of actions taken on user data, one line per action holding the
+// time in seconds since the epoch, the action, the user and who requested it
+pub struct AuditLog {
+    path: PathBuf,
+    key: Option<StorageKey>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path, key: Option<StorageKey>) -> Self {
+        Self {
+            path: path.to_owned(),
+            key: key,
+        }
+    }
+
+    pub fn record(&self, action: &str, uid: UserID, by: Requester) -> Result<()> {
+        let mut data = if self.path.exists() {
+            csr_storage::read(&self.path, self.key.as_ref())?
+        } else {
+            Vec::new()
+        };
+        let by = match by {
+            Requester::Admin => "admin".to_owned(),
+            Requester::User(u) => format!("user {}", u.0),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        data.extend_from_slice(format!("{} {} {} by {}\n", now, action, uid.0, by).as_bytes());
+        csr_storage::write(&self.path, self.key.as_ref(), &data)
+    }
+}
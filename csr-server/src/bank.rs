@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::UserID;
+use csr_storage::StorageKey;
+
+use crate::error::Error;
+
+// chips every user starts out with
+pub const STARTING_CHIPS: u64 = 1000;
+
+// chip balances for every user, saved to a file after every change. Each line
+// of the file holds a user ID and their balance, encrypted when the server has
+// a storage key
+pub struct Bank {
+    path: PathBuf,
+    key: Option<StorageKey>,
+    balances: HashMap<UserID, u64>,
+}
+
+impl Bank {
+    pub fn load(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        let mut balances = HashMap::new();
+        if path.exists() {
+            let data = String::from_utf8(csr_storage::read(path, key.as_ref())?)?;
+            for line in data.lines() {
+                if let Some((uid, chips)) = line.split_once(' ') {
+                    balances.insert(UserID(uid.parse()?), chips.parse()?);
+                }
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            key: key,
+            balances: balances,
+        })
+    }
+
+    pub fn balance(&self, uid: UserID) -> u64 {
+        *self.balances.get(&uid).unwrap_or(&STARTING_CHIPS)
+    }
+
+    // the balance only if one has been stored for the user
+    pub fn stored_balance(&self, uid: UserID) -> Option<u64> {
+        self.balances.get(&uid).copied()
+    }
+
+    pub fn remove(&mut self, uid: UserID) -> Result<()> {
+        if self.balances.remove(&uid).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    // take chips from each user, only if all of them can afford it
+    pub fn debit_all(&mut self, uids: &[UserID], amount: u64) -> Result<()> {
+        for uid in uids {
+            let balance = self.balance(*uid);
+            if balance < amount {
+                return Err(Box::new(Error::InsufficientChips(*uid, balance)));
+            }
+        }
+        for uid in uids {
+            self.balances.insert(*uid, self.balance(*uid) - amount);
+        }
+        self.save()
+    }
+
+    pub fn credit(&mut self, uid: UserID, amount: u64) -> Result<()> {
+        self.balances.insert(uid, self.balance(uid).saturating_add(amount));
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = String::new();
+        for (uid, chips) in &self.balances {
+            data.push_str(&format!("{} {}\n", uid.0, chips));
+        }
+        csr_storage::write(&self.path, self.key.as_ref(), data.as_bytes())
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::{AccountBan, UserID};
+use csr_storage::StorageKey;
+
+// names are banned however they are written
+fn normalize(ban: AccountBan) -> AccountBan {
+    match ban {
+        AccountBan::Name(name) => AccountBan::Name(name.trim().to_lowercase()),
+        ban => ban,
+    }
+}
+
+// accounts the operator has banned from the whole server, saved to a file after
+// every change. Each line of the file holds either "id" and a user ID or "name"
+// and a name, encrypted when the server has a storage key
+pub struct BanList {
+    path: PathBuf,
+    key: Option<StorageKey>,
+    bans: HashSet<AccountBan>,
+}
+
+impl BanList {
+    pub fn load(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        let mut bans = HashSet::new();
+        if path.exists() {
+            let data = String::from_utf8(csr_storage::read(path, key.as_ref())?)?;
+            for line in data.lines() {
+                match line.split_once(' ') {
+                    Some(("id", uid)) => { bans.insert(AccountBan::User(UserID(uid.parse()?))); }
+                    Some(("name", name)) => { bans.insert(AccountBan::Name(name.to_owned())); }
+                    _ => {}
+                }
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            key: key,
+            bans: bans,
+        })
+    }
+
+    // whether the user is banned by ID or under any of the given names
+    pub fn is_banned(&self, uid: UserID, names: &[&str]) -> bool {
+        if self.bans.contains(&AccountBan::User(uid)) {
+            return true;
+        }
+        return names.iter().any(|n| {
+            self.bans.contains(&normalize(AccountBan::Name(n.to_string())))
+        });
+    }
+
+    // returns false if the account was already banned
+    pub fn ban(&mut self, ban: AccountBan) -> Result<bool> {
+        if !self.bans.insert(normalize(ban)) {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // returns false if the account wasn't banned
+    pub fn unban(&mut self, ban: &AccountBan) -> Result<bool> {
+        if !self.bans.remove(&normalize(ban.clone())) {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn bans(&self) -> Vec<AccountBan> {
+        self.bans.iter().cloned().collect()
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = String::new();
+        for ban in &self.bans {
+            match ban {
+                AccountBan::User(uid) => data.push_str(&format!("id {}\n", uid.0)),
+                AccountBan::Name(name) => data.push_str(&format!("name {}\n", name)),
+            }
+        }
+        csr_storage::write(&self.path, self.key.as_ref(), data.as_bytes())
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::UserID;
+use csr_storage::StorageKey;
+
+// the users each user has blocked from messaging them, saved to a file after
+// every change. Each line of the file holds a user ID and one user they have
+// blocked, encrypted when the server has a storage key
+pub struct BlockList {
+    path: PathBuf,
+    key: Option<StorageKey>,
+    blocked: HashMap<UserID, HashSet<UserID>>,
+}
+
+impl BlockList {
+    pub fn load(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        let mut blocked: HashMap<UserID, HashSet<UserID>> = HashMap::new();
+        if path.exists() {
+            let data = String::from_utf8(csr_storage::read(path, key.as_ref())?)?;
+            for line in data.lines() {
+                if let Some((uid, target)) = line.split_once(' ') {
+                    blocked.entry(UserID(uid.parse()?)).or_default()
+                        .insert(UserID(target.parse()?));
+                }
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            key: key,
+            blocked: blocked,
+        })
+    }
+
+    // whether uid has blocked target
+    pub fn is_blocked(&self, uid: UserID, target: UserID) -> bool {
+        self.blocked.get(&uid).map_or(false, |b| b.contains(&target))
+    }
+
+    pub fn block(&mut self, uid: UserID, target: UserID) -> Result<()> {
+        if self.blocked.entry(uid).or_default().insert(target) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn unblock(&mut self, uid: UserID, target: UserID) -> Result<()> {
+        if let Some(b) = self.blocked.get_mut(&uid) {
+            if b.remove(&target) {
+                if b.is_empty() {
+                    self.blocked.remove(&uid);
+                }
+                self.save()?;
+            }
+        }
+        Ok(())
+    }
+
+    // forget everyone the user has blocked
+    pub fn remove(&mut self, uid: UserID) -> Result<()> {
+        if self.blocked.remove(&uid).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = String::new();
+        for (uid, targets) in &self.blocked {
+            for target in targets {
+                data.push_str(&format!("{} {}\n", uid.0, target.0));
+            }
+        }
+        csr_storage::write(&self.path, self.key.as_ref(), data.as_bytes())
+    }
+}
@@ -0,0 +1,393 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use csr_protocol::types::Result;
+use csr_protocol::types::Compression;
+
+use crate::error::Error;
+
+const MAX_SESSIONS_VAR: &str = "CSR_MAX_SESSIONS";
+const MAX_SESSIONS_PER_USER_VAR: &str = "CSR_MAX_SESSIONS_PER_USER";
+const GUEST_MAX_SESSIONS_VAR: &str = "CSR_GUEST_MAX_SESSIONS";
+const EXPIRE_OLDEST_VAR: &str = "CSR_EXPIRE_OLDEST";
+const LOBBY_GRACE_VAR: &str = "CSR_LOBBY_GRACE_SECS";
+const CHAT_HISTORY_VAR: &str = "CSR_CHAT_HISTORY";
+const MAX_NAME_LENGTH_VAR: &str = "CSR_MAX_NAME_LENGTH";
+const KICK_GRACE_VAR: &str = "CSR_KICK_GRACE_SECS";
+const SESSION_TTL_VAR: &str = "CSR_SESSION_TTL_SECS";
+const APPROVAL_TIMEOUT_VAR: &str = "CSR_APPROVAL_TIMEOUT_SECS";
+const JOIN_TOKEN_TTL_VAR: &str = "CSR_JOIN_TOKEN_TTL_SECS";
+const RATE_LIMIT_VAR: &str = "CSR_RATE_LIMIT_PER_MIN";
+const RATE_BURST_VAR: &str = "CSR_RATE_LIMIT_BURST";
+const READY_TIMEOUT_VAR: &str = "CSR_READY_TIMEOUT_SECS";
+const START_COUNTDOWN_VAR: &str = "CSR_START_COUNTDOWN_SECS";
+const VOTE_KICK_ABORT_VAR: &str = "CSR_VOTE_KICK_ABORT";
+const DISCONNECT_POLICY_VAR: &str = "CSR_DISCONNECT_POLICY";
+const RECONNECT_TIMEOUT_VAR: &str = "CSR_RECONNECT_TIMEOUT_SECS";
+const HEARTBEAT_VAR: &str = "CSR_HEARTBEAT_SECS";
+const HEARTBEAT_TIMEOUT_VAR: &str = "CSR_HEARTBEAT_TIMEOUT_SECS";
+const REPLAY_BUFFER_VAR: &str = "CSR_REPLAY_BUFFER";
+const COMPRESSION_VAR: &str = "CSR_COMPRESSION";
+const CORS_ORIGINS_VAR: &str = "CSR_CORS_ORIGINS";
+const CORS_HEADERS_VAR: &str = "CSR_CORS_HEADERS";
+
+// what a running game does when a player's event stream drops
+#[derive(Copy, Clone, Debug)]
+pub enum DisconnectPolicy {
+    // wait this long for the player to reconnect, ending the game if they don't
+    Wait(Duration),
+    // play on without the player
+    Forfeit,
+    // end the game straight away
+    Abort,
+}
+
+// Config files
+//
+// Settings can be kept in a TOML file given with --config instead of the
+// environment, which overrides anything in the file, as flags override both.
+// Every setting is optional, for example
+//
+//     listen = ["0.0.0.0", "[::]"]
+//     port = 5555
+//
+//     [tls]
+//     cert = "server.pem"
+//     key = "server.key"
+//
+//     [sessions]
+//     max = 100
+//     ttl_secs = 1800
+//
+//     [rate_limit]
+//     per_min = 120
+//     burst = 30
+//
+//     [game]
+//     ready_timeout_secs = 30
+//     disconnect_policy = "forfeit"
+//
+// with the same meaning as the variable of the same name
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub listen: Option<Vec<String>>,
+    pub port: Option<u16>,
+    pub tls: TlsFiles,
+    pub sessions: SessionSettings,
+    pub rate_limit: RateLimitSettings,
+    pub game: GameSettings,
+}
+
+// the PEM files to serve TLS with
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSettings {
+    max: Option<usize>,
+    max_per_user: Option<usize>,
+    guest_max: Option<usize>,
+    expire_oldest: Option<bool>,
+    lobby_grace_secs: Option<u64>,
+    ttl_secs: Option<u64>,
+    approval_timeout_secs: Option<u64>,
+    join_token_ttl_secs: Option<u64>,
+    kick_grace_secs: Option<u64>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    per_min: Option<u32>,
+    burst: Option<u32>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameSettings {
+    chat_history: Option<usize>,
+    max_name_length: Option<usize>,
+    ready_timeout_secs: Option<u64>,
+    start_countdown_secs: Option<u64>,
+    vote_kick_abort: Option<bool>,
+    disconnect_policy: Option<String>,
+    reconnect_timeout_secs: Option<u64>,
+}
+
+impl ConfigFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        toml::from_str(&text)
+            .map_err(|e| Error::InvalidConfigFile(path.display().to_string(), e.to_string()).into())
+    }
+}
+
+// server settings, read from the config file and the environment
+pub struct Config {
+    // most sessions hosted at once, unlimited when not set
+    pub max_sessions: Option<usize>,
+    // most sessions one user can host or play in at once, unlimited when not
+    // set. Finished sessions don't count
+    pub max_sessions_per_user: Option<usize>,
+    // most sessions a guest can play in at once, whatever registered users
+    // are allowed
+    pub guest_max_sessions: usize,
+    // at the session cap, make room by expiring the oldest idle lobby instead
+    // of turning the new host away
+    pub expire_oldest: bool,
+    // how long a lobby is kept before it can be expired
+    pub lobby_grace: Duration,
+    // chat messages kept per session for players who join later
+    pub chat_history: usize,
+    // longest name a player can join under, in characters
+    pub max_name_length: usize,
+    // how old a lobby must be before the host can remove players, so players
+    // get a chance to settle in
+    pub kick_grace: Duration,
+    // lobbies left idle for longer than this are closed, never when not set
+    pub session_ttl: Option<Duration>,
+    // how long a player waits for the host to let them in
+    pub approval_timeout: Duration,
+    // the longest a join token the host hands out lasts
+    pub join_token_ttl: Duration,
+    // how many times a minute each user, or address without one, can host or
+    // list sessions, unlimited when not set
+    pub rate_limit: Option<u32>,
+    // how many of those requests can be made at once
+    pub rate_burst: u32,
+    // how long players have to say they are ready before the game starts
+    // without them
+    pub ready_timeout: Duration,
+    // how long a full lobby counts down before its game starts
+    pub start_countdown: Duration,
+    // end the game when players vote someone out of it, instead of playing on
+    // without them
+    pub vote_kick_abort: bool,
+    pub disconnect_policy: DisconnectPolicy,
+    // how often each event stream is sent a heartbeat
+    pub heartbeat: Duration,
+    // a player who hasn't answered a heartbeat for this long is treated as
+    // having dropped
+    pub heartbeat_timeout: Duration,
+    // events kept for each player, to send again when they reconnect
+    pub replay_buffer: usize,
+    // encodings clients can compress messages with, and have their responses
+    // compressed with when they ask
+    pub compression: Vec<Compression>,
+    // origins browsers can call the server from over grpc-web, with * for any.
+    // Browsers are only let in from the server's own origin when empty
+    pub cors_origins: Vec<String>,
+    // headers browsers can send besides the ones gRPC and the server use
+    pub cors_headers: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_sessions: None,
+            max_sessions_per_user: None,
+            guest_max_sessions: 1,
+            expire_oldest: false,
+            lobby_grace: Duration::from_secs(60),
+            chat_history: 50,
+            max_name_length: 32,
+            kick_grace: Duration::ZERO,
+            session_ttl: Some(Duration::from_secs(30 * 60)),
+            approval_timeout: Duration::from_secs(120),
+            join_token_ttl: Duration::from_secs(10 * 60),
+            rate_limit: Some(120),
+            rate_burst: 30,
+            ready_timeout: Duration::from_secs(30),
+            start_countdown: Duration::from_secs(5),
+            vote_kick_abort: false,
+            disconnect_policy: DisconnectPolicy::Wait(Duration::from_secs(30)),
+            heartbeat: Duration::from_secs(10),
+            heartbeat_timeout: Duration::from_secs(30),
+            replay_buffer: 64,
+            compression: vec![Compression::Gzip, Compression::Zstd],
+            cors_origins: Vec::new(),
+            cors_headers: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // the settings in the file, with any set in the environment instead
+    pub fn load(file: &ConfigFile) -> Result<Self> {
+        let mut ret = Self::from_file(file)?;
+        if let Ok(v) = std::env::var(MAX_SESSIONS_VAR) {
+            ret.max_sessions = Some(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(MAX_SESSIONS_PER_USER_VAR) {
+            ret.max_sessions_per_user = Some(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(GUEST_MAX_SESSIONS_VAR) {
+            ret.guest_max_sessions = v.parse()?;
+        }
+        if let Ok(v) = std::env::var(EXPIRE_OLDEST_VAR) {
+            ret.expire_oldest = v == "1" || v == "true";
+        }
+        if let Ok(v) = std::env::var(LOBBY_GRACE_VAR) {
+            ret.lobby_grace = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(CHAT_HISTORY_VAR) {
+            ret.chat_history = v.parse()?;
+        }
+        if let Ok(v) = std::env::var(MAX_NAME_LENGTH_VAR) {
+            ret.max_name_length = v.parse()?;
+        }
+        if let Ok(v) = std::env::var(KICK_GRACE_VAR) {
+            ret.kick_grace = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(SESSION_TTL_VAR) {
+            // zero keeps idle lobbies around forever
+            let secs: u64 = v.parse()?;
+            ret.session_ttl = if secs == 0 { None } else { Some(Duration::from_secs(secs)) };
+        }
+        if let Ok(v) = std::env::var(APPROVAL_TIMEOUT_VAR) {
+            ret.approval_timeout = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(JOIN_TOKEN_TTL_VAR) {
+            ret.join_token_ttl = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(RATE_LIMIT_VAR) {
+            // zero turns the limit off
+            let limit: u32 = v.parse()?;
+            ret.rate_limit = if limit == 0 { None } else { Some(limit) };
+        }
+        if let Ok(v) = std::env::var(RATE_BURST_VAR) {
+            ret.rate_burst = v.parse()?;
+        }
+        if let Ok(v) = std::env::var(READY_TIMEOUT_VAR) {
+            ret.ready_timeout = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(START_COUNTDOWN_VAR) {
+            ret.start_countdown = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(VOTE_KICK_ABORT_VAR) {
+            ret.vote_kick_abort = v == "1" || v == "true";
+        }
+        if let Ok(v) = std::env::var(DISCONNECT_POLICY_VAR) {
+            ret.disconnect_policy = disconnect_policy(v, ret.disconnect_policy)?;
+        }
+        // only used while waiting for players to come back
+        if let Ok(v) = std::env::var(RECONNECT_TIMEOUT_VAR) {
+            if let DisconnectPolicy::Wait(_) = ret.disconnect_policy {
+                ret.disconnect_policy = DisconnectPolicy::Wait(Duration::from_secs(v.parse()?));
+            }
+        }
+        if let Ok(v) = std::env::var(HEARTBEAT_VAR) {
+            // an interval of zero would never tick
+            ret.heartbeat = Duration::from_secs(v.parse::<u64>()?.max(1));
+        }
+        if let Ok(v) = std::env::var(HEARTBEAT_TIMEOUT_VAR) {
+            ret.heartbeat_timeout = Duration::from_secs(v.parse()?);
+        }
+        if let Ok(v) = std::env::var(REPLAY_BUFFER_VAR) {
+            ret.replay_buffer = v.parse()?;
+        }
+        if let Ok(v) = std::env::var(COMPRESSION_VAR) {
+            // none turns compression off
+            ret.compression = match v.as_str() {
+                "none" => Vec::new(),
+                _ => v.split(',').map(|c| c.trim().parse()).collect::<std::result::Result<_, _>>()?,
+            };
+        }
+        if let Ok(v) = std::env::var(CORS_ORIGINS_VAR) {
+            ret.cors_origins = split_list(&v);
+        }
+        if let Ok(v) = std::env::var(CORS_HEADERS_VAR) {
+            ret.cors_headers = split_list(&v);
+        }
+        Ok(ret)
+    }
+
+    fn from_file(file: &ConfigFile) -> Result<Self> {
+        let mut ret = Self::default();
+        let sessions = &file.sessions;
+        if let Some(v) = sessions.max {
+            ret.max_sessions = Some(v);
+        }
+        if let Some(v) = sessions.max_per_user {
+            ret.max_sessions_per_user = Some(v);
+        }
+        if let Some(v) = sessions.guest_max {
+            ret.guest_max_sessions = v;
+        }
+        if let Some(v) = sessions.expire_oldest {
+            ret.expire_oldest = v;
+        }
+        if let Some(v) = sessions.lobby_grace_secs {
+            ret.lobby_grace = Duration::from_secs(v);
+        }
+        if let Some(v) = sessions.ttl_secs {
+            // zero keeps idle lobbies around forever
+            ret.session_ttl = if v == 0 { None } else { Some(Duration::from_secs(v)) };
+        }
+        if let Some(v) = sessions.approval_timeout_secs {
+            ret.approval_timeout = Duration::from_secs(v);
+        }
+        if let Some(v) = sessions.join_token_ttl_secs {
+            ret.join_token_ttl = Duration::from_secs(v);
+        }
+        if let Some(v) = sessions.kick_grace_secs {
+            ret.kick_grace = Duration::from_secs(v);
+        }
+        if let Some(v) = file.rate_limit.per_min {
+            // zero turns the limit off
+            ret.rate_limit = if v == 0 { None } else { Some(v) };
+        }
+        if let Some(v) = file.rate_limit.burst {
+            ret.rate_burst = v;
+        }
+        let game = &file.game;
+        if let Some(v) = game.chat_history {
+            ret.chat_history = v;
+        }
+        if let Some(v) = game.max_name_length {
+            ret.max_name_length = v;
+        }
+        if let Some(v) = game.ready_timeout_secs {
+            ret.ready_timeout = Duration::from_secs(v);
+        }
+        if let Some(v) = game.start_countdown_secs {
+            ret.start_countdown = Duration::from_secs(v);
+        }
+        if let Some(v) = game.vote_kick_abort {
+            ret.vote_kick_abort = v;
+        }
+        if let Some(v) = &game.disconnect_policy {
+            ret.disconnect_policy = disconnect_policy(v.clone(), ret.disconnect_policy)?;
+        }
+        if let (Some(v), DisconnectPolicy::Wait(_)) =
+                (game.reconnect_timeout_secs, ret.disconnect_policy) {
+            ret.disconnect_policy = DisconnectPolicy::Wait(Duration::from_secs(v));
+        }
+        Ok(ret)
+    }
+}
+
+// the policy named, where waiting keeps how long to wait from the current one
+fn disconnect_policy(name: String, current: DisconnectPolicy) -> Result<DisconnectPolicy> {
+    match name.as_str() {
+        "wait" => match current {
+            DisconnectPolicy::Wait(_) => Ok(current),
+            _ => Ok(Config::default().disconnect_policy),
+        },
+        "forfeit" => Ok(DisconnectPolicy::Forfeit),
+        "abort" => Ok(DisconnectPolicy::Abort),
+        _ => Err(Box::new(Error::InvalidDisconnectPolicy(name))),
+    }
+}
+
+// a comma separated list, skipping blank entries
+fn split_list(v: &str) -> Vec<String> {
+    v.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_owned()).collect()
+}
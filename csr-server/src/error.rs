@@ -1,15 +1,169 @@
-use csr_protocol::types::{SessionID, UserID};
+use csr_protocol::types::{ClientAction, ErrorCode, SessionID, UserID};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Client unreachable {0:?}")]
     ClientUnreachable(UserID),
+    #[error("User {0:?} only has {1} chips")]
+    InsufficientChips(UserID, u64),
+    #[error("The admin token in {0} is empty")]
+    EmptyAdminToken(&'static str),
+    #[error("User names can't be empty")]
+    EmptyUserName,
+    #[error("The game was called off after a player was voted out")]
+    GameAborted,
+    #[error("The game in session {0:?} isn't over")]
+    GameNotFinished(SessionID),
+    #[error("The game in session {0:?} isn't paused")]
+    GameNotPaused(SessionID),
+    #[error("No game is being played in session {0:?}")]
+    GameNotRunning(SessionID),
+    #[error("The game in session {0:?} is already paused")]
+    GamePaused(SessionID),
+    #[error("A minimum of {0} players is more than the {1} that can join")]
+    InvalidPlayerRange(u8, u8),
+    #[error("Unknown disconnect policy {0}, expected wait, forfeit or abort")]
+    InvalidDisconnectPolicy(String),
+    #[error("Invalid config file {0}, {1}")]
+    InvalidConfigFile(String, String),
+    #[error("Invalid or expired join token for session {0:?}")]
+    InvalidJoinToken(SessionID),
+    #[error("Invalid preset {0}")]
+    InvalidPreset(String),
+    #[error("Invalid seat {0}")]
+    InvalidSeat(usize),
+    #[error("Not let into session {0:?}")]
+    JoinRejected(SessionID),
+    #[error("Players can't be removed for another {0} seconds")]
+    KickTooSoon(u64),
+    #[error("{0} can't have line breaks in them")]
+    LineBreak(&'static str),
+    #[error("No request to join from {0:?}")]
+    NoJoinRequest(UserID),
+    #[error("{0} is set, but has no addresses in it")]
+    NoListenAddrs(&'static str),
+    #[error("{0} is set, but not {1} which it needs")]
+    MissingSetting(&'static str, &'static str),
+    #[error("{0} is set, but the server was built without the oidc feature")]
+    OidcUnsupported(&'static str),
+    #[error("User {0:?} hasn't added you as a friend")]
+    NotFriends(UserID),
+    #[error("Need at least {0} players to start")]
+    NotEnoughPlayers(u8),
+    #[error("Only the host can do that, not {0:?}")]
+    NotHost(UserID),
+    #[error("User {0:?} is not ready to play")]
+    NotReady(UserID),
+    #[error("Private sessions need a password")]
+    PrivateNeedsPassword,
+    #[cfg(feature = "quic")]
+    #[error("{0} is set, but QUIC clients can't present certificates")]
+    QuicMutualTls(&'static str),
+    #[cfg(not(feature = "quic"))]
+    #[error("{0} is set, but the server was built without the quic feature")]
+    QuicUnsupported(&'static str),
+    #[error("User ID {0:?} is reserved")]
+    ReservedUserID(UserID),
+    #[error("{0} is set, but the server was built without the rest feature")]
+    RestUnsupported(&'static str),
+    #[error("Sessions can't be scheduled to start in the past")]
+    ScheduledInPast,
+    #[error("Session limit of {0} reached")]
+    SessionLimitReached(usize),
+    #[error("User {0:?} is already in {1} sessions")]
+    SessionQuotaReached(UserID, usize),
     #[error("Session not found {0:?}")]
     SessionNotFound(SessionID),
+    #[error("Session {0:?} is full, with {1} players")]
+    SessionFull(SessionID, u8),
+    #[error("Session {0:?} has already started")]
+    SessionStarted(SessionID),
+    #[error("User {0:?} can't vote to kick themselves")]
+    SelfVote(UserID),
+    #[error("A single player needs a house target to play against")]
+    SoloNeedsTarget,
+    #[cfg(not(feature = "sqlite"))]
+    #[error("{0} is set, but the server was built without the sqlite feature")]
+    SqliteUnsupported(&'static str),
+    #[error("User {0:?} must join a team")]
+    TeamRequired(UserID),
+    #[error("{0} is set, but the server was built without the tls feature")]
+    TlsUnsupported(&'static str),
+    #[error("{0} players have already joined, more than {1}")]
+    TooManyPlayers(usize, u8),
+    #[cfg(not(unix))]
+    #[error("{0} is set, but unix sockets aren't supported on this platform")]
+    UnixSocketUnsupported(&'static str),
+    #[error("Unknown game {0}")]
+    UnknownGame(String),
+    #[error("Unknown preset {0}")]
+    UnknownPreset(String),
+    #[error("Unknown invite code {0}")]
+    UnknownInviteCode(String),
+    #[error("Unknown user {0:?}")]
+    UnknownUser(UserID),
     #[error("Winner is unknown")]
     UnknownWinner,
+    #[error("User {0:?} is not accepting messages")]
+    UserBlocked(UserID),
+    #[error("User {0:?} is banned from session {1:?}")]
+    UserBanned(UserID, SessionID),
     #[error("User {0:?} already in session {0:?}")]
     UserAlreadyInSession(UserID, SessionID),
     #[error("User {0:?} not in session {0:?}")]
     UserNotInSession(UserID, SessionID),
+    #[error("User {0:?} is not connected")]
+    UserOffline(UserID),
+    #[error("Wrong password for session {0:?}")]
+    WrongPassword(SessionID),
+}
+
+// the code sent to clients for an error, and what they can do about it
+pub fn guidance(e: &(dyn std::error::Error + 'static)) -> (ErrorCode, ClientAction) {
+    if let Some(e) = e.downcast_ref::<Error>() {
+        return match e {
+            Error::ClientUnreachable(_) =>
+                (ErrorCode::ClientUnreachable, ClientAction::Reconnect),
+            Error::InsufficientChips(_, _) =>
+                (ErrorCode::InsufficientChips, ClientAction::None),
+            Error::SessionFull(_, _) =>
+                (ErrorCode::SessionFull, ClientAction::RetryJoin),
+            Error::SessionNotFound(_) =>
+                (ErrorCode::SessionNotFound, ClientAction::RetryJoin),
+            Error::SessionStarted(_) =>
+                (ErrorCode::SessionStarted, ClientAction::RetryJoin),
+            Error::UnknownInviteCode(_) =>
+                (ErrorCode::SessionNotFound, ClientAction::RetryJoin),
+            Error::SessionLimitReached(_) | Error::SessionQuotaReached(_, _) =>
+                (ErrorCode::SessionLimitReached, ClientAction::None),
+            Error::UserAlreadyInSession(_, _) =>
+                (ErrorCode::UserAlreadyInSession, ClientAction::None),
+            Error::UserNotInSession(_, _) =>
+                (ErrorCode::UserNotInSession, ClientAction::None),
+            Error::NotHost(_) => (ErrorCode::NotHost, ClientAction::None),
+            Error::WrongPassword(_) => (ErrorCode::WrongPassword, ClientAction::RetryJoin),
+            Error::InvalidJoinToken(_) => (ErrorCode::InvalidJoinToken, ClientAction::None),
+            Error::JoinRejected(_) => (ErrorCode::JoinRejected, ClientAction::None),
+            Error::UserBanned(_, _) => (ErrorCode::UserBanned, ClientAction::None),
+            Error::UnknownUser(_) => (ErrorCode::UnknownUser, ClientAction::None),
+            Error::UserOffline(_) => (ErrorCode::UserOffline, ClientAction::None),
+            Error::NotFriends(_) => (ErrorCode::NotFriends, ClientAction::None),
+            Error::UserBlocked(_) => (ErrorCode::UserBlocked, ClientAction::None),
+            Error::EmptyUserName | Error::InvalidPlayerRange(_, _) | Error::InvalidPreset(_)
+                | Error::InvalidSeat(_) | Error::LineBreak(_) | Error::PrivateNeedsPassword
+                | Error::ReservedUserID(_) | Error::ScheduledInPast | Error::SelfVote(_)
+                | Error::SoloNeedsTarget | Error::TeamRequired(_) | Error::TooManyPlayers(_, _)
+                | Error::UnknownGame(_) | Error::UnknownPreset(_) =>
+                (ErrorCode::InvalidRequest, ClientAction::None),
+            Error::GameAborted | Error::GameNotFinished(_) | Error::GameNotPaused(_)
+                | Error::GameNotRunning(_) | Error::GamePaused(_) | Error::KickTooSoon(_)
+                | Error::NoJoinRequest(_) | Error::NotEnoughPlayers(_) | Error::NotReady(_) =>
+                (ErrorCode::InvalidState, ClientAction::None),
+            _ => (ErrorCode::Unspecified, ClientAction::None),
+        };
+    }
+    if let Some(e) = e.downcast_ref::<csr_protocol::error::Error>() {
+        return e.guidance();
+    }
+    (ErrorCode::Unspecified, ClientAction::None)
 }
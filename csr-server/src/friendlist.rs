@@ -0,0 +1,96 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::UserID;
+use csr_storage::StorageKey;
+
+// the users each user has added as a friend, saved to a file after every
+// change. Two users are friends once each has added the other. Each line of
+// the file holds a user ID and one user they have added, encrypted when the
+// server has a storage key
+pub struct FriendList {
+    path: PathBuf,
+    key: Option<StorageKey>,
+    added: HashMap<UserID, HashSet<UserID>>,
+}
+
+impl FriendList {
+    pub fn load(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        let mut added: HashMap<UserID, HashSet<UserID>> = HashMap::new();
+        if path.exists() {
+            let data = String::from_utf8(csr_storage::read(path, key.as_ref())?)?;
+            for line in data.lines() {
+                if let Some((uid, friend)) = line.split_once(' ') {
+                    added.entry(UserID(uid.parse()?)).or_default()
+                        .insert(UserID(friend.parse()?));
+                }
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            key: key,
+            added: added,
+        })
+    }
+
+    // whether uid has added friend
+    pub fn has_added(&self, uid: UserID, friend: UserID) -> bool {
+        self.added.get(&uid).map_or(false, |a| a.contains(&friend))
+    }
+
+    pub fn are_friends(&self, a: UserID, b: UserID) -> bool {
+        self.has_added(a, b) && self.has_added(b, a)
+    }
+
+    // everyone the user has added
+    pub fn friends(&self, uid: UserID) -> Vec<UserID> {
+        let mut friends: Vec<UserID> = self.added.get(&uid)
+            .map_or_else(Vec::new, |a| a.iter().cloned().collect());
+        friends.sort_by_key(|f| f.0);
+        friends
+    }
+
+    pub fn add(&mut self, uid: UserID, friend: UserID) -> Result<()> {
+        if self.added.entry(uid).or_default().insert(friend) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_friend(&mut self, uid: UserID, friend: UserID) -> Result<()> {
+        if let Some(a) = self.added.get_mut(&uid) {
+            if a.remove(&friend) {
+                if a.is_empty() {
+                    self.added.remove(&uid);
+                }
+                self.save()?;
+            }
+        }
+        Ok(())
+    }
+
+    // forget everyone the user has added, and take them off everyone else's
+    // list
+    pub fn remove(&mut self, uid: UserID) -> Result<()> {
+        let mut changed = self.added.remove(&uid).is_some();
+        for a in self.added.values_mut() {
+            changed = a.remove(&uid) || changed;
+        }
+        if changed {
+            self.added.retain(|_, a| !a.is_empty());
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = String::new();
+        for (uid, friends) in &self.added {
+            for friend in friends {
+                data.push_str(&format!("{} {}\n", uid.0, friend.0));
+            }
+        }
+        csr_storage::write(&self.path, self.key.as_ref(), data.as_bytes())
+    }
+}
@@ -0,0 +1,66 @@
+use tokio::sync::broadcast;
+
+use csr_protocol::types::{LobbyChange, SessionData, Visibility};
+
+// changes a watcher can fall behind by before its watch is ended
+const CAPACITY: usize = 256;
+
+// tells everyone watching the lobby about public sessions as they come, change
+// and go. Nothing is kept for watchers that aren't there yet, they start from
+// the session list
+#[derive(Clone)]
+pub struct Lobby {
+    tx: broadcast::Sender<LobbyChange>,
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        Self {
+            tx: tx,
+        }
+    }
+}
+
+impl Lobby {
+    pub fn subscribe(&self) -> broadcast::Receiver<LobbyChange> {
+        self.tx.subscribe()
+    }
+
+    pub fn created(&self, sd: &SessionData) {
+        if sd.visibility() == Visibility::Public {
+            self.send(LobbyChange::Created(listed(sd)));
+        }
+    }
+
+    pub fn updated(&self, sd: &SessionData) {
+        if sd.visibility() == Visibility::Public {
+            self.send(LobbyChange::Updated(listed(sd)));
+        }
+    }
+
+    pub fn removed(&self, sd: &SessionData) {
+        if sd.visibility() == Visibility::Public {
+            self.send(LobbyChange::Removed(sd.session_id()));
+        }
+    }
+
+    // a session made public joins the lobby, and one hidden from it leaves
+    pub fn changed(&self, was: Visibility, sd: &SessionData) {
+        match (was == Visibility::Public, sd.visibility() == Visibility::Public) {
+            (false, true) => self.send(LobbyChange::Created(listed(sd))),
+            (true, false) => self.send(LobbyChange::Removed(sd.session_id())),
+            _ => self.updated(sd),
+        }
+    }
+
+    // with nobody watching there is nobody to tell
+    fn send(&self, change: LobbyChange) {
+        let _ = self.tx.send(change);
+    }
+}
+
+// the session as everyone sees it, the invite code is only for the host
+fn listed(sd: &SessionData) -> SessionData {
+    sd.clone().with_invite_code("")
+}
@@ -3,49 +3,508 @@
 use std::path::Path;
 use std::ffi::OsStr;
 use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::sync::Arc;
 
+use clap::{Parser, ValueEnum};
+use http::{HeaderName, HeaderValue, Method};
+use tonic::transport::server::TcpIncoming;
 use tonic::transport::Server;
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
 use tonic_web::GrpcWebLayer;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+#[cfg(unix)]
+use tokio_stream::wrappers::UnixListenerStream;
+use tokio_stream::{StreamExt, StreamMap};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 
-use csr_protocol::server::make_server;
+use csr_protocol::server::{AdminService, CleanServerBuilder, Legacy, RequestLog, UserService};
 use csr_protocol::types::Result;
+use csr_protocol::types::{
+    AdminToken, ADMIN_TOKEN_KEY, AUTH_TOKEN_KEY, BEARER_KEY, RECONNECT_TOKEN_KEY,
+};
+use csr_storage::{StorageKey, STORAGE_KEY_VAR};
 
+mod audit;
+mod bank;
+mod banlist;
+mod blocklist;
+mod config;
 mod error;
+mod friendlist;
+mod lobby;
+mod names;
+mod observer;
+mod oidc;
+mod plugin;
+mod preset;
+mod profile;
+mod proxy;
+mod ratelimit;
+mod rest;
+mod rules;
+mod script;
 mod service;
+mod sessiondb;
+mod telemetry;
+mod users;
 
+use audit::AuditLog;
+use bank::Bank;
+use banlist::BanList;
+use blocklist::BlockList;
+use config::{Config, ConfigFile, TlsFiles};
+use error::Error;
+use friendlist::FriendList;
+use observer::LogObserver;
+use profile::ProfileStore;
+use rules::GameRegistry;
 use service::CleanService;
+use sessiondb::SessionDb;
+use telemetry::{CallSpans, Telemetry};
+use users::UserRegistry;
+
+const ADMIN_TOKEN_VAR: &str = "CSR_ADMIN_TOKEN";
+const ADMIN_TOKEN_FILE_VAR: &str = "CSR_ADMIN_TOKEN_FILE";
+const AUDIT_FILE: &str = "audit.txt";
+const BALANCE_FILE: &str = "balances.txt";
+const BAN_FILE: &str = "bans.txt";
+const BLOCK_FILE: &str = "blocks.txt";
+const FRIEND_FILE: &str = "friends.txt";
+const NAME_FILTER_FILE: &str = "name-filter.txt";
+const PLUGIN_DIR: &str = "plugins";
+const PRESET_FILE: &str = "presets.txt";
+const PROFILE_FILE: &str = "profiles.txt";
+const SCRIPT_DIR: &str = "scripts";
+const TLS_CERT_VAR: &str = "CSR_TLS_CERT";
+const TLS_KEY_VAR: &str = "CSR_TLS_KEY";
+const TLS_CLIENT_CA_VAR: &str = "CSR_TLS_CLIENT_CA";
+const UNIX_SOCKET_VAR: &str = "CSR_UNIX_SOCKET";
+const QUIC_ADDR_VAR: &str = "CSR_QUIC_ADDR";
+const CONFIG_VAR: &str = "CSR_CONFIG";
+const LISTEN_ADDRS_VAR: &str = "CSR_LISTEN_ADDRS";
+const PORT_VAR: &str = "CSR_PORT";
+const LOG_FORMAT_VAR: &str = "CSR_LOG_FORMAT";
+const PROXY_PROTOCOL_VAR: &str = "CSR_PROXY_PROTOCOL";
+const REQUEST_LOG_VAR: &str = "CSR_REQUEST_LOG";
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 5555;
+const USER_FILE: &str = "users.txt";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// TOML file of settings, overridden by the environment and flags
+    #[arg(short, long, env = CONFIG_VAR)]
+    config: Option<PathBuf>,
+    /// Addresses to listen on over TCP, separated by commas or given more than
+    /// once. Addresses without a port listen on --port [default: 0.0.0.0]
+    #[arg(long, env = LISTEN_ADDRS_VAR, value_delimiter = ',')]
+    listen: Vec<String>,
+    /// Port to listen on for addresses that don't give their own, change it to
+    /// run more than one server on a host [default: 5555]
+    #[arg(short, long, env = PORT_VAR)]
+    port: Option<u16>,
+    /// How log lines are written, json for one object per line
+    #[arg(long, env = LOG_FORMAT_VAR, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let addr = "0.0.0.0:5555".parse().expect("IP address malformed");
+    let cli = Cli::parse();
+    init_logger(cli.log_format);
+    let telemetry = Telemetry::from_env()?;
+    let file = match &cli.config {
+        Some(path) => ConfigFile::read(path)?,
+        None => ConfigFile::default(),
+    };
+
+    // load the custom game plugins and scripts
+    let mut games = GameRegistry::new();
+    for (name, game) in plugin::load_plugins(Path::new(PLUGIN_DIR))? {
+        games.insert(name, Arc::new(game));
+    }
+    for (name, game) in script::load_scripts(Path::new(SCRIPT_DIR))? {
+        if games.insert(name.clone(), Arc::new(game)).is_some() {
+            warn!("Game script {} replaces the plugin of the same name", name);
+        }
+    }
+
+    let presets = preset::load_presets(Path::new(PRESET_FILE))?;
+    let name_filter = names::load_name_filter(Path::new(NAME_FILTER_FILE))?;
+
+    // stored data is encrypted when a storage key is set
+    let key = StorageKey::from_env()?;
+    if key.is_none() {
+        info!("No {} set, stored data will not be encrypted", STORAGE_KEY_VAR);
+    }
+    let bank = Bank::load(Path::new(BALANCE_FILE), key.clone())?;
+    let profiles = ProfileStore::load(Path::new(PROFILE_FILE), key.clone())?;
+    let blocks = BlockList::load(Path::new(BLOCK_FILE), key.clone())?;
+    let friends = FriendList::load(Path::new(FRIEND_FILE), key.clone())?;
+    let bans = BanList::load(Path::new(BAN_FILE), key.clone())?;
+    let users = UserRegistry::load(Path::new(USER_FILE), key.clone())?;
+    let audit = AuditLog::new(Path::new(AUDIT_FILE), key.clone());
+    let db = SessionDb::from_env(key)?;
+    let config = Config::load(&file)?;
+    let compression = config.compression.clone();
+    let cors = cors_layer(&config)?;
+    let oidc = oidc::from_env().await?;
+    let s = Arc::new(CleanService::new(config, games, bank, profiles, blocks, friends, bans,
+                                       users, audit)
+        .with_presets(presets)
+        .with_name_filter(name_filter)
+        .with_oidc(oidc)
+        .with_session_db(db)
+        .with_observer(Arc::new(LogObserver)));
+    s.restore_sessions().await?;
+    s.spawn_session_gc();
+    let signal = s.shutdown_signal();
+
+    // admins can manage any user's data, otherwise users only manage their own.
+    // The admin service is closed to everyone without a token
+    let admin = admin_token()?;
+    if admin.is_none() {
+        info!("No {} or {} set, the admin service is closed", ADMIN_TOKEN_VAR,
+              ADMIN_TOKEN_FILE_VAR);
+    }
+
+    // load balancers and probes can ask the standard health service whether
+    // each service is up, without authenticating. The admin service never is
+    // without a token
+    let (mut health, health_service) = tonic_health::server::health_reporter();
+    health.set_serving::<UserService>().await;
+    health.set_serving::<Legacy<UserService>>().await;
+    if admin.is_some() {
+        health.set_serving::<AdminService>().await;
+        health.set_serving::<Legacy<AdminService>>().await;
+    } else {
+        health.set_not_serving::<AdminService>().await;
+        health.set_not_serving::<Legacy<AdminService>>().await;
+    }
+    let shutdown = async move {
+        signal.await;
+        health.set_not_serving::<UserService>().await;
+        health.set_not_serving::<Legacy<UserService>>().await;
+        health.set_not_serving::<AdminService>().await;
+        health.set_not_serving::<Legacy<AdminService>>().await;
+    };
+
+    rest::spawn(s.clone()).await?;
+    // every call can be logged with how long it took, switched on and off
+    // while the server runs by sending it SIGUSR1
+    let request_log = RequestLog::new(
+        std::env::var(REQUEST_LOG_VAR).is_ok_and(|v| v == "1" || v == "true"));
+    #[cfg(unix)]
+    toggle_on_signal(request_log.clone())?;
+    let clean = CleanServerBuilder::new(s)
+        .with_admin_token(admin)
+        .with_compression(&compression)
+        .with_layer(request_log)
+        .with_layer(CallSpans);
 
-    // initialize logger
-    env_logger::Builder::from_default_env()
-        .format(|buf, record| {
+    let tls = tls_files(&file.tls)?;
+
+    // local clients can connect over a unix socket too, without TLS. It is
+    // served in the background until the server exits
+    let socket = std::env::var(UNIX_SOCKET_VAR).ok();
+    #[cfg(unix)]
+    if let Some(path) = &socket {
+        // one left behind by an earlier run would stop us binding
+        remove_socket(path)?;
+        let incoming = UnixListenerStream::new(UnixListener::bind(path)?);
+        let local = clean.clone()
+            .router(Server::builder()
+                .accept_http1(true)
+                .layer(cors.clone())
+                .layer(GrpcWebLayer::new()))
+            .add_service(health_service.clone());
+        info!("Clean service listening on {}", path);
+        tokio::spawn(async move {
+            if let Err(e) = local.serve_with_incoming(incoming).await {
+                error!("Unix socket listener stopped: {:?}", e);
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    if socket.is_some() {
+        return Err(Box::new(Error::UnixSocketUnsupported(UNIX_SOCKET_VAR)));
+    }
+
+    // clients on lossy networks can connect over QUIC too, with the same
+    // certificate as TLS. It is served in the background until the server
+    // exits
+    let quic = std::env::var(QUIC_ADDR_VAR).ok();
+    #[cfg(feature = "quic")]
+    if let Some(quic_addr) = &quic {
+        let (cert, key, client_ca) = tls.as_ref()
+            .ok_or(Error::MissingSetting(QUIC_ADDR_VAR, TLS_CERT_VAR))?;
+        if client_ca.is_some() {
+            return Err(Error::QuicMutualTls(TLS_CLIENT_CA_VAR).into());
+        }
+        let incoming = csr_protocol::quic::listen(quic_addr.parse()?, cert, key)?;
+        let remote = clean.clone()
+            .router(Server::builder())
+            .add_service(health_service.clone());
+        info!("Clean service listening for QUIC on {}", quic_addr);
+        tokio::spawn(async move {
+            if let Err(e) = remote.serve_with_incoming(incoming).await {
+                error!("QUIC listener stopped: {:?}", e);
+            }
+        });
+    }
+    #[cfg(not(feature = "quic"))]
+    if quic.is_some() {
+        return Err(Error::QuicUnsupported(QUIC_ADDR_VAR).into());
+    }
+
+    // every address is served by the one server, so a client is treated the
+    // same whichever it connects to
+    let mut incoming = StreamMap::new();
+    // flags and variables over the file
+    let listen = match (cli.listen.is_empty(), file.listen) {
+        (false, _) => cli.listen,
+        (true, Some(listen)) => listen,
+        (true, None) => vec![DEFAULT_LISTEN_ADDR.to_owned()],
+    };
+    let port = cli.port.or(file.port).unwrap_or(DEFAULT_PORT);
+    for addr in listen_addrs(&listen, port)? {
+        // with TCP_NODELAY, as the server sets when it binds the address itself
+        incoming.insert(addr, TcpIncoming::from_listener(bind(addr)?, true, None)?);
+        info!("Clean service listening on {}", addr);
+    }
+
+    let mut server = Server::builder();
+    if let Some((cert, key, client_ca)) = tls {
+        server = serve_tls(server, cert, key, client_ca)?;
+    }
+    let router = clean
+        .router(server
+            .accept_http1(true)
+            .layer(cors)
+            .layer(GrpcWebLayer::new()))
+        .add_service(health_service);
+    let incoming = incoming.map(|(_, stream)| stream);
+    // behind a load balancer, each connection starts by saying which client
+    // the balancer is passing on, so they are told apart by their own address
+    if std::env::var(PROXY_PROTOCOL_VAR).is_ok_and(|v| v == "1" || v == "true") {
+        info!("Reading client addresses from PROXY protocol headers");
+        router.serve_with_incoming_shutdown(proxy::accept(incoming), shutdown).await?;
+    } else {
+        router.serve_with_incoming_shutdown(incoming, shutdown).await?;
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = socket {
+        remove_socket(&path)?;
+    }
+    telemetry.shutdown();
+    Ok(())
+}
+
+// text for reading as it scrolls past, or json for log collectors
+fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    match format {
+        LogFormat::Text => builder.format(|buf, record| {
             let level_style = buf.default_level_style(record.level());
-            let filename = Path::new(record.file().unwrap_or("unknown"))
-                .file_name().unwrap_or(OsStr::new("unknown")).to_str()
-                .unwrap_or("unknown");
             writeln!(
                 buf,
                 "{level_style}{} [{}]:{}\t{}{level_style:#}",
                 record.level(),
-                filename,
+                log_file(record),
                 record.line().unwrap_or(0),
                 record.args())
-        })
-        .init();
+        }),
+        LogFormat::Json => builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{{\"time\":\"{}\",\"level\":\"{}\",\"file\":{},\"line\":{},\"message\":{}}}",
+                buf.timestamp(),
+                record.level(),
+                json_string(log_file(record)),
+                record.line().unwrap_or(0),
+                json_string(&record.args().to_string()))
+        }),
+    };
+    builder.init();
+}
 
-    let s = CleanService::new();
+// the file the line was logged from, without its directories
+fn log_file<'a>(record: &'a log::Record) -> &'a str {
+    Path::new(record.file().unwrap_or("unknown"))
+        .file_name().unwrap_or(OsStr::new("unknown")).to_str()
+        .unwrap_or("unknown")
+}
 
-    trace!("Clean service listening on {}", addr);
+// the string as a JSON string, quoted and escaped
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
 
-    Server::builder()
-        .accept_http1(true)
-        .layer(GrpcWebLayer::new())
-        .add_service(make_server(s))
-        .serve(addr)
-        .await?;
+// the addresses to listen on, each with the port given or the default one. An
+// IPv6 address only takes IPv6 clients, so [::] and 0.0.0.0 can both be
+// listened on with the same port
+fn listen_addrs(addrs: &[String], port: u16) -> Result<Vec<SocketAddr>> {
+    let mut ret = Vec::new();
+    for addr in addrs.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        match addr.parse::<SocketAddr>() {
+            Ok(addr) => ret.push(addr),
+            Err(_) => {
+                let ip: IpAddr = addr.trim_start_matches('[').trim_end_matches(']').parse()?;
+                ret.push(SocketAddr::new(ip, port));
+            }
+        }
+    }
+    if ret.is_empty() {
+        return Err(Box::new(Error::NoListenAddrs("--listen")));
+    }
+    Ok(ret)
+}
+
+fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM,
+                                      Some(socket2::Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+#[cfg(unix)]
+fn toggle_on_signal(log: RequestLog) -> Result<()> {
+    let mut signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while signal.recv().await.is_some() {
+            log.set_enabled(!log.is_enabled());
+            info!("Request logging {}", if log.is_enabled() { "on" } else { "off" });
+        }
+    });
+    Ok(())
+}
 
+// only ever removes a socket, never a file that happens to be at the path
+#[cfg(unix)]
+fn remove_socket(path: &str) -> Result<()> {
+    if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
     Ok(())
 }
+
+// lets browsers on the configured origins call the server over grpc-web,
+// sending the headers gRPC and the server use and reading the ones they answer
+// with
+fn cors_layer(config: &Config) -> Result<CorsLayer> {
+    let origins = if config.cors_origins.iter().any(|o| o == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = config.cors_origins.iter().map(|o| HeaderValue::from_str(o))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    let mut headers = vec![
+        HeaderName::from_static("content-type"),
+        HeaderName::from_static("x-grpc-web"),
+        HeaderName::from_static("x-user-agent"),
+        HeaderName::from_static("grpc-timeout"),
+        HeaderName::from_static(AUTH_TOKEN_KEY),
+        HeaderName::from_static(BEARER_KEY),
+        HeaderName::from_static(RECONNECT_TOKEN_KEY),
+        HeaderName::from_static(ADMIN_TOKEN_KEY),
+    ];
+    for h in &config.cors_headers {
+        headers.push(HeaderName::from_bytes(h.as_bytes())?);
+    }
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::POST, Method::OPTIONS])
+        .allow_headers(headers)
+        .expose_headers([
+            HeaderName::from_static("grpc-status"),
+            HeaderName::from_static("grpc-message"),
+            HeaderName::from_static("grpc-status-details-bin"),
+            HeaderName::from_static(RECONNECT_TOKEN_KEY),
+        ]))
+}
+
+// the admin token, read from a file so it stays out of the environment, or
+// straight from a variable
+fn admin_token() -> Result<Option<AdminToken>> {
+    if let Ok(path) = std::env::var(ADMIN_TOKEN_FILE_VAR) {
+        let token = std::fs::read_to_string(path)?.trim().to_owned();
+        if token.is_empty() {
+            return Err(Box::new(Error::EmptyAdminToken(ADMIN_TOKEN_FILE_VAR)));
+        }
+        return Ok(Some(AdminToken(token)));
+    }
+    Ok(std::env::var(ADMIN_TOKEN_VAR).ok().map(AdminToken))
+}
+
+// the PEM files to serve over TLS with, a certificate and its private key, and
+// optionally the CA client certificates have to be signed by for mutual TLS.
+// Each is named by its variable, or else the config file
+fn tls_files(files: &TlsFiles) -> Result<Option<(Vec<u8>, Vec<u8>, Option<Vec<u8>>)>> {
+    let read = |var, file: &Option<PathBuf>| match (std::env::var(var), file) {
+        (Ok(path), _) => std::fs::read(path).map(Some),
+        (Err(_), Some(path)) => std::fs::read(path).map(Some),
+        (Err(_), None) => Ok(None),
+    };
+    match (read(TLS_CERT_VAR, &files.cert)?, read(TLS_KEY_VAR, &files.key)?,
+           read(TLS_CLIENT_CA_VAR, &files.client_ca)?) {
+        (Some(cert), Some(key), client_ca) => Ok(Some((cert, key, client_ca))),
+        (Some(_), None, _) => Err(Box::new(Error::MissingSetting(TLS_CERT_VAR, TLS_KEY_VAR))),
+        (None, Some(_), _) => Err(Box::new(Error::MissingSetting(TLS_KEY_VAR, TLS_CERT_VAR))),
+        (None, None, Some(_)) =>
+            Err(Box::new(Error::MissingSetting(TLS_CLIENT_CA_VAR, TLS_CERT_VAR))),
+        (None, None, None) => Ok(None),
+    }
+}
+
+// with a client CA, connections without a certificate it signed are refused
+#[cfg(feature = "tls")]
+fn serve_tls<L>(server: Server<L>, cert: Vec<u8>, key: Vec<u8>, client_ca: Option<Vec<u8>>)
+        -> Result<Server<L>> {
+    let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    if let Some(ca) = client_ca {
+        tls = tls.client_ca_root(Certificate::from_pem(ca));
+    }
+    Ok(server.tls_config(tls)?)
+}
+
+#[cfg(not(feature = "tls"))]
+fn serve_tls<L>(_server: Server<L>, _cert: Vec<u8>, _key: Vec<u8>,
+                _client_ca: Option<Vec<u8>>) -> Result<Server<L>> {
+    Err(Box::new(Error::TlsUnsupported(TLS_CERT_VAR)))
+}
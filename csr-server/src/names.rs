@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::Path;
+
+use csr_protocol::error::NameError;
+use csr_protocol::types::Result;
+
+// words players can't have in their names, whatever the case
+#[derive(Default)]
+pub struct NameFilter {
+    words: Vec<String>,
+}
+
+impl NameFilter {
+    fn allows(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        return !self.words.iter().any(|w| name.contains(w.as_str()));
+    }
+}
+
+// one word to a line, with # starting a comment. Without the file every name
+// is let through
+pub fn load_name_filter(path: &Path) -> Result<NameFilter> {
+    if !path.exists() {
+        info!("No name filter at {}", path.display());
+        return Ok(NameFilter::default());
+    }
+    let mut words = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        words.push(line.to_lowercase());
+    }
+    info!("Loaded {} filtered words", words.len());
+    Ok(NameFilter {
+        words: words,
+    })
+}
+
+// the name a player joins under, with the whitespace around and inside it
+// tidied up, as long as it isn't blank, too long, or holding control
+// characters or a filtered word
+pub fn sanitize_name(name: &str, max_length: usize, filter: &NameFilter)
+        -> std::result::Result<String, NameError> {
+    if name.chars().any(|c| c.is_control()) {
+        return Err(NameError::ControlCharacters);
+    }
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name.chars().count() > max_length {
+        return Err(NameError::TooLong(max_length));
+    }
+    if !filter.allows(&name) {
+        return Err(NameError::Filtered);
+    }
+    Ok(name)
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use csr_protocol::types::{ScoreEntry, SessionData, SessionID, UserID};
+
+// told about everything that happens to sessions, to feed analytics or extra
+// persistence without changing the service. Callbacks run on the service's own
+// tasks so should return quickly, and all of them do nothing by default
+pub trait SessionObserver: Send + Sync + 'static {
+    fn session_created(&self, _sd: &SessionData) {}
+    fn player_joined(&self, _sid: SessionID, _uid: UserID, _user_name: &str) {}
+    // running totals after each round, highest score first
+    fn round_completed(&self, _sid: SessionID, _round: u32, _entries: &[ScoreEntry]) {}
+    // the winners of a game, and what each of them was paid. Games in
+    // ephemeral sessions aren't reported
+    fn game_finished(&self, _sid: SessionID, _winners: &[UserID], _payout: u64) {}
+}
+
+// every registered observer, called in the order they were added
+#[derive(Clone, Default)]
+pub struct Observers {
+    observers: Vec<Arc<dyn SessionObserver>>,
+}
+
+impl Observers {
+    pub fn add(&mut self, observer: Arc<dyn SessionObserver>) {
+        self.observers.push(observer);
+    }
+}
+
+impl SessionObserver for Observers {
+    fn session_created(&self, sd: &SessionData) {
+        for o in &self.observers {
+            o.session_created(sd);
+        }
+    }
+    fn player_joined(&self, sid: SessionID, uid: UserID, user_name: &str) {
+        for o in &self.observers {
+            o.player_joined(sid, uid, user_name);
+        }
+    }
+    fn round_completed(&self, sid: SessionID, round: u32, entries: &[ScoreEntry]) {
+        for o in &self.observers {
+            o.round_completed(sid, round, entries);
+        }
+    }
+    fn game_finished(&self, sid: SessionID, winners: &[UserID], payout: u64) {
+        for o in &self.observers {
+            o.game_finished(sid, winners, payout);
+        }
+    }
+}
+
+// writes each event to the log at debug level
+pub struct LogObserver;
+
+impl SessionObserver for LogObserver {
+    fn session_created(&self, sd: &SessionData) {
+        debug!("Session {:?} created, type {:?}", sd.session_id(), sd.session_type());
+    }
+    fn player_joined(&self, sid: SessionID, uid: UserID, user_name: &str) {
+        debug!("User {:?} {} joined session {:?}", uid, user_name, sid);
+    }
+    fn round_completed(&self, sid: SessionID, round: u32, entries: &[ScoreEntry]) {
+        debug!("Session {:?} round {} complete, leader {:?}", sid, round,
+               entries.first().map(|e| e.user_id()));
+    }
+    fn game_finished(&self, sid: SessionID, winners: &[UserID], payout: u64) {
+        debug!("Session {:?} game won by {:?}, paid {} each", sid, winners, payout);
+    }
+}
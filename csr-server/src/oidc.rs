@@ -0,0 +1,139 @@
+use std::sync::Arc;
+#[cfg(feature = "oidc")]
+use std::sync::RwLock;
+#[cfg(feature = "oidc")]
+use std::time::Duration;
+
+#[cfg(feature = "oidc")]
+use jsonwebtoken::jwk::JwkSet;
+#[cfg(feature = "oidc")]
+use jsonwebtoken::{DecodingKey, Validation};
+
+use csr_protocol::types::Result;
+
+use crate::error::Error;
+
+const ISSUER_VAR: &str = "CSR_OIDC_ISSUER";
+#[cfg(feature = "oidc")]
+const JWKS_URL_VAR: &str = "CSR_OIDC_JWKS_URL";
+#[cfg(feature = "oidc")]
+const AUDIENCE_VAR: &str = "CSR_OIDC_AUDIENCE";
+
+// how often the provider's signing keys are fetched again, to pick up keys it
+// rotates in
+#[cfg(feature = "oidc")]
+const JWKS_REFRESH: Duration = Duration::from_secs(15 * 60);
+
+// who a valid token says the user is
+pub struct Identity {
+    pub subject: String,
+    pub name: String,
+}
+
+#[cfg(feature = "oidc")]
+#[derive(serde::Deserialize)]
+struct Claims {
+    sub: String,
+    preferred_username: Option<String>,
+    name: Option<String>,
+}
+
+// checks tokens from an external identity provider were signed with one of
+// its keys, by the configured issuer, for this server, and haven't expired
+#[cfg(feature = "oidc")]
+pub struct OidcVerifier {
+    issuer: String,
+    audience: Option<String>,
+    jwks_url: String,
+    keys: RwLock<JwkSet>,
+}
+
+// never built without the oidc feature
+#[cfg(not(feature = "oidc"))]
+pub enum OidcVerifier {}
+
+#[cfg(feature = "oidc")]
+impl OidcVerifier {
+    pub fn verify(&self, token: &str) -> Option<Identity> {
+        let header = jsonwebtoken::decode_header(token).ok()?;
+        let keys = self.keys.read().unwrap();
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid)?,
+            // providers with a single key may leave its ID out
+            None if keys.keys.len() == 1 => &keys.keys[0],
+            None => { return None; }
+        };
+        // the key decides the family of algorithm, so a token can't pick a
+        // weaker one
+        let key = DecodingKey::from_jwk(jwk).ok()?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<Claims>(token, &key, &validation).ok()?.claims;
+        let name = claims.preferred_username.or(claims.name)
+            .unwrap_or_else(|| claims.sub.clone());
+        Some(Identity {
+            subject: claims.sub,
+            name: name,
+        })
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        let keys = reqwest::get(&self.jwks_url).await?.error_for_status()?
+            .json::<JwkSet>().await?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "oidc"))]
+impl OidcVerifier {
+    pub fn verify(&self, _token: &str) -> Option<Identity> {
+        match *self {}
+    }
+}
+
+// the identity provider to trust when an issuer is set, with its keys fetched
+// from the JWKS URL and kept up to date in the background
+#[cfg(feature = "oidc")]
+pub async fn from_env() -> Result<Option<Arc<OidcVerifier>>> {
+    let issuer = match std::env::var(ISSUER_VAR) {
+        Ok(issuer) => issuer,
+        Err(_) => { return Ok(None); }
+    };
+    let jwks_url = std::env::var(JWKS_URL_VAR)
+        .map_err(|_| Error::MissingSetting(ISSUER_VAR, JWKS_URL_VAR))?;
+    let verifier = Arc::new(OidcVerifier {
+        issuer: issuer,
+        audience: std::env::var(AUDIENCE_VAR).ok(),
+        jwks_url: jwks_url,
+        keys: RwLock::new(JwkSet { keys: Vec::new() }),
+    });
+    verifier.refresh().await?;
+    info!("Accepting tokens issued by {}", verifier.issuer);
+
+    let v = verifier.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(JWKS_REFRESH);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            // keep the keys we have until the provider can be reached again
+            if let Err(e) = v.refresh().await {
+                warn!("Failed to refresh the keys from {}: {:?}", v.jwks_url, e);
+            }
+        }
+    });
+    Ok(Some(verifier))
+}
+
+#[cfg(not(feature = "oidc"))]
+pub async fn from_env() -> Result<Option<Arc<OidcVerifier>>> {
+    if std::env::var(ISSUER_VAR).is_ok() {
+        return Err(Box::new(Error::OidcUnsupported(ISSUER_VAR)));
+    }
+    Ok(None)
+}
@@ -0,0 +1,166 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use wasmi::{Caller, Config, Engine, Extern, Linker, Module, Store};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::UserID;
+
+use crate::rules::{GameHost, GameRules};
+
+// the fuel a plugin has for a whole game, roughly one per instruction it runs,
+// so one stuck in a loop fails instead of holding its thread forever
+const FUEL: u64 = 1_000_000_000;
+
+// WebAssembly game plugins
+//
+// A plugin is a core wasm module that exports its linear memory as `memory`
+// and a `play` function taking no arguments. While `play` runs, the plugin
+// can call the host functions imported from the `csr` module:
+//
+// * `player_count() -> i32`
+// * `prompt(seat, msg_ptr, msg_len, out_ptr, out_cap) -> i32` sends the
+//   message to the player in `seat`, writes at most `out_cap` bytes of their
+//   answer to `out_ptr` and returns the number of bytes written
+// * `take_turn(seat, msg_ptr, msg_len, out_ptr, out_cap) -> i32` works like
+//   `prompt`, and tells every other player who they are waiting on
+// * `next_seat() -> i32` the seat whose turn is next
+// * `next_turn(msg_ptr, msg_len, out_ptr, out_cap) -> i32` takes the turn for
+//   `next_seat()`, and passes the turn on to the following seat
+// * `declare_winner(seat)` records the winner of the game
+//
+// The game fails if `play` returns without declaring a winner, runs out of
+// fuel, or passes a seat, pointer or length that is negative or out of range.
+pub struct WasmGame {
+    engine: Engine,
+    module: Module,
+}
+
+impl WasmGame {
+    pub fn new(wasm: &[u8]) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+        Ok(Self {
+            engine: engine,
+            module: module,
+        })
+    }
+}
+
+impl GameRules for WasmGame {
+    fn play(&self, host: GameHost) -> Result<UserID> {
+        let mut store = Store::new(&self.engine, host);
+        store.set_fuel(FUEL).map_err(wasmi::Error::from)?;
+        let mut linker = <Linker<GameHost>>::new(&self.engine);
+        linker.func_wrap("csr", "player_count",
+            |caller: Caller<'_, GameHost>| -> i32 {
+                caller.data().player_count() as i32
+            })?;
+        linker.func_wrap("csr", "prompt",
+            |mut caller: Caller<'_, GameHost>, seat: i32, msg_ptr: i32,
+             msg_len: i32, out_ptr: i32, out_cap: i32|
+                -> std::result::Result<i32, wasmi::Error> {
+                ask(&mut caller, seat, msg_ptr, msg_len, out_ptr, out_cap, false)
+            })?;
+        linker.func_wrap("csr", "take_turn",
+            |mut caller: Caller<'_, GameHost>, seat: i32, msg_ptr: i32,
+             msg_len: i32, out_ptr: i32, out_cap: i32|
+                -> std::result::Result<i32, wasmi::Error> {
+                ask(&mut caller, seat, msg_ptr, msg_len, out_ptr, out_cap, true)
+            })?;
+        linker.func_wrap("csr", "next_seat",
+            |caller: Caller<'_, GameHost>| -> i32 {
+                caller.data().next_seat() as i32
+            })?;
+        linker.func_wrap("csr", "next_turn",
+            |mut caller: Caller<'_, GameHost>, msg_ptr: i32, msg_len: i32, out_ptr: i32,
+             out_cap: i32| -> std::result::Result<i32, wasmi::Error> {
+                let seat = caller.data().next_seat() as i32;
+                let len = ask(&mut caller, seat, msg_ptr, msg_len, out_ptr, out_cap, true)?;
+                caller.data().pass_turn();
+                Ok(len)
+            })?;
+        linker.func_wrap("csr", "declare_winner",
+            |mut caller: Caller<'_, GameHost>, seat: i32|
+                -> std::result::Result<(), wasmi::Error> {
+                let seat = seat_arg(caller.data(), seat)?;
+                caller.data_mut().declare_winner(seat)
+                    .map_err(|e| wasmi::Error::new(format!("{}", e)))
+            })?;
+
+        let instance = linker.instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        let play = instance.get_typed_func::<(), ()>(&store, "play")?;
+        play.call(&mut store, ())?;
+        store.data().winner()
+    }
+}
+
+// read the message out of the plugin's memory, ask the player, and write as
+// much of their answer back as fits, returning its length
+fn ask(caller: &mut Caller<'_, GameHost>, seat: i32, msg_ptr: i32, msg_len: i32,
+       out_ptr: i32, out_cap: i32, turn: bool) -> std::result::Result<i32, wasmi::Error> {
+    let memory = caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("Plugin does not export memory"))?;
+    let seat = seat_arg(caller.data(), seat)?;
+    // check both buffers lie in the plugin's memory before reading any of it
+    let size = memory.data(&*caller).len();
+    let (msg_ptr, msg_len) = buffer_arg(msg_ptr, msg_len, size)?;
+    let (out_ptr, out_cap) = buffer_arg(out_ptr, out_cap, size)?;
+    let mut msg = vec![0; msg_len];
+    memory.read(&*caller, msg_ptr, &mut msg)?;
+    let text = String::from_utf8_lossy(&msg);
+    let answer = if turn {
+        caller.data().take_turn(seat, &text)
+    } else {
+        caller.data().prompt(seat, &text)
+    }.map_err(|e| wasmi::Error::new(format!("{}", e)))?;
+    let len = answer.len().min(out_cap);
+    memory.write(&mut *caller, out_ptr, &answer.as_bytes()[..len])?;
+    Ok(len as i32)
+}
+
+// a seat the plugin passed in, as long as someone is sitting in it
+fn seat_arg(host: &GameHost, seat: i32) -> std::result::Result<usize, wasmi::Error> {
+    match usize::try_from(seat) {
+        Ok(seat) if seat < host.player_count() => Ok(seat),
+        _ => Err(wasmi::Error::new(format!("Invalid seat {}", seat))),
+    }
+}
+
+// a pointer and length the plugin passed in, as long as the bytes they cover
+// are all in its memory
+fn buffer_arg(ptr: i32, len: i32, size: usize)
+        -> std::result::Result<(usize, usize), wasmi::Error> {
+    match (usize::try_from(ptr), usize::try_from(len)) {
+        (Ok(p), Ok(l)) if p.checked_add(l).is_some_and(|end| end <= size) => Ok((p, l)),
+        _ => Err(wasmi::Error::new(format!("Buffer of {} bytes at {} is out of bounds", len, ptr))),
+    }
+}
+
+// load every .wasm file in a directory, named after the file stem
+pub fn load_plugins(dir: &Path) -> Result<Vec<(String, WasmGame)>> {
+    let mut ret = Vec::new();
+    if !dir.is_dir() {
+        info!("No plugin directory at {}", dir.display());
+        return Ok(ret);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("wasm")) {
+            continue;
+        }
+        let name = match path.file_stem().and_then(OsStr::to_str) {
+            Some(n) => n.to_owned(),
+            None => continue,
+        };
+        let game = WasmGame::new(&fs::read(&path)?)?;
+        info!("Loaded game plugin {} from {}", name, path.display());
+        ret.push((name, game));
+    }
+    Ok(ret)
+}
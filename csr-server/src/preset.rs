@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use csr_protocol::types::Result;
+use csr_protocol::types::{HostInfo, SessionType};
+
+use crate::error::Error;
+
+// session settings hosts can pick by name instead of entering each one
+pub type Presets = HashMap<String, HostInfo>;
+
+// Preset files
+//
+// Each line of the file names a preset and its settings, in the form
+// `name = setting=value ...`, with `#` starting a comment. For example
+//
+//     quick = type=c players=2
+//     league = type=d players=6 min=4 rounds=3 wager=50
+//
+// The settings are `type` (c for coin, d for dice, or a custom game name) and
+// `players`, which are needed, and optionally `min` players to start with,
+// `rounds` for a best of series, `teams` (y or n), `wager` in chips and `house`
+// for the points a single player needs to beat the house
+pub fn load_presets(path: &Path) -> Result<Presets> {
+    let mut ret = Presets::new();
+    if !path.exists() {
+        info!("No presets at {}", path.display());
+        return Ok(ret);
+    }
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, settings) = line.split_once('=')
+            .ok_or_else(|| Error::InvalidPreset(line.to_owned()))?;
+        let name = name.trim().to_owned();
+        let hi = parse_preset(settings)?.with_name(&name);
+        info!("Loaded preset {}", name);
+        ret.insert(name, hi);
+    }
+    Ok(ret)
+}
+
+fn parse_preset(settings: &str) -> Result<HostInfo> {
+    let mut values = HashMap::new();
+    for setting in settings.split_whitespace() {
+        let (key, value) = setting.split_once('=')
+            .ok_or_else(|| Error::InvalidPreset(setting.to_owned()))?;
+        values.insert(key, value);
+    }
+    let typ = match values.get("type") {
+        Some(&"c") => SessionType::Coin,
+        Some(&"d") => SessionType::Dice,
+        Some(t) => SessionType::Custom(t.to_string()),
+        None => { return Err(Box::new(Error::InvalidPreset(settings.trim().to_owned()))); }
+    };
+    let players = values.get("players")
+        .ok_or_else(|| Error::InvalidPreset(settings.trim().to_owned()))?;
+
+    let mut hi = HostInfo::new(typ, players.parse()?);
+    if let Some(min) = values.get("min") {
+        hi = hi.with_min_players(min.parse()?);
+    }
+    if let Some(rounds) = values.get("rounds") {
+        hi = hi.with_series_length(rounds.parse()?);
+    }
+    if let Some(teams) = values.get("teams") {
+        hi = hi.with_team_mode(*teams == "y");
+    }
+    if let Some(wager) = values.get("wager") {
+        hi = hi.with_wager(wager.parse()?);
+    }
+    if let Some(house) = values.get("house") {
+        hi = hi.with_house_target(house.parse()?);
+    }
+    Ok(hi)
+}
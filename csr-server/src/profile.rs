@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::{Profile, SessionType, UserID};
+use csr_storage::StorageKey;
+
+// how many games a user has finished and won, and how many of each type of
+// game they have played
+#[derive(Default)]
+struct Record {
+    played: u32,
+    wins: u32,
+    types: HashMap<String, u32>,
+}
+
+impl Record {
+    // the type played the most, the first by name on a tie
+    fn favorite_type(&self) -> Option<SessionType> {
+        let (name, _) = self.types.iter()
+            .max_by(|(an, ac), (bn, bc)| ac.cmp(bc).then(bn.cmp(an)))?;
+        Some(from_key(name))
+    }
+}
+
+// the built in games are written the same way presets name them
+fn key(st: &SessionType) -> String {
+    match st {
+        SessionType::Coin => "c".to_owned(),
+        SessionType::Dice => "d".to_owned(),
+        SessionType::Custom(name) => name.clone(),
+    }
+}
+
+fn from_key(key: &str) -> SessionType {
+    match key {
+        "c" => SessionType::Coin,
+        "d" => SessionType::Dice,
+        name => SessionType::Custom(name.to_owned()),
+    }
+}
+
+// every user's record over the games they have finished, saved to a file after
+// every change. Each line of the file holds a user ID, the games they have
+// played and won, then a type:count pair for each type of game they have
+// played, encrypted when the server has a storage key
+pub struct ProfileStore {
+    path: PathBuf,
+    key: Option<StorageKey>,
+    records: HashMap<UserID, Record>,
+}
+
+impl ProfileStore {
+    pub fn load(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        let mut records = HashMap::new();
+        if path.exists() {
+            let data = String::from_utf8(csr_storage::read(path, key.as_ref())?)?;
+            for line in data.lines() {
+                let mut fields = line.split(' ');
+                let (uid, played, wins) = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(uid), Some(played), Some(wins)) => (uid, played, wins),
+                    _ => { continue; }
+                };
+                let mut record = Record {
+                    played: played.parse()?,
+                    wins: wins.parse()?,
+                    types: HashMap::new(),
+                };
+                // custom game names can hold a colon, the count can't
+                for t in fields {
+                    if let Some((name, count)) = t.rsplit_once(':') {
+                        record.types.insert(name.to_owned(), count.parse()?);
+                    }
+                }
+                records.insert(UserID(uid.parse()?), record);
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            key: key,
+            records: records,
+        })
+    }
+
+    // count a finished game for everyone who played it, and a win for the
+    // winners
+    pub fn record_game(&mut self, st: &SessionType, players: &[UserID], winners: &[UserID])
+            -> Result<()> {
+        for uid in players {
+            let record = self.records.entry(*uid).or_default();
+            record.played = record.played + 1;
+            if winners.contains(uid) {
+                record.wins = record.wins + 1;
+            }
+            let count = record.types.entry(key(st)).or_default();
+            *count = *count + 1;
+        }
+        self.save()
+    }
+
+    // users who have yet to finish a game have an empty profile
+    pub fn profile(&self, uid: UserID, name: &str) -> Profile {
+        self.stored_profile(uid, name).unwrap_or_else(|| Profile::new(uid, name, 0, 0))
+    }
+
+    // the profile only if a game has been recorded for the user
+    pub fn stored_profile(&self, uid: UserID, name: &str) -> Option<Profile> {
+        self.records.get(&uid).map(|r| {
+            Profile::new(uid, name, r.played, r.wins).with_favorite_type(r.favorite_type())
+        })
+    }
+
+    pub fn remove(&mut self, uid: UserID) -> Result<()> {
+        if self.records.remove(&uid).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = String::new();
+        for (uid, r) in &self.records {
+            data.push_str(&format!("{} {} {}", uid.0, r.played, r.wins));
+            for (name, count) in &r.types {
+                data.push_str(&format!(" {}:{}", name, count));
+            }
+            data.push('\n');
+        }
+        csr_storage::write(&self.path, self.key.as_ref(), data.as_bytes())
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+// every PROXY protocol v2 header starts with this
+const SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// the load balancer checking the server is up, rather than passing a client on
+const LOCAL: u8 = 0x20;
+const PROXY: u8 = 0x21;
+const TCP4: u8 = 0x11;
+const TCP6: u8 = 0x21;
+// how long a connection has to send its header before it is dropped
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+// connections with their header read that the server hasn't taken yet
+const BACKLOG: usize = 64;
+
+// a connection passed on by a load balancer, which reports the address of the
+// client it came from instead of the balancer's
+pub struct ProxiedStream {
+    inner: TcpStream,
+    client: SocketAddr,
+}
+
+impl Connected for ProxiedStream {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        TcpConnectInfo {
+            local_addr: self.inner.local_addr().ok(),
+            remote_addr: Some(self.client),
+        }
+    }
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>)
+            -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+            -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>)
+            -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// reads the PROXY protocol v2 header off each connection before handing it to
+// the server, so the server only ever sees connections through the balancer.
+// Headers are read alongside each other, so a slow connection doesn't hold up
+// the rest, and a connection without one is dropped
+pub fn accept<S>(mut incoming: S) -> ReceiverStream<std::io::Result<ProxiedStream>>
+        where S: Stream<Item = std::io::Result<TcpStream>> + Unpin + Send + 'static {
+    let (tx, rx) = mpsc::channel(BACKLOG);
+    tokio::spawn(async move {
+        while let Some(stream) = incoming.next().await {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    if tx.send(Err(e)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let header = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await;
+                let client = match header {
+                    Ok(Ok(Some(client))) => client,
+                    // the balancer's own health checks are answered as it
+                    Ok(Ok(None)) => match stream.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(_) => { return; }
+                    },
+                    Ok(Err(e)) => {
+                        debug!("Dropped connection without a PROXY header: {}", e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("Dropped connection that sent no PROXY header in time");
+                        return;
+                    }
+                };
+                if let Ok(balancer) = stream.peer_addr() {
+                    debug!("Connection from {} through {}", client, balancer);
+                }
+                let _ = tx.send(Ok(ProxiedStream { inner: stream, client: client })).await;
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+// the client the balancer is passing on, or none for the balancer's own
+// connections
+async fn read_header(stream: &mut TcpStream) -> std::io::Result<Option<SocketAddr>> {
+    let invalid = |why| Error::new(ErrorKind::InvalidData, why);
+    let mut fixed = [0u8; 16];
+    stream.read_exact(&mut fixed).await?;
+    if &fixed[..12] != SIGNATURE {
+        return Err(invalid("not a PROXY protocol v2 header"));
+    }
+    let len = u16::from_be_bytes([fixed[14], fixed[15]]) as usize;
+    let mut rest = vec![0u8; len];
+    stream.read_exact(&mut rest).await?;
+    match (fixed[12], fixed[13]) {
+        (LOCAL, _) => Ok(None),
+        (PROXY, TCP4) if len >= 12 => {
+            let ip = Ipv4Addr::new(rest[0], rest[1], rest[2], rest[3]);
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([rest[8], rest[9]]))))
+        }
+        (PROXY, TCP6) if len >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&rest[..16]);
+            let ip = Ipv6Addr::from(octets);
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([rest[32], rest[33]]))))
+        }
+        // UDP or unix sockets, which a gRPC client can't have come over
+        (PROXY, _) => Err(invalid("unsupported PROXY protocol address family")),
+        _ => Err(invalid("unsupported PROXY protocol version or command")),
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use csr_protocol::types::RateKey;
+
+// past this many callers, those whose allowance has filled back up are
+// forgotten
+const MAX_TRACKED: usize = 4096;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+// a bucket of requests for each caller, holding up to the burst. Each request
+// spends one, and the bucket fills back up at the rate allowed
+pub struct RateLimiter {
+    burst: f64,
+    // requests a second
+    rate: f64,
+    buckets: HashMap<RateKey, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(requests: u32, per: Duration, burst: u32) -> Self {
+        Self {
+            burst: burst.max(1) as f64,
+            rate: requests as f64 / per.as_secs_f64(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn allow(&mut self, key: RateKey) -> bool {
+        let now = Instant::now();
+        if self.buckets.len() >= MAX_TRACKED {
+            self.forget_idle(now);
+        }
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            last: now,
+        });
+        let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.last = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens = bucket.tokens - 1.0;
+        true
+    }
+
+    // a full bucket is the same as a new one
+    fn forget_idle(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, b| {
+            b.tokens + now.duration_since(b.last).as_secs_f64() * rate < burst
+        });
+    }
+}
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+#[cfg(feature = "rest")]
+use std::time::UNIX_EPOCH;
+
+#[cfg(feature = "rest")]
+use axum::extract::{Path, Query, State};
+#[cfg(feature = "rest")]
+use axum::http::{HeaderMap, StatusCode};
+#[cfg(feature = "rest")]
+use axum::response::{IntoResponse, Response};
+#[cfg(feature = "rest")]
+use axum::routing::{get, post};
+#[cfg(feature = "rest")]
+use axum::{Json, Router};
+
+use csr_protocol::server::Clean;
+use csr_protocol::types::Result;
+#[cfg(feature = "rest")]
+use csr_protocol::types::{
+    AccountType, AuthToken, ErrorCode, HostInfo, JoinInfo, ListFilter, RateKey, SessionData,
+    SessionID, SessionType, TeamID, UserID, Visibility, AUTH_TOKEN_KEY, BEARER_KEY,
+};
+
+#[cfg(feature = "rest")]
+use crate::error::guidance;
+#[cfg(not(feature = "rest"))]
+use crate::error::Error;
+
+const REST_ADDR_VAR: &str = "CSR_REST_ADDR";
+
+// the query string of GET /sessions
+#[cfg(feature = "rest")]
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct ListQuery {
+    #[serde(rename = "type")]
+    typ: Option<String>,
+    joinable: bool,
+    // only the sessions the caller is hosting
+    mine: bool,
+}
+
+// the body of POST /sessions
+#[cfg(feature = "rest")]
+#[derive(serde::Deserialize)]
+struct HostRequest {
+    #[serde(rename = "type")]
+    typ: String,
+    max_players: u8,
+    #[serde(default)]
+    min_players: Option<u8>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    series_length: Option<u8>,
+    #[serde(default)]
+    team_mode: bool,
+    #[serde(default)]
+    wager: u64,
+    #[serde(default)]
+    visibility: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    approve_joins: bool,
+}
+
+// the body of POST /sessions/{id}/join
+#[cfg(feature = "rest")]
+#[derive(serde::Deserialize)]
+struct JoinRequest {
+    name: String,
+    #[serde(default)]
+    team: Option<u32>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    join_token: Option<String>,
+}
+
+#[cfg(feature = "rest")]
+#[derive(serde::Serialize)]
+struct SessionJson {
+    id: u64,
+    #[serde(rename = "type")]
+    typ: String,
+    name: String,
+    users: Vec<String>,
+    max_players: u8,
+    min_players: u8,
+    team_mode: bool,
+    wager: u64,
+    visibility: &'static str,
+    locked: bool,
+    approve_joins: bool,
+    // seconds since the epoch
+    start_at: Option<u64>,
+    // only given to the host, when they host the session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    invite_code: Option<String>,
+}
+
+#[cfg(feature = "rest")]
+impl SessionJson {
+    fn new(sd: &SessionData) -> Self {
+        let code = sd.invite_code();
+        Self {
+            id: sd.session_id().0,
+            typ: type_name(sd.session_type()),
+            name: sd.name().to_owned(),
+            users: sd.users().to_vec(),
+            max_players: sd.max_players(),
+            min_players: sd.min_players(),
+            team_mode: sd.team_mode(),
+            wager: sd.wager(),
+            visibility: match sd.visibility() {
+                Visibility::Public => "public",
+                Visibility::Unlisted => "unlisted",
+                Visibility::Private => "private",
+            },
+            locked: sd.locked(),
+            approve_joins: sd.approve_joins(),
+            start_at: sd.start_at()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            invite_code: if code.is_empty() { None } else { Some(code.to_owned()) },
+        }
+    }
+}
+
+#[cfg(feature = "rest")]
+fn type_name(typ: &SessionType) -> String {
+    match typ {
+        SessionType::Dice => "dice".to_owned(),
+        SessionType::Coin => "coin".to_owned(),
+        SessionType::Custom(name) => name.clone(),
+    }
+}
+
+#[cfg(feature = "rest")]
+fn session_type(name: &str) -> SessionType {
+    match name {
+        "dice" => SessionType::Dice,
+        "coin" => SessionType::Coin,
+        _ => SessionType::Custom(name.to_owned()),
+    }
+}
+
+// why a request was turned away, sent back as a JSON error with the matching
+// status
+#[cfg(feature = "rest")]
+struct Refusal(StatusCode, String);
+
+#[cfg(feature = "rest")]
+impl IntoResponse for Refusal {
+    fn into_response(self) -> Response {
+        #[derive(serde::Serialize)]
+        struct Body {
+            error: String,
+        }
+        (self.0, Json(Body { error: self.1 })).into_response()
+    }
+}
+
+// the status for each error code, matching the gRPC code it is sent with
+#[cfg(feature = "rest")]
+impl From<Box<dyn std::error::Error + Send + Sync>> for Refusal {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        let status = match guidance(e.as_ref()).0 {
+            ErrorCode::Unspecified => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ClientUnreachable | ErrorCode::UserOffline =>
+                StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::SessionNotFound | ErrorCode::UnknownUser => StatusCode::NOT_FOUND,
+            ErrorCode::UserAlreadyInSession | ErrorCode::NameTaken => StatusCode::CONFLICT,
+            ErrorCode::SessionStarted | ErrorCode::InsufficientChips
+                | ErrorCode::UserNotInSession | ErrorCode::InvalidState | ErrorCode::Banned =>
+                StatusCode::PRECONDITION_FAILED,
+            ErrorCode::SessionFull | ErrorCode::SessionLimitReached | ErrorCode::RateLimited =>
+                StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::NotHost | ErrorCode::WrongPassword | ErrorCode::JoinRejected
+                | ErrorCode::UserBanned | ErrorCode::NotFriends | ErrorCode::UserBlocked
+                | ErrorCode::GuestCannotHost => StatusCode::FORBIDDEN,
+            ErrorCode::Unauthenticated => StatusCode::UNAUTHORIZED,
+            ErrorCode::InvalidResponse | ErrorCode::InvalidRequest | ErrorCode::InvalidJoinToken
+                | ErrorCode::InvalidName | ErrorCode::StaleResponse => StatusCode::BAD_REQUEST,
+        };
+        Refusal(status, format!("{}", e))
+    }
+}
+
+#[cfg(feature = "rest")]
+fn refuse(e: csr_protocol::error::Error) -> Refusal {
+    let e: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
+    e.into()
+}
+
+// the user the request's token was issued to, sent as x-auth-token or as a
+// bearer token from a trusted identity provider, the same as over gRPC
+#[cfg(feature = "rest")]
+fn caller(server: &dyn Clean, headers: &HeaderMap) -> std::result::Result<UserID, Refusal> {
+    let presented = |key| headers.get(key).and_then(|t| t.to_str().ok());
+    let uid = if let Some(t) = presented(AUTH_TOKEN_KEY) {
+        server.authenticate(&AuthToken(t.to_owned()), None)
+    } else {
+        presented(BEARER_KEY).and_then(|t| t.strip_prefix("Bearer "))
+            .and_then(|jwt| server.authenticate_bearer(jwt))
+    };
+    uid.ok_or_else(|| refuse(csr_protocol::error::Error::Unauthenticated))
+}
+
+// hosting and listing count against the caller's requests, as over gRPC
+#[cfg(feature = "rest")]
+fn throttle(server: &dyn Clean, uid: UserID) -> std::result::Result<(), Refusal> {
+    if !server.allow_request(RateKey::User(uid)) {
+        return Err(refuse(csr_protocol::error::Error::RateLimited));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "rest")]
+async fn list_sessions(State(server): State<Arc<dyn Clean>>, headers: HeaderMap,
+                       Query(q): Query<ListQuery>)
+        -> std::result::Result<Json<Vec<SessionJson>>, Refusal> {
+    let uid = caller(server.as_ref(), &headers)?;
+    throttle(server.as_ref(), uid)?;
+    let mut filter = ListFilter::new().with_joinable_only(q.joinable);
+    if let Some(typ) = &q.typ {
+        filter = filter.with_session_type(session_type(typ));
+    }
+    if q.mine {
+        filter = filter.with_host(uid);
+    }
+    let sessions = server.list_sessions(&filter).await?;
+    Ok(Json(sessions.iter().map(SessionJson::new).collect()))
+}
+
+#[cfg(feature = "rest")]
+async fn host_session(State(server): State<Arc<dyn Clean>>, headers: HeaderMap,
+                      Json(hr): Json<HostRequest>)
+        -> std::result::Result<(StatusCode, Json<SessionJson>), Refusal> {
+    let uid = caller(server.as_ref(), &headers)?;
+    throttle(server.as_ref(), uid)?;
+    if server.account_type(uid) == AccountType::Guest {
+        return Err(refuse(csr_protocol::error::Error::GuestCannotHost));
+    }
+    let visibility = match hr.visibility.as_deref() {
+        None | Some("public") => Visibility::Public,
+        Some("unlisted") => Visibility::Unlisted,
+        Some("private") => Visibility::Private,
+        Some(v) => {
+            return Err(Refusal(StatusCode::BAD_REQUEST, format!("Unknown visibility {}", v)));
+        }
+    };
+    let mut hi = HostInfo::new(session_type(&hr.typ), hr.max_players)
+        .with_host(uid)
+        .with_team_mode(hr.team_mode)
+        .with_wager(hr.wager)
+        .with_visibility(visibility)
+        .with_password(hr.password)
+        .with_approve_joins(hr.approve_joins);
+    if let Some(min) = hr.min_players {
+        hi = hi.with_min_players(min);
+    }
+    if let Some(name) = &hr.name {
+        hi = hi.with_name(name);
+    }
+    if let Some(series) = hr.series_length {
+        hi = hi.with_series_length(series);
+    }
+    let sd = server.host_session(hi).await?;
+    Ok((StatusCode::CREATED, Json(SessionJson::new(&sd))))
+}
+
+// joins the caller to the session, who then listens for its events over gRPC
+#[cfg(feature = "rest")]
+async fn join_session(State(server): State<Arc<dyn Clean>>, headers: HeaderMap,
+                      Path(sid): Path<u64>, Json(jr): Json<JoinRequest>)
+        -> std::result::Result<StatusCode, Refusal> {
+    let uid = caller(server.as_ref(), &headers)?;
+    let mut ji = JoinInfo::new(SessionID(sid), uid, &jr.name)
+        .with_team(jr.team.map(TeamID));
+    if let Some(password) = &jr.password {
+        ji = ji.with_password(password);
+    }
+    if let Some(token) = &jr.join_token {
+        ji = ji.with_join_token(token);
+    }
+    server.join_session(ji).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// serves the lobby over plain HTTP and JSON on the address in CSR_REST_ADDR,
+// for scripts and dashboards without gRPC tooling
+#[cfg(feature = "rest")]
+pub async fn spawn(server: Arc<dyn Clean>) -> Result<()> {
+    let addr = match std::env::var(REST_ADDR_VAR) {
+        Ok(addr) => addr,
+        Err(_) => { return Ok(()); }
+    };
+    let app = Router::new()
+        .route("/sessions", get(list_sessions).post(host_session))
+        .route("/sessions/:id/join", post(join_session))
+        .with_state(server);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("REST gateway listening on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("REST gateway stopped: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "rest"))]
+pub async fn spawn(_server: Arc<dyn Clean>) -> Result<()> {
+    if std::env::var(REST_ADDR_VAR).is_ok() {
+        return Err(Box::new(Error::RestUnsupported(REST_ADDR_VAR)));
+    }
+    Ok(())
+}
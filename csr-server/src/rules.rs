@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::runtime::Handle;
+
+use csr_protocol::event::ServerEvent;
+use csr_protocol::types::Result;
+use csr_protocol::types::UserID;
+
+use crate::error::Error;
+use crate::service::Callback;
+
+// game rules provided from outside the server binary, such as plugins
+pub trait GameRules: Send + Sync + 'static {
+    fn play(&self, host: GameHost) -> Result<UserID>;
+}
+
+pub type GameRegistry = HashMap<String, Arc<dyn GameRules>>;
+
+// the interface custom game rules use to talk to the players. Players are
+// addressed by their seat, an index into the seated players.
+//
+// Games where everyone acts at once prompt each seat directly. Turn based
+// games instead take turns in seat order with `next_turn`, and everyone else
+// is told who they are waiting on while a player takes their turn
+pub struct GameHost {
+    seats: Vec<UserID>,
+    names: Vec<String>,
+    cb: Arc<Callback>,
+    handle: Handle,
+    winner: Option<UserID>,
+    next_seat: AtomicUsize,
+}
+
+impl GameHost {
+    // must be created from within the tokio runtime
+    pub fn new(players: Vec<(UserID, String)>, cb: Arc<Callback>) -> Self {
+        let (seats, names) = players.into_iter().unzip();
+        Self {
+            seats: seats,
+            names: names,
+            cb: cb,
+            handle: Handle::current(),
+            winner: None,
+            next_seat: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn player_count(&self) -> usize { self.seats.len() }
+
+    pub fn prompt(&self, seat: usize, text: &str) -> Result<String> {
+        let uid = self.seat(seat)?;
+        self.handle.block_on(self.cb.call(uid, |s| async move { s.prompt(text).await }))
+    }
+
+    // prompt the player in the seat, while everyone else is told they're
+    // waiting on them
+    pub fn take_turn(&self, seat: usize, text: &str) -> Result<String> {
+        let uid = self.seat(seat)?;
+        let name = &self.names[seat];
+        for other in self.seats.iter().filter(|other| **other != uid) {
+            self.handle.block_on(self.cb.route(*other)?.waiting_for_player(uid, name))?;
+        }
+        self.prompt(seat, text)
+    }
+
+    // the seat whose turn is next
+    pub fn next_seat(&self) -> usize { self.next_seat.load(Ordering::Relaxed) }
+
+    // give the next seat their turn and pass it on around the table,
+    // returning the seat that played and their answer
+    pub fn next_turn(&self, text: &str) -> Result<(usize, String)> {
+        let seat = self.next_seat();
+        let answer = self.take_turn(seat, text)?;
+        self.pass_turn();
+        Ok((seat, answer))
+    }
+
+    // move the turn on to the following seat
+    pub fn pass_turn(&self) {
+        let seat = self.next_seat();
+        self.next_seat.store((seat + 1) % self.seats.len(), Ordering::Relaxed);
+    }
+
+    pub fn declare_winner(&mut self, seat: usize) -> Result<()> {
+        self.winner = Some(self.seat(seat)?);
+        Ok(())
+    }
+
+    pub fn winner(&self) -> Result<UserID> {
+        Ok(self.winner.ok_or_else(|| Error::UnknownWinner)?)
+    }
+
+    pub fn seat(&self, seat: usize) -> Result<UserID> {
+        Ok(*self.seats.get(seat).ok_or_else(|| Error::InvalidSeat(seat))?)
+    }
+}
+
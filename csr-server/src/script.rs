@@ -0,0 +1,115 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::UserID;
+
+use crate::rules::{GameHost, GameRules};
+
+// what a script can use up over a whole game, so one stuck in a loop, or
+// recursing or building strings without end, fails instead of holding its
+// thread forever
+const MAX_OPERATIONS: u64 = 100_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 10_000;
+const MAX_MAP_SIZE: usize = 10_000;
+
+// rhai scripted games
+//
+// A script defines a `play` function that runs the game and returns the seat
+// of the winner. Scripts can call the following functions:
+//
+// * `player_count()` the number of seated players
+// * `prompt(seat, text)` prompts the player in `seat` and returns their answer
+// * `take_turn(seat, text)` like `prompt`, telling every other player who they
+//   are waiting on
+// * `next_seat()` the seat whose turn is next
+// * `next_turn(text)` gives the next seat their turn, returning the seat and
+//   their answer as `[seat, answer]`
+// * `random(low, high)` a random number between low and high inclusive
+//
+// The game fails if the script runs past any of the limits above.
+pub struct ScriptGame {
+    ast: AST,
+}
+
+impl ScriptGame {
+    pub fn new(script: &str) -> Result<Self> {
+        let ast = Engine::new().compile(script)?;
+        Ok(Self {
+            ast: ast,
+        })
+    }
+}
+
+impl GameRules for ScriptGame {
+    fn play(&self, host: GameHost) -> Result<UserID> {
+        let host = Arc::new(host);
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE);
+
+        let h = host.clone();
+        engine.register_fn("player_count", move || h.player_count() as i64);
+        let h = host.clone();
+        engine.register_fn("prompt", move |seat: i64, text: &str|
+                -> std::result::Result<String, Box<EvalAltResult>> {
+            h.prompt(seat as usize, text).map_err(|e| format!("{}", e).into())
+        });
+        let h = host.clone();
+        engine.register_fn("take_turn", move |seat: i64, text: &str|
+                -> std::result::Result<String, Box<EvalAltResult>> {
+            h.take_turn(seat as usize, text).map_err(|e| format!("{}", e).into())
+        });
+        let h = host.clone();
+        engine.register_fn("next_seat", move || h.next_seat() as i64);
+        let h = host.clone();
+        engine.register_fn("next_turn", move |text: &str|
+                -> std::result::Result<Array, Box<EvalAltResult>> {
+            let (seat, answer) = h.next_turn(text).map_err(|e| format!("{}", e))?;
+            Ok(vec![Dynamic::from(seat as i64), Dynamic::from(answer)])
+        });
+        engine.register_fn("random", |low: i64, high: i64|
+                -> std::result::Result<i64, Box<EvalAltResult>> {
+            if low > high {
+                return Err(format!("Invalid random range {}..={}", low, high).into());
+            }
+            Ok(rand::thread_rng().gen_range(low..=high))
+        });
+
+        let seat: i64 = engine.call_fn(&mut Scope::new(), &self.ast, "play", ())?;
+        host.seat(seat as usize)
+    }
+}
+
+// load every .rhai file in a directory, named after the file stem
+pub fn load_scripts(dir: &Path) -> Result<Vec<(String, ScriptGame)>> {
+    let mut ret = Vec::new();
+    if !dir.is_dir() {
+        info!("No script directory at {}", dir.display());
+        return Ok(ret);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("rhai")) {
+            continue;
+        }
+        let name = match path.file_stem().and_then(OsStr::to_str) {
+            Some(n) => n.to_owned(),
+            None => continue,
+        };
+        let game = ScriptGame::new(&fs::read_to_string(&path)?)?;
+        info!("Loaded game script {} from {}", name, path.display());
+        ret.push((name, game));
+    }
+    Ok(ret)
+}
@@ -1,274 +1,2608 @@
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use rand::Rng;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, Notify, RwLock};
+use tokio::task::JoinSet;
+use tracing::Instrument;
 
-use csr_protocol::event::{ServerEvent, ServerEventSender};
+use csr_protocol::event::{ServerEvent, ServerEventNotifier, ServerEventSender};
 use csr_protocol::server::Clean;
 use csr_protocol::types::Result;
 use csr_protocol::types::{
-    Coin, SessionData, SessionID, SessionType, UserID,
+    AccountBan, AccountType, AuthToken, ChatMessage, ClientAction, CloseReason, Coin, CoinCount,
+    DiceCount, DiceSides, ErrorCode, Friend, HostInfo, Invitation, JoinInfo, JoinToken, ListFilter,
+    LobbyChange, OnlineUser, PeerIdentity, Preset, Profile, RateKey, Requester, Score, ScoreEntry,
+    ServerStats, SessionChanges, SessionData, SessionDetails, SessionID, SessionPlayer,
+    SessionStatus, SessionType, TeamID, UserDataExport, UserID, Visibility, HOUSE_ID, HOUSE_NAME,
 };
 
-use crate::error::Error;
+use crate::audit::AuditLog;
+use crate::bank::Bank;
+use crate::banlist::BanList;
+use crate::blocklist::BlockList;
+use crate::config::{Config, DisconnectPolicy};
+use crate::error::{guidance, Error};
+use crate::friendlist::FriendList;
+use crate::lobby::Lobby;
+use crate::names::{sanitize_name, NameFilter};
+use crate::oidc::OidcVerifier;
+use crate::observer::{Observers, SessionObserver};
+use crate::preset::Presets;
+use crate::profile::ProfileStore;
+use crate::ratelimit::RateLimiter;
+use crate::rules::{GameHost, GameRegistry, GameRules};
+use crate::sessiondb::SessionDb;
+use crate::users::UserRegistry;
 
 #[derive(Clone)]
 pub struct UserData {
     pub name: String,
+    pub team: Option<TeamID>,
+    pub account_type: AccountType,
+}
+
+// how the game in a session is played
+#[derive(Clone)]
+pub struct GameSettings {
+    pub session_type: SessionType,
+    pub series_length: u8,
+    pub team_mode: bool,
+    pub wager: u64,
+    // points a single player needs each round to beat the house
+    pub house_target: Option<u32>,
+}
+
+impl GameSettings {
+    // the house target when a single player is playing against the house
+    fn solo_target(&self, players: usize) -> Option<u32> {
+        if players == 1 { self.house_target } else { None }
+    }
 }
 
 pub struct SessionState {
-    pub player_count: u8,
+    pub max_players: u8,
     pub users: HashMap<UserID, UserData>,
-    pub session_type: SessionType,
+    pub settings: GameSettings,
+    pub ephemeral: bool,
+    pub created: Instant,
+    // the last time anyone did anything in the session
+    pub active: Instant,
+    pub started: bool,
+    // the game is over, and the host can offer its players a rematch
+    pub finished: bool,
+    pub host: Option<UserID>,
+    pub min_players: u8,
+    pub auto_start: bool,
+    // the game starts at this time, whoever asks to start it earlier
+    pub start_at: Option<SystemTime>,
+    pub chat: VecDeque<ChatMessage>,
+    pub name: String,
+    pub visibility: Visibility,
+    pub password: Option<String>,
+    pub invite_code: String,
+    // single use tokens the host handed out, and when each expires
+    pub join_tokens: HashMap<String, SystemTime>,
+    // players wait for the host to let them in
+    pub approve_joins: bool,
+    pub pending: HashMap<UserID, PendingJoin>,
+    // players the host won't let back in
+    pub banned: HashSet<UserID>,
+    // who has voted to kick each player from the running game
+    pub votes: HashMap<UserID, HashSet<UserID>>,
+    // the running game, so players who reconnect can be put back into it
+    pub game: Option<Arc<Callback>>,
 
     pub server_event_senders: HashMap<UserID, ServerEventSender>,
 }
 
+// a player waiting on the host to answer their request to join
+pub struct PendingJoin {
+    name: String,
+    answer: oneshot::Sender<bool>,
+}
+
+impl SessionState {
+    // a new lobby for the host's settings, that nobody has joined yet
+    fn new(hi: &HostInfo, invite_code: String) -> Self {
+        Self {
+            max_players: hi.max_players(),
+            users: HashMap::new(),
+            settings: GameSettings {
+                session_type: hi.session_type().clone(),
+                series_length: hi.series_length(),
+                team_mode: hi.team_mode(),
+                wager: hi.wager(),
+                house_target: hi.house_target(),
+            },
+            ephemeral: hi.ephemeral(),
+            created: Instant::now(),
+            active: Instant::now(),
+            started: false,
+            finished: false,
+            host: hi.host(),
+            min_players: hi.min_players(),
+            auto_start: hi.auto_start(),
+            start_at: hi.start_at(),
+            chat: VecDeque::new(),
+            name: hi.name().to_owned(),
+            visibility: hi.visibility(),
+            password: hi.password().map(|p| p.to_owned()),
+            invite_code: invite_code,
+            join_tokens: HashMap::new(),
+            approve_joins: hi.approve_joins(),
+            pending: HashMap::new(),
+            banned: HashSet::new(),
+            votes: HashMap::new(),
+            game: None,
+            server_event_senders: HashMap::new(),
+        }
+    }
+
+    // how the session is described to clients
+    fn session_data(&self, sid: SessionID) -> SessionData {
+        let users: Vec<_> = self.users.iter().map(|(_, ud)| {
+                                     ud.name.clone()
+                                 }).collect();
+        SessionData::new(sid, self.settings.session_type.clone(), &users)
+            .with_ephemeral(self.ephemeral)
+            .with_team_mode(self.settings.team_mode)
+            .with_wager(self.settings.wager)
+            .with_name(&self.name)
+            .with_max_players(self.max_players)
+            .with_min_players(self.min_players)
+            .with_visibility(self.visibility)
+            .with_locked(self.password.is_some())
+            .with_house_target(self.settings.house_target)
+            .with_approve_joins(self.approve_joins)
+            .with_start_at(self.start_at)
+    }
+
+    // the settings the session was hosted with, to host another like it
+    fn host_info(&self) -> HostInfo {
+        let mut hi = HostInfo::new(self.settings.session_type.clone(), self.max_players)
+            .with_ephemeral(self.ephemeral)
+            .with_series_length(self.settings.series_length)
+            .with_team_mode(self.settings.team_mode)
+            .with_wager(self.settings.wager)
+            .with_min_players(self.min_players)
+            .with_auto_start(self.auto_start)
+            .with_name(&self.name)
+            .with_visibility(self.visibility)
+            .with_password(self.password.clone())
+            .with_approve_joins(self.approve_joins);
+        if let Some(host) = self.host {
+            hi = hi.with_host(host);
+        }
+        if let Some(t) = self.settings.house_target {
+            hi = hi.with_house_target(t);
+        }
+        hi
+    }
+}
+
 pub type Session = Arc<RwLock<SessionState>>;
 
+// routes game events to the players. A player who drops out of a running game
+// and registers again has their new sender swapped in, so the game carries on.
+// A player voted out of the game has their sender taken away for good
 pub struct Callback {
-    senders: HashMap<UserID, ServerEventSender>,
+    senders: std::sync::Mutex<HashMap<UserID, Arc<ServerEventSender>>>,
+    reconnected: Notify,
+    removed: Notify,
+    aborted: AtomicBool,
+    paused: AtomicBool,
+    resumed: Notify,
+    policy: DisconnectPolicy,
 }
 
 impl Callback {
-    pub fn new() -> Self {
+    pub fn new(policy: DisconnectPolicy) -> Self {
+        Self {
+            senders: std::sync::Mutex::new(HashMap::new()),
+            reconnected: Notify::new(),
+            removed: Notify::new(),
+            aborted: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            resumed: Notify::new(),
+            policy: policy,
+        }
+    }
+
+    pub fn attach(&mut self, uid: UserID, s: ServerEventSender) {
+        self.senders.get_mut().unwrap().insert(uid, Arc::new(s));
+    }
+
+    // hands the sender back when the user isn't playing in this game
+    pub fn replace(&self, uid: UserID, s: ServerEventSender)
+            -> std::result::Result<(), ServerEventSender> {
+        let mut senders = self.senders.lock().unwrap();
+        if !senders.contains_key(&uid) {
+            return Err(s);
+        }
+        senders.insert(uid, Arc::new(s));
+        self.reconnected.notify_waiters();
+        Ok(())
+    }
+
+    pub fn route(&self, uid: UserID) -> Result<Arc<ServerEventSender>> {
+        let senders = self.senders.lock().unwrap();
+        Ok(senders.get(&uid).cloned().ok_or_else(|| Box::new(Error::ClientUnreachable(uid)))?)
+    }
+
+    // whether the user is still playing in this game
+    pub fn seated(&self, uid: UserID) -> bool {
+        self.senders.lock().unwrap().contains_key(&uid)
+    }
+
+    pub fn players(&self) -> Vec<(UserID, Arc<ServerEventSender>)> {
+        let senders = self.senders.lock().unwrap();
+        senders.iter().map(|(uid, s)| (*uid, s.clone())).collect()
+    }
+
+    // take a player out of the game, giving up on anything they were asked
+    pub fn remove(&self, uid: UserID) -> Option<Arc<ServerEventSender>> {
+        let ret = self.senders.lock().unwrap().remove(&uid);
+        self.removed.notify_waiters();
+        self.reconnected.notify_waiters();
+        ret
+    }
+
+    // stop the game, failing anything the players were asked
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.removed.notify_waiters();
+        self.reconnected.notify_waiters();
+        self.resumed.notify_waiters();
+    }
+
+    // hold the game before its next round, false if it was already held
+    pub fn pause(&self) -> bool {
+        !self.paused.swap(true, Ordering::SeqCst)
+    }
+
+    // false if the game wasn't paused
+    pub fn resume(&self) -> bool {
+        let ret = self.paused.swap(false, Ordering::SeqCst);
+        self.resumed.notify_waiters();
+        ret
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // wait until the game isn't paused, or it is stopped
+    pub async fn unpaused(&self) -> Result<()> {
+        loop {
+            let resumed = self.resumed.notified();
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(Box::new(Error::GameAborted));
+            }
+            if !self.is_paused() {
+                return Ok(());
+            }
+            resumed.await;
+        }
+    }
+
+    // ask a player something. If their stream drops before they answer, either
+    // wait a while for them to reconnect and ask again, give up on them, or
+    // stop the game, depending on the policy
+    pub async fn call<T, F, Fut>(&self, uid: UserID, f: F) -> Result<T>
+            where F: Fn(Arc<ServerEventSender>) -> Fut,
+                  Fut: Future<Output = Result<T>> {
+        loop {
+            let ses = self.route(uid)?;
+            match self.answer(uid, f(ses.clone())).await {
+                Err(e) if is_disconnect(e.as_ref()) => match self.policy {
+                    DisconnectPolicy::Wait(timeout) => {
+                        warn!("User {:?} dropped mid game, waiting for them", uid);
+                        tokio::time::timeout(timeout, self.reconnect(uid, &ses)).await
+                            .map_err(|_| Box::new(Error::ClientUnreachable(uid)))??;
+                    }
+                    DisconnectPolicy::Forfeit => {
+                        warn!("User {:?} dropped mid game, playing on without them", uid);
+                        self.remove(uid);
+                        return Err(Box::new(Error::ClientUnreachable(uid)));
+                    }
+                    DisconnectPolicy::Abort => {
+                        warn!("User {:?} dropped mid game, ending it", uid);
+                        self.abort();
+                        return Err(Box::new(Error::GameAborted));
+                    }
+                },
+                r => return r,
+            }
+        }
+    }
+
+    // like call, but a player voted out of the game before answering has
+    // no answer
+    pub async fn ask<T, F, Fut>(&self, uid: UserID, f: F) -> Result<Option<T>>
+            where F: Fn(Arc<ServerEventSender>) -> Fut,
+                  Fut: Future<Output = Result<T>> {
+        match self.call(uid, f).await {
+            Err(_) if !self.seated(uid) => Ok(None),
+            r => r.map(Some),
+        }
+    }
+
+    // wait for the player's answer, unless they are taken out of the game or
+    // it is stopped first
+    async fn answer<T>(&self, uid: UserID, asked: impl Future<Output = Result<T>>)
+            -> Result<T> {
+        tokio::pin!(asked);
+        loop {
+            let removed = self.removed.notified();
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(Box::new(Error::GameAborted));
+            }
+            self.route(uid)?;
+            tokio::select! {
+                r = &mut asked => return r,
+                _ = removed => {}
+            }
+        }
+    }
+
+    // wait until the user has a different sender to the one that dropped
+    async fn reconnect(&self, uid: UserID, dropped: &Arc<ServerEventSender>) -> Result<()> {
+        loop {
+            let notified = self.reconnected.notified();
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(Box::new(Error::GameAborted));
+            }
+            if !Arc::ptr_eq(&self.route(uid)?, dropped) {
+                return Ok(());
+            }
+            notified.await;
+        }
+    }
+
+    // end every player's event stream
+    pub async fn close(&self, reason: CloseReason) {
+        let senders: Vec<_> = self.senders.lock().unwrap().drain().collect();
+        for (uid, ses) in senders {
+            close_stream(uid, &ses, reason).await;
+        }
+    }
+
+    pub fn into_senders(self) -> HashMap<UserID, ServerEventSender> {
+        // only called before the game starts or once it is over, while
+        // nothing else holds a sender
+        self.senders.into_inner().unwrap().into_iter().filter_map(|(uid, s)| {
+            Arc::try_unwrap(s).ok().map(|s| (uid, s))
+        }).collect()
+    }
+}
+
+fn is_disconnect(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(e.downcast_ref::<csr_protocol::error::Error>(),
+             Some(csr_protocol::error::Error::ClientDisconnected))
+}
+
+// whether a player in the session already goes by the name, ignoring case and
+// surrounding spaces so lookalike names are caught too
+fn name_taken(state: &SessionState, name: &str) -> bool {
+    state.users.values().any(|ud| ud.name.trim().eq_ignore_ascii_case(name.trim()))
+}
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+// letters that can't be mistaken for digits, so a code never looks like an ID
+const INVITE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+const INVITE_LENGTH: usize = 6;
+
+// how often idle lobbies are looked for
+const GC_INTERVAL: Duration = Duration::from_secs(30);
+
+// how many times to check every player's stream before cancelling a start
+const WARMUP_ATTEMPTS: u32 = 3;
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(5);
+const WARMUP_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// how long a finished session stays open for the host to offer a rematch, and
+// how long each player has to answer the offer
+const REMATCH_WINDOW: Duration = Duration::from_secs(60);
+const REMATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// how long before a scheduled session starts its players are reminded
+const START_REMINDERS: [Duration; 3] = [
+    Duration::from_secs(15 * 60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(60),
+];
+
+// where to reach a user outside of their sessions, for as long as any of
+// their event streams are open
+struct Presence {
+    name: String,
+    notifiers: Vec<ServerEventNotifier>,
+}
+
+pub struct CleanService {
+    sessions: Arc<RwLock<HashMap<SessionID, Session>>>,
+    codes: Arc<RwLock<HashMap<String, SessionID>>>,
+    presence: RwLock<HashMap<UserID, Presence>>,
+    games: Arc<GameRegistry>,
+    bank: Arc<Mutex<Bank>>,
+    profiles: Arc<Mutex<ProfileStore>>,
+    blocks: Mutex<BlockList>,
+    friends: Mutex<FriendList>,
+    bans: Mutex<BanList>,
+    // checked on every request, so never held across an await
+    users: std::sync::Mutex<UserRegistry>,
+    rate_limiter: Option<std::sync::Mutex<RateLimiter>>,
+    audit: Mutex<AuditLog>,
+    observers: Arc<Observers>,
+    lobby: Lobby,
+    db: SessionDb,
+    presets: Presets,
+    name_filter: NameFilter,
+    oidc: Option<Arc<OidcVerifier>>,
+    config: Config,
+}
+
+impl CleanService {
+    pub fn new(config: Config, games: GameRegistry, bank: Bank, profiles: ProfileStore,
+               blocks: BlockList, friends: FriendList, bans: BanList, users: UserRegistry,
+               audit: AuditLog)
+            -> Self {
+        let rate_limiter = config.rate_limit.map(|limit| {
+            std::sync::Mutex::new(RateLimiter::new(limit, Duration::from_secs(60),
+                                                   config.rate_burst))
+        });
         Self {
-            senders: HashMap::new(),
+            config: config,
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            codes: Arc::new(RwLock::new(HashMap::new())),
+            presence: RwLock::new(HashMap::new()),
+            games: Arc::new(games),
+            bank: Arc::new(Mutex::new(bank)),
+            profiles: Arc::new(Mutex::new(profiles)),
+            blocks: Mutex::new(blocks),
+            friends: Mutex::new(friends),
+            bans: Mutex::new(bans),
+            users: std::sync::Mutex::new(users),
+            rate_limiter: rate_limiter,
+            audit: Mutex::new(audit),
+            observers: Arc::new(Observers::default()),
+            lobby: Lobby::default(),
+            db: SessionDb::default(),
+            presets: Presets::new(),
+            name_filter: NameFilter::default(),
+            oidc: None,
+        }
+    }
+
+    // periodically close lobbies that have been idle for longer than the
+    // session TTL, and drop senders for clients that have gone away
+    pub fn spawn_session_gc(&self) {
+        let ttl = match self.config.session_ttl {
+            Some(ttl) => ttl,
+            None => { return; }
+        };
+        let sessions = self.sessions.clone();
+        let codes = self.codes.clone();
+        let lobby = self.lobby.clone();
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(GC_INTERVAL);
+            loop {
+                interval.tick().await;
+                collect_sessions(&sessions, &codes, &lobby, &db, ttl).await;
+            }
+        });
+    }
+
+    // open the lobbies saved before the server last stopped again, with the
+    // same IDs and invite codes. Their players only need to listen for the
+    // session's events again
+    pub async fn restore_sessions(&self) -> Result<()> {
+        let mut restored = 0;
+        for saved in self.db.restore()? {
+            let sid = saved.session_id;
+            if let SessionType::Custom(name) = saved.host_info.session_type() {
+                if !self.games.contains_key(name) {
+                    warn!("Game {} is no longer loaded, not restoring session {:?}", name, sid);
+                    self.db.remove(sid);
+                    continue;
+                }
+            }
+            let mut state = SessionState::new(&saved.host_info, saved.invite_code.clone());
+            state.users = saved.users;
+            state.banned = saved.banned;
+            // sessions hosted from now on come after every restored one
+            NEXT_SESSION_ID.fetch_max(sid.0 + 1, Ordering::Relaxed);
+            self.codes.write().await.insert(saved.invite_code, sid);
+            let session = Arc::new(RwLock::new(state));
+            self.sessions.write().await.insert(sid, session.clone());
+            if let Some(at) = saved.host_info.start_at() {
+                self.schedule(sid, session, at);
+            }
+            debug!("Restored session {:?}", sid);
+            restored = restored + 1;
+        }
+        if restored > 0 {
+            info!("Restored {} lobbies", restored);
+        }
+        Ok(())
+    }
+
+    // resolves once the server is asked to stop, after telling every client
+    // their event stream is closing
+    pub fn shutdown_signal(&self) -> impl Future<Output = ()> {
+        let sessions = self.sessions.clone();
+        async move {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                error!("Unable to listen for shutdown: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+            info!("Shutting down");
+            for session in sessions.read().await.values() {
+                let mut state = session.write().await;
+                state.pending.clear();
+                for (uid, ses) in state.server_event_senders.drain() {
+                    close_stream(uid, &ses, CloseReason::Shutdown).await;
+                }
+                if let Some(cb) = &state.game {
+                    cb.close(CloseReason::Shutdown).await;
+                }
+            }
+        }
+    }
+
+    // remind everyone in a scheduled lobby that its game is coming up, then
+    // start it, or cancel it if too few have joined by the time it starts
+    fn schedule(&self, sid: SessionID, session: Session, start_at: SystemTime) {
+        let runner = self.runner();
+        tokio::spawn(async move {
+            for before in START_REMINDERS {
+                let left = until(start_at);
+                if left < before {
+                    continue;
+                }
+                tokio::time::sleep(left - before).await;
+                let state = session.read().await;
+                if state.started {
+                    return;
+                }
+                for (uid, ses) in &state.server_event_senders {
+                    if let Err(e) = ses.start_reminder(before.as_secs()).await {
+                        warn!("Failed to remind user {:?} of the start: {:?}", uid, e);
+                    }
+                }
+            }
+
+            tokio::time::sleep(until(start_at)).await;
+            let mut sessions = runner.sessions.write().await;
+            // the host may have cancelled it already
+            if !sessions.get(&sid).is_some_and(|s| Arc::ptr_eq(s, &session)) {
+                return;
+            }
+            let mut state = session.write().await;
+            if state.started {
+                return;
+            }
+            // a single player needs the house to play against
+            let joined = state.users.len();
+            if joined >= state.min_players as usize
+                    && (joined > 1 || (joined == 1 && state.settings.house_target.is_some())) {
+                state.started = true;
+                runner.db.save(sid, &state);
+                drop(state);
+                drop(sessions);
+                if let Err(e) = runner.clone().play(sid, session, false).await {
+                    warn!("Scheduled session {:?} didn't start: {:?}", sid, e);
+                }
+                return;
+            }
+            sessions.remove(&sid);
+            for (uid, ses) in &state.server_event_senders {
+                if let Err(e) = ses.session_cancelled(sid).await {
+                    warn!("Failed to tell user {:?} session was cancelled: {:?}", uid, e);
+                }
+            }
+            teardown(sid, &mut state, &runner.codes, &runner.lobby, &runner.db,
+                     CloseReason::Cancelled).await;
+            info!("Session {:?} cancelled, too few players by its start", sid);
+        });
+    }
+
+    // ask each player of a finished game to play again in its rematch, seating
+    // those who agree. The host asked for the rematch, so isn't asked
+    async fn seat_rematch(&self, sd: &SessionData, host: UserID,
+                          players: &[(UserID, UserData)],
+                          senders: &HashMap<UserID, Arc<ServerEventSender>>,
+                          approve_joins: bool) -> Result<SessionData> {
+        let sid = sd.session_id();
+        let s = self.get_session(sid).await?;
+
+        // offer everyone the rematch at once, so nobody waits on anyone else
+        let mut asking = JoinSet::new();
+        for (uid, _) in players {
+            if *uid == host {
+                continue;
+            }
+            let ses = match senders.get(uid) {
+                Some(ses) => ses.clone(),
+                None => {
+                    info!("User {:?} is not listening, left out of the rematch", uid);
+                    continue;
+                }
+            };
+            let (uid, sd) = (*uid, sd.clone());
+            asking.spawn(async move {
+                match tokio::time::timeout(REMATCH_TIMEOUT, ses.rematch(&sd)).await {
+                    Ok(Ok(true)) => { return Some(uid); }
+                    Ok(Ok(false)) => { info!("User {:?} turned down the rematch", uid); }
+                    Ok(Err(e)) => { warn!("Failed to offer user {:?} a rematch: {:?}", uid, e); }
+                    Err(_) => { warn!("User {:?} did not answer the rematch offer", uid); }
+                }
+                None
+            });
+        }
+        let mut accepted = HashSet::from([host]);
+        while let Some(answer) = asking.join_next().await {
+            if let Ok(Some(uid)) = answer {
+                accepted.insert(uid);
+            }
+        }
+
+        for (uid, ud) in players {
+            if !accepted.contains(uid) {
+                continue;
+            }
+            let ji = JoinInfo::new(sid, *uid, &ud.name).with_team(ud.team);
+            if let Err(e) = self.add_user(&s, &ji, true).await {
+                warn!("Unable to seat user {:?} for the rematch: {:?}", uid, e);
+            }
+        }
+
+        // anyone else joining waits for the host, if they did before
+        let mut state = s.write().await;
+        state.approve_joins = approve_joins;
+        self.db.save(sid, &state);
+        Ok(state.session_data(sid).with_invite_code(&state.invite_code))
+    }
+
+    // what a game started in one of the sessions is played with
+    fn runner(&self) -> GameRunner {
+        GameRunner {
+            sessions: self.sessions.clone(),
+            codes: self.codes.clone(),
+            lobby: self.lobby.clone(),
+            db: self.db.clone(),
+            games: self.games.clone(),
+            bank: self.bank.clone(),
+            profiles: self.profiles.clone(),
+            observers: self.observers.clone(),
+            ready_timeout: self.config.ready_timeout,
+            start_countdown: self.config.start_countdown,
+            policy: self.config.disconnect_policy,
+        }
+    }
+
+    // offer hosts the presets instead of entering every setting
+    pub fn with_presets(mut self, presets: Presets) -> Self {
+        self.presets = presets;
+        self
+    }
+
+    // refuse players whose names hold a filtered word
+    pub fn with_name_filter(mut self, name_filter: NameFilter) -> Self {
+        self.name_filter = name_filter;
+        self
+    }
+
+    // sign users in with tokens from an identity provider as well
+    pub fn with_oidc(mut self, oidc: Option<Arc<OidcVerifier>>) -> Self {
+        self.oidc = oidc;
+        self
+    }
+
+    // keep lobbies and game results in the database, so lobbies can be
+    // restored after a restart
+    pub fn with_session_db(mut self, db: SessionDb) -> Self {
+        self.db = db;
+        self
+    }
+
+    // tell the observer about everything that happens to sessions
+    pub fn with_observer(mut self, observer: Arc<dyn SessionObserver>) -> Self {
+        Arc::make_mut(&mut self.observers).add(observer);
+        self
+    }
+
+    async fn get_session(&self, sid: SessionID) -> Result<Session> {
+        match self.sessions.read().await.get(&sid) {
+            Some(s) => { return Ok(s.clone()); }
+            None => { return Err(Box::new(Error::SessionNotFound(sid))); }
+        }
+    }
+
+    // turn the user away if they are already hosting or playing in as many
+    // sessions as they are allowed, not counting the given one
+    async fn check_quota(&self, uid: UserID, except: Option<&Session>,
+                         sessions: &HashMap<SessionID, Session>) -> Result<()> {
+        // guests have a cap of their own, and are held to the lower of the two
+        let guest = self.users.lock().unwrap().account_type(uid) == AccountType::Guest;
+        let max = match (guest, self.config.max_sessions_per_user) {
+            (true, Some(max)) => max.min(self.config.guest_max_sessions),
+            (true, None) => self.config.guest_max_sessions,
+            (false, Some(max)) => max,
+            (false, None) => { return Ok(()); }
+        };
+        let mut count = 0;
+        for session in sessions.values() {
+            if except.is_some_and(|e| Arc::ptr_eq(e, session)) {
+                continue;
+            }
+            let state = session.read().await;
+            if !state.finished && (state.host == Some(uid) || state.users.contains_key(&uid)) {
+                count = count + 1;
+            }
+        }
+        if count >= max {
+            return Err(Box::new(Error::SessionQuotaReached(uid, count)));
+        }
+        Ok(())
+    }
+
+    // tell everyone else online that a registered user came online or went
+    // offline
+    async fn announce_presence(&self, uid: UserID, name: &str, online: bool) {
+        if self.account_type(uid) != AccountType::Registered {
+            return;
+        }
+        let notifiers: Vec<ServerEventNotifier> = self.presence.read().await.iter()
+            .filter(|(other, _)| **other != uid)
+            .flat_map(|(_, p)| p.notifiers.iter().filter(|n| n.is_connected()).cloned())
+            .collect();
+        for n in notifiers {
+            if let Err(e) = n.presence_changed(uid, name, online).await {
+                debug!("Failed to send presence of user {:?}: {:?}", uid, e);
+            }
+        }
+    }
+
+    // turns away users the operator has banned, by ID, by the name they
+    // registered with or by the name they are playing under
+    async fn check_ban(&self, uid: UserID, name: Option<&str>) -> Result<()> {
+        let registered = self.users.lock().unwrap().name(uid).map(|n| n.to_owned());
+        let names: Vec<&str> = registered.as_deref().into_iter().chain(name).collect();
+        if self.bans.lock().await.is_banned(uid, &names) {
+            return Err(Box::new(csr_protocol::error::Error::Banned));
+        }
+        Ok(())
+    }
+
+    async fn get_session_for_user(&self, sid: SessionID, uid: UserID)
+            -> Result<Session> {
+        let guard = self.sessions.read().await;
+        let s = guard.get(&sid).ok_or_else(|| Error::SessionNotFound(sid))?;
+        if s.read().await.users.contains_key(&uid) {
+            return Ok(s.clone());
+        }
+        Err(Box::new(Error::UserNotInSession(uid, sid)))
+    }
+
+    // close the oldest lobby that hasn't started, has at most one player, and
+    // is past its grace period. Returns false if there was nothing to close
+    async fn expire_oldest_lobby(&self, sessions: &mut HashMap<SessionID, Session>)
+            -> Result<bool> {
+        let mut oldest: Option<(SessionID, Instant)> = None;
+        for (sid, session) in sessions.iter() {
+            let s = session.read().await;
+            if s.started || s.users.len() > 1
+                || s.created.elapsed() < self.config.lobby_grace {
+                continue;
+            }
+            if oldest.map_or(true, |(_, created)| s.created < created) {
+                oldest = Some((*sid, s.created));
+            }
+        }
+        let sid = match oldest {
+            Some((sid, _)) => sid,
+            None => { return Ok(false); }
+        };
+        if let Some(session) = sessions.remove(&sid) {
+            info!("Expiring lobby {:?} to make room", sid);
+            let mut state = session.write().await;
+            for (uid, ses) in &state.server_event_senders {
+                if let Err(e) = ses.session_expired(sid).await {
+                    warn!("Failed to tell user {:?} session expired: {:?}", uid, e);
+                }
+            }
+            teardown(sid, &mut state, &self.codes, &self.lobby, &self.db,
+                     CloseReason::Expired).await;
+        }
+        Ok(true)
+    }
+
+    // reserve a code no other session is using
+    async fn new_invite_code(&self, sid: SessionID) -> String {
+        let mut codes = self.codes.write().await;
+        loop {
+            let mut rng = rand::thread_rng();
+            let code: String = (0..INVITE_LENGTH).map(|_| {
+                INVITE_LETTERS[rng.gen_range(0..INVITE_LETTERS.len())] as char
+            }).collect();
+            if !codes.contains_key(&code) {
+                codes.insert(code.clone(), sid);
+                return code;
+            }
+        }
+    }
+
+    // add a player to a session. Players invited with the session's code, or
+    // a join token, don't need its password
+    async fn add_user(&self, s: &Session, ji: &JoinInfo, invited: bool) -> Result<()> {
+        let sid = ji.session_id();
+        let uid = ji.user_id();
+
+        if uid == HOUSE_ID {
+            return Err(Box::new(Error::ReservedUserID(uid)));
+        }
+        let name = sanitize_name(ji.user_name(), self.config.max_name_length, &self.name_filter)
+            .map_err(csr_protocol::error::Error::InvalidName)?;
+        self.check_ban(uid, Some(&name)).await?;
+        if s.read().await.users.contains_key(&uid) {
+            return Err(Box::new(Error::UserAlreadyInSession(uid, sid)));
+        }
+        if s.read().await.banned.contains(&uid) {
+            return Err(Box::new(Error::UserBanned(uid, sid)));
+        }
+        // announcements name players, so no two in a session can share a name
+        if name_taken(&*s.read().await, &name) {
+            return Err(Box::new(csr_protocol::error::Error::NameTaken));
+        }
+
+        // locked sessions need the password
+        if let Some(password) = &s.read().await.password {
+            if !invited && ji.password() != password {
+                return Err(Box::new(Error::WrongPassword(sid)));
+            }
+        }
+
+        // players have to pick a team in team mode, and can't otherwise
+        let team_mode = s.read().await.settings.team_mode;
+        let team = match (team_mode, ji.team()) {
+            (true, None) => { return Err(Box::new(Error::TeamRequired(uid))); }
+            (true, team) => team,
+            (false, _) => None,
+        };
+
+        self.check_quota(uid, Some(s), &*self.sessions.read().await).await?;
+
+        // turn players away once the session is full, before they wait on the
+        // host
+        {
+            let state = s.read().await;
+            if state.users.len() >= state.max_players as usize {
+                return Err(Box::new(Error::SessionFull(sid, state.max_players)));
+            }
+        }
+
+        // players need enough chips to cover the wager
+        let wager = s.read().await.settings.wager;
+        let chips = self.bank.lock().await.balance(uid);
+        if chips < wager {
+            return Err(Box::new(Error::InsufficientChips(uid, chips)));
+        }
+
+        // the host lets everyone else in, when approving joins
+        let approve = {
+            let state = s.read().await;
+            state.approve_joins && state.host != Some(uid)
+        };
+        if approve {
+            self.await_approval(s, sid, uid, &name).await?;
+        }
+
+        // insert the user in the session
+        let ud = UserData {
+            name: name.clone(),
+            team: team,
+            account_type: self.users.lock().unwrap().account_type(uid),
+        };
+        {
+            // they may have joined or hosted another session meanwhile, so the
+            // quota is counted again under the lock that seats them
+            let sessions = self.sessions.write().await;
+            self.check_quota(uid, Some(s), &sessions).await?;
+            let mut state = s.write().await;
+            if state.users.contains_key(&uid) {
+                return Err(Box::new(Error::UserAlreadyInSession(uid, sid)));
+            }
+            // someone else may have taken the last seat, or the name, meanwhile
+            if state.users.len() >= state.max_players as usize {
+                return Err(Box::new(Error::SessionFull(sid, state.max_players)));
+            }
+            if name_taken(&state, &name) {
+                return Err(Box::new(csr_protocol::error::Error::NameTaken));
+            }
+            state.users.insert(uid, ud);
+            state.active = Instant::now();
+            self.lobby.updated(&state.session_data(sid));
+            self.db.save(sid, &state);
+        }
+        self.observers.player_joined(sid, uid, &name);
+
+        Ok(())
+    }
+
+    // queue the player for the host, and wait until the host answers
+    async fn await_approval(&self, s: &Session, sid: SessionID, uid: UserID, name: &str)
+            -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = s.write().await;
+            // asking again replaces the earlier request, which is turned away
+            state.pending.insert(uid, PendingJoin {
+                name: name.to_owned(),
+                answer: tx,
+            });
+            if let Some(ses) = state.host.and_then(|h| state.server_event_senders.get(&h)) {
+                if let Err(e) = ses.join_request(sid, uid, name).await {
+                    warn!("Failed to ask the host to let {:?} in: {:?}", uid, e);
+                }
+            }
+        }
+        info!("User {:?} waiting to join session {:?}", uid, sid);
+
+        match tokio::time::timeout(self.config.approval_timeout, rx).await {
+            Ok(Ok(true)) => Ok(()),
+            // rejected, or the session closed
+            Ok(_) => Err(Box::new(Error::JoinRejected(sid))),
+            Err(_) => {
+                s.write().await.pending.remove(&uid);
+                Err(Box::new(Error::JoinRejected(sid)))
+            }
+        }
+    }
+}
+
+// everything a session's game needs to be played, and its session closed
+// once it is over, so the game can run on a task of its own rather than on the
+// call that started it
+#[derive(Clone)]
+struct GameRunner {
+    sessions: Arc<RwLock<HashMap<SessionID, Session>>>,
+    codes: Arc<RwLock<HashMap<String, SessionID>>>,
+    lobby: Lobby,
+    db: SessionDb,
+    games: Arc<GameRegistry>,
+    bank: Arc<Mutex<Bank>>,
+    profiles: Arc<Mutex<ProfileStore>>,
+    observers: Arc<Observers>,
+    ready_timeout: Duration,
+    start_countdown: Duration,
+    policy: DisconnectPolicy,
+}
+
+impl GameRunner {
+    // check everyone is ready, then play the game and close the session once
+    // it is over. The session is already marked started, which is undone if
+    // the game never gets going
+    async fn play(self, sid: SessionID, s: Session, countdown: bool) -> Result<()> {
+        // everyone has to be ready before the game is set up
+        if let Some(uid) = self.ready_check(sid, &s).await {
+            info!("User {:?} is not ready, not starting session {:?}", uid, sid);
+            let mut state = s.write().await;
+            state.started = false;
+            self.db.save(sid, &state);
+            return Err(Box::new(Error::NotReady(uid)));
+        }
+
+        // a full lobby gives everyone a moment before the game starts
+        if countdown {
+            self.countdown(sid, &s).await;
+        }
+
+        info!("Game is starting for session {:?}", sid);
+        game_setup(sid, s.clone(), self.games.clone(), self.bank.clone(), self.profiles.clone(),
+                   self.observers.clone(), self.db.clone(), self.policy).await;
+        let (started, finished) = {
+            let state = s.read().await;
+            (state.started, state.finished)
+        };
+        if finished {
+            self.close_when_finished(sid, s);
+        } else if started {
+            // the game got going but never finished
+            self.close_failed(sid, &s).await;
+        } else {
+            // the game never got going, so the lobby is waiting again
+            self.db.save(sid, &*s.read().await);
+        }
+        Ok(())
+    }
+
+    // ask every player if they are ready, telling everyone in the lobby how
+    // each answers. Returns the first player to say they aren't ready. Anyone
+    // who doesn't answer in time is left for the warm up to check on
+    async fn ready_check(&self, sid: SessionID, session: &Session) -> Option<UserID> {
+        let (users, senders) = {
+            let mut state = session.write().await;
+            let senders: HashMap<UserID, Arc<ServerEventSender>> =
+                state.server_event_senders.drain().map(|(uid, s)| (uid, Arc::new(s))).collect();
+            (state.users.clone(), senders)
+        };
+
+        // ask everyone at once, so nobody waits on anyone else to answer
+        let (tx, mut rx) = mpsc::channel(senders.len().max(1));
+        let mut asking = JoinSet::new();
+        for (uid, ses) in &senders {
+            let (uid, ses, tx) = (*uid, ses.clone(), tx.clone());
+            asking.spawn(async move {
+                match ses.ready_check().await {
+                    Ok(ready) => { let _ = tx.send((uid, ready)).await; }
+                    Err(e) => { warn!("Ready check for user {:?} failed: {:?}", uid, e); }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut declined = None;
+        let deadline = tokio::time::sleep(self.ready_timeout);
+        tokio::pin!(deadline);
+        loop {
+            let (uid, ready) = tokio::select! {
+                answer = rx.recv() => match answer {
+                    Some(a) => a,
+                    None => { break; }
+                },
+                _ = &mut deadline => {
+                    info!("Ready check timed out for session {:?}", sid);
+                    break;
+                }
+            };
+            let name = users.get(&uid).map_or("", |ud| ud.name.as_str());
+            for (other, ses) in &senders {
+                if let Err(e) = ses.player_ready(uid, name, ready).await {
+                    warn!("Failed to tell user {:?} if {:?} is ready: {:?}", other, uid, e);
+                }
+            }
+            if !ready {
+                declined = Some(uid);
+                break;
+            }
+        }
+        asking.shutdown().await;
+
+        // hand the senders back, unless the player has reconnected since
+        let mut state = session.write().await;
+        for (uid, ses) in senders {
+            if let Ok(ses) = Arc::try_unwrap(ses) {
+                state.server_event_senders.entry(uid).or_insert(ses);
+            }
+        }
+        declined
+    }
+
+    // tell everyone how long until the game starts, once a second
+    async fn countdown(&self, sid: SessionID, session: &Session) {
+        let mut left = self.start_countdown.as_secs() as u32;
+        if left > 0 {
+            info!("Session {:?} starts in {} seconds", sid, left);
+        }
+        while left > 0 {
+            for (uid, ses) in &session.read().await.server_event_senders {
+                if let Err(e) = ses.countdown(left).await {
+                    warn!("Failed to tell user {:?} the game is starting: {:?}", uid, e);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            left -= 1;
+        }
+    }
+
+    // close a finished session once the host has had a chance to offer its
+    // players a rematch
+    fn close_when_finished(&self, sid: SessionID, session: Session) {
+        let sessions = self.sessions.clone();
+        let codes = self.codes.clone();
+        let lobby = self.lobby.clone();
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(REMATCH_WINDOW).await;
+            let mut sessions = sessions.write().await;
+            // a rematch may have closed it already
+            if !sessions.get(&sid).is_some_and(|s| Arc::ptr_eq(s, &session)) {
+                return;
+            }
+            sessions.remove(&sid);
+            let mut state = session.write().await;
+            teardown(sid, &mut state, &codes, &lobby, &db, CloseReason::Finished).await;
+        });
+    }
+
+    // close a session straight away once its game fails, after everyone has
+    // been told why
+    async fn close_failed(&self, sid: SessionID, session: &Session) {
+        let mut sessions = self.sessions.write().await;
+        if !sessions.get(&sid).is_some_and(|s| Arc::ptr_eq(s, session)) {
+            return;
+        }
+        sessions.remove(&sid);
+        let mut state = session.write().await;
+        teardown(sid, &mut state, &self.codes, &self.lobby, &self.db, CloseReason::Failed).await;
+    }
+}
+
+#[tonic::async_trait]
+impl Clean for CleanService {
+    // client initiated API
+    async fn host_session(&self, hi: HostInfo) -> Result<SessionData> {
+        let typ = hi.session_type().clone();
+        if let SessionType::Custom(name) = &typ {
+            if !self.games.contains_key(name) {
+                return Err(Box::new(Error::UnknownGame(name.clone())));
+            }
+        }
+
+        if hi.min_players() > hi.max_players() {
+            return Err(Box::new(Error::InvalidPlayerRange(hi.min_players(),
+                                                          hi.max_players())));
+        }
+
+        if hi.visibility() == Visibility::Private && hi.password().is_none() {
+            return Err(Box::new(Error::PrivateNeedsPassword));
+        }
+
+        // a single player needs the house to play against
+        if hi.max_players() == 1 && hi.house_target().is_none() {
+            return Err(Box::new(Error::SoloNeedsTarget));
+        }
+
+        if hi.start_at().is_some_and(|at| at <= SystemTime::now()) {
+            return Err(Box::new(Error::ScheduledInPast));
+        }
+
+        if let Some(host) = hi.host() {
+            if self.users.lock().unwrap().account_type(host) == AccountType::Guest {
+                return Err(Box::new(csr_protocol::error::Error::GuestCannotHost));
+            }
+            self.check_ban(host, None).await?;
+        }
+
+        // the host's quota and the server's room are checked under the lock
+        // that stores the session, so no one else takes either meanwhile
+        let mut sessions = self.sessions.write().await;
+        if let Some(host) = hi.host() {
+            self.check_quota(host, None, &sessions).await?;
+        }
+        // make room for the session if the server is full
+        if let Some(max) = self.config.max_sessions {
+            if sessions.len() >= max {
+                if !self.config.expire_oldest || !self.expire_oldest_lobby(&mut sessions).await? {
+                    return Err(Box::new(Error::SessionLimitReached(max)));
+                }
+            }
+        }
+
+        let sid = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let session_id = SessionID(sid);
+        let code = self.new_invite_code(session_id).await;
+
+        // create state for a session
+        let session = Arc::new(RwLock::new(SessionState::new(&hi, code.clone())));
+        let sd = session.read().await.session_data(session_id).with_invite_code(&code);
+
+        // store the session
+        sessions.insert(session_id, session.clone());
+        drop(sessions);
+        self.db.save(session_id, &*session.read().await);
+        self.observers.session_created(&sd);
+        self.lobby.created(&sd);
+        if let Some(at) = hi.start_at() {
+            self.schedule(session_id, session, at);
+        }
+
+        // return the session info
+        Ok(sd)
+    }
+    async fn presets(&self) -> Result<Vec<Preset>> {
+        let mut ret: Vec<Preset> = self.presets.iter()
+            .map(|(name, hi)| Preset::new(name, hi.clone())).collect();
+        ret.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(ret)
+    }
+    async fn host_from_preset(&self, name: &str, host: UserID) -> Result<SessionData> {
+        let hi = self.presets.get(name).ok_or_else(|| Error::UnknownPreset(name.to_owned()))?;
+        self.host_session(hi.clone().with_host(host)).await
+    }
+    async fn list_sessions(&self, filter: &ListFilter) -> Result<Vec<SessionData>> {
+        let mut ret = Vec::new();
+        for (sid, session) in self.sessions.read().await.iter() {
+            let s = session.read().await;
+            // only public sessions are listed
+            if s.visibility != Visibility::Public {
+                continue;
+            }
+            if filter.session_type().is_some_and(|t| *t != s.settings.session_type) {
+                continue;
+            }
+            if filter.host().is_some() && filter.host() != s.host {
+                continue;
+            }
+            if filter.joinable_only()
+                && (s.started || s.users.len() >= s.max_players as usize) {
+                continue;
+            }
+            ret.push(s.session_data(*sid));
+        }
+        Ok(ret)
+    }
+    async fn session_details(&self, sid: SessionID) -> Result<SessionDetails> {
+        let s = self.get_session(sid).await?;
+        let state = s.read().await;
+        let mut players: Vec<SessionPlayer> = state.users.iter().map(|(uid, ud)| {
+            SessionPlayer::new(*uid, &ud.name).with_team(ud.team)
+                .with_account_type(ud.account_type)
+        }).collect();
+        players.sort_by_key(|p| p.user_id());
+        let status = if state.finished {
+            SessionStatus::Finished
+        } else if state.started {
+            SessionStatus::InProgress
+        } else {
+            SessionStatus::Waiting
+        };
+        Ok(SessionDetails::new(state.session_data(sid), players, status))
+    }
+    async fn custom_types(&self) -> Result<Vec<String>> {
+        let mut ret: Vec<String> = self.games.keys().cloned().collect();
+        ret.sort();
+        Ok(ret)
+    }
+    async fn join_session(&self, ji: JoinInfo) -> Result<()> {
+        let sid = ji.session_id();
+        let s = self.get_session(sid).await?;
+        let token = match ji.join_token() {
+            Some(t) => {
+                let expires = s.write().await.join_tokens.remove(t)
+                    .filter(|expires| *expires > SystemTime::now())
+                    .ok_or_else(|| Error::InvalidJoinToken(sid))?;
+                Some((t.to_owned(), expires))
+            }
+            None => None,
+        };
+        let joined = self.add_user(&s, &ji, token.is_some()).await;
+        // the token is only used up once it lets someone in
+        if let (Err(_), Some((t, expires))) = (&joined, token) {
+            s.write().await.join_tokens.insert(t, expires);
+        }
+        joined
+    }
+    async fn join_by_code(&self, code: &str, uid: UserID, user_name: &str,
+                          team: Option<TeamID>) -> Result<SessionData> {
+        let code = code.trim().to_uppercase();
+        let sid = *self.codes.read().await.get(&code)
+            .ok_or_else(|| Error::UnknownInviteCode(code.clone()))?;
+        let s = self.get_session(sid).await?;
+        let ji = JoinInfo::new(sid, uid, user_name).with_team(team);
+        self.add_user(&s, &ji, true).await?;
+        let sd = s.read().await.session_data(sid);
+        Ok(sd)
+    }
+    async fn create_join_token(&self, sid: SessionID, host: UserID, ttl: Option<Duration>)
+            -> Result<JoinToken> {
+        let s = self.get_session(sid).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        let longest = self.config.join_token_ttl;
+        let now = SystemTime::now();
+        // forget the tokens nobody used in time
+        state.join_tokens.retain(|_, expires| *expires > now);
+        let jt = JoinToken::generate(sid, now + ttl.map_or(longest, |t| t.min(longest)));
+        state.join_tokens.insert(jt.token().to_owned(), jt.expires_at());
+        Ok(jt)
+    }
+    async fn start_session(&self, sid: SessionID, uid: UserID) -> Result<()> {
+        let s = self.get_session_for_user(sid, uid).await?;
+
+        // a scheduled session keeps its lobby open until its start, when it
+        // starts by itself, so asking early has nothing to wait for
+        let scheduled = s.read().await.start_at;
+        if scheduled.is_some_and(|at| at > SystemTime::now()) {
+            return Ok(());
+        }
+
+        let hosted;
+        {
+            let mut state = s.write().await;
+            if state.started {
+                return Ok(());
+            }
+            let joined = state.users.len();
+            hosted = state.host == Some(uid);
+            if hosted || scheduled.is_some() {
+                // the host can start with however many have joined
+                if joined < state.min_players as usize {
+                    return Err(Box::new(Error::NotEnoughPlayers(state.min_players)));
+                }
+                if joined == 1 && state.settings.house_target.is_none() {
+                    return Err(Box::new(Error::SoloNeedsTarget));
+                }
+            } else if !state.auto_start || joined < state.max_players as usize {
+                // everyone else waits for the host, or for the session to
+                // fill up
+                return Ok(());
+            }
+            state.started = true;
+            self.db.save(sid, &state);
+        }
+
+        // the call returns once the game is over, but the game is played on
+        // a task of its own so it is seen through, and its session closed,
+        // even if the call goes away first
+        let countdown = !hosted && scheduled.is_none();
+        tokio::spawn(self.runner().play(sid, s, countdown)).await?
+    }
+    async fn leave_session(&self, sid: SessionID, uid: UserID) -> Result<()> {
+        let s = self.get_session_for_user(sid, uid).await?;
+        let mut state = s.write().await;
+        if state.started && !state.finished {
+            return Err(Box::new(Error::SessionStarted(sid)));
+        }
+        let ud = state.users.remove(&uid)
+            .ok_or_else(|| Error::UserNotInSession(uid, sid))?;
+        // dropping the sender ends the user's event stream
+        if let Some(ses) = state.server_event_senders.remove(&uid) {
+            close_stream(uid, &ses, CloseReason::Left).await;
+        }
+
+        // let everyone still waiting know
+        for (other, ses) in &state.server_event_senders {
+            if let Err(e) = ses.user_left(sid, uid, &ud.name).await {
+                warn!("Failed to tell user {:?} that {:?} left: {:?}", other, uid, e);
+            }
+        }
+        self.lobby.updated(&state.session_data(sid));
+        self.db.save(sid, &state);
+        Ok(())
+    }
+    async fn kick_user(&self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        if state.started {
+            return Err(Box::new(Error::SessionStarted(sid)));
+        }
+        let age = state.created.elapsed();
+        if age < self.config.kick_grace {
+            let wait = self.config.kick_grace - age;
+            return Err(Box::new(Error::KickTooSoon(wait.as_secs().max(1))));
+        }
+        remove_player(&mut state, sid, target).await?;
+        self.lobby.updated(&state.session_data(sid));
+        self.db.save(sid, &state);
+        info!("User {:?} kicked from session {:?} by {:?}", target, sid, host);
+        Ok(())
+    }
+    async fn ban_user(&self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        state.banned.insert(target);
+        // anyone asking to join is turned away too
+        if let Some(p) = state.pending.remove(&target) {
+            let _ = p.answer.send(false);
+        }
+        if !state.started && state.users.contains_key(&target) {
+            remove_player(&mut state, sid, target).await?;
+            self.lobby.updated(&state.session_data(sid));
+        }
+        self.db.save(sid, &state);
+        info!("User {:?} banned from session {:?} by {:?}", target, sid, host);
+        Ok(())
+    }
+    async fn unban_user(&self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        state.banned.remove(&target);
+        self.db.save(sid, &state);
+        info!("User {:?} unbanned from session {:?} by {:?}", target, sid, host);
+        Ok(())
+    }
+    async fn cancel_session(&self, sid: SessionID, uid: UserID) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        let s = sessions.get(&sid).ok_or_else(|| Error::SessionNotFound(sid))?.clone();
+        let mut state = s.write().await;
+        if state.host != Some(uid) {
+            return Err(Box::new(Error::NotHost(uid)));
+        }
+        if state.started {
+            return Err(Box::new(Error::SessionStarted(sid)));
+        }
+        sessions.remove(&sid);
+        for (other, ses) in &state.server_event_senders {
+            if let Err(e) = ses.session_cancelled(sid).await {
+                warn!("Failed to tell user {:?} session was cancelled: {:?}", other, e);
+            }
+        }
+        teardown(sid, &mut state, &self.codes, &self.lobby, &self.db,
+                 CloseReason::Cancelled).await;
+        info!("Session {:?} cancelled by {:?}", sid, uid);
+        Ok(())
+    }
+    async fn update_session(&self, sid: SessionID, host: UserID, changes: SessionChanges)
+            -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        if state.started {
+            return Err(Box::new(Error::SessionStarted(sid)));
+        }
+
+        // check the players who already joined fit the new settings, before
+        // changing anything
+        if let Some(SessionType::Custom(name)) = changes.session_type() {
+            if !self.games.contains_key(name) {
+                return Err(Box::new(Error::UnknownGame(name.clone())));
+            }
+        }
+        // a private session can't be left without a password
+        let visibility = changes.visibility().unwrap_or(state.visibility);
+        let locked = match changes.password() {
+            Some(password) => !password.is_empty(),
+            None => state.password.is_some(),
+        };
+        if visibility == Visibility::Private && !locked {
+            return Err(Box::new(Error::PrivateNeedsPassword));
+        }
+        if let Some(max_players) = changes.max_players() {
+            if max_players == 0 || state.users.len() > max_players as usize {
+                return Err(Box::new(Error::TooManyPlayers(state.users.len(),
+                                                          max_players)));
+            }
+        }
+        let max_players = changes.max_players().unwrap_or(state.max_players);
+        let min_players = changes.min_players().unwrap_or(state.min_players);
+        if min_players > max_players {
+            return Err(Box::new(Error::InvalidPlayerRange(min_players, max_players)));
+        }
+        let house_target = changes.house_target().or(state.settings.house_target);
+        if max_players == 1 && house_target.is_none() {
+            return Err(Box::new(Error::SoloNeedsTarget));
+        }
+        if changes.team_mode() == Some(true) {
+            if let Some((uid, _)) = state.users.iter().find(|(_, ud)| ud.team.is_none()) {
+                return Err(Box::new(Error::TeamRequired(*uid)));
+            }
+        }
+        if let Some(wager) = changes.wager() {
+            let bank = self.bank.lock().await;
+            for uid in state.users.keys() {
+                let chips = bank.balance(*uid);
+                if chips < wager {
+                    return Err(Box::new(Error::InsufficientChips(*uid, chips)));
+                }
+            }
+        }
+
+        if let Some(typ) = changes.session_type() {
+            state.settings.session_type = typ.clone();
+        }
+        if let Some(max_players) = changes.max_players() {
+            state.max_players = max_players;
+        }
+        if let Some(series_length) = changes.series_length() {
+            state.settings.series_length = series_length;
+        }
+        if let Some(team_mode) = changes.team_mode() {
+            state.settings.team_mode = team_mode;
+            if !team_mode {
+                for ud in state.users.values_mut() {
+                    ud.team = None;
+                }
+            }
+        }
+        if let Some(wager) = changes.wager() {
+            state.settings.wager = wager;
+        }
+        if let Some(house_target) = changes.house_target() {
+            state.settings.house_target = Some(house_target);
+        }
+        if let Some(min_players) = changes.min_players() {
+            state.min_players = min_players;
+        }
+        if let Some(auto_start) = changes.auto_start() {
+            state.auto_start = auto_start;
+        }
+        if let Some(name) = changes.name() {
+            state.name = name.to_owned();
+        }
+        let was = state.visibility;
+        if let Some(visibility) = changes.visibility() {
+            state.visibility = visibility;
+        }
+        if let Some(password) = changes.password() {
+            state.password = if password.is_empty() { None } else { Some(password.to_owned()) };
+        }
+        if let Some(approve_joins) = changes.approve_joins() {
+            state.approve_joins = approve_joins;
+            // nobody needs letting in any more
+            if !approve_joins {
+                for (_, p) in state.pending.drain() {
+                    let _ = p.answer.send(true);
+                }
+            }
+        }
+
+        let sd = state.session_data(sid);
+        for (uid, ses) in &state.server_event_senders {
+            if let Err(e) = ses.session_updated(&sd).await {
+                warn!("Failed to tell user {:?} session was updated: {:?}", uid, e);
+            }
+        }
+        self.lobby.changed(was, &sd);
+        state.active = Instant::now();
+        self.db.save(sid, &state);
+        info!("Session {:?} updated by {:?}", sid, host);
+        Ok(())
+    }
+    async fn answer_join(&self, sid: SessionID, host: UserID, uid: UserID, accept: bool)
+            -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        let p = state.pending.remove(&uid).ok_or_else(|| Error::NoJoinRequest(uid))?;
+        // the player may have stopped waiting
+        if p.answer.send(accept).is_err() {
+            return Err(Box::new(Error::NoJoinRequest(uid)));
+        }
+        info!("Host {:?} answered {} to {:?} joining session {:?}", host, accept, uid, sid);
+        Ok(())
+    }
+    async fn rematch(&self, sid: SessionID, host: UserID) -> Result<SessionData> {
+        // close the finished session, keeping hold of its players' streams to
+        // offer them the rematch
+        let (hi, mut players, approve_joins, senders) = {
+            let mut sessions = self.sessions.write().await;
+            let s = sessions.get(&sid).ok_or_else(|| Error::SessionNotFound(sid))?.clone();
+            let mut state = s.write().await;
+            if state.host != Some(host) {
+                return Err(Box::new(Error::NotHost(host)));
+            }
+            if !state.finished {
+                return Err(Box::new(Error::GameNotFinished(sid)));
+            }
+            sessions.remove(&sid);
+            self.codes.write().await.remove(&state.invite_code);
+            self.lobby.removed(&state.session_data(sid));
+            let players: Vec<(UserID, UserData)> = state.users.iter()
+                .map(|(uid, ud)| (*uid, ud.clone())).collect();
+            let senders: HashMap<UserID, Arc<ServerEventSender>> =
+                state.server_event_senders.drain().map(|(uid, s)| (uid, Arc::new(s))).collect();
+            // the returning players were let in last time
+            (state.host_info().with_approve_joins(false), players, state.approve_joins, senders)
+        };
+        players.sort_by_key(|(uid, _)| *uid);
+
+        let r = match self.host_session(hi).await {
+            Ok(sd) => self.seat_rematch(&sd, host, &players, &senders, approve_joins).await,
+            Err(e) => Err(e),
+        };
+
+        // players listen to the new session from now on
+        for (uid, ses) in senders {
+            close_stream(uid, &ses, CloseReason::Finished).await;
+        }
+        info!("Host {:?} offered a rematch of session {:?}", host, sid);
+        r
+    }
+    async fn transfer_host(&self, sid: SessionID, host: UserID, target: UserID)
+            -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        let target_user = state.users.get(&target)
+            .ok_or_else(|| Error::UserNotInSession(target, sid))?;
+        if target_user.account_type == AccountType::Guest {
+            return Err(Box::new(csr_protocol::error::Error::GuestCannotHost));
+        }
+        let name = target_user.name.clone();
+        state.host = Some(target);
+        self.db.save(sid, &state);
+
+        for (uid, ses) in &state.server_event_senders {
+            if let Err(e) = ses.host_changed(sid, target, &name).await {
+                warn!("Failed to tell user {:?} the host changed: {:?}", uid, e);
+            }
+        }
+        // the new host answers anyone still waiting to join
+        if let Some(ses) = state.server_event_senders.get(&target) {
+            for (other, p) in &state.pending {
+                if let Err(e) = ses.join_request(sid, *other, &p.name).await {
+                    warn!("Failed to ask the host to let {:?} in: {:?}", other, e);
+                }
+            }
+        }
+        state.active = Instant::now();
+        info!("Host {:?} handed session {:?} to {:?}", host, sid, target);
+        Ok(())
+    }
+    async fn vote_kick(&self, sid: SessionID, uid: UserID, target: UserID) -> Result<bool> {
+        let s = self.get_session_for_user(sid, uid).await?;
+        let mut state = s.write().await;
+        let cb = state.game.clone().ok_or_else(|| Error::GameNotRunning(sid))?;
+        if !cb.seated(uid) {
+            return Err(Box::new(Error::UserNotInSession(uid, sid)));
+        }
+        if !cb.seated(target) {
+            return Err(Box::new(Error::UserNotInSession(target, sid)));
+        }
+        if uid == target {
+            return Err(Box::new(Error::SelfVote(uid)));
+        }
+
+        // a majority of everyone else still playing has to agree, counting
+        // only votes from players who are still in the game
+        let voters = state.votes.entry(target).or_default();
+        voters.insert(uid);
+        voters.retain(|v| cb.seated(*v));
+        let votes = voters.len();
+        let others = cb.players().len() - 1;
+        info!("User {:?} voted to kick {:?} from session {:?}, {} of {}",
+              uid, target, sid, votes, others);
+        if votes * 2 <= others {
+            return Ok(false);
+        }
+
+        state.votes.remove(&target);
+        let name = state.users.remove(&target).map(|ud| ud.name).unwrap_or_default();
+        if let Some(ses) = cb.remove(target) {
+            close_stream(target, &ses, CloseReason::Kicked).await;
+        }
+        for (other, ses) in cb.players() {
+            if let Err(e) = ses.user_kicked(sid, target, &name).await {
+                warn!("Failed to tell user {:?} that {:?} was kicked: {:?}", other, target, e);
+            }
+        }
+        self.lobby.updated(&state.session_data(sid));
+        if self.config.vote_kick_abort {
+            cb.abort();
+        }
+        state.active = Instant::now();
+        info!("User {:?} voted out of session {:?}", target, sid);
+        Ok(true)
+    }
+    async fn pause_session(&self, sid: SessionID, host: UserID) -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        let cb = state.game.clone().ok_or_else(|| Error::GameNotRunning(sid))?;
+        if !cb.pause() {
+            return Err(Box::new(Error::GamePaused(sid)));
+        }
+        for (uid, ses) in cb.players() {
+            if let Err(e) = ses.game_paused(sid).await {
+                warn!("Failed to tell user {:?} the game is paused: {:?}", uid, e);
+            }
+        }
+        state.active = Instant::now();
+        info!("Session {:?} paused by {:?}", sid, host);
+        Ok(())
+    }
+    async fn resume_session(&self, sid: SessionID, host: UserID) -> Result<()> {
+        let s = self.get_session_for_user(sid, host).await?;
+        let mut state = s.write().await;
+        if state.host != Some(host) {
+            return Err(Box::new(Error::NotHost(host)));
+        }
+        let cb = state.game.clone().ok_or_else(|| Error::GameNotRunning(sid))?;
+        if !cb.resume() {
+            return Err(Box::new(Error::GameNotPaused(sid)));
+        }
+        for (uid, ses) in cb.players() {
+            if let Err(e) = ses.game_resumed(sid).await {
+                warn!("Failed to tell user {:?} the game has resumed: {:?}", uid, e);
+            }
+        }
+        state.active = Instant::now();
+        info!("Session {:?} resumed by {:?}", sid, host);
+        Ok(())
+    }
+    async fn send_chat(&self, sid: SessionID, uid: UserID, text: &str) -> Result<()> {
+        let s = self.get_session_for_user(sid, uid).await?;
+        let mut state = s.write().await;
+        let name = state.users.get(&uid)
+            .ok_or_else(|| Error::UserNotInSession(uid, sid))?.name.clone();
+
+        // keep the most recent messages for anyone catching up
+        state.chat.push_back(ChatMessage::new(sid, uid, &name, text));
+        state.active = Instant::now();
+        while state.chat.len() > self.config.chat_history {
+            state.chat.pop_front();
+        }
+
+        for (other, ses) in &state.server_event_senders {
+            if let Err(e) = ses.chat(sid, uid, &name, text).await {
+                warn!("Failed to send chat to user {:?}: {:?}", other, e);
+            }
+        }
+        Ok(())
+    }
+    async fn chat_history(&self, sid: SessionID) -> Result<Vec<ChatMessage>> {
+        let s = self.get_session(sid).await?;
+        let history = s.read().await.chat.iter().cloned().collect();
+        Ok(history)
+    }
+    async fn send_dm(&self, from: UserID, to: UserID, text: &str) -> Result<()> {
+        if self.blocks.lock().await.is_blocked(to, from) {
+            return Err(Box::new(Error::UserBlocked(to)));
+        }
+        let presence = self.presence.read().await;
+        let name = presence.get(&from)
+            .ok_or_else(|| Error::UserOffline(from))?.name.clone();
+        let notifiers = presence.get(&to)
+            .ok_or_else(|| Error::UserOffline(to))?.notifiers.clone();
+        drop(presence);
+
+        // deliver to every stream the user has open, in any session
+        let mut delivered = false;
+        for n in notifiers {
+            match n.direct_message(from, &name, to, text).await {
+                Ok(_) => { delivered = true; }
+                Err(e) => { warn!("Failed to send message to user {:?}: {:?}", to, e); }
+            }
+        }
+        if !delivered {
+            return Err(Box::new(Error::UserOffline(to)));
+        }
+        Ok(())
+    }
+    async fn block_user(&self, uid: UserID, target: UserID) -> Result<()> {
+        self.blocks.lock().await.block(uid, target)
+    }
+    async fn unblock_user(&self, uid: UserID, target: UserID) -> Result<()> {
+        self.blocks.lock().await.unblock(uid, target)
+    }
+    async fn add_friend(&self, uid: UserID, friend: UserID) -> Result<()> {
+        if friend == uid || self.users.lock().unwrap().name(friend).is_none() {
+            return Err(Box::new(Error::UnknownUser(friend)));
+        }
+        self.friends.lock().await.add(uid, friend)
+    }
+    async fn remove_friend(&self, uid: UserID, friend: UserID) -> Result<()> {
+        self.friends.lock().await.remove_friend(uid, friend)
+    }
+    async fn friends(&self, uid: UserID) -> Result<Vec<Friend>> {
+        let friends = self.friends.lock().await;
+        let users = self.users.lock().unwrap();
+        Ok(friends.friends(uid).into_iter().map(|f| {
+            Friend::new(f, users.name(f).unwrap_or(""), friends.has_added(f, uid))
+        }).collect())
+    }
+    async fn send_invite(&self, sid: SessionID, from: UserID, to: UserID) -> Result<()> {
+        if !self.friends.lock().await.are_friends(from, to) {
+            return Err(Box::new(Error::NotFriends(to)));
+        }
+        if self.blocks.lock().await.is_blocked(to, from) {
+            return Err(Box::new(Error::UserBlocked(to)));
+        }
+        let presence = self.presence.read().await;
+        let name = presence.get(&from)
+            .ok_or_else(|| Error::UserOffline(from))?.name.clone();
+        let notifiers = presence.get(&to)
+            .ok_or_else(|| Error::UserOffline(to))?.notifiers.clone();
+        drop(presence);
+
+        let s = self.get_session(sid).await?;
+        let mut state = s.write().await;
+        if !state.users.contains_key(&from) {
+            return Err(Box::new(Error::UserNotInSession(from, sid)));
+        }
+        // the host's invitations let the friend straight in
+        let mut join_token = None;
+        if state.host == Some(from) {
+            let jt = JoinToken::generate(sid, SystemTime::now() + self.config.join_token_ttl);
+            state.join_tokens.insert(jt.token().to_owned(), jt.expires_at());
+            join_token = Some(jt.token().to_owned());
+        }
+        drop(state);
+
+        // deliver to every stream the friend has open, in any session
+        let mut delivered = false;
+        for n in notifiers {
+            let inv = Invitation::new(from, &name, to, sid).with_join_token(join_token.as_deref());
+            match n.invitation(inv).await {
+                Ok(_) => { delivered = true; }
+                Err(e) => { warn!("Failed to send invitation to user {:?}: {:?}", to, e); }
+            }
+        }
+        if !delivered {
+            return Err(Box::new(Error::UserOffline(to)));
+        }
+        Ok(())
+    }
+    async fn register_user(&self, name: &str, account_type: AccountType,
+                           peer: Option<&PeerIdentity>) -> Result<(UserID, AuthToken)> {
+        let name = name.trim();
+        // guests are given a name when they don't pick one
+        if name.is_empty() && account_type == AccountType::Registered {
+            return Err(Box::new(Error::EmptyUserName));
+        }
+        let name = match name {
+            "" => String::new(),
+            name => sanitize_name(name, self.config.max_name_length, &self.name_filter)
+                .map_err(csr_protocol::error::Error::InvalidName)?,
+        };
+        let (uid, token) = self.users.lock().unwrap().register(&name, account_type, peer)?;
+        info!("Registered {:?} {} as user {:?}", account_type, name, uid);
+        Ok((uid, token))
+    }
+    fn account_type(&self, uid: UserID) -> AccountType {
+        self.users.lock().unwrap().account_type(uid)
+    }
+    fn authenticate(&self, token: &AuthToken, peer: Option<&PeerIdentity>) -> Option<UserID> {
+        self.users.lock().unwrap().authenticate(token, peer)
+    }
+    fn authenticate_bearer(&self, token: &str) -> Option<UserID> {
+        let identity = self.oidc.as_ref()?.verify(token)?;
+        // the provider's name is held to the same rules as any other
+        let name = match sanitize_name(&identity.name, self.config.max_name_length,
+                                       &self.name_filter) {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("Failed to sign in {}, their name is refused: {}", identity.subject, e);
+                return None;
+            }
+        };
+        match self.users.lock().unwrap().sign_in(&identity.subject, &name) {
+            Ok(uid) => Some(uid),
+            Err(e) => {
+                warn!("Failed to sign in {}: {:?}", identity.subject, e);
+                None
+            }
         }
     }
-
-    pub fn attach(&mut self, uid: UserID, s: ServerEventSender) {
-        self.senders.insert(uid, s);
+    fn allow_request(&self, key: RateKey) -> bool {
+        let allowed = self.rate_limiter.as_ref()
+            .map_or(true, |limiter| limiter.lock().unwrap().allow(key));
+        if !allowed {
+            debug!("Too many requests from {:?}", key);
+        }
+        allowed
     }
-
-    pub fn route(&self, uid: UserID) -> Result<&ServerEventSender> {
-        Ok(self.senders.get(&uid).ok_or_else(|| Box::new(Error::ClientUnreachable(uid)))?)
+    fn guidance(&self, e: &(dyn std::error::Error + 'static)) -> (ErrorCode, ClientAction) {
+        guidance(e)
     }
-}
-
-static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
-
-pub struct CleanService {
-    sessions: Arc<RwLock<HashMap<SessionID, Session>>>,
-}
-
-impl CleanService {
-    pub fn new() -> Self {
-        Self {
-            sessions: Arc::new(RwLock::new(HashMap::new())),
+    fn heartbeat(&self) -> (Duration, Duration) {
+        (self.config.heartbeat, self.config.heartbeat_timeout)
+    }
+    fn replay_buffer(&self) -> usize {
+        self.config.replay_buffer
+    }
+    fn watch_sessions(&self) -> broadcast::Receiver<LobbyChange> {
+        self.lobby.subscribe()
+    }
+    async fn balance(&self, uid: UserID) -> Result<u64> {
+        Ok(self.bank.lock().await.balance(uid))
+    }
+    async fn profile(&self, uid: UserID) -> Result<Profile> {
+        let profiles = self.profiles.lock().await;
+        let users = self.users.lock().unwrap();
+        let name = users.name(uid).ok_or_else(|| Error::UnknownUser(uid))?;
+        Ok(profiles.profile(uid, name))
+    }
+    async fn export_user_data(&self, uid: UserID) -> Result<UserDataExport> {
+        let chips = self.bank.lock().await.stored_balance(uid);
+        let profiles = self.profiles.lock().await;
+        let users = self.users.lock().unwrap();
+        let name = users.name(uid);
+        let ud = UserDataExport::new(uid, chips).with_name(name)
+            .with_profile(profiles.stored_profile(uid, name.unwrap_or("")));
+        self.db.export_user(uid, ud)
+    }
+    async fn delete_user_data(&self, uid: UserID, by: Requester) -> Result<()> {
+        // hold the audit log so the deletion and its record can't interleave
+        // with another deletion
+        let audit = self.audit.lock().await;
+        self.bank.lock().await.remove(uid)?;
+        self.profiles.lock().await.remove(uid)?;
+        self.blocks.lock().await.remove(uid)?;
+        self.friends.lock().await.remove(uid)?;
+        self.users.lock().unwrap().remove(uid)?;
+        self.db.forget_user(uid)?;
+        audit.record("delete", uid, by)?;
+        info!("Deleted data for user {:?}, requested by {:?}", uid, by);
+        Ok(())
+    }
+    async fn online_users(&self) -> Result<Vec<OnlineUser>> {
+        let presence = self.presence.read().await;
+        let mut users: Vec<OnlineUser> = presence.iter()
+            .filter(|(uid, p)| p.notifiers.iter().any(|n| n.is_connected())
+                    && self.account_type(**uid) == AccountType::Registered)
+            .map(|(uid, p)| OnlineUser::new(*uid, &p.name))
+            .collect();
+        users.sort_by_key(|u| u.user_id().0);
+        Ok(users)
+    }
+    async fn server_stats(&self) -> Result<ServerStats> {
+        let mut sessions = 0;
+        let mut games_running = 0;
+        for session in self.sessions.read().await.values() {
+            let state = session.read().await;
+            sessions = sessions + 1;
+            if state.started && !state.finished {
+                games_running = games_running + 1;
+            }
         }
+        let users_online = self.presence.read().await.values()
+            .filter(|p| p.notifiers.iter().any(|n| n.is_connected()))
+            .count() as u32;
+        Ok(ServerStats::new(sessions, games_running, users_online))
     }
-
-    async fn get_session(&self, sid: SessionID) -> Result<Session> {
-        match self.sessions.read().await.get(&sid) {
-            Some(s) => { return Ok(s.clone()); }
-            None => { return Err(Box::new(Error::SessionNotFound(sid))); }
+    async fn ban_account(&self, ban: AccountBan) -> Result<()> {
+        if self.bans.lock().await.ban(ban.clone())? {
+            info!("Banned {:?} from the server", ban);
         }
+        Ok(())
     }
-
-    async fn get_session_for_user(&self, sid: SessionID, uid: UserID)
-            -> Result<Session> {
-        let guard = self.sessions.read().await;
-        let s = guard.get(&sid).ok_or_else(|| Error::SessionNotFound(sid))?;
-        if s.read().await.users.contains_key(&uid) {
-            return Ok(s.clone());
+    async fn unban_account(&self, ban: AccountBan) -> Result<()> {
+        if self.bans.lock().await.unban(&ban)? {
+            info!("Lifted the server ban on {:?}", ban);
         }
-        Err(Box::new(Error::UserNotInSession(uid, sid)))
+        Ok(())
     }
-}
-
-#[tonic::async_trait]
-impl Clean for CleanService {
-    // client initiated API
-    async fn host_session(&self, typ: SessionType, player_count: u8)
-            -> Result<SessionData> {
-        let sid = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
-        let session_id = SessionID(sid);
-
-        // create state for a session
-        let session = Arc::new(RwLock::new(SessionState {
-            player_count: player_count,
-            users: HashMap::new(),
-            session_type: typ,
-            server_event_senders: HashMap::new(),
-        }));
-
-        // store the session
-        self.sessions.write().await.insert(session_id, session);
-
-        // return the session info
-        Ok(SessionData::new(session_id, typ, &[]))
+    async fn account_bans(&self) -> Result<Vec<AccountBan>> {
+        Ok(self.bans.lock().await.bans())
     }
-    async fn list_sessions(&self) -> Result<Vec<SessionData>> {
-        let mut ret = Vec::new();
-        for (sid, session) in self.sessions.read().await.iter() {
-            let s = session.read().await;
-            let users: Vec<_> = s.users.iter().map(|(_, ud)| {
-                                          ud.name.clone()
-                                      }).collect();
-            ret.push(SessionData::new(*sid, s.session_type, &users));
+    // server callbacks
+    async fn register_server_event_sender(&self, sid: SessionID, uid: UserID,
+                          s: ServerEventSender) -> Result<()> {
+        let z = self.get_session_for_user(sid, uid).await?;
+        let mut state = z.write().await;
+        let name = state.users.get(&uid)
+            .ok_or_else(|| Error::UserNotInSession(uid, sid))?.name.clone();
+        self.check_ban(uid, Some(&name)).await?;
+
+        // make the user reachable for direct messages, dropping any streams
+        // that have since closed
+        let mut presence = self.presence.write().await;
+        let p = presence.entry(uid).or_insert_with(|| Presence {
+            name: name.clone(),
+            notifiers: Vec::new(),
+        });
+        p.name = name.clone();
+        p.notifiers.retain(|n| n.is_connected());
+        let came_online = p.notifiers.is_empty();
+        p.notifiers.push(s.notifier());
+        drop(presence);
+        if came_online {
+            self.announce_presence(uid, &name, true).await;
         }
-        Ok(ret)
-    }
-    async fn join_session(&self, sid: SessionID, uid: UserID, user_name: &str)
-        -> Result<()> {
-        let s = self.get_session(sid).await?;
 
-        if s.read().await.users.contains_key(&uid) {
-            return Err(Box::new(Error::UserAlreadyInSession(uid, sid)));
+        // the host may have missed requests to join while not listening
+        if state.host == Some(uid) {
+            for (other, p) in &state.pending {
+                if let Err(e) = s.join_request(sid, *other, &p.name).await {
+                    warn!("Failed to ask the host to let {:?} in: {:?}", other, e);
+                }
+            }
         }
 
-        // insert the user in the session
-        let ud = UserData {
-            name: user_name.to_owned(),
+        state.active = Instant::now();
+        // a player coming back to a paused game needs to know it is held
+        if let Some(cb) = &state.game {
+            if cb.seated(uid) && cb.is_paused() {
+                if let Err(e) = s.game_paused(sid).await {
+                    warn!("Failed to tell user {:?} the game is paused: {:?}", uid, e);
+                }
+            }
+        }
+        let s = match &state.game {
+            Some(cb) => match cb.replace(uid, s) {
+                Ok(_) => {
+                    info!("User {:?} rejoined the game in session {:?}", uid, sid);
+                    return Ok(());
+                }
+                Err(s) => s,
+            },
+            None => s,
         };
-        s.write().await.users.insert(uid, ud);
-
+        state.server_event_senders.insert(uid, s);
+        Ok(())
+    }
+    async fn stream_closed(&self, _sid: SessionID, uid: UserID) -> Result<()> {
+        let mut presence = self.presence.write().await;
+        let name = match presence.get_mut(&uid) {
+            Some(p) => {
+                p.notifiers.retain(|n| n.is_connected());
+                // still listening to another session
+                if !p.notifiers.is_empty() {
+                    return Ok(());
+                }
+                p.name.clone()
+            }
+            None => { return Ok(()); }
+        };
+        presence.remove(&uid);
+        drop(presence);
+        self.announce_presence(uid, &name, false).await;
         Ok(())
     }
-    async fn start_session(&self, sid: SessionID) -> Result<()> {
+    async fn client_disconnected(&self, sid: SessionID, uid: UserID) -> Result<()> {
         let s = self.get_session(sid).await?;
-        // check if we have enough players to start the game
-        if s.read().await.users.len() as u8 == s.read().await.player_count {
-            info!("Game is starting for session {:?}", sid);
-            let session = self.get_session(sid).await?;
-            game_setup(session).await;
+        let mut state = s.write().await;
+        // lobbies drop disconnected players' senders as they go
+        let cb = match &state.game {
+            Some(cb) => cb.clone(),
+            None => { return Ok(()); }
+        };
+        // the player may have registered again since
+        if cb.route(uid).is_ok_and(|ses| ses.is_connected()) {
+            return Ok(());
+        }
+        match self.config.disconnect_policy {
+            // the game waits once it next needs them
+            DisconnectPolicy::Wait(_) => {}
+            DisconnectPolicy::Forfeit => {
+                cb.remove(uid);
+                if let Some(ud) = state.users.remove(&uid) {
+                    for (other, ses) in cb.players() {
+                        if let Err(e) = ses.user_left(sid, uid, &ud.name).await {
+                            warn!("Failed to tell user {:?} that {:?} left: {:?}", other, uid, e);
+                        }
+                    }
+                    self.lobby.updated(&state.session_data(sid));
+                }
+                info!("User {:?} forfeited the game in session {:?}", uid, sid);
+            }
+            DisconnectPolicy::Abort => {
+                cb.abort();
+                info!("User {:?} dropped, ending the game in session {:?}", uid, sid);
+            }
         }
-
         Ok(())
     }
-    // server callbacks
-    async fn register_server_event_sender(&self, sid: SessionID, uid: UserID,
-                          s: ServerEventSender) -> Result<()> {
-        let z = self.get_session_for_user(sid, uid).await?;
-        z.write().await.server_event_senders.insert(uid, s);
-        Ok(())
+}
+
+// how long until the given time, or nothing once it has passed
+fn until(at: SystemTime) -> Duration {
+    at.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+async fn collect_sessions(sessions: &RwLock<HashMap<SessionID, Session>>,
+                          codes: &RwLock<HashMap<String, SessionID>>, lobby: &Lobby,
+                          db: &SessionDb, ttl: Duration) {
+    let mut sessions = sessions.write().await;
+    let mut idle = Vec::new();
+    for (sid, session) in sessions.iter() {
+        let mut s = session.write().await;
+        s.server_event_senders.retain(|_, ses| ses.is_connected());
+        // scheduled lobbies are left open until their start
+        let scheduled = s.start_at.is_some_and(|at| at > SystemTime::now());
+        if !s.started && !scheduled && s.active.elapsed() > ttl {
+            idle.push(*sid);
+        }
+    }
+    for sid in idle {
+        if let Some(session) = sessions.remove(&sid) {
+            info!("Closing idle lobby {:?}", sid);
+            let mut state = session.write().await;
+            for (uid, ses) in &state.server_event_senders {
+                if let Err(e) = ses.session_expired(sid).await {
+                    warn!("Failed to tell user {:?} session expired: {:?}", uid, e);
+                }
+            }
+            teardown(sid, &mut state, codes, lobby, db, CloseReason::Expired).await;
+        }
     }
 }
 
-async fn game_setup(session: Session) {
-    match game_setup_impl(session.clone()).await {
+async fn game_setup(sid: SessionID, session: Session, games: Arc<GameRegistry>,
+                    bank: Arc<Mutex<Bank>>, profiles: Arc<Mutex<ProfileStore>>,
+                    observers: Arc<Observers>, db: SessionDb, policy: DisconnectPolicy) {
+    match game_setup_impl(sid, session.clone(), games, bank, profiles, observers, db,
+                          policy).await {
         Ok(_) => { info!("Game complete"); }
         Err(e) => {
             error!("Unable to start game {:?}", e);
-            report_error(session, e).await;
+            // the game never got going, so it can be started again
+            session.write().await.started = false;
+            report_error(session, e.as_ref()).await;
         }
     }
 }
 
-async fn game_setup_impl(session: Session) -> Result<()> {
+async fn game_setup_impl(sid: SessionID, session: Session, games: Arc<GameRegistry>,
+                         bank: Arc<Mutex<Bank>>, profiles: Arc<Mutex<ProfileStore>>,
+                         observers: Arc<Observers>, db: SessionDb, policy: DisconnectPolicy)
+        -> Result<()> {
     // read the values out of the session
     let users = session.read().await.users.clone();
-    let session_type = session.read().await.session_type;
-    // load up the senders
-    let mut cb = Callback::new();
-    for (uid, _) in &users {
-        if let Some(ses) = session.write().await.server_event_senders.remove(uid) {
-            cb.attach(*uid, ses);
-        }
-    }
+    let settings = session.read().await.settings.clone();
+    let sd = session.read().await.session_data(sid);
+    // load up the senders, once they have all answered
+    let cb = Arc::new(warm_up(&session, &users, policy).await?);
+    session.write().await.game = Some(cb.clone());
 
-    // run the game
+    // run the game, traced as part of the call that started it
+    let span = tracing::info_span!("game", session = sid.0);
     let handle = tokio::spawn(async move {
-        match game_thread(users, session_type, cb).await {
-            Ok(r) => Ok(r),
-            Err(e) => Err(e),
+        let r = game_thread(sd, users.clone(), settings, cb.clone(), games, bank, profiles,
+                            observers, db).await;
+        // the players' senders are with the game now, so report through them
+        if let Err(e) = &r {
+            for (uid, _) in &users {
+                if let Ok(ses) = cb.route(*uid) {
+                    send_error(*uid, &ses, e.as_ref()).await;
+                }
+            }
+        }
+        r
+    }.instrument(span));
+    let r = handle.await;
+    {
+        let mut state = session.write().await;
+        let game = state.game.take();
+        state.votes.clear();
+        // players keep listening once the game is over, in case the host
+        // offers a rematch, or to be told why the session is closing
+        if let Some(cb) = game.and_then(|cb| Arc::try_unwrap(cb).ok()) {
+            for (uid, ses) in cb.into_senders() {
+                state.server_event_senders.insert(uid, ses);
+            }
+        }
+        if let Ok(Ok(_)) = &r {
+            state.finished = true;
+            state.active = Instant::now();
         }
-    });
-    match handle.await {
-        Ok(_) => { info!("Game complete"); }
-        Err(e) => { report_error(session, e).await; }
+    }
+    match r {
+        Ok(Ok(_)) => { info!("Game complete"); }
+        Ok(Err(e)) => { error!("Game ended with error {:?}", e); }
+        Err(e) => { report_error(session, &e).await; }
     }
     Ok(())
 }
 
-async fn game_thread(users: HashMap<UserID, UserData>,
-                     session_type: SessionType, cb: Callback) -> Result<()> {
+// ping every player before the game starts, giving any that are missing or
+// don't answer a few chances to reconnect. If some never answer the start is
+// cancelled, and the senders go back to the session so it can be started again
+async fn warm_up(session: &Session, users: &HashMap<UserID, UserData>,
+                 policy: DisconnectPolicy) -> Result<Callback> {
+    let mut cb = Callback::new(policy);
+    let mut waiting: Vec<UserID> = users.keys().copied().collect();
+    for attempt in 1..=WARMUP_ATTEMPTS {
+        let mut dead = Vec::new();
+        for uid in waiting {
+            let ses = match session.write().await.server_event_senders.remove(&uid) {
+                Some(s) => s,
+                None => {
+                    warn!("User {:?} is not listening for events yet", uid);
+                    dead.push(uid);
+                    continue;
+                }
+            };
+            match tokio::time::timeout(WARMUP_TIMEOUT, ses.ping("Warm up")).await {
+                Ok(Ok(_)) => { cb.attach(uid, ses); }
+                Ok(Err(e)) => {
+                    warn!("Warm up ping to user {:?} failed: {:?}", uid, e);
+                    dead.push(uid);
+                }
+                Err(_) => {
+                    warn!("Warm up ping to user {:?} timed out", uid);
+                    // keep the sender unless the user has reconnected since
+                    session.write().await.server_event_senders.entry(uid).or_insert(ses);
+                    dead.push(uid);
+                }
+            }
+        }
+        if dead.is_empty() {
+            return Ok(cb);
+        }
+        waiting = dead;
+        if attempt < WARMUP_ATTEMPTS {
+            info!("Delaying start, waiting on {:?}", waiting);
+            tokio::time::sleep(WARMUP_RETRY_DELAY).await;
+        }
+    }
+
+    let mut s = session.write().await;
+    for (uid, ses) in cb.into_senders() {
+        s.server_event_senders.insert(uid, ses);
+    }
+    Err(Box::new(Error::ClientUnreachable(waiting[0])))
+}
+
+// who won a round or a series, a single player or a whole team
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+enum Contender {
+    User(UserID),
+    Team(TeamID),
+    House,
+}
+
+// running scores across every round played, over all the games in a session
+#[derive(Default)]
+struct Standings {
+    rounds: u32,
+    totals: HashMap<UserID, Score>,
+    // just the game being played
+    game: HashMap<UserID, Score>,
+}
+
+impl Standings {
+    fn record(&mut self, scores: &HashMap<UserID, Score>) {
+        self.rounds = self.rounds + 1;
+        for (uid, score) in scores {
+            self.totals.entry(*uid).or_default().add(score.value());
+            self.game.entry(*uid).or_default().add(score.value());
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.game.clear();
+    }
+
+    fn entries(&self, users: &HashMap<UserID, UserData>) -> Vec<ScoreEntry> {
+        ranked(&self.totals, users)
+    }
+
+    fn game_entries(&self, users: &HashMap<UserID, UserData>) -> Vec<ScoreEntry> {
+        ranked(&self.game, users)
+    }
+}
+
+// highest score first, with ties in user order
+fn ranked(totals: &HashMap<UserID, Score>, users: &HashMap<UserID, UserData>)
+        -> Vec<ScoreEntry> {
+    let mut entries: Vec<ScoreEntry> = users.iter().map(|(uid, ud)| {
+        let score = totals.get(uid).copied().unwrap_or_default();
+        ScoreEntry::new(*uid, &ud.name, score)
+    }).collect();
+    entries.sort_by_key(|e| (std::cmp::Reverse(e.score()), e.user_id()));
+    entries
+}
+
+async fn game_thread(sd: SessionData, mut users: HashMap<UserID, UserData>,
+                     settings: GameSettings, cb: Arc<Callback>, games: Arc<GameRegistry>,
+                     bank: Arc<Mutex<Bank>>, profiles: Arc<Mutex<ProfileStore>>,
+                     observers: Arc<Observers>, db: SessionDb) -> Result<()> {
+    let sid = sd.session_id();
+    let mut standings = Standings::default();
     loop {
+        // players voted out of the last game don't play this one
+        users.retain(|uid, _| cb.seated(*uid));
+        let mut uids: Vec<UserID> = users.keys().copied().collect();
+        uids.sort();
+        standings.new_game();
+
+        // tell everyone what is being played
+        for (uid, _) in &users {
+            cb.route(*uid)?.game_started(&sd).await?;
+        }
+
         // ping the players and get their response
         for (uid,_) in &users {
-            let msg = cb.route(*uid)?.ping("Game start").await?;
-            info!("Received ping response: {} from {:?}", msg, uid);
+            let msg = cb.ask(*uid, |s| async move { s.ping("Game start").await }).await?;
+            info!("Received ping response: {:?} from {:?}", msg, uid);
         }
 
-        // depending on the session type, take different actions
-        let winner = match session_type {
-            SessionType::Dice => dice_game(&users, &cb).await?,
-            SessionType::Coin => coin_game(&users, &cb).await?,
-        };
+        // everyone puts the wager in the pot before playing
+        bank.lock().await.debit_all(&uids, settings.wager)?;
+        let mut pot = settings.wager * uids.len() as u64;
+        if settings.solo_target(uids.len()).is_some() {
+            // the house matches a single player's wager
+            pot = pot + settings.wager;
+        }
 
-        // get the name of the winner
-        let username;
-        if let Some(ud) = users.get(&winner) {
-            username = ud.name.clone();
-        } else {
-            return Err(Box::new(Error::UnknownWinner));
+        let winner = match series(sid, &mut users, &settings, &cb, &games, &mut standings,
+                                  &observers).await {
+            Ok(w) => w,
+            Err(e) => {
+                // nobody won, so hand the wagers back
+                let mut b = bank.lock().await;
+                for uid in &uids {
+                    b.credit(*uid, settings.wager)?;
+                }
+                return Err(e);
+            }
+        };
+        let payout = pay_out(&uids, &users, &bank, winner, pot).await?;
+        let winners = winner_ids(&uids, &users, winner);
+        let scores = standings.game_entries(&users);
+        // ephemeral sessions are left out of everyone's stats, and observers'
+        if !sd.ephemeral() {
+            observers.game_finished(sid, &winners, payout);
+            profiles.lock().await.record_game(sd.session_type(), &uids, &winners)?;
+            db.record_result(&sd, &scores, &winners, payout);
         }
 
-        // let everyone know who the winner is
-        for (uid,_) in &users {
-            cb.route(*uid)?.winner(winner, &username).await?;
+        // let everyone know who the winner is, and how everyone finished
+        announce_winner(&users, &cb, winner, payout).await?;
+        for (uid, _) in &users {
+            cb.route(*uid)?.game_ended(sid, &scores).await?;
         }
 
         // ask if people want to play again, only continue if everyone
         // votes yes
         let mut play_again = true;
         for (uid, _) in &users {
-            play_again = play_again & cb.route(*uid)?.try_again().await?;
+            if let Some(a) = cb.ask(*uid, |s| async move { s.try_again().await }).await? {
+                play_again = play_again & a;
+            }
         }
         if !play_again {
             break;
         }
     }
+    users.retain(|uid, _| cb.seated(*uid));
+
+    // the best score over every game played is the overall champion
+    if let Some(c) = standings.entries(&users).first() {
+        for (uid, _) in &users {
+            cb.route(*uid)?.champion(c.user_id(), c.user_name(), c.score()).await?;
+        }
+    }
+    for (uid, _) in &users {
+        cb.route(*uid)?.game_over(sid).await?;
+    }
+
+    Ok(())
+}
+
+// play rounds until a contender has won the majority of the series, or all
+// the rounds have been played
+async fn series(sid: SessionID, users: &mut HashMap<UserID, UserData>,
+                settings: &GameSettings, cb: &Arc<Callback>, games: &GameRegistry,
+                standings: &mut Standings, observers: &Observers) -> Result<Contender> {
+    let series_length = settings.series_length;
+    // each contender's wins, and the round they last won, to settle a tie
+    let mut wins: HashMap<Contender, (u8, u8)> = HashMap::new();
+    for round in 1..=series_length {
+        // the host can hold the game between rounds
+        cb.unpaused().await?;
+        let span = tracing::info_span!("round", session = sid.0, round = round);
+        let mut scores = play_round(users, &settings.session_type, cb, games)
+            .instrument(span)
+            .await?;
+        // the round carries on without anyone voted out during it
+        users.retain(|uid, _| cb.seated(*uid));
+        scores.retain(|uid, _| users.contains_key(uid));
+        let users = &*users;
+        let winner = round_winner(users, &scores, settings)?;
+
+        // keep everyone up to date with the running totals
+        standings.record(&scores);
+        let entries = standings.entries(users);
+        for (uid, _) in users {
+            cb.route(*uid)?.scoreboard(standings.rounds, &entries).await?;
+        }
+        observers.round_completed(sid, standings.rounds, &entries);
+
+        let count = wins.entry(winner).or_insert((0, 0));
+        *count = (count.0 + 1, round);
+        let count = count.0;
+
+        // a single game has no rounds to report
+        if series_length > 1 {
+            announce_round(users, cb, round, winner, count).await?;
+        }
+
+        if count > series_length / 2 {
+            return Ok(winner);
+        }
+    }
+    // nobody won a majority, so the most wins takes the series, and of those
+    // tied on wins, whoever got there first
+    match wins.iter().max_by_key(|(_, (w, last))| (*w, Reverse(*last))) {
+        Some((w, _)) => { return Ok(*w); }
+        None => { return Err(Box::new(Error::UnknownWinner)); }
+    }
+}
+
+// the highest score wins, summed across the team in team mode, with a tie
+// going to the first seat, or the lowest team. A single player has to reach
+// the house target instead
+fn round_winner(users: &HashMap<UserID, UserData>, scores: &HashMap<UserID, Score>,
+                settings: &GameSettings) -> Result<Contender> {
+    if let Some(target) = settings.solo_target(users.len()) {
+        let (uid, score) = scores.iter().next().ok_or_else(|| Error::UnknownWinner)?;
+        if score.value() >= target {
+            return Ok(Contender::User(*uid));
+        }
+        return Ok(Contender::House);
+    }
+    let winner = if settings.team_mode {
+        let mut totals: HashMap<TeamID, Score> = HashMap::new();
+        for (uid, score) in scores {
+            let team = users.get(uid).and_then(|ud| ud.team)
+                .ok_or_else(|| Error::TeamRequired(*uid))?;
+            totals.entry(team).or_default().add(score.value());
+        }
+        totals.iter().max_by_key(|(t, s)| (**s, Reverse(**t)))
+            .map(|(t, _)| Contender::Team(*t))
+    } else {
+        scores.iter().max_by_key(|(u, s)| (**s, Reverse(**u)))
+            .map(|(u, _)| Contender::User(*u))
+    };
+    Ok(winner.ok_or_else(|| Error::UnknownWinner)?)
+}
+
+// give the pot to the winner, or split it evenly across the winning team with
+// any remainder going to the first member. Returns what each winner was paid
+async fn pay_out(uids: &[UserID], users: &HashMap<UserID, UserData>,
+                 bank: &Mutex<Bank>, winner: Contender, pot: u64) -> Result<u64> {
+    let mut b = bank.lock().await;
+    match winner {
+        Contender::User(w) => {
+            b.credit(w, pot)?;
+            return Ok(pot);
+        }
+        Contender::Team(_) => {
+            let members = winner_ids(uids, users, winner);
+            if members.is_empty() {
+                return Err(Box::new(Error::UnknownWinner));
+            }
+            let share = pot / members.len() as u64;
+            let remainder = pot % members.len() as u64;
+            for (i, uid) in members.iter().enumerate() {
+                let amount = if i == 0 { share + remainder } else { share };
+                b.credit(*uid, amount)?;
+            }
+            return Ok(share);
+        }
+        // the house keeps the pot
+        Contender::House => {
+            return Ok(0);
+        }
+    }
+}
+
+// the players who won, everyone on the team in team mode
+fn winner_ids(uids: &[UserID], users: &HashMap<UserID, UserData>, winner: Contender)
+        -> Vec<UserID> {
+    match winner {
+        Contender::User(w) => vec![w],
+        Contender::Team(t) => uids.iter().copied()
+            .filter(|uid| users.get(uid).and_then(|ud| ud.team) == Some(t))
+            .collect(),
+        Contender::House => Vec::new(),
+    }
+}
+
+fn team_members(users: &HashMap<UserID, UserData>, team: TeamID) -> Vec<String> {
+    let mut members: Vec<String> = users.values()
+        .filter(|ud| ud.team == Some(team))
+        .map(|ud| ud.name.clone())
+        .collect();
+    members.sort();
+    members
+}
+
+async fn announce_round(users: &HashMap<UserID, UserData>, cb: &Callback,
+                        round: u8, winner: Contender, wins: u8) -> Result<()> {
+    match winner {
+        Contender::User(w) => {
+            let username = &users.get(&w).ok_or_else(|| Error::UnknownWinner)?.name;
+            for (uid, _) in users {
+                cb.route(*uid)?.round_result(round, w, username, wins).await?;
+            }
+        }
+        Contender::Team(t) => {
+            for (uid, _) in users {
+                cb.route(*uid)?.team_round_result(round, t, wins).await?;
+            }
+        }
+        Contender::House => {
+            for (uid, _) in users {
+                cb.route(*uid)?.round_result(round, HOUSE_ID, HOUSE_NAME, wins).await?;
+            }
+        }
+    }
+    Ok(())
+}
 
+async fn announce_winner(users: &HashMap<UserID, UserData>, cb: &Callback,
+                         winner: Contender, payout: u64) -> Result<()> {
+    match winner {
+        Contender::User(w) => {
+            let username = &users.get(&w).ok_or_else(|| Error::UnknownWinner)?.name;
+            for (uid, _) in users {
+                cb.route(*uid)?.winner(w, username, payout).await?;
+            }
+        }
+        Contender::Team(t) => {
+            let members = team_members(users, t);
+            for (uid, _) in users {
+                cb.route(*uid)?.team_winner(t, &members, payout).await?;
+            }
+        }
+        Contender::House => {
+            for (uid, _) in users {
+                cb.route(*uid)?.winner(HOUSE_ID, HOUSE_NAME, payout).await?;
+            }
+        }
+    }
     Ok(())
 }
 
+async fn play_round(users: &HashMap<UserID, UserData>, session_type: &SessionType,
+                    cb: &Arc<Callback>, games: &GameRegistry)
+        -> Result<HashMap<UserID, Score>> {
+    // depending on the session type, take different actions
+    match session_type {
+        SessionType::Dice => dice_game(users, cb).await,
+        SessionType::Coin => coin_game(users, cb).await,
+        SessionType::Custom(name) => {
+            let rules = games.get(name)
+                .ok_or_else(|| Error::UnknownGame(name.clone()))?;
+            custom_game(users, cb, rules.clone()).await
+        }
+    }
+}
+
 async fn dice_game(users: &HashMap<UserID, UserData>,
-                   cb: &Callback) -> Result<UserID> {
+                   cb: &Callback) -> Result<HashMap<UserID, Score>> {
     // pick how many sides the dice have, out of 4, 6, 8, 12, and 20
-    let sides_array = vec![4, 6, 8, 12, 20];
-    let sides_index = rand::thread_rng().gen_range(0..sides_array.len());
-    let sides = sides_array[sides_index];
+    let sides_index = rand::thread_rng().gen_range(0..DiceSides::VALID.len());
+    let sides = DiceSides::new(DiceSides::VALID[sides_index])?;
     // pick a random number of dice to roll, between 1 and 6
-    let count = rand::thread_rng().gen_range(1..=6);
+    let count = DiceCount::new(
+        rand::thread_rng().gen_range(DiceCount::MIN..=DiceCount::MAX))?;
     let mut results = Vec::new();
-    for _ in 0..count {
-        let roll = rand::thread_rng().gen_range(1..=sides);
+    for _ in 0..count.value() {
+        let roll = rand::thread_rng().gen_range(1..=sides.value());
         results.push(roll);
     }
     // ask each user for their rolls
-    let mut winner = None;
-    let mut winner_score = 0;
+    let mut scores = HashMap::new();
     for (uid, _) in users {
-        let guess = cb.route(*uid)?.roll_dice(sides, count).await?;
-        let mut score = 0;
-        for g in guess {
-            if results.contains(&g) {
-                score = score + 1;
+        let guess = match cb.ask(*uid, |s| async move { s.roll_dice(sides, count).await }).await? {
+            Some(g) => g,
+            None => { continue; }
+        };
+        let mut score = Score::default();
+        for g in guess.iter().take(count.value() as usize) {
+            if results.contains(g) {
+                score.add(1);
             }
         }
-        if score >= winner_score {
-            winner_score = score;
-            winner = Some(*uid);
-        }
-    }
-    match winner {
-        Some(w) => { return Ok(w); }
-        None => { return Err(Box::new(Error::UnknownWinner)); }
+        scores.insert(*uid, score);
     }
+    Ok(scores)
 }
 
 async fn coin_game(users: &HashMap<UserID, UserData>,
-                   cb: &Callback) -> Result<UserID> {
+                   cb: &Callback) -> Result<HashMap<UserID, Score>> {
     // pick how many coins to flip between 1 and 6
-    let count = rand::thread_rng().gen_range(1..=6);
+    let count = CoinCount::new(
+        rand::thread_rng().gen_range(CoinCount::MIN..=CoinCount::MAX))?;
     // flip coins
     let mut results = Vec::new();
-    for _ in 0..count {
+    for _ in 0..count.value() {
         let flip = rand::thread_rng().gen_range(0..=1);
         if flip == 0 {
             results.push(Coin::Heads);
@@ -276,41 +2610,46 @@ async fn coin_game(users: &HashMap<UserID, UserData>,
             results.push(Coin::Tails);
         }
     }
-    let mut winner = None;
-    let mut winner_score = 0;
+    let mut scores = HashMap::new();
     for (uid, _) in users {
-        let result = cb.route(*uid)?.flip_coin(count).await?;
-        let mut score = 0;
+        let result = match cb.ask(*uid, |s| async move { s.flip_coin(count).await }).await? {
+            Some(r) => r,
+            None => { continue; }
+        };
+        let mut score = Score::default();
         for x in 0..result.len() {
-            if x > results.len() { break; }
+            if x >= results.len() { break; }
             if results[x] == result[x] {
-                score = score + 1;
+                score.add(1);
             }
         }
-        if score >= winner_score {
-            winner_score = score;
-            winner = Some(*uid);
-        }
-    }
-    match winner {
-        Some(w) => { return Ok(w); }
-        None => { return Err(Box::new(Error::UnknownWinner)); }
+        scores.insert(*uid, score);
     }
+    Ok(scores)
+}
+
+async fn custom_game(users: &HashMap<UserID, UserData>, cb: &Arc<Callback>,
+                     rules: Arc<dyn GameRules>) -> Result<HashMap<UserID, Score>> {
+    // seat the players in a stable order so the rules can address them
+    let mut seats: Vec<(UserID, String)> = users.iter()
+        .map(|(uid, ud)| (*uid, ud.name.clone())).collect();
+    seats.sort();
+    let host = GameHost::new(seats, cb.clone());
+    // custom rules run synchronously, calling back into the runtime for
+    // each prompt, so keep them off the async worker
+    let winner = tokio::task::block_in_place(|| rules.play(host))?;
+    // custom rules only pick a winner, so award them the single point
+    Ok(users.keys().map(|uid| {
+        (*uid, Score::new(if *uid == winner { 1 } else { 0 }))
+    }).collect())
 }
 
-async fn report_error(session: Session, ew: impl std::fmt::Display) {
+async fn report_error(session: Session,
+                      ew: &(dyn std::error::Error + Send + Sync + 'static)) {
     let users = session.read().await.users.clone();
     for (uid, _) in users {
         match session.read().await.server_event_senders.get(&uid) {
-            Some(ses) => {
-                match ses.error(&format!("{}", ew)).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Failed to send error to user {:?}: [{}], due to {:?}",
-                               uid, ew, e);
-                    }
-                }
-            }
+            Some(ses) => { send_error(uid, ses, ew).await; }
             None => {
                 error!("Failed to send error to user {:?}: {}, no sender",
                        uid, ew);
@@ -318,3 +2657,55 @@ async fn report_error(session: Session, ew: impl std::fmt::Display) {
         }
     }
 }
+
+// the end of a session's life, once it is out of the session map. Anyone
+// waiting to join is turned away and everyone listening is told why their
+// stream is closing before their sender is dropped
+async fn teardown(sid: SessionID, state: &mut SessionState,
+                  codes: &RwLock<HashMap<String, SessionID>>, lobby: &Lobby, db: &SessionDb,
+                  reason: CloseReason) {
+    state.pending.clear();
+    codes.write().await.remove(&state.invite_code);
+    lobby.removed(&state.session_data(sid));
+    db.remove(sid);
+    for (uid, ses) in state.server_event_senders.drain() {
+        close_stream(uid, &ses, reason).await;
+    }
+    if let Some(cb) = state.game.take() {
+        cb.close(reason).await;
+    }
+    info!("Closed session {:?}: {:?}", sid, reason);
+}
+
+// remove a player from a lobby, telling everyone, including the player, before
+// dropping their sender to end their event stream
+async fn remove_player(state: &mut SessionState, sid: SessionID, target: UserID)
+        -> Result<()> {
+    let ud = state.users.remove(&target)
+        .ok_or_else(|| Error::UserNotInSession(target, sid))?;
+    for (other, ses) in &state.server_event_senders {
+        if let Err(e) = ses.user_kicked(sid, target, &ud.name).await {
+            warn!("Failed to tell user {:?} that {:?} was kicked: {:?}", other, target, e);
+        }
+    }
+    if let Some(ses) = state.server_event_senders.remove(&target) {
+        close_stream(target, &ses, CloseReason::Kicked).await;
+    }
+    Ok(())
+}
+
+// tell the user why their event stream is ending, before the sender is dropped
+async fn close_stream(uid: UserID, ses: &ServerEventSender, reason: CloseReason) {
+    if let Err(e) = ses.stream_closing(reason).await {
+        warn!("Failed to tell user {:?} their stream is closing: {:?}", uid, e);
+    }
+}
+
+// send an error along with what the client can do about it
+async fn send_error(uid: UserID, ses: &ServerEventSender,
+                    ew: &(dyn std::error::Error + Send + Sync + 'static)) {
+    let (code, action) = guidance(ew);
+    if let Err(e) = ses.error(code, action, &format!("{}", ew)).await {
+        error!("Failed to send error to user {:?}: [{}], due to {:?}", uid, ew, e);
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+#[cfg(feature = "sqlite")]
+use std::sync::Mutex;
+#[cfg(feature = "sqlite")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "sqlite")]
+use rusqlite::types::Value;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::{HostInfo, ScoreEntry, SessionData, SessionID, UserDataExport, UserID};
+#[cfg(feature = "sqlite")]
+use csr_protocol::types::{AccountType, PlayedGame, Score, SessionType, TeamID, Visibility};
+use csr_storage::StorageKey;
+
+#[cfg(not(feature = "sqlite"))]
+use crate::error::Error;
+use crate::service::{SessionState, UserData};
+
+const SQLITE_PATH_VAR: &str = "CSR_SQLITE_PATH";
+
+// lobbies are rewritten whole whenever they change, and removed once their
+// game starts or they close. Players and bans go with their lobby
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS sessions (
+        session_id INTEGER PRIMARY KEY,
+        invite_code TEXT NOT NULL,
+        session_type TEXT NOT NULL,
+        custom_type TEXT NOT NULL,
+        max_players INTEGER NOT NULL,
+        min_players INTEGER NOT NULL,
+        series_length INTEGER NOT NULL,
+        team_mode INTEGER NOT NULL,
+        wager INTEGER NOT NULL,
+        house_target INTEGER,
+        ephemeral INTEGER NOT NULL,
+        host INTEGER,
+        auto_start INTEGER NOT NULL,
+        start_at INTEGER,
+        name TEXT NOT NULL,
+        visibility TEXT NOT NULL,
+        password BLOB,
+        approve_joins INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS players (
+        session_id INTEGER NOT NULL REFERENCES sessions ON DELETE CASCADE,
+        user_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        team INTEGER,
+        guest INTEGER NOT NULL,
+        PRIMARY KEY (session_id, user_id)
+    );
+    CREATE TABLE IF NOT EXISTS bans (
+        session_id INTEGER NOT NULL REFERENCES sessions ON DELETE CASCADE,
+        user_id INTEGER NOT NULL,
+        PRIMARY KEY (session_id, user_id)
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        result_id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL,
+        session_type TEXT NOT NULL,
+        custom_type TEXT NOT NULL,
+        finished_at INTEGER NOT NULL,
+        payout INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS result_players (
+        result_id INTEGER NOT NULL REFERENCES results,
+        user_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        winner INTEGER NOT NULL,
+        PRIMARY KEY (result_id, user_id)
+    );
+";
+
+// a lobby as it was when the server last saw it, to be opened again
+pub struct SavedSession {
+    pub session_id: SessionID,
+    pub host_info: HostInfo,
+    pub invite_code: String,
+    pub users: HashMap<UserID, UserData>,
+    pub banned: HashSet<UserID>,
+}
+
+// keeps every lobby still waiting for its game in SQLite, so a restart can
+// open them again, along with the result of every game played. Nothing is
+// kept without a database, and a database that can't be written is logged
+// rather than failing the players' calls
+#[derive(Clone, Default)]
+pub struct SessionDb {
+    db: Option<Arc<Db>>,
+}
+
+#[cfg(feature = "sqlite")]
+struct Db {
+    conn: Mutex<Connection>,
+    // lobby passwords, and the names and scores of players, are encrypted
+    // with it, like the rest of stored data
+    key: Option<StorageKey>,
+}
+
+// never built without the sqlite feature
+#[cfg(not(feature = "sqlite"))]
+enum Db {}
+
+impl SessionDb {
+    // the database at the path in the variable, created if it isn't there
+    #[cfg(feature = "sqlite")]
+    pub fn from_env(key: Option<StorageKey>) -> Result<Self> {
+        let path = match std::env::var(SQLITE_PATH_VAR) {
+            Ok(path) => path,
+            Err(_) => {
+                info!("No {} set, lobbies will not survive a restart", SQLITE_PATH_VAR);
+                return Ok(Self::default());
+            }
+        };
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(SCHEMA)?;
+        info!("Keeping lobbies and results in {}", path);
+        Ok(Self {
+            db: Some(Arc::new(Db {
+                conn: Mutex::new(conn),
+                key: key,
+            })),
+        })
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn from_env(_key: Option<StorageKey>) -> Result<Self> {
+        if std::env::var(SQLITE_PATH_VAR).is_ok() {
+            return Err(Box::new(Error::SqliteUnsupported(SQLITE_PATH_VAR)));
+        }
+        Ok(Self::default())
+    }
+
+    // write the lobby as it is now, or forget it once its game has started
+    pub fn save(&self, sid: SessionID, state: &SessionState) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.save(sid, state) {
+                error!("Unable to save session {:?}: {:?}", sid, e);
+            }
+        }
+    }
+
+    pub fn remove(&self, sid: SessionID) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.remove(sid) {
+                error!("Unable to remove session {:?}: {:?}", sid, e);
+            }
+        }
+    }
+
+    // how everyone finished a game, and what each winner was paid
+    pub fn record_result(&self, sd: &SessionData, scores: &[ScoreEntry], winners: &[UserID],
+                         payout: u64) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.record_result(sd, scores, winners, payout) {
+                error!("Unable to record the result in session {:?}: {:?}", sd.session_id(), e);
+            }
+        }
+    }
+
+    // every lobby saved, oldest first
+    pub fn restore(&self) -> Result<Vec<SavedSession>> {
+        match &self.db {
+            Some(db) => db.restore(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // add the games the user finished, and the lobbies they are saved in or
+    // banned from, to what else is stored about them
+    pub fn export_user(&self, uid: UserID, ud: UserDataExport) -> Result<UserDataExport> {
+        match &self.db {
+            Some(db) => db.export_user(uid, ud),
+            None => Ok(ud),
+        }
+    }
+
+    // forget the user's part in every game and lobby. The games themselves
+    // are kept for everyone else who played them
+    pub fn forget_user(&self, uid: UserID) -> Result<()> {
+        match &self.db {
+            Some(db) => db.forget_user(uid),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Db {
+    fn save(&self, sid: SessionID, state: &SessionState) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM sessions WHERE session_id = ?1", [sid.0])?;
+        // only lobbies still waiting for their game are opened again
+        if !state.started {
+            let (kind, custom) = type_names(&state.settings.session_type);
+            let password = match &state.password {
+                Some(p) => Some(self.seal(p.as_bytes())?),
+                None => None,
+            };
+            tx.execute(
+                "INSERT INTO sessions (session_id, invite_code, session_type, custom_type,
+                     max_players, min_players, series_length, team_mode, wager, house_target,
+                     ephemeral, host, auto_start, start_at, name, visibility, password,
+                     approve_joins)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                     ?17, ?18)",
+                params![sid.0, state.invite_code, kind, custom, state.max_players,
+                        state.min_players, state.settings.series_length,
+                        state.settings.team_mode, state.settings.wager,
+                        state.settings.house_target, state.ephemeral, state.host.map(|h| h.0),
+                        state.auto_start, state.start_at.map(to_unix), state.name,
+                        visibility_name(state.visibility), password, state.approve_joins])?;
+            for (uid, ud) in &state.users {
+                tx.execute(
+                    "INSERT INTO players (session_id, user_id, name, team, guest)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![sid.0, uid.0, self.seal_text(&ud.name)?, ud.team.map(|t| t.0),
+                            ud.account_type == AccountType::Guest])?;
+            }
+            for uid in &state.banned {
+                tx.execute("INSERT INTO bans (session_id, user_id) VALUES (?1, ?2)",
+                           [sid.0, uid.0])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove(&self, sid: SessionID) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM sessions WHERE session_id = ?1", [sid.0])?;
+        Ok(())
+    }
+
+    fn record_result(&self, sd: &SessionData, scores: &[ScoreEntry], winners: &[UserID],
+                     payout: u64) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let (kind, custom) = type_names(sd.session_type());
+        tx.execute(
+            "INSERT INTO results (session_id, session_type, custom_type, finished_at, payout)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![sd.session_id().0, kind, custom, to_unix(SystemTime::now()), payout])?;
+        let result_id = tx.last_insert_rowid();
+        for e in scores {
+            let winner = if winners.contains(&e.user_id()) { "1" } else { "0" };
+            tx.execute(
+                "INSERT INTO result_players (result_id, user_id, name, score, winner)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![result_id, e.user_id().0, self.seal_text(e.user_name())?,
+                        self.seal_text(&e.score().value().to_string())?,
+                        self.seal_text(winner)?])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<Vec<SavedSession>> {
+        let conn = self.conn.lock().unwrap();
+        let mut ret = Vec::new();
+        let mut stmt = conn.prepare(
+            "SELECT session_id, invite_code, session_type, custom_type, max_players,
+                 min_players, series_length, team_mode, wager, house_target, ephemeral, host,
+                 auto_start, start_at, name, visibility, password, approve_joins
+             FROM sessions ORDER BY session_id")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let sid = SessionID(row.get(0)?);
+            let typ = match session_type(&row.get::<_, String>(2)?, row.get(3)?) {
+                Some(typ) => typ,
+                None => {
+                    warn!("Session {:?} has an unknown game type, not restoring it", sid);
+                    continue;
+                }
+            };
+            let password = match row.get::<_, Option<Vec<u8>>>(16)? {
+                Some(p) => Some(String::from_utf8(self.unseal(&p)?)?),
+                None => None,
+            };
+            let mut hi = HostInfo::new(typ, row.get(4)?)
+                .with_min_players(row.get(5)?)
+                .with_series_length(row.get(6)?)
+                .with_team_mode(row.get(7)?)
+                .with_wager(row.get(8)?)
+                .with_ephemeral(row.get(10)?)
+                .with_auto_start(row.get(12)?)
+                .with_name(&row.get::<_, String>(14)?)
+                .with_visibility(visibility(&row.get::<_, String>(15)?))
+                .with_password(password)
+                .with_approve_joins(row.get(17)?);
+            if let Some(target) = row.get(9)? {
+                hi = hi.with_house_target(target);
+            }
+            if let Some(host) = row.get(11)? {
+                hi = hi.with_host(UserID(host));
+            }
+            if let Some(at) = row.get(13)? {
+                hi = hi.with_start_at(from_unix(at));
+            }
+            ret.push(SavedSession {
+                session_id: sid,
+                host_info: hi,
+                invite_code: row.get(1)?,
+                users: HashMap::new(),
+                banned: HashSet::new(),
+            });
+        }
+
+        for saved in &mut ret {
+            let mut stmt = conn.prepare(
+                "SELECT user_id, name, team, guest FROM players WHERE session_id = ?1")?;
+            let mut rows = stmt.query([saved.session_id.0])?;
+            while let Some(row) = rows.next()? {
+                let guest: bool = row.get(3)?;
+                saved.users.insert(UserID(row.get(0)?), UserData {
+                    name: self.unseal_text(row.get(1)?)?,
+                    team: row.get::<_, Option<u32>>(2)?.map(TeamID),
+                    account_type: if guest { AccountType::Guest } else { AccountType::Registered },
+                });
+            }
+            let mut stmt = conn.prepare("SELECT user_id FROM bans WHERE session_id = ?1")?;
+            let mut rows = stmt.query([saved.session_id.0])?;
+            while let Some(row) = rows.next()? {
+                saved.banned.insert(UserID(row.get(0)?));
+            }
+        }
+        Ok(ret)
+    }
+
+    fn export_user(&self, uid: UserID, ud: UserDataExport) -> Result<UserDataExport> {
+        let conn = self.conn.lock().unwrap();
+        let mut games = Vec::new();
+        let mut stmt = conn.prepare(
+            "SELECT r.session_id, r.session_type, r.custom_type, r.finished_at, p.name, p.score,
+                 p.winner
+             FROM result_players p JOIN results r ON r.result_id = p.result_id
+             WHERE p.user_id = ?1 ORDER BY r.result_id")?;
+        let mut rows = stmt.query([uid.0])?;
+        while let Some(row) = rows.next()? {
+            let sid = SessionID(row.get(0)?);
+            let typ = match session_type(&row.get::<_, String>(1)?, row.get(2)?) {
+                Some(typ) => typ,
+                None => {
+                    warn!("Result in session {:?} has an unknown game type, not exporting it",
+                          sid);
+                    continue;
+                }
+            };
+            let score = self.unseal_text(row.get(5)?)?.parse()?;
+            let winner = self.unseal_text(row.get(6)?)? == "1";
+            games.push(PlayedGame::new(sid, typ, from_unix(row.get(3)?),
+                                       &self.unseal_text(row.get(4)?)?, Score::new(score),
+                                       winner));
+        }
+        let mut lobbies = Vec::new();
+        let mut stmt = conn.prepare(
+            "SELECT session_id FROM players WHERE user_id = ?1 ORDER BY session_id")?;
+        let mut rows = stmt.query([uid.0])?;
+        while let Some(row) = rows.next()? {
+            lobbies.push(SessionID(row.get(0)?));
+        }
+        let mut banned_from = Vec::new();
+        let mut stmt = conn.prepare(
+            "SELECT session_id FROM bans WHERE user_id = ?1 ORDER BY session_id")?;
+        let mut rows = stmt.query([uid.0])?;
+        while let Some(row) = rows.next()? {
+            banned_from.push(SessionID(row.get(0)?));
+        }
+        Ok(ud.with_games(games).with_lobbies(lobbies).with_banned_from(banned_from))
+    }
+
+    fn forget_user(&self, uid: UserID) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM result_players WHERE user_id = ?1", [uid.0])?;
+        tx.execute("DELETE FROM players WHERE user_id = ?1", [uid.0])?;
+        tx.execute("DELETE FROM bans WHERE user_id = ?1", [uid.0])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        csr_storage::seal(plain, self.key.as_ref())
+    }
+
+    // values saved before a key was set are read as they are
+    fn unseal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        csr_storage::unseal(data.to_vec(), self.key.as_ref())
+    }
+
+    // without a key the text is kept as it is, so numbers stay numbers that
+    // can be queried like any other
+    fn seal_text(&self, text: &str) -> Result<Value> {
+        match &self.key {
+            Some(k) => Ok(Value::Blob(csr_storage::encrypt(k, text.as_bytes())?)),
+            None => Ok(Value::Text(text.to_owned())),
+        }
+    }
+
+    fn unseal_text(&self, value: Value) -> Result<String> {
+        match value {
+            Value::Blob(b) => Ok(String::from_utf8(self.unseal(&b)?)?),
+            Value::Text(s) => Ok(s),
+            Value::Integer(i) => Ok(i.to_string()),
+            Value::Real(r) => Ok(r.to_string()),
+            Value::Null => Ok(String::new()),
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl Db {
+    fn save(&self, _sid: SessionID, _state: &SessionState) -> Result<()> {
+        match *self {}
+    }
+
+    fn remove(&self, _sid: SessionID) -> Result<()> {
+        match *self {}
+    }
+
+    fn record_result(&self, _sd: &SessionData, _scores: &[ScoreEntry], _winners: &[UserID],
+                     _payout: u64) -> Result<()> {
+        match *self {}
+    }
+
+    fn restore(&self) -> Result<Vec<SavedSession>> {
+        match *self {}
+    }
+
+    fn export_user(&self, _uid: UserID, _ud: UserDataExport) -> Result<UserDataExport> {
+        match *self {}
+    }
+
+    fn forget_user(&self, _uid: UserID) -> Result<()> {
+        match *self {}
+    }
+}
+
+// custom games keep their name alongside
+#[cfg(feature = "sqlite")]
+fn type_names(st: &SessionType) -> (&'static str, &str) {
+    match st {
+        SessionType::Dice => ("dice", ""),
+        SessionType::Coin => ("coin", ""),
+        SessionType::Custom(name) => ("custom", name),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn session_type(kind: &str, custom: String) -> Option<SessionType> {
+    match kind {
+        "dice" => Some(SessionType::Dice),
+        "coin" => Some(SessionType::Coin),
+        "custom" if !custom.is_empty() => Some(SessionType::Custom(custom)),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn visibility_name(v: Visibility) -> &'static str {
+    match v {
+        Visibility::Public => "public",
+        Visibility::Unlisted => "unlisted",
+        Visibility::Private => "private",
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn visibility(name: &str) -> Visibility {
+    match name {
+        "unlisted" => Visibility::Unlisted,
+        "private" => Visibility::Private,
+        _ => Visibility::Public,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn to_unix(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(feature = "sqlite")]
+fn from_unix(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
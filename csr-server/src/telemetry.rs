@@ -0,0 +1,163 @@
+use std::task::{Context, Poll};
+
+use tower::{Layer, Service};
+use tracing::instrument::{Instrument, Instrumented};
+
+use csr_protocol::types::Result;
+
+// spans are only exported to a collector when given one
+#[cfg(feature = "otel")]
+const OTLP_ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+#[cfg(feature = "otel")]
+const SERVICE_NAME_VAR: &str = "OTEL_SERVICE_NAME";
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "csr-server";
+
+// what is watching the server's spans
+#[cfg(any(feature = "otel", feature = "console"))]
+type Layers = Vec<Box<dyn tracing_subscriber::Layer<tracing_subscriber::Registry> + Send + Sync>>;
+
+// sends the server's spans to the OpenTelemetry collector the standard
+// variables point at, until it is shut down, and serves its tasks to
+// tokio-console. Without either feature spans are never recorded
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Telemetry {
+    #[cfg(any(feature = "otel", feature = "console"))]
+    pub fn from_env() -> Result<Self> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut layers = Layers::new();
+        #[cfg(feature = "otel")]
+        let provider = export_spans(&mut layers)?;
+        #[cfg(feature = "console")]
+        {
+            use tracing_subscriber::Layer;
+            // served on 127.0.0.1:6669 unless TOKIO_CONSOLE_BIND says otherwise
+            layers.push(console_subscriber::spawn().boxed());
+            info!("Serving tasks to tokio-console");
+        }
+        // log lines are still written by the logger, this only takes spans
+        if !layers.is_empty() {
+            tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layers))?;
+        }
+        Ok(Self {
+            #[cfg(feature = "otel")]
+            provider: provider,
+        })
+    }
+
+    #[cfg(not(any(feature = "otel", feature = "console")))]
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {})
+    }
+
+    // sends the spans still waiting to go
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                warn!("Unable to export the last spans: {:?}", e);
+            }
+        }
+    }
+}
+
+// adds a layer exporting the server's own spans to the collector, when there
+// is one
+#[cfg(feature = "otel")]
+fn export_spans(layers: &mut Layers)
+        -> Result<Option<opentelemetry_sdk::trace::TracerProvider>> {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::Layer;
+
+    if std::env::var(OTLP_ENDPOINT_VAR).is_err() {
+        info!("No {} set, spans will not be exported", OTLP_ENDPOINT_VAR);
+        return Ok(None);
+    }
+    let name = std::env::var(SERVICE_NAME_VAR).unwrap_or_else(|_| SERVICE_NAME.to_owned());
+    let exporter = opentelemetry_otlp::SpanExporter::builder().with_tonic().build()?;
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new(vec![
+            opentelemetry::KeyValue::new("service.name", name),
+        ]))
+        .build();
+    // callers say which trace they are part of with W3C trace context
+    opentelemetry::global::set_text_map_propagator(
+        opentelemetry_sdk::propagation::TraceContextPropagator::new());
+    // only the server's own spans, not every event the transport traces
+    let targets = tracing_subscriber::filter::Targets::new()
+        .with_target("csr_server", tracing::Level::INFO);
+    layers.push(tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(SERVICE_NAME))
+        .with_filter(targets)
+        .boxed());
+    info!("Exporting spans to {}", std::env::var(OTLP_ENDPOINT_VAR).unwrap_or_default());
+    Ok(Some(provider))
+}
+
+// a span for every call, named for its method. With the otel feature, a call
+// carrying a traceparent in its metadata has its span joined to the caller's
+// trace
+#[derive(Clone, Copy, Default)]
+pub struct CallSpans;
+
+impl<S> Layer<S> for CallSpans {
+    type Service = Spanned<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Spanned {
+            inner: inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Spanned<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for Spanned<S>
+        where S: Service<http::Request<B>> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Instrumented<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let method = request.uri().path().trim_start_matches('/');
+        let span = tracing::info_span!("call", otel.name = method, otel.kind = "server",
+                                       rpc.system = "grpc");
+        #[cfg(feature = "otel")]
+        {
+            use tracing_opentelemetry::OpenTelemetrySpanExt;
+            let parent = opentelemetry::global::get_text_map_propagator(|p| {
+                p.extract(&Metadata(request.headers()))
+            });
+            span.set_parent(parent);
+        }
+        self.inner.call(request).instrument(span)
+    }
+}
+
+// reads the trace context out of a call's metadata
+#[cfg(feature = "otel")]
+struct Metadata<'a>(&'a http::HeaderMap);
+
+#[cfg(feature = "otel")]
+impl opentelemetry::propagation::Extractor for Metadata<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
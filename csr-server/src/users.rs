@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use csr_protocol::types::Result;
+use csr_protocol::types::{AccountType, AuthToken, PeerIdentity, UserID, HOUSE_ID};
+use csr_storage::StorageKey;
+
+use crate::error::Error;
+
+// written in place of the certificate of a user who registered without one
+const NO_PEER: &str = "-";
+// starts the lines tying an identity provider's subjects to user IDs
+const SUBJECT: &str = "sub";
+
+// a registered user, and the token that authenticates them
+struct User {
+    token: AuthToken,
+    // the client certificate they registered with, which the token can then
+    // only be used with
+    peer: Option<PeerIdentity>,
+    name: String,
+    account_type: AccountType,
+}
+
+// the user IDs the server has handed out, with the token, certificate and name
+// each was registered with, saved to a file after every change. Each line of
+// the file holds a user ID, its token, its hex encoded certificate or a dash,
+// and its name, encrypted when the server has a storage key. Deleting a user
+// forgets all but the ID, which is kept on its own line so it is never handed
+// out again. Guests are only ever saved that way, so they are forgotten once
+// the server stops. Users who signed in through an identity provider also have
+// a line holding "sub", their user ID and the subject the provider knows them
+// by
+pub struct UserRegistry {
+    path: PathBuf,
+    key: Option<StorageKey>,
+    users: HashMap<UserID, Option<User>>,
+    tokens: HashMap<AuthToken, UserID>,
+    subjects: HashMap<String, UserID>,
+    next: u64,
+}
+
+impl UserRegistry {
+    pub fn load(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        let mut users = HashMap::new();
+        let mut tokens = HashMap::new();
+        let mut subjects = HashMap::new();
+        if path.exists() {
+            let data = String::from_utf8(csr_storage::read(path, key.as_ref())?)?;
+            for line in data.lines() {
+                if let Some(sub) = line.strip_prefix(SUBJECT).and_then(|l| l.strip_prefix(' ')) {
+                    if let Some((uid, subject)) = sub.split_once(' ') {
+                        subjects.insert(subject.to_owned(), UserID(uid.parse()?));
+                    }
+                    continue;
+                }
+                let mut fields = line.splitn(4, ' ');
+                let uid = match fields.next() {
+                    Some(uid) if !uid.is_empty() => UserID(uid.parse()?),
+                    _ => { continue; }
+                };
+                let user = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(token), Some(peer), Some(name)) => {
+                        let token = AuthToken(token.to_owned());
+                        let peer = match peer {
+                            NO_PEER => None,
+                            p => Some(PeerIdentity(hex::decode(p)?)),
+                        };
+                        tokens.insert(token.clone(), uid);
+                        Some(User {
+                            token: token,
+                            peer: peer,
+                            name: name.to_owned(),
+                            account_type: AccountType::Registered,
+                        })
+                    }
+                    _ => None,
+                };
+                users.insert(uid, user);
+            }
+        }
+        let next = users.keys().map(|uid| uid.0).max().unwrap_or(HOUSE_ID.0) + 1;
+        Ok(Self {
+            path: path.to_owned(),
+            key: key,
+            users: users,
+            tokens: tokens,
+            subjects: subjects,
+            next: next,
+        })
+    }
+
+    // hand out an ID nobody else has been given, and a token to prove it.
+    // Guests without a name are named after their ID
+    pub fn register(&mut self, name: &str, account_type: AccountType,
+                    peer: Option<&PeerIdentity>) -> Result<(UserID, AuthToken)> {
+        // the file holds a user to a line, so a name with a line break in it
+        // could pass for another user
+        if name.contains(['\n', '\r']) {
+            return Err(Box::new(Error::LineBreak("User names")));
+        }
+        let uid = UserID(self.next);
+        let token = AuthToken::generate();
+        let name = match (account_type, name) {
+            (AccountType::Guest, "") => format!("Guest {}", uid.0),
+            (_, name) => name.to_owned(),
+        };
+        self.users.insert(uid, Some(User {
+            token: token.clone(),
+            peer: peer.cloned(),
+            name: name,
+            account_type: account_type,
+        }));
+        self.tokens.insert(token.clone(), uid);
+        self.next = self.next + 1;
+        self.save()?;
+        Ok((uid, token))
+    }
+
+    // the user the token was issued to. Users who registered with a client
+    // certificate have to keep connecting with it, so a stolen token is no use
+    // without it
+    pub fn authenticate(&self, token: &AuthToken, peer: Option<&PeerIdentity>)
+            -> Option<UserID> {
+        let uid = *self.tokens.get(token)?;
+        match &self.users.get(&uid)?.as_ref()?.peer {
+            Some(p) if Some(p) != peer => None,
+            _ => Some(uid),
+        }
+    }
+
+    // the user an identity provider's subject signs in as, registering them
+    // under the name the provider gave the first time they sign in
+    pub fn sign_in(&mut self, subject: &str, name: &str) -> Result<UserID> {
+        if let Some(uid) = self.subjects.get(subject) {
+            return Ok(*uid);
+        }
+        if subject.contains(['\n', '\r']) {
+            return Err(Box::new(Error::LineBreak("Subjects")));
+        }
+        let (uid, _) = self.register(name, AccountType::Registered, None)?;
+        self.subjects.insert(subject.to_owned(), uid);
+        self.save()?;
+        Ok(uid)
+    }
+
+    // the name the user registered with, unless they were deleted
+    pub fn name(&self, uid: UserID) -> Option<&str> {
+        self.users.get(&uid)?.as_ref().map(|u| u.name.as_str())
+    }
+
+    // users the registry doesn't know, such as those acted for by an admin,
+    // count as registered
+    pub fn account_type(&self, uid: UserID) -> AccountType {
+        self.users.get(&uid).and_then(|u| u.as_ref())
+            .map_or(AccountType::Registered, |u| u.account_type)
+    }
+
+    // forget the user's token, name and subject, keeping their ID taken
+    pub fn remove(&mut self, uid: UserID) -> Result<()> {
+        if let Some(user) = self.users.get_mut(&uid).and_then(|u| u.take()) {
+            self.tokens.remove(&user.token);
+            self.subjects.retain(|_, u| *u != uid);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = String::new();
+        for (uid, user) in &self.users {
+            match user {
+                Some(u) if u.account_type == AccountType::Registered => {
+                    let peer = match &u.peer {
+                        Some(p) => hex::encode(&p.0),
+                        None => NO_PEER.to_owned(),
+                    };
+                    data.push_str(&format!("{} {} {} {}\n", uid.0, u.token.0, peer, u.name));
+                }
+                _ => data.push_str(&format!("{}\n", uid.0)),
+            }
+        }
+        for (subject, uid) in &self.subjects {
+            data.push_str(&format!("{} {} {}\n", SUBJECT, uid.0, subject));
+        }
+        csr_storage::write(&self.path, self.key.as_ref(), data.as_bytes())
+    }
+}
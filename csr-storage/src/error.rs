@@ -0,0 +1,11 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Unable to decrypt stored data, the key may be wrong")]
+    DecryptFailed,
+    #[error("Stored data is encrypted but no key was given")]
+    EncryptedWithoutKey,
+    #[error("Unable to encrypt stored data")]
+    EncryptFailed,
+    #[error("Invalid storage key, expected {0} hex digits")]
+    InvalidKey(usize),
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::Rng;
+
+use csr_protocol::types::Result;
+
+pub mod error;
+
+use crate::error::Error;
+
+// written at the start of every encrypted file, so plain files written before
+// encryption was turned on can still be read
+const MAGIC: &[u8] = b"CSRENC1\n";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+// the environment variable the server reads its storage key from
+pub const STORAGE_KEY_VAR: &str = "CSR_STORAGE_KEY";
+
+// AES-256-GCM key used to encrypt stored data
+#[derive(Clone)]
+pub struct StorageKey([u8; KEY_LEN]);
+
+impl StorageKey {
+    pub fn generate() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let bytes = hex::decode(s.trim()).map_err(|_| Error::InvalidKey(KEY_LEN * 2))?;
+        let key: [u8; KEY_LEN] = bytes.try_into()
+            .map_err(|_| Error::InvalidKey(KEY_LEN * 2))?;
+        Ok(Self(key))
+    }
+
+    // the key from the environment, if one is set
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(STORAGE_KEY_VAR) {
+            Ok(k) => Ok(Some(Self::from_hex(&k)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn encrypt(key: &StorageKey, plain: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0));
+    let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+    let sealed = cipher.encrypt(Nonce::from_slice(&nonce), plain)
+        .map_err(|_| Error::EncryptFailed)?;
+    let mut ret = MAGIC.to_vec();
+    ret.extend_from_slice(&nonce);
+    ret.extend_from_slice(&sealed);
+    Ok(ret)
+}
+
+pub fn decrypt(key: &StorageKey, data: &[u8]) -> Result<Vec<u8>> {
+    let body = data.strip_prefix(MAGIC).ok_or_else(|| Error::DecryptFailed)?;
+    if body.len() < NONCE_LEN {
+        return Err(Box::new(Error::DecryptFailed));
+    }
+    let (nonce, sealed) = body.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0));
+    Ok(cipher.decrypt(Nonce::from_slice(nonce), sealed)
+       .map_err(|_| Error::DecryptFailed)?)
+}
+
+// stored data, decrypted if it was encrypted. Plain data is read as it is so
+// that turning on encryption doesn't lose existing data
+pub fn unseal(data: Vec<u8>, key: Option<&StorageKey>) -> Result<Vec<u8>> {
+    if !is_encrypted(&data) {
+        return Ok(data);
+    }
+    match key {
+        Some(k) => decrypt(k, &data),
+        None => Err(Box::new(Error::EncryptedWithoutKey)),
+    }
+}
+
+// data to store, encrypted when there is a key
+pub fn seal(plain: &[u8], key: Option<&StorageKey>) -> Result<Vec<u8>> {
+    match key {
+        Some(k) => encrypt(k, plain),
+        None => Ok(plain.to_vec()),
+    }
+}
+
+// read a stored file, decrypting it if it was encrypted
+pub fn read(path: &Path, key: Option<&StorageKey>) -> Result<Vec<u8>> {
+    unseal(fs::read(path)?, key)
+}
+
+// write a stored file, encrypted when there is a key. The data goes to a
+// temporary file first so a failed write never leaves a half written file
+pub fn write(path: &Path, key: Option<&StorageKey>, plain: &[u8]) -> Result<()> {
+    let data = seal(plain, key)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}